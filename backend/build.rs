@@ -4,12 +4,22 @@ use std::path::PathBuf;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let out_dir: PathBuf = env::var("OUT_DIR")?.into();
 
+    // `sqlx::migrate!` embeds the migrations, new ones need a rebuild.
+    println!("cargo:rerun-if-changed=migrations");
+
     tonic_build::configure()
+        // Status details for clients to decode, nothing here reads or builds them.
+        .type_attribute("types.FieldError", "#[allow(dead_code)]")
+        .type_attribute("types.FieldErrors", "#[allow(dead_code)]")
         .file_descriptor_set_path(out_dir.join("cpass_descriptor.bin"))
         .compile(
             &[
                 "proto/auth_service.proto",
                 "proto/pass_service.proto",
+                "proto/org_service.proto",
+                "proto/emergency_service.proto",
+                "proto/token_service.proto",
+                "proto/notify_service.proto",
                 "proto/types.proto",
             ],
             &["proto"],
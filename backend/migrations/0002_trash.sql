@@ -0,0 +1,4 @@
+ALTER TABLE passwords
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+CREATE INDEX idx_passwords_deleted_at ON passwords (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,60 @@
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS revision BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE passwords
+    ADD COLUMN IF NOT EXISTS revision   BIGINT      NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+UPDATE users SET revision = 1 WHERE id IN (SELECT owner_id FROM passwords);
+UPDATE passwords SET revision = 1;
+
+CREATE TABLE IF NOT EXISTS password_tombstones
+(
+    id         UUID PRIMARY KEY,
+    owner_id   UUID        NOT NULL,
+    revision   BIGINT      NOT NULL,
+    deleted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT fk_owner FOREIGN KEY (owner_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_passwords_owner_revision ON passwords (owner_id, revision);
+CREATE INDEX idx_password_tombstones_owner_revision ON password_tombstones (owner_id, revision);
+
+-- Every change to an item takes the next revision of its owner. The row lock on `users`
+-- serializes concurrent writers, so revisions become visible in increasing order.
+CREATE OR REPLACE FUNCTION bump_password_revision() RETURNS TRIGGER AS
+$$
+BEGIN
+    UPDATE users SET revision = revision + 1 WHERE id = NEW.owner_id RETURNING revision INTO NEW.revision;
+    NEW.updated_at = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION record_password_tombstone() RETURNS TRIGGER AS
+$$
+DECLARE
+    next_revision BIGINT;
+BEGIN
+    UPDATE users SET revision = revision + 1 WHERE id = OLD.owner_id RETURNING revision INTO next_revision;
+    IF FOUND THEN
+        INSERT INTO password_tombstones(id, owner_id, revision)
+        VALUES (OLD.id, OLD.owner_id, next_revision)
+        ON CONFLICT (id) DO UPDATE SET revision = EXCLUDED.revision, deleted_at = now();
+    END IF;
+    RETURN OLD;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_passwords_revision
+    BEFORE INSERT OR UPDATE
+    ON passwords
+    FOR EACH ROW
+EXECUTE FUNCTION bump_password_revision();
+
+CREATE TRIGGER trg_passwords_tombstone
+    AFTER DELETE
+    ON passwords
+    FOR EACH ROW
+EXECUTE FUNCTION record_password_tombstone();
@@ -0,0 +1,22 @@
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS public_key          BYTEA,
+    ADD COLUMN IF NOT EXISTS wrapped_private_key BYTEA;
+
+-- Per-item key wrapped by the vault key of the owner. Items without one are encrypted
+-- directly under the vault key and can not be shared.
+ALTER TABLE passwords
+    ADD COLUMN IF NOT EXISTS item_key BYTEA;
+
+CREATE TABLE IF NOT EXISTS shares
+(
+    item_id          UUID        NOT NULL,
+    recipient_id     UUID        NOT NULL,
+    wrapped_item_key BYTEA       NOT NULL,
+    permission       TEXT        NOT NULL CHECK (permission IN ('read', 'write')),
+    created_at       TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (item_id, recipient_id),
+    CONSTRAINT fk_item FOREIGN KEY (item_id) REFERENCES passwords (id) ON DELETE CASCADE,
+    CONSTRAINT fk_recipient FOREIGN KEY (recipient_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_shares_recipient_id ON shares (recipient_id);
@@ -0,0 +1,81 @@
+CREATE TABLE IF NOT EXISTS organizations
+(
+    id         UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    name       TEXT        NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- The organization key wrapped to the public key of the member. Invites are pending
+-- memberships, they grant nothing until accepted.
+CREATE TABLE IF NOT EXISTS org_members
+(
+    org_id          UUID        NOT NULL,
+    user_id         UUID        NOT NULL,
+    role            TEXT        NOT NULL CHECK (role IN ('owner', 'admin', 'member', 'read_only')),
+    wrapped_org_key BYTEA       NOT NULL,
+    invited_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    accepted_at     TIMESTAMPTZ,
+    PRIMARY KEY (org_id, user_id),
+    CONSTRAINT fk_org FOREIGN KEY (org_id) REFERENCES organizations (id) ON DELETE CASCADE,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_org_members_user_id ON org_members (user_id);
+
+CREATE TABLE IF NOT EXISTS collections
+(
+    id         UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    org_id     UUID        NOT NULL,
+    name       BYTEA       NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT fk_org FOREIGN KEY (org_id) REFERENCES organizations (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_collections_org_id ON collections (org_id);
+
+-- Items in a collection are encrypted under the organization key, `owner_id` only records
+-- who created them and grants nothing.
+ALTER TABLE passwords
+    ADD COLUMN IF NOT EXISTS collection_id UUID
+        CONSTRAINT fk_collection REFERENCES collections (id) ON DELETE CASCADE;
+
+CREATE INDEX idx_passwords_collection_id ON passwords (collection_id) WHERE collection_id IS NOT NULL;
+
+-- `read` is granted to every member, `write` to members and above, `manage` to admins
+-- and owners.
+CREATE OR REPLACE FUNCTION collection_can(p_collection UUID, p_user UUID, p_action TEXT) RETURNS BOOLEAN AS
+$$
+SELECT EXISTS (
+    SELECT 1
+    FROM collections c
+    JOIN org_members m ON m.org_id = c.org_id
+    WHERE c.id = p_collection AND m.user_id = p_user AND m.accepted_at IS NOT NULL
+        AND CASE p_action
+            WHEN 'read' THEN true
+            WHEN 'write' THEN m.role IN ('owner', 'admin', 'member')
+            ELSE m.role IN ('owner', 'admin')
+        END
+);
+$$ LANGUAGE sql STABLE;
+
+-- Single source of truth for access to an item, see `ItemAction` for the actions.
+-- Personal items belong to their owner, share recipients may read them and update them
+-- with a write share. Collection items follow the role of the user in the organization.
+CREATE OR REPLACE FUNCTION item_can(p_item UUID, p_user UUID, p_action TEXT) RETURNS BOOLEAN AS
+$$
+SELECT CASE
+    WHEN p.collection_id IS NOT NULL THEN collection_can(
+        p.collection_id,
+        p_user,
+        CASE p_action WHEN 'read' THEN 'read' WHEN 'purge' THEN 'manage' ELSE 'write' END
+    )
+    WHEN p.owner_id = p_user THEN true
+    ELSE EXISTS (
+        SELECT 1 FROM shares s
+        WHERE s.item_id = p.id AND s.recipient_id = p_user
+            AND (p_action = 'read' OR (p_action = 'update' AND s.permission = 'write'))
+    )
+END
+FROM passwords p
+WHERE p.id = p_item;
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,17 @@
+-- Decoy items whose retrieval signals a compromised account or token. The flag never
+-- leaves the server.
+ALTER TABLE passwords
+    ADD COLUMN IF NOT EXISTS honeytoken BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS honeytoken_alerts
+(
+    id           UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    item_id      UUID        NOT NULL,
+    owner_id     UUID        NOT NULL,
+    accessor_id  UUID        NOT NULL,
+    session_id   UUID        NOT NULL,
+    transport    TEXT        NOT NULL,
+    triggered_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_honeytoken_alerts_owner_id ON honeytoken_alerts (owner_id);
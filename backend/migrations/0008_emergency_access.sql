@@ -0,0 +1,25 @@
+-- Emergency contacts of a grantor. The vault key of the grantor is wrapped to the public
+-- key of the grantee and only handed out once a request is approved, by the grantor or
+-- by the waiting period running out. A key rotation clears `wrapped_vault_key` until the
+-- grantor uploads a new one.
+CREATE TABLE IF NOT EXISTS emergency_contacts
+(
+    id                UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    grantor_id        UUID        NOT NULL,
+    grantee_id        UUID        NOT NULL,
+    access            TEXT        NOT NULL CHECK (access IN ('read', 'takeover')),
+    wait_days         INT         NOT NULL CHECK (wait_days BETWEEN 1 AND 90),
+    wrapped_vault_key BYTEA,
+    status            TEXT        NOT NULL DEFAULT 'invited'
+        CHECK (status IN ('invited', 'confirmed', 'requested', 'approved')),
+    created_at        TIMESTAMPTZ NOT NULL DEFAULT now(),
+    requested_at      TIMESTAMPTZ,
+    approved_at       TIMESTAMPTZ,
+    UNIQUE (grantor_id, grantee_id),
+    CHECK (grantor_id <> grantee_id),
+    CONSTRAINT fk_grantor FOREIGN KEY (grantor_id) REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT fk_grantee FOREIGN KEY (grantee_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_emergency_contacts_grantee_id ON emergency_contacts (grantee_id);
+CREATE INDEX idx_emergency_contacts_requested ON emergency_contacts (requested_at) WHERE status = 'requested';
@@ -0,0 +1,6 @@
+-- Recovery key generated by the client at registration. The secret derived from it is
+-- hashed like the login password, the vault key wrapped by it lets the user set a new
+-- master password without re-encrypting their items. A key rotation clears both.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS recovery_secret    TEXT,
+    ADD COLUMN IF NOT EXISTS recovery_vault_key BYTEA;
@@ -0,0 +1,13 @@
+-- Deleting a user removes their items, the tombstone trigger skips owners that are gone.
+ALTER TABLE passwords
+    DROP CONSTRAINT fk_owner,
+    ADD CONSTRAINT fk_owner FOREIGN KEY (owner_id) REFERENCES users (id) ON DELETE CASCADE;
+
+-- Deletion requested with a grace period. Only the SHA-256 of the cancellation token is
+-- stored.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS deletion_scheduled_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS deletion_cancel_token BYTEA;
+
+CREATE UNIQUE INDEX idx_users_deletion_cancel_token ON users (deletion_cancel_token)
+    WHERE deletion_cancel_token IS NOT NULL;
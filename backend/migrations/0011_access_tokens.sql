@@ -0,0 +1,31 @@
+-- Machine accounts are users without a password, created and managed by a person. They
+-- authenticate with access tokens only and go away with their owner.
+ALTER TABLE users
+    ALTER COLUMN password DROP NOT NULL,
+    ADD COLUMN IF NOT EXISTS machine_owner_id UUID
+        CONSTRAINT fk_machine_owner REFERENCES users (id) ON DELETE CASCADE,
+    ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD CONSTRAINT chk_machine_password CHECK ((machine_owner_id IS NULL) = (password IS NOT NULL));
+
+CREATE INDEX idx_users_machine_owner_id ON users (machine_owner_id) WHERE machine_owner_id IS NOT NULL;
+
+-- Long-lived credentials of a user or machine account. Only the SHA-256 of the token is
+-- stored, `prefix` is its public part identifying it in listings and secret scanners.
+-- `collection_ids` restricts the token to those collections, NULL leaves it unrestricted.
+CREATE TABLE IF NOT EXISTS access_tokens
+(
+    id             UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    user_id        UUID        NOT NULL,
+    name           TEXT        NOT NULL,
+    prefix         TEXT        NOT NULL UNIQUE,
+    token_hash     BYTEA       NOT NULL UNIQUE,
+    scopes         TEXT[]      NOT NULL
+        CHECK (cardinality(scopes) > 0 AND scopes <@ ARRAY['vault:read', 'vault:write']),
+    collection_ids UUID[],
+    created_at     TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at     TIMESTAMPTZ,
+    last_used_at   TIMESTAMPTZ,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_access_tokens_user_id ON access_tokens (user_id);
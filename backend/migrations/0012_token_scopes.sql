@@ -0,0 +1,16 @@
+-- Access tokens may be granted any scope but `account`, which stays with sessions. Tokens
+-- restricted to collections keep to the vault scopes.
+ALTER TABLE access_tokens
+    DROP CONSTRAINT IF EXISTS access_tokens_scopes_check;
+
+-- `vault:read` used to cover listing the organizations of the holder.
+UPDATE access_tokens
+SET scopes = array_append(scopes, 'org:read')
+WHERE 'vault:read' = ANY (scopes) AND collection_ids IS NULL;
+
+ALTER TABLE access_tokens
+    ADD CONSTRAINT access_tokens_scopes_check CHECK (
+        cardinality(scopes) > 0
+        AND scopes <@ ARRAY['vault:read', 'vault:write', 'vault:manage', 'org:read', 'org:manage', 'emergency']
+        AND (collection_ids IS NULL OR scopes <@ ARRAY['vault:read', 'vault:write'])
+    );
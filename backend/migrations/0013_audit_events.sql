@@ -0,0 +1,34 @@
+-- Append-only record of who did what. Every row carries the hash of the previous one, so
+-- editing, removing or reordering rows breaks the chain. `actor_id` has no foreign key,
+-- events outlive the accounts they mention.
+CREATE TABLE IF NOT EXISTS audit_events
+(
+    seq         BIGINT PRIMARY KEY,
+    occurred_at TIMESTAMPTZ NOT NULL,
+    event_type  TEXT        NOT NULL,
+    actor_id    UUID,
+    session_id  UUID,
+    item_id     UUID,
+    ip          TEXT,
+    user_agent  TEXT,
+    detail      TEXT,
+    prev_hash   BYTEA       NOT NULL,
+    hash        BYTEA       NOT NULL UNIQUE
+);
+
+CREATE INDEX idx_audit_events_actor_id ON audit_events (actor_id, occurred_at);
+
+CREATE OR REPLACE FUNCTION audit_events_append_only() RETURNS TRIGGER AS
+$$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_no_update
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION audit_events_append_only();
+
+CREATE TRIGGER audit_events_no_truncate
+    BEFORE TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_events_append_only();
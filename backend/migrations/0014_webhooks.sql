@@ -0,0 +1,43 @@
+-- Endpoints notified of security events of a user, or of every member of an organization.
+-- `secret` is the HMAC-SHA256 key signing each payload, so it is kept as is.
+CREATE TABLE IF NOT EXISTS webhooks
+(
+    id         UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    user_id    UUID,
+    org_id     UUID,
+    url        TEXT        NOT NULL,
+    secret     TEXT        NOT NULL,
+    events     TEXT[]      NOT NULL CHECK (cardinality(events) > 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT fk_org FOREIGN KEY (org_id) REFERENCES organizations (id) ON DELETE CASCADE,
+    CONSTRAINT chk_webhook_owner CHECK ((user_id IS NULL) <> (org_id IS NULL))
+);
+
+CREATE INDEX idx_webhooks_user_id ON webhooks (user_id) WHERE user_id IS NOT NULL;
+CREATE INDEX idx_webhooks_org_id ON webhooks (org_id) WHERE org_id IS NOT NULL;
+
+-- Outbox of payloads waiting to be delivered, kept afterwards as the delivery log. Failed
+-- attempts are retried at `next_attempt_at` with an exponential backoff until the delivery
+-- succeeds or runs out of attempts.
+CREATE TABLE IF NOT EXISTS webhook_deliveries
+(
+    id              UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    webhook_id      UUID        NOT NULL,
+    event_type      TEXT        NOT NULL,
+    payload         TEXT        NOT NULL,
+    status          TEXT        NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'delivered', 'failed')),
+    attempts        INT         NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- HTTP status or error of the last attempt.
+    last_status     INT,
+    last_error      TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at    TIMESTAMPTZ,
+    CONSTRAINT fk_webhook FOREIGN KEY (webhook_id) REFERENCES webhooks (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_webhook_deliveries_due ON webhook_deliveries (next_attempt_at)
+    WHERE status = 'pending';
+CREATE INDEX idx_webhook_deliveries_webhook_id ON webhook_deliveries (webhook_id, created_at);
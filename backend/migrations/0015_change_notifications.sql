@@ -0,0 +1,109 @@
+-- Changes are published on the `cpass_changes` channel once their transaction commits, so
+-- every replica listening there can push them to its subscribers. Payloads only carry ids:
+-- personal items go to each user who can see them, collection items to their organization,
+-- whose members are checked by the subscriber.
+CREATE OR REPLACE FUNCTION publish_change(p_event TEXT, p_user UUID, p_org UUID, p_collection UUID,
+                                          p_item UUID, p_revision BIGINT, p_session UUID) RETURNS VOID AS
+$$
+SELECT pg_notify('cpass_changes', json_build_object(
+    'event', p_event,
+    'user_id', p_user,
+    'org_id', p_org,
+    'collection_id', p_collection,
+    'item_id', p_item,
+    'revision', p_revision,
+    'session_id', p_session
+)::TEXT);
+$$ LANGUAGE sql;
+
+-- Items moved to the trash or purged are deleted as far as clients are concerned.
+CREATE OR REPLACE FUNCTION publish_password_change() RETURNS TRIGGER AS
+$$
+DECLARE
+    item      passwords;
+    event     TEXT;
+    revision  BIGINT;
+    recipient UUID;
+BEGIN
+    -- A purge takes its revision with the tombstone, after this runs.
+    IF TG_OP = 'DELETE' THEN
+        item := OLD;
+        event := 'item.deleted';
+    ELSE
+        item := NEW;
+        event := CASE WHEN NEW.deleted_at IS NULL THEN 'item.changed' ELSE 'item.deleted' END;
+        revision := NEW.revision;
+    END IF;
+
+    IF item.collection_id IS NOT NULL THEN
+        PERFORM publish_change(event, NULL, c.org_id, c.id, item.id, revision, NULL)
+        FROM collections c
+        WHERE c.id = item.collection_id;
+    ELSE
+        PERFORM publish_change(event, item.owner_id, NULL, NULL, item.id, revision, NULL);
+        FOR recipient IN SELECT recipient_id FROM shares WHERE item_id = item.id
+        LOOP
+            PERFORM publish_change(event, recipient, NULL, NULL, item.id, revision, NULL);
+        END LOOP;
+    END IF;
+
+    -- Lets the row go when fired before a delete, ignored otherwise.
+    RETURN item;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_passwords_publish
+    AFTER INSERT OR UPDATE
+    ON passwords
+    FOR EACH ROW
+EXECUTE FUNCTION publish_password_change();
+
+-- Shares are gone by the time a purge fires AFTER triggers, so their recipients are read
+-- before the row goes.
+CREATE TRIGGER trg_passwords_publish_delete
+    BEFORE DELETE
+    ON passwords
+    FOR EACH ROW
+EXECUTE FUNCTION publish_password_change();
+
+-- A recipient sees a shared item appear, and disappear once the share is revoked.
+CREATE OR REPLACE FUNCTION publish_share_change() RETURNS TRIGGER AS
+$$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM publish_change('item.deleted', OLD.recipient_id, NULL, NULL, OLD.item_id, NULL, NULL);
+    ELSE
+        PERFORM publish_change('item.changed', NEW.recipient_id, NULL, NULL, NEW.item_id, p.revision, NULL)
+        FROM passwords p
+        WHERE p.id = NEW.item_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_shares_publish
+    AFTER INSERT OR DELETE
+    ON shares
+    FOR EACH ROW
+EXECUTE FUNCTION publish_share_change();
+
+-- Sessions and access tokens are both revoked by deleting them.
+CREATE OR REPLACE FUNCTION publish_session_revoked() RETURNS TRIGGER AS
+$$
+BEGIN
+    PERFORM publish_change('session.revoked', OLD.user_id, NULL, NULL, NULL, NULL, OLD.id);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_sessions_publish
+    AFTER DELETE
+    ON sessions
+    FOR EACH ROW
+EXECUTE FUNCTION publish_session_revoked();
+
+CREATE TRIGGER trg_access_tokens_publish
+    AFTER DELETE
+    ON access_tokens
+    FOR EACH ROW
+EXECUTE FUNCTION publish_session_revoked();
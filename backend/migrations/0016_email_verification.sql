@@ -0,0 +1,40 @@
+-- Accounts created before verification existed, and machine accounts, which have no
+-- mailbox, count as verified. `pending_email` is the address an email change waits to
+-- verify, `email` stays in use until then.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS pending_email     TEXT;
+
+UPDATE users SET email_verified_at = created_at;
+
+-- Outstanding proof of ownership of `email`, at most one per user. Only the SHA-256 of the
+-- token sent by mail is stored.
+CREATE TABLE IF NOT EXISTS email_verifications
+(
+    token_hash BYTEA PRIMARY KEY,
+    user_id    UUID        NOT NULL UNIQUE,
+    email      TEXT        NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+-- Rendered mails waiting to be sent, removed once they are. Failed attempts are retried at
+-- `next_attempt_at` until they run out of attempts and are left as `failed`.
+CREATE TABLE IF NOT EXISTS mail_outbox
+(
+    id              UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    user_id         UUID,
+    recipient       TEXT        NOT NULL,
+    template        TEXT        NOT NULL,
+    subject         TEXT        NOT NULL,
+    body            TEXT        NOT NULL,
+    status          TEXT        NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'failed')),
+    attempts        INT         NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error      TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_mail_outbox_due ON mail_outbox (next_attempt_at) WHERE status = 'pending';
@@ -0,0 +1,31 @@
+-- Devices a user logged in from, recognized by a secret token the client keeps and sends
+-- with every login. Only the SHA-256 of the token is stored. A device waiting for approval
+-- has no `approved_at` and gets no session until an approved device of the user approves
+-- it, which only happens when the user asked for it with `require_device_approval`.
+CREATE TABLE IF NOT EXISTS devices
+(
+    id            UUID PRIMARY KEY     DEFAULT uuid_generate_v4(),
+    user_id       UUID        NOT NULL,
+    token_hash    BYTEA       NOT NULL,
+    name          TEXT        NOT NULL,
+    device_type   TEXT        NOT NULL
+        CHECK (device_type IN ('browser', 'desktop', 'mobile', 'cli', 'other')),
+    approved_at   TIMESTAMPTZ,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at  TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_ip       TEXT,
+    user_agent    TEXT,
+    CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT uq_device_token UNIQUE (user_id, token_hash)
+);
+
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS require_device_approval BOOLEAN NOT NULL DEFAULT false;
+
+-- Removing a device ends its sessions. Sessions opened at signup, or before devices were
+-- recorded, have none.
+ALTER TABLE sessions
+    ADD COLUMN IF NOT EXISTS device_id UUID
+        CONSTRAINT fk_device REFERENCES devices (id) ON DELETE CASCADE;
+
+CREATE INDEX idx_sessions_device_id ON sessions (device_id) WHERE device_id IS NOT NULL;
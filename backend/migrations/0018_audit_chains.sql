@@ -0,0 +1,36 @@
+-- Audit events are chained per account instead of in one chain, so appends for different
+-- accounts no longer wait on each other. `chain_seq` numbers the events of a chain and is
+-- what the hash covers, `seq` only orders events across chains. Events without an account,
+-- and every event recorded before, stay in the shared chain of the nil UUID.
+CREATE SEQUENCE IF NOT EXISTS audit_events_seq OWNED BY audit_events.seq;
+SELECT setval('audit_events_seq', COALESCE((SELECT max(seq) FROM audit_events), 0) + 1, false);
+
+ALTER TABLE audit_events
+    ALTER COLUMN seq SET DEFAULT nextval('audit_events_seq'),
+    ADD COLUMN IF NOT EXISTS chain_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000',
+    ADD COLUMN IF NOT EXISTS chain_seq BIGINT;
+
+ALTER TABLE audit_events DISABLE TRIGGER audit_events_no_update;
+UPDATE audit_events SET chain_seq = seq WHERE chain_seq IS NULL;
+ALTER TABLE audit_events ENABLE TRIGGER audit_events_no_update;
+
+ALTER TABLE audit_events
+    ALTER COLUMN chain_seq SET NOT NULL,
+    ALTER COLUMN chain_id DROP DEFAULT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_audit_events_chain ON audit_events (chain_id, chain_seq);
+
+-- Last event of every chain. Appending locks the row of its chain until the operation it
+-- records commits.
+CREATE TABLE IF NOT EXISTS audit_chains
+(
+    chain_id UUID PRIMARY KEY,
+    seq      BIGINT NOT NULL,
+    head     BYTEA  NOT NULL
+);
+
+INSERT INTO audit_chains(chain_id, seq, head)
+SELECT chain_id, chain_seq, hash FROM audit_events
+ORDER BY seq DESC
+LIMIT 1
+ON CONFLICT DO NOTHING;
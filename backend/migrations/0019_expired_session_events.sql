@@ -0,0 +1,11 @@
+-- Expired sessions and access tokens are deleted to clean them up, which is not a
+-- revocation subscribers need to hear of. Only rows deleted before they expired are.
+CREATE OR REPLACE FUNCTION publish_session_revoked() RETURNS TRIGGER AS
+$$
+BEGIN
+    IF OLD.expires_at IS NULL OR OLD.expires_at >= now() THEN
+        PERFORM publish_change('session.revoked', OLD.user_id, NULL, NULL, NULL, NULL, OLD.id);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
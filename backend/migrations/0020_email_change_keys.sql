@@ -0,0 +1,6 @@
+-- Clients derive the login password and the key wrapping the vault key from the master
+-- password salted with the email address, so both change with it. An email change carries
+-- them derived with the new address, they replace the current ones once it is verified.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS pending_password  TEXT,
+    ADD COLUMN IF NOT EXISTS pending_vault_key BYTEA;
@@ -0,0 +1,257 @@
+use chrono::{DateTime, Utc};
+use ring::digest::{digest, SHA256};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::CpassError,
+    jwt::{generate::generate_bytes, models::Principal},
+    machine_account,
+    policy::Scope,
+};
+
+/// Start of every access token, telling it apart from a JWT.
+pub const TOKEN_PREFIX: &str = "cpat_";
+
+pub struct AccessTokenRow {
+    pub id: Uuid,
+    /// Email of the user or machine account the token acts as.
+    pub holder: String,
+    pub name: String,
+    /// Public start of the token.
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    /// Collections the token is restricted to, `None` when unrestricted.
+    pub collection_ids: Option<Vec<Uuid>>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A freshly created access token, `token` is never shown again.
+pub struct NewAccessToken {
+    pub row: AccessTokenRow,
+    pub token: String,
+}
+
+/// Create an access token acting as `user_id`, or as their machine account `machine_id`.
+///
+/// Every collection the token is restricted to must be readable by the holder.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_token(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    machine_id: Option<Uuid>,
+    name: &str,
+    scopes: &[Scope],
+    collection_ids: Option<Vec<Uuid>>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<NewAccessToken, CpassError> {
+    if name.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "token name can not be empty".to_string(),
+        ));
+    }
+    if scopes.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "a token needs at least one scope".to_string(),
+        ));
+    }
+    if scopes.contains(&Scope::Account) {
+        return Err(CpassError::InvalidRequest(
+            "the account scope is reserved to sessions".to_string(),
+        ));
+    }
+    if collection_ids.is_some()
+        && scopes
+            .iter()
+            .any(|scope| !matches!(scope, Scope::VaultRead | Scope::VaultWrite))
+    {
+        return Err(CpassError::InvalidRequest(
+            "a restricted token can only be granted vault:read and vault:write".to_string(),
+        ));
+    }
+    if collection_ids.as_ref().is_some_and(Vec::is_empty) {
+        return Err(CpassError::InvalidRequest(
+            "a restricted token needs at least one collection".to_string(),
+        ));
+    }
+    if expires_at.is_some_and(|at| at <= Utc::now()) {
+        return Err(CpassError::InvalidRequest(
+            "expiry must be in the future".to_string(),
+        ));
+    }
+
+    let holder_id = match machine_id {
+        Some(machine_id) => {
+            machine_account::owned(&mut *conn, user_id, machine_id).await?;
+            machine_id
+        }
+        None => user_id,
+    };
+
+    if let Some(collection_ids) = &collection_ids {
+        let readable = sqlx::query_scalar!(
+            r#"
+            SELECT bool_and(collection_can(c, $2, 'read')) AS "readable!"
+            FROM unnest($1::UUID[]) c
+            "#,
+            collection_ids,
+            holder_id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if !readable {
+            return Err(CpassError::NotFound(
+                "Collection with that id not found".to_string(),
+            ));
+        }
+    }
+
+    let prefix = format!("{}{}", TOKEN_PREFIX, hex::encode(generate_bytes(4)));
+    let token = format!("{}_{}", prefix, hex::encode(generate_bytes(32)));
+    let token_hash = digest(&SHA256, token.as_bytes());
+    let scopes: Vec<String> = scopes
+        .iter()
+        .map(|scope| scope.as_str().to_string())
+        .collect();
+
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO access_tokens(user_id, name, prefix, token_hash, scopes, collection_ids, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, created_at, (SELECT email FROM users WHERE id = $1) AS "holder!"
+        "#,
+        holder_id,
+        name,
+        prefix,
+        token_hash.as_ref(),
+        &scopes,
+        collection_ids.as_deref(),
+        expires_at
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(NewAccessToken {
+        row: AccessTokenRow {
+            id: row.id,
+            holder: row.holder,
+            name: name.to_string(),
+            prefix,
+            scopes,
+            collection_ids,
+            created_at: row.created_at,
+            expires_at,
+            last_used_at: None,
+        },
+        token,
+    })
+}
+
+/// Access tokens of `user_id` and of their machine accounts.
+pub async fn list_tokens(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Vec<AccessTokenRow>, CpassError> {
+    let tokens = sqlx::query_as!(
+        AccessTokenRow,
+        r#"
+        SELECT
+            t.id, u.email AS holder, t.name, t.prefix, t.scopes, t.collection_ids, t.created_at,
+            t.expires_at, t.last_used_at
+        FROM access_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE u.id = $1 OR u.machine_owner_id = $1
+        ORDER BY t.created_at, t.id
+        "#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(tokens)
+}
+
+/// Revoke an access token of `user_id` or of one of their machine accounts.
+pub async fn revoke_token(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<(), CpassError> {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM access_tokens t
+        USING users u
+        WHERE t.id = $1 AND u.id = t.user_id AND (u.id = $2 OR u.machine_owner_id = $2)
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(CpassError::NotFound(
+            "Access token with that id not found".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check an access token presented with a request and record its use.
+pub async fn verify(pool: &PgPool, token: &str) -> Result<Principal, CpassError> {
+    let token_hash = digest(&SHA256, token.as_bytes());
+    let row = sqlx::query!(
+        r#"
+        UPDATE access_tokens
+        SET last_used_at = now()
+        WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > now())
+        RETURNING id, user_id, scopes, collection_ids, expires_at
+        "#,
+        token_hash.as_ref()
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| CpassError::Unauthorized("Access token is invalid or expired".to_string()))?;
+
+    Ok(Principal {
+        sub: row.user_id,
+        sid: row.id,
+        scopes: Scope::parse_all(&row.scopes)?,
+        collections: row.collection_ids,
+        expires_at: row.expires_at,
+    })
+}
+
+/// Fail unless `principal` may reach the item `item_id`, as far as its token restriction
+/// goes. Whether the item is accessible at all is up to the query using it.
+pub async fn check_item(
+    conn: &mut PgConnection,
+    principal: &Principal,
+    item_id: Uuid,
+) -> Result<(), CpassError> {
+    if principal.collections.is_none() {
+        return Ok(());
+    }
+
+    let collection_id = sqlx::query_scalar!(
+        r#"
+        SELECT collection_id FROM passwords
+        WHERE id = $1
+        "#,
+        item_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .flatten();
+
+    match principal.reaches(collection_id) {
+        true => Ok(()),
+        false => Err(CpassError::NotFound(
+            "Password with that id not found".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,454 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use ring::digest::{digest, SHA256};
+use sqlx::{PgConnection, PgPool};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    db::Db,
+    error::CpassError,
+    hashing::Argon,
+    jwt::{generate::generate_bytes, session},
+    verification,
+};
+
+const DELETION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    static ref GRACE_DAYS: i32 =
+        grace_days().expect("ACCOUNT_DELETION_GRACE_DAYS is checked at startup");
+}
+
+/// Days between a deletion request and the deletion.
+///
+/// Read from `ACCOUNT_DELETION_GRACE_DAYS`, accounts are deleted right away by default.
+pub fn grace_days() -> anyhow::Result<i32> {
+    match dotenvy::var("ACCOUNT_DELETION_GRACE_DAYS") {
+        Ok(days) => {
+            let days: i32 = days
+                .parse()
+                .context("ACCOUNT_DELETION_GRACE_DAYS must be a number of days")?;
+            anyhow::ensure!(days >= 0, "ACCOUNT_DELETION_GRACE_DAYS can not be negative");
+            Ok(days)
+        }
+        Err(_) => Ok(0),
+    }
+}
+
+/// A deletion waiting for its grace period to end.
+pub struct ScheduledDeletion {
+    pub deletes_at: DateTime<Utc>,
+    /// Secret cancelling the deletion, only its hash is stored.
+    pub cancel_token: String,
+}
+
+/// Email address and username of `user_id`, words an attacker guessing their master
+/// password knows.
+pub async fn user_inputs(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<[String; 2], CpassError> {
+    let user = sqlx::query!(
+        r#"
+        SELECT email, username FROM users
+        WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("User not found".to_string()))?;
+
+    Ok([user.email, user.username])
+}
+
+/// Check `password` against the login password of `user_id`, locking the user row until
+/// the end of the transaction.
+pub async fn verify_password(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    password: &str,
+) -> Result<(), CpassError> {
+    let hash = sqlx::query_scalar!(
+        r#"
+        SELECT password FROM users
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .flatten()
+    .ok_or(CpassError::InvalidUsernameOrPassword)?;
+
+    match Argon::verify(password.as_bytes(), &hash)? {
+        true => Ok(()),
+        false => Err(CpassError::InvalidUsernameOrPassword),
+    }
+}
+
+/// Hash `password` again when the stored `hash` of `user_id` was made with weaker
+/// parameters than new hashes are, see [`Argon::needs_rehash`]. Only called once `password`
+/// was verified against `hash`.
+pub async fn upgrade_password_hash(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    hash: &str,
+    password: &str,
+) -> Result<(), CpassError> {
+    if !Argon::needs_rehash(hash) {
+        return Ok(());
+    }
+    let rehashed = Argon::hash_password(password.as_bytes())?;
+
+    // Left alone when the password changed since `hash` was read.
+    sqlx::query!(
+        r#"
+        UPDATE users SET password = $3
+        WHERE id = $1 AND password = $2
+        "#,
+        user_id,
+        hash,
+        rehashed
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Replace the master password of `user_id` once `current_password` is confirmed.
+///
+/// `password_hash` is the hashed login password derived from the new master password and
+/// `vault_key` the unchanged vault key wrapped by it, both are written together so the
+/// vault can never be left wrapped by a password the user can not log in with. Every
+/// session of the user is revoked.
+pub async fn change_master_password(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    current_password: &str,
+    password_hash: String,
+    vault_key: Vec<u8>,
+) -> Result<(), CpassError> {
+    if vault_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "vault key can not be empty".to_string(),
+        ));
+    }
+
+    verify_password(&mut *conn, user_id, current_password).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password = $1, vault_key = $2,
+            pending_email = NULL, pending_password = NULL, pending_vault_key = NULL
+        WHERE id = $3
+        "#,
+        password_hash,
+        vault_key,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    session::revoke_all(conn, user_id).await?;
+
+    Ok(())
+}
+
+/// Ask for the address of `user_id` to change to `email` once `current_password` is
+/// confirmed.
+///
+/// Clients derive the login password and the key wrapping the vault key with the address,
+/// so `password_hash` and `vault_key` are derived from the unchanged master password with
+/// `email`. They replace the current ones together with the address once it is verified,
+/// see [`verification::verify`]. Changing the master password or the vault key meanwhile
+/// drops the pending change.
+pub async fn change_email(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    email: &str,
+    current_password: &str,
+    password_hash: &str,
+    vault_key: &[u8],
+) -> Result<(), CpassError> {
+    if vault_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "vault key can not be empty".to_string(),
+        ));
+    }
+
+    verify_password(&mut *conn, user_id, current_password).await?;
+
+    verification::request_change(conn, user_id, email, password_hash, vault_key).await
+}
+
+/// Fail if deleting `user_id` would leave other members of an organization without an
+/// owner, those organizations need another owner first.
+async fn check_owners(conn: &mut PgConnection, user_id: Uuid) -> Result<(), CpassError> {
+    let orphaned = sqlx::query_scalar!(
+        r#"
+        SELECT o.name FROM organizations o
+        JOIN org_members m ON m.org_id = o.id
+        WHERE m.user_id = $1 AND m.role = 'owner' AND m.accepted_at IS NOT NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM org_members x
+                WHERE x.org_id = o.id AND x.user_id <> $1 AND x.role = 'owner'
+                    AND x.accepted_at IS NOT NULL
+            )
+            AND EXISTS (
+                SELECT 1 FROM org_members x
+                WHERE x.org_id = o.id AND x.user_id <> $1 AND x.accepted_at IS NOT NULL
+            )
+        ORDER BY o.name
+        "#,
+        user_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if !orphaned.is_empty() {
+        return Err(CpassError::InvalidRequest(format!(
+            "another owner is required before leaving {}",
+            orphaned.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Get organizations out of the way of deleting `user_id`.
+///
+/// Organizations the user is the only member of are deleted with their collections, see
+/// [`check_owners`] for the others. Items the user created in the remaining collections are
+/// handed to the longest standing owner.
+async fn release_organizations(conn: &mut PgConnection, user_id: Uuid) -> Result<(), CpassError> {
+    check_owners(&mut *conn, user_id).await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM organizations o
+        WHERE EXISTS (
+                SELECT 1 FROM org_members m
+                WHERE m.org_id = o.id AND m.user_id = $1 AND m.accepted_at IS NOT NULL
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM org_members x
+                WHERE x.org_id = o.id AND x.user_id <> $1 AND x.accepted_at IS NOT NULL
+            )
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE passwords p
+        SET owner_id = (
+            SELECT m.user_id FROM collections c
+            JOIN org_members m ON m.org_id = c.org_id
+            WHERE c.id = p.collection_id AND m.user_id <> $1 AND m.role = 'owner'
+                AND m.accepted_at IS NOT NULL
+            ORDER BY m.accepted_at, m.user_id
+            LIMIT 1
+        )
+        WHERE p.owner_id = $1 AND p.collection_id IS NOT NULL
+        "#,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// `user_id` followed by their machine accounts, which are deleted along with them.
+async fn accounts_of(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<Uuid>, CpassError> {
+    let machines = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM users
+        WHERE machine_owner_id = $1
+        "#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok([user_id].into_iter().chain(machines).collect())
+}
+
+/// Delete `user_id` with everything they own.
+///
+/// Personal items, trashed ones included, their shares, tombstones, sessions, access
+/// tokens, machine accounts, memberships and emergency contacts go with the user row.
+/// Honeytoken alerts have no foreign key to outlive their items and are removed explicitly.
+pub async fn delete_account(conn: &mut PgConnection, user_id: Uuid) -> Result<(), CpassError> {
+    let accounts = accounts_of(&mut *conn, user_id).await?;
+    for account_id in &accounts {
+        release_organizations(&mut *conn, *account_id).await?;
+    }
+
+    sqlx::query!(
+        r#"
+        DELETE FROM honeytoken_alerts
+        WHERE owner_id = ANY($1)
+        "#,
+        &accounts
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM users
+        WHERE id = $1
+        "#,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(CpassError::NotFound("User not found".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Delete `user_id` once `password` is confirmed, or schedule the deletion when a grace
+/// period is configured.
+///
+/// A scheduled deletion revokes every session and returns the token cancelling it, asking
+/// again replaces the token. Returns `None` when the account is already gone.
+pub async fn request_deletion(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    password: &str,
+) -> Result<Option<ScheduledDeletion>, CpassError> {
+    verify_password(&mut *conn, user_id, password).await?;
+
+    if *GRACE_DAYS == 0 {
+        delete_account(&mut *conn, user_id).await?;
+
+        info!(%user_id, "Account deleted");
+
+        return Ok(None);
+    }
+
+    // Fail now rather than when the grace period ends.
+    for account_id in accounts_of(&mut *conn, user_id).await? {
+        check_owners(&mut *conn, account_id).await?;
+    }
+
+    let cancel_token = hex::encode(generate_bytes(32));
+    let token_hash = digest(&SHA256, cancel_token.as_bytes());
+    let deletes_at = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET
+            deletion_scheduled_at = now() + make_interval(days => $1),
+            deletion_cancel_token = $2
+        WHERE id = $3
+        RETURNING deletion_scheduled_at AS "deletion_scheduled_at!"
+        "#,
+        *GRACE_DAYS,
+        token_hash.as_ref(),
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    session::revoke_all(conn, user_id).await?;
+
+    warn!(%user_id, %deletes_at, "Account deletion scheduled");
+
+    Ok(Some(ScheduledDeletion {
+        deletes_at,
+        cancel_token,
+    }))
+}
+
+/// Refuse logins to an account scheduled for deletion, a new session would undo the
+/// revocation of its sessions. The deletion has to be cancelled first.
+pub fn check_not_scheduled(deletion_scheduled_at: Option<DateTime<Utc>>) -> Result<(), CpassError> {
+    match deletion_scheduled_at {
+        Some(deletes_at) => Err(CpassError::Forbidden(format!(
+            "the account is scheduled for deletion at {}, cancel the deletion to log in",
+            deletes_at
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Cancel the scheduled deletion matching `cancel_token`, returning whose it was.
+pub async fn cancel_deletion(
+    conn: &mut PgConnection,
+    cancel_token: &str,
+) -> Result<Uuid, CpassError> {
+    let token_hash = digest(&SHA256, cancel_token.as_bytes());
+    let user_id = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET deletion_scheduled_at = NULL, deletion_cancel_token = NULL
+        WHERE deletion_cancel_token = $1
+        RETURNING id
+        "#,
+        token_hash.as_ref()
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("Scheduled deletion not found".to_string()))?;
+
+    info!(%user_id, "Account deletion cancelled");
+
+    Ok(user_id)
+}
+
+/// Delete every account whose grace period is over, each in its own transaction.
+pub async fn delete_expired(pool: &PgPool) -> Result<u64, CpassError> {
+    let due = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM users
+        WHERE deletion_scheduled_at <= now()
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut deleted = 0;
+    for user_id in due {
+        let mut tx = pool.tx().await?;
+
+        match delete_account(&mut tx, user_id).await {
+            Ok(()) => {
+                tx.commit().await?;
+                deleted += 1;
+            }
+            Err(err) => error!(%user_id, "Failed to delete account: {:?}", err),
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Background task deleting accounts whose grace period is over once an hour.
+pub async fn deletion_task(pool: PgPool) {
+    let mut interval = tokio::time::interval(DELETION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match delete_expired(&pool).await {
+            Ok(0) => {}
+            Ok(count) => info!("Deleted {} accounts after their grace period", count),
+            Err(err) => error!("Failed to delete accounts: {:?}", err),
+        }
+    }
+}
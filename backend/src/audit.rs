@@ -0,0 +1,800 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use ring::digest::{Context, SHA256};
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::{batch::BatchOutcome, error::CpassError, jwt::models::Principal, webhook};
+
+/// Previous hash of the first event of a chain.
+const GENESIS: [u8; 32] = [0; 32];
+/// Chain of the events without an account, like failed logins for unknown emails, which
+/// also holds every event recorded before chains were kept per account.
+const SHARED_CHAIN: Uuid = Uuid::nil();
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// What happened. Stored as its dotted name, which is also what listings filter on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventType {
+    Login,
+    LoginFailed,
+    UserCreated,
+    UserUpdated,
+    DeletionScheduled,
+    AccountDeleted,
+    DeletionCancelled,
+    KeyPairSet,
+    KeysRead,
+    PublicKeyRead,
+    RecoveryKeySet,
+    RecoveryVaultKeyRead,
+    RecoveryFailed,
+    AccountRecovered,
+    MasterPasswordChanged,
+    EmailChangeRequested,
+    EmailVerified,
+    NewDeviceLogin,
+    DeviceApproved,
+    DeviceRemoved,
+    DeviceApprovalChanged,
+    ItemRead,
+    ItemsListed,
+    ItemsStreamed,
+    ItemCreated,
+    ItemUpdated,
+    ItemTrashed,
+    ItemRestored,
+    ItemPurged,
+    TrashListed,
+    VaultSynced,
+    BatchAdded,
+    BatchUpdated,
+    BatchDeleted,
+    VaultKeyRotated,
+    ItemShared,
+    ShareRevoked,
+    SharedListed,
+    CollectionItemsListed,
+    EmergencyVaultRead,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 40] = [
+        EventType::Login,
+        EventType::LoginFailed,
+        EventType::UserCreated,
+        EventType::UserUpdated,
+        EventType::DeletionScheduled,
+        EventType::AccountDeleted,
+        EventType::DeletionCancelled,
+        EventType::KeyPairSet,
+        EventType::KeysRead,
+        EventType::PublicKeyRead,
+        EventType::RecoveryKeySet,
+        EventType::RecoveryVaultKeyRead,
+        EventType::RecoveryFailed,
+        EventType::AccountRecovered,
+        EventType::MasterPasswordChanged,
+        EventType::EmailChangeRequested,
+        EventType::EmailVerified,
+        EventType::NewDeviceLogin,
+        EventType::DeviceApproved,
+        EventType::DeviceRemoved,
+        EventType::DeviceApprovalChanged,
+        EventType::ItemRead,
+        EventType::ItemsListed,
+        EventType::ItemsStreamed,
+        EventType::ItemCreated,
+        EventType::ItemUpdated,
+        EventType::ItemTrashed,
+        EventType::ItemRestored,
+        EventType::ItemPurged,
+        EventType::TrashListed,
+        EventType::VaultSynced,
+        EventType::BatchAdded,
+        EventType::BatchUpdated,
+        EventType::BatchDeleted,
+        EventType::VaultKeyRotated,
+        EventType::ItemShared,
+        EventType::ShareRevoked,
+        EventType::SharedListed,
+        EventType::CollectionItemsListed,
+        EventType::EmergencyVaultRead,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::Login => "auth.login",
+            EventType::LoginFailed => "auth.login_failed",
+            EventType::UserCreated => "auth.user_created",
+            EventType::UserUpdated => "auth.user_updated",
+            EventType::DeletionScheduled => "auth.deletion_scheduled",
+            EventType::AccountDeleted => "auth.account_deleted",
+            EventType::DeletionCancelled => "auth.deletion_cancelled",
+            EventType::KeyPairSet => "auth.key_pair_set",
+            EventType::KeysRead => "auth.keys_read",
+            EventType::PublicKeyRead => "auth.public_key_read",
+            EventType::RecoveryKeySet => "auth.recovery_key_set",
+            EventType::RecoveryVaultKeyRead => "auth.recovery_vault_key_read",
+            EventType::RecoveryFailed => "auth.recovery_failed",
+            EventType::AccountRecovered => "auth.account_recovered",
+            EventType::MasterPasswordChanged => "auth.master_password_changed",
+            EventType::EmailChangeRequested => "auth.email_change_requested",
+            EventType::EmailVerified => "auth.email_verified",
+            EventType::NewDeviceLogin => "auth.new_device_login",
+            EventType::DeviceApproved => "auth.device_approved",
+            EventType::DeviceRemoved => "auth.device_removed",
+            EventType::DeviceApprovalChanged => "auth.device_approval_changed",
+            EventType::ItemRead => "pass.item_read",
+            EventType::ItemsListed => "pass.items_listed",
+            EventType::ItemsStreamed => "pass.items_streamed",
+            EventType::ItemCreated => "pass.item_created",
+            EventType::ItemUpdated => "pass.item_updated",
+            EventType::ItemTrashed => "pass.item_trashed",
+            EventType::ItemRestored => "pass.item_restored",
+            EventType::ItemPurged => "pass.item_purged",
+            EventType::TrashListed => "pass.trash_listed",
+            EventType::VaultSynced => "pass.vault_synced",
+            EventType::BatchAdded => "pass.batch_added",
+            EventType::BatchUpdated => "pass.batch_updated",
+            EventType::BatchDeleted => "pass.batch_deleted",
+            EventType::VaultKeyRotated => "pass.vault_key_rotated",
+            EventType::ItemShared => "pass.item_shared",
+            EventType::ShareRevoked => "pass.share_revoked",
+            EventType::SharedListed => "pass.shared_listed",
+            EventType::CollectionItemsListed => "pass.collection_items_listed",
+            EventType::EmergencyVaultRead => "pass.emergency_vault_read",
+        }
+    }
+
+    pub fn parse(event_type: &str) -> Result<Self, CpassError> {
+        EventType::ALL
+            .into_iter()
+            .find(|known| known.as_str() == event_type)
+            .ok_or_else(|| CpassError::InvalidRequest(format!("unknown event type {}", event_type)))
+    }
+
+    pub fn parse_all(event_types: &[String]) -> Result<Vec<Self>, CpassError> {
+        event_types
+            .iter()
+            .map(|event_type| EventType::parse(event_type))
+            .collect()
+    }
+}
+
+/// Where a request came from, put into its extensions by the policy layers.
+#[derive(Clone, Debug, Default)]
+pub struct Origin {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// An event about to be recorded.
+#[derive(Clone)]
+pub struct Event {
+    event_type: EventType,
+    actor_id: Option<Uuid>,
+    session_id: Option<Uuid>,
+    item_id: Option<Uuid>,
+    detail: Option<String>,
+    /// Whether the event read the whole vault, only told to webhooks.
+    whole_vault: bool,
+}
+
+impl Event {
+    /// Event caused by the authenticated caller of a request.
+    pub fn by(event_type: EventType, principal: &Principal) -> Self {
+        Event {
+            event_type,
+            actor_id: Some(principal.sub),
+            session_id: Some(principal.sid),
+            item_id: None,
+            detail: None,
+            whole_vault: false,
+        }
+    }
+
+    /// Event of a request made without a session, like a login, concerning `user_id` when
+    /// the account is known.
+    pub fn of(event_type: EventType, user_id: Option<Uuid>) -> Self {
+        Event {
+            event_type,
+            actor_id: user_id,
+            session_id: None,
+            item_id: None,
+            detail: None,
+            whole_vault: false,
+        }
+    }
+
+    pub fn item(mut self, item_id: Uuid) -> Self {
+        self.item_id = Some(item_id);
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Mark a read of every item of the vault, which webhooks are notified of as
+    /// `vault.exported`. Paged reads are marked on their first page.
+    pub fn whole_vault(mut self) -> Self {
+        self.whole_vault = true;
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditEventRow {
+    /// Orders events across chains, not covered by the hash.
+    pub seq: i64,
+    /// The account of the event, [`SHARED_CHAIN`] for events without one.
+    pub chain_id: Uuid,
+    /// Position of the event in its chain, from 1.
+    pub chain_seq: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub event_type: String,
+    /// Account acting, or claimed by a failed login or recovery.
+    pub actor_id: Option<Uuid>,
+    /// Session or access token the event was caused with.
+    pub session_id: Option<Uuid>,
+    pub item_id: Option<Uuid>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
+    pub prev_hash: Vec<u8>,
+    pub hash: Vec<u8>,
+}
+
+impl AuditEventRow {
+    /// Hash of every field of the event but `hash` itself. Each field is length prefixed so
+    /// no two events encode the same.
+    fn digest(&self) -> Vec<u8> {
+        fn field(context: &mut Context, value: Option<&[u8]>) {
+            match value {
+                Some(value) => {
+                    context.update(&(value.len() as u32).to_be_bytes());
+                    context.update(value);
+                }
+                None => context.update(&u32::MAX.to_be_bytes()),
+            }
+        }
+
+        let mut context = Context::new(&SHA256);
+        field(&mut context, Some(&self.prev_hash));
+        field(&mut context, Some(&self.chain_seq.to_be_bytes()));
+        field(
+            &mut context,
+            Some(&self.occurred_at.timestamp_micros().to_be_bytes()),
+        );
+        field(&mut context, Some(self.event_type.as_bytes()));
+        field(
+            &mut context,
+            self.actor_id.as_ref().map(|id| id.as_bytes().as_slice()),
+        );
+        field(
+            &mut context,
+            self.session_id.as_ref().map(|id| id.as_bytes().as_slice()),
+        );
+        field(
+            &mut context,
+            self.item_id.as_ref().map(|id| id.as_bytes().as_slice()),
+        );
+        field(&mut context, self.ip.as_deref().map(str::as_bytes));
+        field(&mut context, self.user_agent.as_deref().map(str::as_bytes));
+        field(&mut context, self.detail.as_deref().map(str::as_bytes));
+        context.finish().as_ref().to_vec()
+    }
+}
+
+async fn append(conn: &mut PgConnection, origin: &Origin, event: Event) -> Result<(), CpassError> {
+    // A savepoint in the transaction of the operation, or a transaction of its own.
+    let mut tx = conn.begin().await?;
+    let chain_id = event.actor_id.unwrap_or(SHARED_CHAIN);
+
+    // Locks the head of the chain until the operation commits, so the chain never forks.
+    // Appends to the chains of other accounts go on.
+    let last = sqlx::query!(
+        r#"
+        INSERT INTO audit_chains(chain_id, seq, head)
+        VALUES ($1, 0, $2)
+        ON CONFLICT (chain_id) DO UPDATE SET seq = audit_chains.seq
+        RETURNING seq, head
+        "#,
+        chain_id,
+        &GENESIS[..]
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // Stored with microsecond precision, hashed as stored.
+    let now = Utc::now().timestamp_micros();
+    let occurred_at = DateTime::from_timestamp_micros(now)
+        .ok_or_else(|| CpassError::Unknown("event time out of range".into()))?;
+
+    let mut row = AuditEventRow {
+        seq: 0,
+        chain_id,
+        chain_seq: last.seq + 1,
+        occurred_at,
+        event_type: event.event_type.as_str().to_string(),
+        actor_id: event.actor_id,
+        session_id: event.session_id,
+        item_id: event.item_id,
+        ip: origin.ip.clone(),
+        user_agent: origin.user_agent.clone(),
+        detail: event.detail,
+        prev_hash: last.head,
+        hash: vec![],
+    };
+    row.hash = row.digest();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_events(
+            chain_id, chain_seq, occurred_at, event_type, actor_id, session_id, item_id, ip,
+            user_agent, detail, prev_hash, hash
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        "#,
+        row.chain_id,
+        row.chain_seq,
+        row.occurred_at,
+        row.event_type,
+        row.actor_id,
+        row.session_id,
+        row.item_id,
+        row.ip,
+        row.user_agent,
+        row.detail,
+        row.prev_hash,
+        row.hash
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE audit_chains SET seq = $2, head = $3
+        WHERE chain_id = $1
+        "#,
+        row.chain_id,
+        row.chain_seq,
+        row.hash
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Append `event` to the audit log and notify the webhooks of security events.
+///
+/// Called with the transaction of the operation the event records, so neither commits
+/// without the other. Events of reads may use a connection of their own.
+pub async fn record(
+    conn: &mut PgConnection,
+    origin: &Origin,
+    event: Event,
+) -> Result<(), CpassError> {
+    let event_type = event.event_type;
+    let actor_id = event.actor_id;
+    let whole_vault = event.whole_vault;
+
+    append(&mut *conn, origin, event).await?;
+
+    if let Some(actor_id) = actor_id {
+        webhook::audit_event(conn, origin, event_type, whole_vault, actor_id).await;
+    }
+
+    Ok(())
+}
+
+/// Record `event` once for every item a batch wrote.
+pub async fn record_batch(
+    conn: &mut PgConnection,
+    origin: &Origin,
+    event: Event,
+    outcome: &BatchOutcome,
+) -> Result<(), CpassError> {
+    let written = outcome
+        .results
+        .iter()
+        .filter(|result| result.error.is_none())
+        .filter_map(|result| result.id);
+
+    for item_id in written {
+        record(&mut *conn, origin, event.clone().item(item_id)).await?;
+    }
+
+    Ok(())
+}
+
+/// Filters of an audit event listing.
+pub struct AuditQuery {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Every type when empty.
+    pub event_types: Vec<EventType>,
+    /// Only events before this sequence number, to page through older events.
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Events `user_id` caused or was the claimed target of, newest first.
+pub async fn list_events(
+    pool: &PgPool,
+    user_id: Uuid,
+    query: AuditQuery,
+) -> Result<Vec<AuditEventRow>, CpassError> {
+    let limit = query.limit.unwrap_or(100);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(CpassError::InvalidRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+    let event_types: Vec<String> = query
+        .event_types
+        .iter()
+        .map(|event_type| event_type.as_str().to_string())
+        .collect();
+
+    let rows = sqlx::query_as!(
+        AuditEventRow,
+        r#"
+        SELECT seq, chain_id, chain_seq, occurred_at, event_type, actor_id, session_id,
+            item_id, ip, user_agent, detail, prev_hash, hash
+        FROM audit_events
+        WHERE actor_id = $1
+            AND ($2::TIMESTAMPTZ IS NULL OR occurred_at >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR occurred_at < $3)
+            AND (cardinality($4::TEXT[]) = 0 OR event_type = ANY($4))
+            AND ($5::BIGINT IS NULL OR seq < $5)
+        ORDER BY seq DESC
+        LIMIT $6
+        "#,
+        user_id,
+        query.since,
+        query.until,
+        &event_types,
+        query.before,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Outcome of walking every chain.
+#[derive(Debug)]
+pub struct ChainReport {
+    pub events: i64,
+    pub chains: i64,
+    /// Hash of the heads of every intact chain. Keep it somewhere else to also notice the
+    /// newest events being cut off together with the heads.
+    pub head: Option<Vec<u8>>,
+    /// First event not following from the one before it in its chain, and why.
+    pub broken: Option<(i64, &'static str)>,
+}
+
+/// Checks events, ordered by chain and by position in their chain, against the one before.
+struct ChainVerifier {
+    /// Last event and hash of every chain, as appends recorded them.
+    heads: HashMap<Uuid, (i64, Vec<u8>)>,
+    /// Chain walked and the last event of it checked.
+    current: Option<(Uuid, i64, Vec<u8>)>,
+    last_seq: i64,
+    report: ChainReport,
+    verified_heads: Vec<(Uuid, Vec<u8>)>,
+}
+
+impl ChainVerifier {
+    fn new(heads: HashMap<Uuid, (i64, Vec<u8>)>) -> Self {
+        ChainVerifier {
+            heads,
+            current: None,
+            last_seq: 0,
+            report: ChainReport {
+                events: 0,
+                chains: 0,
+                head: None,
+                broken: None,
+            },
+            verified_heads: vec![],
+        }
+    }
+
+    /// Close the chain walked, checking it ends where its head says.
+    fn end_chain(&mut self) -> Option<&'static str> {
+        let (chain_id, chain_seq, hash) = self.current.take()?;
+        match self.heads.remove(&chain_id) {
+            Some((head_seq, head)) if head_seq == chain_seq && head == hash => {
+                self.report.chains += 1;
+                self.verified_heads.push((chain_id, hash));
+                None
+            }
+            _ => Some("events are missing after it"),
+        }
+    }
+
+    /// Check the next event, returning false once the chain is broken.
+    fn check(&mut self, row: AuditEventRow) -> bool {
+        if self.current.as_ref().map(|(chain_id, ..)| *chain_id) != Some(row.chain_id) {
+            if let Some(reason) = self.end_chain() {
+                self.report.broken = Some((self.last_seq, reason));
+                return false;
+            }
+            self.current = Some((row.chain_id, 0, GENESIS.to_vec()));
+        }
+        let (_, chain_seq, prev_hash) = self.current.as_ref().expect("a chain is walked");
+
+        let broken = if row.chain_seq != chain_seq + 1 {
+            Some("events are missing before it")
+        } else if row.prev_hash != *prev_hash {
+            Some("it does not link to the event before")
+        } else if row.hash != row.digest() {
+            Some("its content does not match its hash")
+        } else if row.chain_id != SHARED_CHAIN && row.actor_id != Some(row.chain_id) {
+            Some("it is in the chain of another account")
+        } else {
+            None
+        };
+        if let Some(reason) = broken {
+            self.report.broken = Some((row.seq, reason));
+            return false;
+        }
+
+        self.report.events += 1;
+        self.last_seq = row.seq;
+        self.current = Some((row.chain_id, row.chain_seq, row.hash));
+        true
+    }
+
+    fn finish(mut self) -> ChainReport {
+        if self.report.broken.is_none() {
+            if let Some(reason) = self.end_chain() {
+                self.report.broken = Some((self.last_seq, reason));
+            } else if !self.heads.is_empty() {
+                self.report.broken = Some((self.last_seq, "a whole chain is missing"));
+            }
+        }
+
+        if !self.verified_heads.is_empty() {
+            let mut context = Context::new(&SHA256);
+            for (chain_id, head) in &self.verified_heads {
+                context.update(chain_id.as_bytes());
+                context.update(head);
+            }
+            self.report.head = Some(context.finish().as_ref().to_vec());
+        }
+
+        self.report
+    }
+}
+
+/// Check every event against the one before it in its chain, stopping at the first break.
+pub async fn verify_chain(pool: &PgPool) -> Result<ChainReport, CpassError> {
+    let heads = sqlx::query!(
+        r#"
+        SELECT chain_id, seq, head FROM audit_chains
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|chain| (chain.chain_id, (chain.seq, chain.head)))
+    .collect();
+
+    let mut rows = sqlx::query_as!(
+        AuditEventRow,
+        r#"
+        SELECT seq, chain_id, chain_seq, occurred_at, event_type, actor_id, session_id,
+            item_id, ip, user_agent, detail, prev_hash, hash
+        FROM audit_events
+        ORDER BY chain_id, chain_seq
+        "#
+    )
+    .fetch(pool);
+
+    let mut verifier = ChainVerifier::new(heads);
+    while let Some(row) = rows.next().await {
+        if !verifier.check(row?) {
+            break;
+        }
+    }
+
+    Ok(verifier.finish())
+}
+
+/// `verify-audit` command of the server binaries, exiting with an error on tampering.
+pub async fn verify_command(pool: &PgPool) -> anyhow::Result<()> {
+    let report = verify_chain(pool).await?;
+    let head = report.head.as_deref().map(hex::encode).unwrap_or_default();
+
+    match report.broken {
+        None => {
+            println!(
+                "{} audit events in {} chains verified, head {}",
+                report.events, report.chains, head
+            );
+            Ok(())
+        }
+        Some((seq, reason)) => Err(anyhow::anyhow!(
+            "audit log tampered at event {}: {} ({} events verified before it, head {})",
+            seq,
+            reason,
+            report.events,
+            head
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chain of `len` events, linked and hashed like appends do.
+    fn chain(chain_id: Uuid, len: i64) -> Vec<AuditEventRow> {
+        let mut prev_hash = GENESIS.to_vec();
+        (1..=len)
+            .map(|chain_seq| {
+                let mut row = AuditEventRow {
+                    seq: chain_seq,
+                    chain_id,
+                    chain_seq,
+                    occurred_at: DateTime::from_timestamp_micros(1_700_000_000_000_000 + chain_seq)
+                        .unwrap(),
+                    event_type: EventType::ItemRead.as_str().to_string(),
+                    actor_id: (chain_id != SHARED_CHAIN).then_some(chain_id),
+                    session_id: None,
+                    item_id: Some(Uuid::new_v4()),
+                    ip: Some("192.0.2.1".to_string()),
+                    user_agent: None,
+                    detail: Some(format!("event {}", chain_seq)),
+                    prev_hash: prev_hash.clone(),
+                    hash: vec![],
+                };
+                row.hash = row.digest();
+                prev_hash = row.hash.clone();
+                row
+            })
+            .collect()
+    }
+
+    fn heads(rows: &[AuditEventRow]) -> HashMap<Uuid, (i64, Vec<u8>)> {
+        rows.iter()
+            .map(|row| (row.chain_id, (row.chain_seq, row.hash.clone())))
+            .collect()
+    }
+
+    fn verify(heads: HashMap<Uuid, (i64, Vec<u8>)>, rows: Vec<AuditEventRow>) -> ChainReport {
+        let mut verifier = ChainVerifier::new(heads);
+        for row in rows {
+            if !verifier.check(row) {
+                break;
+            }
+        }
+        verifier.finish()
+    }
+
+    #[test]
+    fn digest_is_deterministic() {
+        let row = &chain(Uuid::new_v4(), 1)[0];
+
+        assert_eq!(row.digest(), row.clone().digest());
+        assert_eq!(row.digest().len(), 32);
+    }
+
+    #[test]
+    fn digest_covers_every_field() {
+        let row = chain(Uuid::new_v4(), 1).remove(0);
+        let changes: [fn(&mut AuditEventRow); 6] = [
+            |row| row.chain_seq += 1,
+            |row| row.event_type = EventType::ItemUpdated.as_str().to_string(),
+            |row| row.actor_id = None,
+            |row| row.ip = Some("192.0.2.2".to_string()),
+            |row| row.detail = None,
+            |row| row.prev_hash[0] ^= 1,
+        ];
+
+        for change in changes {
+            let mut changed = row.clone();
+            change(&mut changed);
+            assert_ne!(changed.digest(), row.digest());
+        }
+    }
+
+    #[test]
+    fn digest_tells_missing_from_empty() {
+        let mut row = chain(Uuid::new_v4(), 1).remove(0);
+        row.detail = None;
+        let missing = row.digest();
+        row.detail = Some(String::new());
+
+        assert_ne!(row.digest(), missing);
+    }
+
+    #[test]
+    fn intact_chains_verify() {
+        let mut rows = chain(SHARED_CHAIN, 2);
+        rows.extend(chain(Uuid::new_v4(), 3));
+        let heads = heads(&rows);
+
+        let report = verify(heads, rows);
+
+        assert_eq!(report.broken, None);
+        assert_eq!(report.events, 5);
+        assert_eq!(report.chains, 2);
+        assert!(report.head.is_some());
+    }
+
+    #[test]
+    fn edited_event_breaks_the_chain() {
+        let mut rows = chain(Uuid::new_v4(), 3);
+        let heads = heads(&rows);
+        rows[1].detail = Some("edited".to_string());
+
+        let report = verify(heads, rows);
+
+        assert_eq!(
+            report.broken,
+            Some((2, "its content does not match its hash"))
+        );
+        assert_eq!(report.events, 1);
+    }
+
+    #[test]
+    fn deleted_event_breaks_the_chain() {
+        let mut rows = chain(Uuid::new_v4(), 3);
+        let heads = heads(&rows);
+        rows.remove(1);
+
+        let report = verify(heads, rows);
+
+        assert_eq!(report.broken, Some((3, "events are missing before it")));
+    }
+
+    #[test]
+    fn deleted_last_event_breaks_the_chain() {
+        let mut rows = chain(Uuid::new_v4(), 3);
+        let heads = heads(&rows);
+        rows.pop();
+
+        let report = verify(heads, rows);
+
+        assert_eq!(report.broken, Some((2, "events are missing after it")));
+        assert_eq!(report.head, None);
+    }
+
+    #[test]
+    fn deleted_chain_is_noticed() {
+        let first = chain(Uuid::new_v4(), 2);
+        let second = chain(Uuid::new_v4(), 2);
+        let heads = heads(&[first.clone(), second].concat());
+
+        let report = verify(heads, first);
+
+        assert_eq!(report.broken, Some((2, "a whole chain is missing")));
+    }
+
+    #[test]
+    fn event_moved_to_another_chain_is_noticed() {
+        let mut rows = chain(Uuid::new_v4(), 1);
+        rows[0].chain_id = Uuid::new_v4();
+        let heads = heads(&rows);
+
+        let report = verify(heads, rows);
+
+        assert_eq!(
+            report.broken,
+            Some((1, "it is in the chain of another account"))
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use sqlx::{Acquire, PgConnection, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    concurrency::{conditional_write_error, ItemAction},
+    error::CpassError,
+};
+
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+pub struct NewItem {
+    pub name: Vec<u8>,
+    pub password: Vec<u8>,
+    pub website: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub description: Option<Vec<u8>>,
+    pub item_key: Option<Vec<u8>>,
+    /// Collection of an organization the item is added to, personal vault otherwise.
+    pub collection_id: Option<Uuid>,
+    /// Decoy item, see [`crate::honeytoken`].
+    pub honeytoken: bool,
+}
+
+pub struct ItemChange {
+    pub id: Uuid,
+    pub name: Option<Vec<u8>>,
+    pub password: Option<Vec<u8>>,
+    pub website: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub description: Option<Vec<u8>>,
+    pub item_key: Option<Vec<u8>>,
+    pub expected_version: i64,
+}
+
+pub struct ItemRemoval {
+    pub id: Uuid,
+    pub expected_version: i64,
+}
+
+/// Outcome of a single batch item, in the same position as the request item.
+pub struct ItemResult {
+    pub id: Option<Uuid>,
+    pub version: Option<i64>,
+    pub error: Option<CpassError>,
+}
+
+pub struct BatchOutcome {
+    /// Whether the changes were committed. Always `true` unless `all_or_nothing` was
+    /// requested and at least one item failed.
+    pub committed: bool,
+    pub results: Vec<ItemResult>,
+}
+
+fn check_size<T>(items: &[T]) -> Result<(), CpassError> {
+    if items.is_empty() || items.len() > MAX_BATCH_SIZE {
+        return Err(CpassError::InvalidRequest(format!(
+            "a batch must contain between 1 and {} items",
+            MAX_BATCH_SIZE
+        )));
+    }
+    Ok(())
+}
+
+fn require_version(expected_version: i64) -> Result<(), CpassError> {
+    match expected_version {
+        0 => Err(CpassError::PreconditionRequired(
+            "expected version is required".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Release the savepoint of a successful item, roll it back otherwise.
+async fn settle(
+    savepoint: Transaction<'_, Postgres>,
+    result: Result<ItemResult, CpassError>,
+) -> Result<ItemResult, CpassError> {
+    match result {
+        Ok(result) => {
+            savepoint.commit().await?;
+            Ok(result)
+        }
+        Err(error) => {
+            savepoint.rollback().await?;
+            Ok(ItemResult {
+                id: None,
+                version: None,
+                error: Some(error),
+            })
+        }
+    }
+}
+
+async fn finish(
+    tx: Transaction<'_, Postgres>,
+    mut results: Vec<ItemResult>,
+    all_or_nothing: bool,
+) -> Result<BatchOutcome, CpassError> {
+    let failed = results.iter().any(|result| result.error.is_some());
+    let committed = !(all_or_nothing && failed);
+
+    if committed {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+
+        // Nothing was written, so ids and versions of the items that succeeded are void.
+        for result in results.iter_mut() {
+            result.id = None;
+            result.version = None;
+        }
+    }
+
+    Ok(BatchOutcome { committed, results })
+}
+
+async fn insert_item(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    item: NewItem,
+) -> Result<ItemResult, CpassError> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO passwords(
+            owner_id, name, password, website, username, description, item_key, collection_id,
+            honeytoken
+        )
+        SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9
+        WHERE $8::UUID IS NULL OR collection_can($8, $1, 'write')
+        RETURNING id, revision
+        "#,
+        owner_id,
+        item.name,
+        item.password,
+        item.website,
+        item.username,
+        item.description,
+        item.item_key,
+        item.collection_id,
+        item.honeytoken
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| {
+        CpassError::Forbidden("Not allowed to add items to that collection".to_string())
+    })?;
+
+    Ok(ItemResult {
+        id: Some(row.id),
+        version: Some(row.revision),
+        error: None,
+    })
+}
+
+async fn update_item(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    item: ItemChange,
+) -> Result<ItemResult, CpassError> {
+    require_version(item.expected_version)?;
+    let action = ItemAction::update(item.item_key.as_ref());
+
+    let version = sqlx::query_scalar!(
+        r#"
+        UPDATE passwords
+        SET
+            name = COALESCE($1, name),
+            password = COALESCE($2, password),
+            website = COALESCE($3, website),
+            username = COALESCE($4, username),
+            description = COALESCE($5, description),
+            item_key = COALESCE($9, item_key)
+        WHERE id = $6 AND deleted_at IS NULL AND revision = $8 AND item_can(id, $7, $10)
+        RETURNING revision
+        "#,
+        item.name,
+        item.password,
+        item.website,
+        item.username,
+        item.description,
+        item.id,
+        user_id,
+        item.expected_version,
+        item.item_key,
+        action.as_str()
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match version {
+        Some(version) => Ok(ItemResult {
+            id: Some(item.id),
+            version: Some(version),
+            error: None,
+        }),
+        None => Err(conditional_write_error(conn, item.id, user_id, action).await),
+    }
+}
+
+async fn trash_item(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    item: ItemRemoval,
+) -> Result<ItemResult, CpassError> {
+    require_version(item.expected_version)?;
+
+    let version = sqlx::query_scalar!(
+        r#"
+        UPDATE passwords
+        SET deleted_at = now()
+        WHERE id = $1 AND deleted_at IS NULL AND revision = $3 AND item_can(id, $2, $4)
+        RETURNING revision
+        "#,
+        item.id,
+        user_id,
+        item.expected_version,
+        ItemAction::Trash.as_str()
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match version {
+        Some(version) => Ok(ItemResult {
+            id: Some(item.id),
+            version: Some(version),
+            error: None,
+        }),
+        None => Err(conditional_write_error(conn, item.id, user_id, ItemAction::Trash).await),
+    }
+}
+
+/// Insert every item in the transaction of the caller.
+pub async fn add_items(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    items: Vec<Result<NewItem, CpassError>>,
+    all_or_nothing: bool,
+) -> Result<BatchOutcome, CpassError> {
+    check_size(&items)?;
+
+    let mut tx = conn.begin().await?;
+    let mut results = Vec::with_capacity(items.len());
+
+    for item in items {
+        let mut savepoint = tx.begin().await?;
+        let result = match item {
+            Ok(item) => insert_item(&mut savepoint, owner_id, item).await,
+            Err(err) => Err(err),
+        };
+        results.push(settle(savepoint, result).await?);
+    }
+
+    finish(tx, results, all_or_nothing).await
+}
+
+/// Apply every change in the transaction of the caller, each guarded by its expected
+/// version.
+///
+/// Items shared with `user_id` with write permission and items of collections they can
+/// write to can be changed as well.
+pub async fn update_items(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    items: Vec<Result<ItemChange, CpassError>>,
+    all_or_nothing: bool,
+) -> Result<BatchOutcome, CpassError> {
+    check_size(&items)?;
+
+    let mut tx = conn.begin().await?;
+    let mut results = Vec::with_capacity(items.len());
+
+    for item in items {
+        let mut savepoint = tx.begin().await?;
+        let result = match item {
+            Ok(item) => update_item(&mut savepoint, user_id, item).await,
+            Err(err) => Err(err),
+        };
+        results.push(settle(savepoint, result).await?);
+    }
+
+    finish(tx, results, all_or_nothing).await
+}
+
+/// Move every item to the trash in the transaction of the caller.
+pub async fn delete_items(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    items: Vec<Result<ItemRemoval, CpassError>>,
+    all_or_nothing: bool,
+) -> Result<BatchOutcome, CpassError> {
+    check_size(&items)?;
+
+    let mut tx = conn.begin().await?;
+    let mut results = Vec::with_capacity(items.len());
+
+    for item in items {
+        let mut savepoint = tx.begin().await?;
+        let result = match item {
+            Ok(item) => trash_item(&mut savepoint, user_id, item).await,
+            Err(err) => Err(err),
+        };
+        results.push(settle(savepoint, result).await?);
+    }
+
+    finish(tx, results, all_or_nothing).await
+}
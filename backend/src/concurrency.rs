@@ -0,0 +1,72 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::error::CpassError;
+
+/// What a caller wants to do with an item, checked by the `item_can` SQL function.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ItemAction {
+    Read,
+    /// Change the ciphertext of the item.
+    Update,
+    /// Replace the item key, reserved to whoever holds the key the item is encrypted with.
+    Rekey,
+    Trash,
+    Restore,
+    Purge,
+}
+
+impl ItemAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ItemAction::Read => "read",
+            ItemAction::Update => "update",
+            ItemAction::Rekey => "rekey",
+            ItemAction::Trash => "trash",
+            ItemAction::Restore => "restore",
+            ItemAction::Purge => "purge",
+        }
+    }
+
+    /// [`ItemAction::Rekey`] when the change carries a new item key.
+    pub fn update(item_key: Option<&Vec<u8>>) -> Self {
+        match item_key {
+            Some(_) => ItemAction::Rekey,
+            None => ItemAction::Update,
+        }
+    }
+}
+
+/// Explain why a conditional write on a live item matched no rows.
+///
+/// Returns [`CpassError::VersionMismatch`] with the current version when the caller may
+/// `action` the item, [`CpassError::Forbidden`] when they can only read it, otherwise
+/// [`CpassError::NotFound`].
+pub async fn conditional_write_error(
+    conn: &mut PgConnection,
+    pass_id: Uuid,
+    user_id: Uuid,
+    action: ItemAction,
+) -> CpassError {
+    let access = sqlx::query!(
+        r#"
+        SELECT revision, COALESCE(item_can(id, $2, $3), false) AS "allowed!"
+        FROM passwords
+        WHERE id = $1 AND deleted_at IS NULL AND item_can(id, $2, 'read')
+        "#,
+        pass_id,
+        user_id,
+        action.as_str()
+    )
+    .fetch_optional(conn)
+    .await;
+
+    match access {
+        Ok(Some(access)) if access.allowed => CpassError::VersionMismatch(access.revision),
+        Ok(Some(_)) => {
+            CpassError::Forbidden(format!("Not allowed to {} that item", action.as_str()))
+        }
+        Ok(None) => CpassError::NotFound("Password with that id not found".to_string()),
+        Err(err) => CpassError::DatabaseError(err),
+    }
+}
@@ -1,20 +1,27 @@
 use crate::error::CpassError;
 
 use async_trait::async_trait;
-use sqlx::{pool::PoolConnection, PgPool, Postgres};
+use sqlx::{pool::PoolConnection, PgPool, Postgres, Transaction};
 
 #[async_trait]
 pub trait Db {
     type Conn;
+    type Tx;
 
     async fn conn(&self) -> Result<Self::Conn, CpassError>;
+    async fn tx(&self) -> Result<Self::Tx, CpassError>;
 }
 
 #[async_trait]
 impl Db for PgPool {
     type Conn = PoolConnection<Postgres>;
+    type Tx = Transaction<'static, Postgres>;
 
     async fn conn(&self) -> Result<Self::Conn, CpassError> {
         self.acquire().await.map_err(CpassError::DatabaseError)
     }
+
+    async fn tx(&self) -> Result<Self::Tx, CpassError> {
+        self.begin().await.map_err(CpassError::DatabaseError)
+    }
 }
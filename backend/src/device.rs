@@ -0,0 +1,373 @@
+use chrono::{DateTime, Utc};
+use ring::digest::{digest, SHA256};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    audit::Origin,
+    error::CpassError,
+    jwt::models::Principal,
+    mail::{self, Template},
+    webhook::{self, Notification, WebhookEvent},
+};
+
+const MAX_NAME_LENGTH: usize = 100;
+const UNKNOWN_NAME: &str = "Unknown device";
+
+/// Kind of client a device runs, as told by the client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    Browser,
+    Desktop,
+    Mobile,
+    Cli,
+    Other,
+}
+
+impl DeviceType {
+    pub const ALL: [DeviceType; 5] = [
+        DeviceType::Browser,
+        DeviceType::Desktop,
+        DeviceType::Mobile,
+        DeviceType::Cli,
+        DeviceType::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceType::Browser => "browser",
+            DeviceType::Desktop => "desktop",
+            DeviceType::Mobile => "mobile",
+            DeviceType::Cli => "cli",
+            DeviceType::Other => "other",
+        }
+    }
+
+    pub fn parse(device_type: &str) -> Result<Self, CpassError> {
+        DeviceType::ALL
+            .into_iter()
+            .find(|known| known.as_str() == device_type)
+            .ok_or_else(|| {
+                CpassError::InvalidRequest(format!("unknown device type {}", device_type))
+            })
+    }
+}
+
+/// What a login tells about the device it comes from, checked by
+/// [`crate::validation::login_fields`].
+#[derive(Clone, Debug, Default)]
+pub struct DeviceLogin {
+    /// Secret the client generated once and sends with every login. Clients without one
+    /// are told apart by their user agent.
+    pub token: Option<String>,
+    pub name: Option<String>,
+    pub device_type: Option<String>,
+}
+
+/// Device a login came from, once recorded.
+pub struct SeenDevice {
+    pub id: Uuid,
+    pub name: String,
+    /// Unapproved devices get no session.
+    pub approved: bool,
+    /// Whether the user already had other devices, and was told about this one.
+    pub new: bool,
+}
+
+pub struct DeviceRow {
+    pub id: Uuid,
+    pub name: String,
+    pub device_type: String,
+    /// `None` while the device waits for approval.
+    pub approved_at: Option<DateTime<Utc>>,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub last_ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Whether the caller listing the devices uses this one.
+    pub current: bool,
+}
+
+/// `name` as shown in mails and webhook details, which are read outside of cpass. The
+/// client chooses it, so anything but letters, digits, spaces and `-_'` is replaced and it
+/// can not pass off links or lines of its own.
+fn display_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() || " -_'".contains(c) {
+            true => c,
+            false => '?',
+        })
+        .collect()
+}
+
+/// Record that `user_id` logged in from the device of `login`, or that a known device was
+/// seen again.
+///
+/// A device the user never logged in from before is mailed about, and reported to the
+/// webhooks, unless it is their first. When the user requires device approval it waits
+/// for an approved device to approve it, which only devices sending a token can be.
+pub async fn record_login(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    login: DeviceLogin,
+    origin: &Origin,
+) -> Result<SeenDevice, CpassError> {
+    // Serializes logins of the user, so two new devices can not both be the first.
+    let user = sqlx::query!(
+        r#"
+        SELECT email, require_device_approval FROM users
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let fingerprint = match (&login.token, user.require_device_approval) {
+        (Some(token), _) => format!("token:{}", token),
+        (None, false) => format!("user-agent:{}", origin.user_agent.as_deref().unwrap_or("")),
+        (None, true) => {
+            return Err(CpassError::Forbidden(
+                "the account requires device approval, logins must send a device_token".to_string(),
+            ))
+        }
+    };
+    let token_hash = digest(&SHA256, fingerprint.as_bytes());
+
+    let known = sqlx::query!(
+        r#"
+        UPDATE devices
+        SET last_seen_at = now(), last_ip = $3, user_agent = $4,
+            name = COALESCE($5, name), device_type = COALESCE($6, device_type)
+        WHERE user_id = $1 AND token_hash = $2
+        RETURNING id, name, approved_at IS NOT NULL AS "approved!"
+        "#,
+        user_id,
+        token_hash.as_ref(),
+        origin.ip,
+        origin.user_agent,
+        login.name,
+        login.device_type
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some(known) = known {
+        return Ok(SeenDevice {
+            id: known.id,
+            name: known.name,
+            approved: known.approved,
+            new: false,
+        });
+    }
+
+    let others = sqlx::query!(
+        r#"
+        SELECT
+            count(*) AS "known!",
+            count(*) FILTER (WHERE approved_at IS NOT NULL) AS "approved!"
+        FROM devices
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // The first device is approved, or the account could never be used again.
+    let approved = !user.require_device_approval || others.approved == 0;
+    let name = login
+        .name
+        .or_else(|| origin.user_agent.clone())
+        .map(|name| name.chars().take(MAX_NAME_LENGTH).collect())
+        .unwrap_or_else(|| UNKNOWN_NAME.to_string());
+    let device_type = login
+        .device_type
+        .unwrap_or_else(|| DeviceType::Other.as_str().to_string());
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO devices(user_id, token_hash, name, device_type, approved_at, last_ip, user_agent)
+        VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN now() END, $6, $7)
+        RETURNING id
+        "#,
+        user_id,
+        token_hash.as_ref(),
+        name,
+        device_type,
+        approved,
+        origin.ip,
+        origin.user_agent
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let new = others.known > 0;
+    if new {
+        let status = match approved {
+            true => "It is signed in.",
+            false => "It waits for approval from one of your other devices.",
+        };
+        mail::enqueue(
+            conn,
+            Some(user_id),
+            &user.email,
+            Template::NewDevice,
+            &[
+                ("email", &user.email),
+                ("name", &display_name(&name)),
+                ("device_type", &device_type),
+                ("ip", origin.ip.as_deref().unwrap_or("unknown")),
+                ("status", status),
+                ("devices", &mail::link("/api/v1/auth/devices")),
+            ],
+        )
+        .await?;
+
+        let notification =
+            Notification::new(WebhookEvent::NewDeviceLogin, user_id).detail(display_name(&name));
+        webhook::notify(conn, origin, notification).await;
+    }
+
+    Ok(SeenDevice {
+        id,
+        name,
+        approved,
+        new,
+    })
+}
+
+/// Devices of the caller, the one it uses flagged as current.
+pub async fn list(
+    conn: &mut PgConnection,
+    principal: &Principal,
+) -> Result<Vec<DeviceRow>, CpassError> {
+    let devices = sqlx::query_as!(
+        DeviceRow,
+        r#"
+        SELECT
+            d.id, d.name, d.device_type, d.approved_at, d.first_seen_at, d.last_seen_at,
+            d.last_ip, d.user_agent,
+            EXISTS(SELECT 1 FROM sessions s WHERE s.id = $2 AND s.device_id = d.id) AS "current!"
+        FROM devices d
+        WHERE d.user_id = $1
+        ORDER BY d.first_seen_at, d.id
+        "#,
+        principal.sub,
+        principal.sid
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(devices)
+}
+
+/// Forget a device of `user_id`, ending its sessions. Its next login is a new device.
+pub async fn remove(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    device_id: Uuid,
+) -> Result<String, CpassError> {
+    sqlx::query_scalar!(
+        r#"
+        DELETE FROM devices
+        WHERE id = $1 AND user_id = $2
+        RETURNING name
+        "#,
+        device_id,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("Device with that id not found".to_string()))
+}
+
+/// Approve a device of the caller waiting for approval, from an approved device.
+pub async fn approve(
+    conn: &mut PgConnection,
+    principal: &Principal,
+    device_id: Uuid,
+) -> Result<String, CpassError> {
+    let approved = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM sessions s
+            JOIN devices d ON d.id = s.device_id
+            WHERE s.id = $1 AND d.approved_at IS NOT NULL
+        ) AS "approved!"
+        "#,
+        principal.sid
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !approved {
+        return Err(CpassError::Forbidden(
+            "devices can only be approved from an approved device".to_string(),
+        ));
+    }
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE devices SET approved_at = now()
+        WHERE id = $1 AND user_id = $2 AND approved_at IS NULL
+        RETURNING name
+        "#,
+        device_id,
+        principal.sub
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| {
+        CpassError::NotFound("Device waiting for approval with that id not found".to_string())
+    })
+}
+
+/// Require, or stop requiring, new devices of `user_id` to be approved before they get a
+/// session. Devices waiting for approval are approved when it is no longer required.
+pub async fn set_approval(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    required: bool,
+) -> Result<(), CpassError> {
+    sqlx::query!(
+        r#"
+        UPDATE users SET require_device_approval = $2
+        WHERE id = $1
+        "#,
+        user_id,
+        required
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if !required {
+        sqlx::query!(
+            r#"
+            UPDATE devices SET approved_at = now()
+            WHERE user_id = $1 AND approved_at IS NULL
+            "#,
+            user_id
+        )
+        .execute(conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_name_keeps_a_safe_character_set() {
+        assert_eq!(display_name("Alice's laptop-2_b"), "Alice's laptop-2_b");
+        assert_eq!(
+            display_name("Visit https://evil.example\nnow"),
+            "Visit https???evil?example?now"
+        );
+        assert_eq!(display_name("Zoë {{name}}"), "Zo? ??name??");
+    }
+}
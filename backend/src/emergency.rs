@@ -0,0 +1,596 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{db::Db, error::CpassError, jwt::session, pagination::PasswordRow};
+
+const APPROVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_WAIT_DAYS: i32 = 90;
+
+/// What an emergency contact gets once their request is approved, ordered from the least
+/// to the most powerful.
+#[derive(
+    Deserialize, Serialize, ToSchema, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencyAccess {
+    /// Read the personal vault of the grantor.
+    #[default]
+    Read,
+    /// Set a new master password for the grantor as well.
+    Takeover,
+}
+
+impl EmergencyAccess {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmergencyAccess::Read => "read",
+            EmergencyAccess::Takeover => "takeover",
+        }
+    }
+
+    fn from_db(access: &str) -> Self {
+        match access {
+            "takeover" => EmergencyAccess::Takeover,
+            _ => EmergencyAccess::Read,
+        }
+    }
+}
+
+/// State of an emergency contact.
+///
+/// `invited` becomes `confirmed` once the grantee accepts. The grantee moves it to
+/// `requested`, the grantor either approves or rejects it back to `confirmed`, and a
+/// request left unanswered for the waiting period is approved automatically.
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EmergencyStatus {
+    Invited,
+    Confirmed,
+    Requested,
+    Approved,
+}
+
+impl EmergencyStatus {
+    fn from_db(status: &str) -> Self {
+        match status {
+            "approved" => EmergencyStatus::Approved,
+            "requested" => EmergencyStatus::Requested,
+            "confirmed" => EmergencyStatus::Confirmed,
+            _ => EmergencyStatus::Invited,
+        }
+    }
+}
+
+/// An emergency contact, seen by either the grantor or the grantee.
+pub struct ContactRow {
+    pub id: Uuid,
+    pub grantor_email: String,
+    pub grantee_email: String,
+    pub access: EmergencyAccess,
+    pub wait_days: i32,
+    pub status: EmergencyStatus,
+    /// `false` after a key rotation of the grantor until they wrap the new vault key.
+    pub has_vault_key: bool,
+    pub requested_at: Option<DateTime<Utc>>,
+    /// When a pending request gets approved unless the grantor rejects it first.
+    pub approves_at: Option<DateTime<Utc>>,
+    pub approved_at: Option<DateTime<Utc>>,
+}
+
+/// Emergency contacts matching every given filter.
+async fn fetch_contacts(
+    conn: &mut PgConnection,
+    id: Option<Uuid>,
+    grantor_id: Option<Uuid>,
+    grantee_id: Option<Uuid>,
+) -> Result<Vec<ContactRow>, CpassError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT e.id, grantor.email AS grantor_email, grantee.email AS grantee_email, e.access,
+            e.wait_days, e.status, e.wrapped_vault_key IS NOT NULL AS "has_vault_key!",
+            e.requested_at, e.approved_at,
+            CASE WHEN e.status = 'requested'
+                THEN e.requested_at + make_interval(days => e.wait_days)
+            END AS approves_at
+        FROM emergency_contacts e
+        JOIN users grantor ON grantor.id = e.grantor_id
+        JOIN users grantee ON grantee.id = e.grantee_id
+        WHERE ($1::UUID IS NULL OR e.id = $1)
+            AND ($2::UUID IS NULL OR e.grantor_id = $2)
+            AND ($3::UUID IS NULL OR e.grantee_id = $3)
+        ORDER BY e.created_at, e.id
+        "#,
+        id,
+        grantor_id,
+        grantee_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| ContactRow {
+            id: row.id,
+            grantor_email: row.grantor_email,
+            grantee_email: row.grantee_email,
+            access: EmergencyAccess::from_db(&row.access),
+            wait_days: row.wait_days,
+            status: EmergencyStatus::from_db(&row.status),
+            has_vault_key: row.has_vault_key,
+            requested_at: row.requested_at,
+            approves_at: row.approves_at,
+            approved_at: row.approved_at,
+        })
+        .collect())
+}
+
+async fn fetch_contact(conn: &mut PgConnection, id: Uuid) -> Result<ContactRow, CpassError> {
+    fetch_contacts(conn, Some(id), None, None)
+        .await?
+        .pop()
+        .ok_or_else(|| CpassError::NotFound("Emergency contact with that id not found".to_string()))
+}
+
+/// Explain why a transition of the emergency contact `id` matched no rows.
+async fn transition_error(conn: &mut PgConnection, id: Uuid, user_id: Uuid) -> CpassError {
+    let status = sqlx::query_scalar!(
+        r#"
+        SELECT status FROM emergency_contacts
+        WHERE id = $1 AND (grantor_id = $2 OR grantee_id = $2)
+        "#,
+        id,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await;
+
+    match status {
+        Ok(Some(status)) => CpassError::InvalidRequest(format!(
+            "not allowed while the emergency access is {}",
+            status
+        )),
+        Ok(None) => CpassError::NotFound("Emergency contact with that id not found".to_string()),
+        Err(err) => CpassError::DatabaseError(err),
+    }
+}
+
+/// Approve every request of `user_id`, as grantor or grantee, whose waiting period is over,
+/// or every such request when `user_id` is `None`.
+pub async fn approve_due(
+    conn: &mut PgConnection,
+    user_id: Option<Uuid>,
+) -> Result<u64, CpassError> {
+    let approved = sqlx::query!(
+        r#"
+        UPDATE emergency_contacts
+        SET status = 'approved', approved_at = now()
+        WHERE status = 'requested'
+            AND requested_at + make_interval(days => wait_days) <= now()
+            AND ($1::UUID IS NULL OR grantor_id = $1 OR grantee_id = $1)
+        RETURNING id, grantor_id, grantee_id
+        "#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    for contact in &approved {
+        warn!(
+            target: "emergency_access",
+            id = %contact.id,
+            grantor_id = %contact.grantor_id,
+            grantee_id = %contact.grantee_id,
+            "Emergency access approved after the waiting period"
+        );
+    }
+
+    Ok(approved.len() as u64)
+}
+
+/// Background task approving requests whose waiting period is over once an hour.
+pub async fn approve_task(pool: PgPool) {
+    let mut interval = tokio::time::interval(APPROVE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let approved = match pool.conn().await {
+            Ok(mut conn) => approve_due(&mut conn, None).await,
+            Err(err) => Err(err),
+        };
+
+        match approved {
+            Ok(0) => {}
+            Ok(count) => info!("Approved {} emergency access requests", count),
+            Err(err) => error!("Failed to approve emergency access requests: {:?}", err),
+        }
+    }
+}
+
+/// Designate `email` as an emergency contact of `grantor_id`, or change an existing one.
+///
+/// `wrapped_vault_key` is the vault key of the grantor wrapped to the public key of the
+/// contact. Changing a contact withdraws any pending request or approved access.
+pub async fn add_contact(
+    conn: &mut PgConnection,
+    grantor_id: Uuid,
+    email: &str,
+    access: EmergencyAccess,
+    wait_days: i32,
+    wrapped_vault_key: Vec<u8>,
+) -> Result<ContactRow, CpassError> {
+    if wrapped_vault_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "wrapped vault key can not be empty".to_string(),
+        ));
+    }
+    if !(1..=MAX_WAIT_DAYS).contains(&wait_days) {
+        return Err(CpassError::InvalidRequest(format!(
+            "the waiting period must be between 1 and {} days",
+            MAX_WAIT_DAYS
+        )));
+    }
+
+    let grantee = sqlx::query!(
+        r#"
+        SELECT id, public_key IS NOT NULL AS "has_key_pair!" FROM users
+        WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("User with that email not found".to_string()))?;
+
+    if grantee.id == grantor_id {
+        return Err(CpassError::InvalidRequest(
+            "a user can not be their own emergency contact".to_string(),
+        ));
+    }
+    if !grantee.has_key_pair {
+        return Err(CpassError::InvalidRequest(
+            "the contact has no key pair yet".to_string(),
+        ));
+    }
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO emergency_contacts(grantor_id, grantee_id, access, wait_days, wrapped_vault_key)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (grantor_id, grantee_id) DO UPDATE
+        SET
+            access = EXCLUDED.access,
+            wait_days = EXCLUDED.wait_days,
+            wrapped_vault_key = EXCLUDED.wrapped_vault_key,
+            status = CASE emergency_contacts.status
+                WHEN 'invited' THEN 'invited'
+                ELSE 'confirmed'
+            END,
+            requested_at = NULL,
+            approved_at = NULL
+        RETURNING id
+        "#,
+        grantor_id,
+        grantee.id,
+        access.as_str(),
+        wait_days,
+        wrapped_vault_key
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    fetch_contact(conn, id).await
+}
+
+/// Emergency contacts designated by `grantor_id`.
+pub async fn list_contacts(
+    conn: &mut PgConnection,
+    grantor_id: Uuid,
+) -> Result<Vec<ContactRow>, CpassError> {
+    approve_due(conn, Some(grantor_id)).await?;
+
+    fetch_contacts(conn, None, Some(grantor_id), None).await
+}
+
+/// Users who designated `grantee_id` as their emergency contact.
+pub async fn list_grants(
+    conn: &mut PgConnection,
+    grantee_id: Uuid,
+) -> Result<Vec<ContactRow>, CpassError> {
+    approve_due(conn, Some(grantee_id)).await?;
+
+    fetch_contacts(conn, None, None, Some(grantee_id)).await
+}
+
+/// Accept to be the emergency contact `id`.
+pub async fn accept_invite(
+    conn: &mut PgConnection,
+    grantee_id: Uuid,
+    id: Uuid,
+) -> Result<(), CpassError> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE emergency_contacts
+        SET status = 'confirmed'
+        WHERE id = $1 AND grantee_id = $2 AND status = 'invited'
+        "#,
+        id,
+        grantee_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(transition_error(conn, id, grantee_id).await);
+    }
+
+    Ok(())
+}
+
+/// Remove the emergency contact `id`, either party can.
+pub async fn remove_contact(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    id: Uuid,
+) -> Result<(), CpassError> {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM emergency_contacts
+        WHERE id = $1 AND (grantor_id = $2 OR grantee_id = $2)
+        "#,
+        id,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(CpassError::NotFound(
+            "Emergency contact with that id not found".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Start the waiting period of the emergency contact `id` and notify the grantor.
+pub async fn request_access(
+    conn: &mut PgConnection,
+    grantee_id: Uuid,
+    id: Uuid,
+) -> Result<ContactRow, CpassError> {
+    let grantor_id = sqlx::query_scalar!(
+        r#"
+        UPDATE emergency_contacts
+        SET status = 'requested', requested_at = now()
+        WHERE id = $1 AND grantee_id = $2 AND status = 'confirmed'
+        RETURNING grantor_id
+        "#,
+        id,
+        grantee_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(grantor_id) = grantor_id else {
+        return Err(transition_error(conn, id, grantee_id).await);
+    };
+
+    let contact = fetch_contact(conn, id).await?;
+
+    warn!(
+        target: "emergency_access",
+        %id,
+        %grantor_id,
+        %grantee_id,
+        access = contact.access.as_str(),
+        approves_at = ?contact.approves_at,
+        "Emergency access requested"
+    );
+
+    Ok(contact)
+}
+
+/// Approve the pending request of the emergency contact `id` before the waiting period ends.
+pub async fn approve_access(
+    conn: &mut PgConnection,
+    grantor_id: Uuid,
+    id: Uuid,
+) -> Result<(), CpassError> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE emergency_contacts
+        SET status = 'approved', approved_at = now()
+        WHERE id = $1 AND grantor_id = $2 AND status = 'requested'
+        "#,
+        id,
+        grantor_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(transition_error(conn, id, grantor_id).await);
+    }
+
+    Ok(())
+}
+
+/// Reject the pending request of the emergency contact `id`, or revoke the access it was
+/// granted. A grantee who already fetched the vault key keeps knowing it, the grantor
+/// should rotate it.
+pub async fn reject_access(
+    conn: &mut PgConnection,
+    grantor_id: Uuid,
+    id: Uuid,
+) -> Result<(), CpassError> {
+    approve_due(conn, Some(grantor_id)).await?;
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE emergency_contacts
+        SET status = 'confirmed', requested_at = NULL, approved_at = NULL
+        WHERE id = $1 AND grantor_id = $2 AND status IN ('requested', 'approved')
+        "#,
+        id,
+        grantor_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(transition_error(conn, id, grantor_id).await);
+    }
+
+    Ok(())
+}
+
+/// Grantor and wrapped vault key of the emergency contact `id`, once `grantee_id` was
+/// granted at least `access`.
+async fn approved_grant(
+    conn: &mut PgConnection,
+    grantee_id: Uuid,
+    id: Uuid,
+    access: EmergencyAccess,
+) -> Result<(Uuid, Vec<u8>), CpassError> {
+    approve_due(conn, Some(grantee_id)).await?;
+
+    let grant = sqlx::query!(
+        r#"
+        SELECT grantor_id, access, status, wrapped_vault_key FROM emergency_contacts
+        WHERE id = $1 AND grantee_id = $2
+        "#,
+        id,
+        grantee_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("Emergency contact with that id not found".to_string()))?;
+
+    if EmergencyStatus::from_db(&grant.status) != EmergencyStatus::Approved {
+        return Err(CpassError::Forbidden(
+            "Emergency access has not been approved".to_string(),
+        ));
+    }
+    if EmergencyAccess::from_db(&grant.access) < access {
+        return Err(CpassError::Forbidden(format!(
+            "The {} emergency access is required",
+            access.as_str()
+        )));
+    }
+
+    let wrapped_vault_key = grant.wrapped_vault_key.ok_or_else(|| {
+        CpassError::InvalidRequest(
+            "the grantor rotated their vault key and has not wrapped the new one".to_string(),
+        )
+    })?;
+
+    Ok((grant.grantor_id, wrapped_vault_key))
+}
+
+/// The wrapped vault key and the live personal items of the grantor of the emergency
+/// contact `id`.
+pub async fn vault(
+    conn: &mut PgConnection,
+    grantee_id: Uuid,
+    id: Uuid,
+) -> Result<(Vec<u8>, Vec<PasswordRow>), CpassError> {
+    let (grantor_id, wrapped_vault_key) =
+        approved_grant(conn, grantee_id, id, EmergencyAccess::Read).await?;
+
+    let items = sqlx::query_as!(
+        PasswordRow,
+        r#"
+        SELECT id, name, password, website, username, description, revision, item_key,
+            collection_id, created_at, updated_at
+        FROM passwords
+        WHERE owner_id = $1 AND collection_id IS NULL AND deleted_at IS NULL
+        ORDER BY created_at, id
+        "#,
+        grantor_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok((wrapped_vault_key, items))
+}
+
+/// Grantor of the emergency contact `id`, once `grantee_id` was granted the takeover.
+pub async fn takeover_grantor(
+    conn: &mut PgConnection,
+    grantee_id: Uuid,
+    id: Uuid,
+) -> Result<Uuid, CpassError> {
+    let (grantor_id, _) = approved_grant(conn, grantee_id, id, EmergencyAccess::Takeover).await?;
+
+    Ok(grantor_id)
+}
+
+/// Set a new master password for the grantor of the emergency contact `id`.
+///
+/// `password_hash` is the hashed login password derived from the new master password and
+/// `vault_key` the unchanged vault key wrapped by it, so items need no re-encryption.
+/// Every session of the grantor is revoked and the contact goes back to `confirmed`.
+pub async fn takeover(
+    pool: &PgPool,
+    grantee_id: Uuid,
+    id: Uuid,
+    password_hash: String,
+    vault_key: Vec<u8>,
+) -> Result<(), CpassError> {
+    if vault_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "vault key can not be empty".to_string(),
+        ));
+    }
+
+    let mut tx = pool.tx().await?;
+
+    let (grantor_id, _) =
+        approved_grant(&mut tx, grantee_id, id, EmergencyAccess::Takeover).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password = $1, vault_key = $2,
+            pending_email = NULL, pending_password = NULL, pending_vault_key = NULL
+        WHERE id = $3
+        "#,
+        password_hash,
+        vault_key,
+        grantor_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE emergency_contacts
+        SET status = 'confirmed', requested_at = NULL, approved_at = NULL
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    session::revoke_all(&mut tx, grantor_id).await?;
+
+    tx.commit().await?;
+
+    warn!(
+        target: "emergency_access",
+        %id,
+        %grantor_id,
+        %grantee_id,
+        "Account taken over by an emergency contact"
+    );
+
+    Ok(())
+}
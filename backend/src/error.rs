@@ -1,5 +1,12 @@
-use axum::{http::StatusCode, response::Response};
-use tonic::Status;
+use axum::{
+    http::{header, StatusCode},
+    response::Response,
+};
+use prost::Message;
+use serde_json::json;
+use tonic::{Code, Status};
+
+use crate::validation::{FieldError, FieldErrors};
 
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
@@ -8,6 +15,10 @@ pub enum CpassError {
     #[error("the request was invalid {0}")]
     InvalidRequest(String),
 
+    /// Fields of the request break their constraints, every one of them is listed.
+    #[error("the request has invalid fields")]
+    Validation(Vec<FieldError>),
+
     /// If the username and password combination did not match when attempting to authenticate.
     #[error("invalid username or password")]
     InvalidUsernameOrPassword,
@@ -28,13 +39,35 @@ pub enum CpassError {
     #[error("hashing error")]
     HashingError(#[from] argon2::Error),
 
+    /// The token is well formed but its session is no longer valid.
+    #[error("unauthorized {0}")]
+    Unauthorized(String),
+
+    /// The caller can see the resource but is not allowed to perform the operation.
+    #[error("forbidden {0}")]
+    Forbidden(String),
+
     /// Not found error
     #[error("not found")]
     NotFound(String),
 
+    /// The item was modified since the client read it, holds the current version.
+    #[error("version mismatch, current version is {0}")]
+    VersionMismatch(i64),
+
+    /// A conditional request was expected but no version was supplied.
+    #[error("precondition required {0}")]
+    PreconditionRequired(String),
+
     /// Any other, unknown error sources.
     #[error("{0}")]
-    Unknown(#[source] Box<dyn std::error::Error>),
+    Unknown(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// One line naming every invalid field, the message of validation errors.
+fn describe(fields: &[FieldError]) -> String {
+    let fields: Vec<String> = fields.iter().map(FieldError::to_string).collect();
+    format!("invalid fields, {}", fields.join("; "))
 }
 
 impl From<CpassError> for tonic::Status {
@@ -42,12 +75,22 @@ impl From<CpassError> for tonic::Status {
         let error = format!("{:?}", cpass_error);
         match cpass_error {
             CpassError::InvalidRequest(_) => Status::invalid_argument(error),
+            // The fields are detailed as `types.FieldErrors` for clients to decode.
+            CpassError::Validation(fields) => Status::with_details(
+                Code::InvalidArgument,
+                describe(&fields),
+                FieldErrors { fields }.encode_to_vec().into(),
+            ),
             CpassError::InvalidUsernameOrPassword => Status::unauthenticated(error),
             CpassError::UserAlreadyExists(_) => Status::invalid_argument(error),
             CpassError::InvalidToken(_) => Status::unauthenticated(error),
             CpassError::DatabaseError(_) => Status::unavailable(error),
             CpassError::HashingError(_) => Status::unauthenticated(error),
+            CpassError::Unauthorized(_) => Status::unauthenticated(error),
+            CpassError::Forbidden(_) => Status::permission_denied(error),
             CpassError::NotFound(_) => Status::not_found(error),
+            CpassError::VersionMismatch(_) => Status::aborted(error),
+            CpassError::PreconditionRequired(_) => Status::failed_precondition(error),
             CpassError::Unknown(_) => Status::unknown(error),
         }
     }
@@ -55,16 +98,34 @@ impl From<CpassError> for tonic::Status {
 
 impl From<CpassError> for Response<String> {
     fn from(cpass_error: CpassError) -> Self {
+        if let CpassError::Validation(fields) = cpass_error {
+            let body = json!({ "error": describe(&fields), "fields": fields });
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body.to_string())
+                .unwrap();
+        }
+
         let error = format!("{:?}", cpass_error);
         let builder = Response::builder();
         match cpass_error {
             CpassError::InvalidRequest(_) => builder.status(StatusCode::BAD_REQUEST),
+            CpassError::Validation(_) => builder.status(StatusCode::BAD_REQUEST),
             CpassError::InvalidUsernameOrPassword => builder.status(StatusCode::UNAUTHORIZED),
             CpassError::UserAlreadyExists(_) => builder.status(StatusCode::CONFLICT),
             CpassError::InvalidToken(_) => builder.status(StatusCode::UNAUTHORIZED),
             CpassError::DatabaseError(_) => builder.status(StatusCode::INTERNAL_SERVER_ERROR),
             CpassError::HashingError(_) => builder.status(StatusCode::INTERNAL_SERVER_ERROR),
+            CpassError::Unauthorized(_) => builder.status(StatusCode::UNAUTHORIZED),
+            CpassError::Forbidden(_) => builder.status(StatusCode::FORBIDDEN),
             CpassError::NotFound(_) => builder.status(StatusCode::NOT_FOUND),
+            CpassError::VersionMismatch(version) => builder
+                .status(StatusCode::CONFLICT)
+                .header(header::ETAG, format!("\"{}\"", version)),
+            CpassError::PreconditionRequired(_) => {
+                builder.status(StatusCode::PRECONDITION_REQUIRED)
+            }
             CpassError::Unknown(_) => builder.status(StatusCode::INTERNAL_SERVER_ERROR),
         }
         .body(error)
@@ -1,16 +1,43 @@
+mod access_token;
+mod account;
+mod audit;
+mod batch;
+mod concurrency;
 mod db;
+mod device;
+mod emergency;
 mod error;
 mod hashing;
+mod honeytoken;
 mod jwt;
+mod machine_account;
+mod mail;
+mod organizations;
+mod pagination;
+mod password_policy;
+mod password_rules;
+mod policy;
 mod proto;
+mod realtime;
+mod recovery;
+mod rotation;
+mod sharing;
+mod trash;
+mod validation;
+mod verification;
+mod webhook;
 
-use std::fs::read_to_string;
+use std::{env, fs::read_to_string};
 
 use crate::proto::{
-    auth::AuthService, auth_proto::auth_server::AuthServer, pass::PassService,
-    pass_proto::pass_server::PassServer,
+    auth::AuthService, auth_proto::auth_server::AuthServer, emergency::EmergencyService,
+    emergency_proto::emergency_server::EmergencyServer, notify::NotifyService,
+    notify_proto::notify_server::NotifyServer, org::OrgService, org_proto::org_server::OrgServer,
+    pass::PassService, pass_proto::pass_server::PassServer, token::TokenService,
+    token_proto::token_server::TokenServer,
 };
 use sqlx::PgPool;
+use tokio::spawn;
 use tonic::transport::Server;
 use tracing::{info, Level};
 
@@ -33,11 +60,37 @@ async fn main() -> anyhow::Result<()> {
         ),
         Err(_) => dotenvy::var("DATABASE_URL")?,
     };
-    let addr = dotenvy::var("ADDR")?.parse()?;
     let pool = PgPool::connect(&db_url).await?;
 
     sqlx::migrate!("./migrations").run(&pool).await?;
 
+    if env::args().nth(1).as_deref() == Some("verify-audit") {
+        return audit::verify_command(&pool).await;
+    }
+
+    let addr = dotenvy::var("ADDR")?.parse()?;
+
+    // Settings read on demand are checked now, not by the first request using them.
+    account::grace_days()?;
+    honeytoken::revoke_sessions()?;
+    webhook::allow_private_urls()?;
+    password_policy::min_length()?;
+    password_policy::min_score()?;
+    hashing::params()?;
+    hashing::pepper()?;
+
+    spawn(trash::purge_task(pool.clone(), trash::retention_days()?));
+    spawn(emergency::approve_task(pool.clone()));
+    spawn(account::deletion_task(pool.clone()));
+    spawn(webhook::delivery_task(
+        pool.clone(),
+        webhook::retry_base_seconds()?,
+    ));
+    spawn(realtime::listen_task(pool.clone()));
+    spawn(mail::delivery_task(pool.clone(), mail::mailer()?));
+
+    let max_request_bytes = validation::max_request_bytes()?;
+
     let reflection = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
         .build()?;
@@ -45,9 +98,32 @@ async fn main() -> anyhow::Result<()> {
     info!("gRPC server listening on {}", addr);
 
     Server::builder()
+        .layer(proto::PolicyLayer::new(pool.clone()))
         .add_service(reflection)
-        .add_service(AuthServer::new(AuthService::new(pool.clone())))
-        .add_service(PassServer::new(PassService::new(pool.clone())))
+        .add_service(
+            AuthServer::new(AuthService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            PassServer::new(PassService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            OrgServer::new(OrgService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            EmergencyServer::new(EmergencyService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            TokenServer::new(TokenService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            NotifyServer::new(NotifyService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
         .serve(addr)
         .await?;
 
@@ -1,16 +1,381 @@
+use anyhow::Context;
+use argon2::{Config, Variant, Version};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use lazy_static::lazy_static;
+use ring::digest::{digest, SHA256};
+
 use crate::{error::CpassError, jwt::generate::generate_bytes};
 
+/// OWASP recommended Argon2id parameters, 19 MiB of memory and 2 iterations.
+const DEFAULT_MEMORY_KIB: u32 = 19456;
+const DEFAULT_ITERATIONS: u32 = 2;
+const DEFAULT_PARALLELISM: u32 = 1;
+const HASH_LENGTH: u32 = 32;
+
+/// Shortest `PASSWORD_PEPPER` accepted, a pepper is only worth having if it can not be guessed.
+const MIN_PEPPER_BYTES: usize = 16;
+
+lazy_static! {
+    static ref PARAMS: Params = params().expect("ARGON2_* are checked at startup");
+    static ref PEPPER: Option<Pepper> = pepper().expect("PASSWORD_PEPPER is checked at startup");
+}
+
+/// Parameters of new hashes, read from `ARGON2_VARIANT`, `ARGON2_MEMORY_KIB`,
+/// `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`.
+pub fn params() -> anyhow::Result<Params> {
+    let variant = match dotenvy::var("ARGON2_VARIANT") {
+        Ok(variant) => Variant::from_str(&variant)
+            .ok()
+            .context("ARGON2_VARIANT must be argon2d, argon2i or argon2id")?,
+        Err(_) => Variant::Argon2id,
+    };
+    let params = Params {
+        variant,
+        memory_kib: env_u32("ARGON2_MEMORY_KIB", DEFAULT_MEMORY_KIB)?,
+        iterations: env_u32("ARGON2_ITERATIONS", DEFAULT_ITERATIONS)?,
+        parallelism: env_u32("ARGON2_PARALLELISM", DEFAULT_PARALLELISM)?,
+    };
+
+    anyhow::ensure!(
+        params.iterations > 0,
+        "ARGON2_ITERATIONS must be at least 1"
+    );
+    anyhow::ensure!(
+        params.parallelism > 0,
+        "ARGON2_PARALLELISM must be at least 1"
+    );
+    // Argon2 needs 8 KiB for each lane.
+    anyhow::ensure!(
+        u64::from(params.memory_kib) >= 8 * u64::from(params.parallelism),
+        "ARGON2_MEMORY_KIB must be at least 8 times ARGON2_PARALLELISM"
+    );
+
+    Ok(params)
+}
+
+/// Server-side secret mixed into every hash, read from `PASSWORD_PEPPER`. Hashes made
+/// with it carry the `keyid` of the pepper, those without are rehashed at login.
+pub fn pepper() -> anyhow::Result<Option<Pepper>> {
+    match dotenvy::var("PASSWORD_PEPPER") {
+        Ok(pepper) if !pepper.is_empty() => {
+            anyhow::ensure!(
+                pepper.len() >= MIN_PEPPER_BYTES,
+                "PASSWORD_PEPPER must be at least {} bytes",
+                MIN_PEPPER_BYTES
+            );
+            Ok(Some(Pepper::new(pepper.into_bytes())))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn env_u32(name: &str, default: u32) -> anyhow::Result<u32> {
+    match dotenvy::var(name) {
+        Ok(value) => value
+            .parse()
+            .with_context(|| format!("{} must be a whole number", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+pub struct Params {
+    variant: Variant,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl Params {
+    /// Whether `encoded` was made with weaker parameters than these, or with another
+    /// pepper than `pepper`.
+    fn outdated(&self, encoded: &Encoded, pepper: Option<&Pepper>) -> bool {
+        let peppered = pepper.map(|pepper| pepper.key_id.as_str());
+
+        encoded.variant != self.variant
+            || encoded.version < Version::Version13.as_u32()
+            || encoded.memory_kib < self.memory_kib
+            || encoded.iterations < self.iterations
+            || encoded.parallelism < self.parallelism
+            || encoded.key_id != peppered
+    }
+}
+
+pub struct Pepper {
+    secret: Vec<u8>,
+    /// Names the pepper in the hashes made with it without revealing it.
+    key_id: String,
+}
+
+impl Pepper {
+    fn new(secret: Vec<u8>) -> Self {
+        let key_id = STANDARD_NO_PAD.encode(&digest(&SHA256, &secret).as_ref()[..6]);
+        Pepper { secret, key_id }
+    }
+}
+
+/// Parameters of an encoded hash, `$argon2id$v=19$m=19456,t=2,p=1[,keyid=..]$salt$hash`.
+struct Encoded<'a> {
+    variant: Variant,
+    version: u32,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    key_id: Option<&'a str>,
+    /// The hash without its `keyid`, as the argon2 crate reads it.
+    phc: String,
+}
+
+/// Add the `keyid` of a pepper to the parameters of an encoded hash.
+fn with_key_id(hash: &str, key_id: &str) -> String {
+    // The parameters end at the fourth `$`, the salt and hash follow.
+    let at = hash
+        .match_indices('$')
+        .nth(3)
+        .map_or(hash.len(), |(at, _)| at);
+    format!("{},keyid={}{}", &hash[..at], key_id, &hash[at..])
+}
+
+impl<'a> Encoded<'a> {
+    fn parse(hash: &'a str) -> Option<Self> {
+        let fields: Vec<&str> = hash.split('$').collect();
+        let [empty, variant, version, params, salt, digest] = fields[..] else {
+            return None;
+        };
+        if !empty.is_empty() {
+            return None;
+        }
+
+        let mut encoded = Encoded {
+            variant: Variant::from_str(variant).ok()?,
+            version: version.strip_prefix("v=")?.parse().ok()?,
+            memory_kib: 0,
+            iterations: 0,
+            parallelism: 0,
+            key_id: None,
+            phc: String::new(),
+        };
+        let mut kept = vec![];
+        for param in params.split(',') {
+            let (name, value) = param.split_once('=')?;
+            match name {
+                "m" => encoded.memory_kib = value.parse().ok()?,
+                "t" => encoded.iterations = value.parse().ok()?,
+                "p" => encoded.parallelism = value.parse().ok()?,
+                "keyid" => {
+                    encoded.key_id = Some(value);
+                    continue;
+                }
+                _ => return None,
+            }
+            kept.push(param);
+        }
+        encoded.phc = format!(
+            "${}${}${}${}${}",
+            variant,
+            version,
+            kept.join(","),
+            salt,
+            digest
+        );
+
+        Some(encoded)
+    }
+}
+
 pub struct Argon;
 
 impl Argon {
     pub fn hash_password(password: &[u8]) -> Result<String, CpassError> {
-        let config = argon2::Config::original();
+        let config = Config {
+            variant: PARAMS.variant,
+            version: Version::Version13,
+            mem_cost: PARAMS.memory_kib,
+            time_cost: PARAMS.iterations,
+            lanes: PARAMS.parallelism,
+            secret: PEPPER.as_ref().map_or(&[], |pepper| &pepper.secret),
+            ad: &[],
+            hash_length: HASH_LENGTH,
+        };
         let salt = generate_bytes(16);
 
-        argon2::hash_encoded(password, &salt, &config).map_err(CpassError::HashingError)
+        let hash = argon2::hash_encoded(password, &salt, &config)?;
+
+        Ok(match PEPPER.as_ref() {
+            Some(pepper) => with_key_id(&hash, &pepper.key_id),
+            None => hash,
+        })
     }
 
     pub fn verify(password: &[u8], hash: &str) -> Result<bool, CpassError> {
-        argon2::verify_encoded(hash, password).map_err(CpassError::HashingError)
+        let encoded =
+            Encoded::parse(hash).ok_or(CpassError::HashingError(argon2::Error::DecodingFail))?;
+
+        let secret: &[u8] = match (encoded.key_id, PEPPER.as_ref()) {
+            (None, _) => &[],
+            (Some(key_id), Some(pepper)) if key_id == pepper.key_id => &pepper.secret,
+            (Some(_), _) => {
+                return Err(CpassError::Unknown(
+                    "the password hash was made with another pepper than PASSWORD_PEPPER".into(),
+                ))
+            }
+        };
+
+        argon2::verify_encoded_ext(&encoded.phc, password, secret, &[])
+            .map_err(CpassError::HashingError)
+    }
+
+    /// Whether `hash` was made with weaker parameters than new hashes are, or without the
+    /// pepper, and should be replaced once the password is known.
+    pub fn needs_rehash(hash: &str) -> bool {
+        let Some(encoded) = Encoded::parse(hash) else {
+            return true;
+        };
+
+        PARAMS.outdated(&encoded, PEPPER.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHRzYWx0$aGFzaGhhc2hoYXNoaGFzaA";
+
+    fn default_params() -> Params {
+        Params {
+            variant: Variant::Argon2id,
+            memory_kib: DEFAULT_MEMORY_KIB,
+            iterations: DEFAULT_ITERATIONS,
+            parallelism: DEFAULT_PARALLELISM,
+        }
+    }
+
+    #[test]
+    fn parse_reads_parameters() {
+        let encoded = Encoded::parse(HASH).unwrap();
+
+        assert_eq!(encoded.variant, Variant::Argon2id);
+        assert_eq!(encoded.version, 19);
+        assert_eq!(encoded.memory_kib, 19456);
+        assert_eq!(encoded.iterations, 2);
+        assert_eq!(encoded.parallelism, 1);
+        assert_eq!(encoded.key_id, None);
+        assert_eq!(encoded.phc, HASH);
+    }
+
+    #[test]
+    fn parse_strips_key_id() {
+        let hash = "$argon2id$v=19$m=19456,t=2,keyid=abcd,p=1$c2FsdA$aGFzaA";
+        let encoded = Encoded::parse(hash).unwrap();
+
+        assert_eq!(encoded.key_id, Some("abcd"));
+        assert_eq!(encoded.parallelism, 1);
+        assert_eq!(encoded.phc, "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_hashes() {
+        for hash in [
+            "",
+            "plaintext",
+            "x$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+            "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA",
+            "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA$",
+            "$argon2x$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+            "$argon2id$19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+            "$argon2id$v=19$m=lots,t=2,p=1$c2FsdA$aGFzaA",
+            "$argon2id$v=19$m=19456,t=2,p=1,x=1$c2FsdA$aGFzaA",
+            "$argon2id$v=19$m=19456,t,p=1$c2FsdA$aGFzaA",
+        ] {
+            assert!(Encoded::parse(hash).is_none(), "{hash}");
+        }
+    }
+
+    #[test]
+    fn key_id_goes_after_parameters() {
+        let hash = with_key_id(HASH, "abcd");
+
+        assert_eq!(
+            hash,
+            "$argon2id$v=19$m=19456,t=2,p=1,keyid=abcd$c2FsdHNhbHRzYWx0$aGFzaGhhc2hoYXNoaGFzaA"
+        );
+        let encoded = Encoded::parse(&hash).unwrap();
+        assert_eq!(encoded.key_id, Some("abcd"));
+        assert_eq!(encoded.phc, HASH);
+    }
+
+    #[test]
+    fn peppered_hash_verifies_with_its_pepper() {
+        let pepper = Pepper::new(b"a pepper of sixteen bytes".to_vec());
+        let config = Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: 8,
+            time_cost: 1,
+            lanes: 1,
+            secret: &pepper.secret,
+            ad: &[],
+            hash_length: HASH_LENGTH,
+        };
+        let hash = argon2::hash_encoded(b"password", b"somesaltsomesalt", &config).unwrap();
+        let hash = with_key_id(&hash, &pepper.key_id);
+
+        let encoded = Encoded::parse(&hash).unwrap();
+        assert_eq!(encoded.key_id, Some(pepper.key_id.as_str()));
+        assert!(
+            argon2::verify_encoded_ext(&encoded.phc, b"password", &pepper.secret, &[]).unwrap()
+        );
+        assert!(!argon2::verify_encoded_ext(&encoded.phc, b"password", &[], &[]).unwrap_or(false));
+    }
+
+    #[test]
+    fn current_hash_is_not_outdated() {
+        let encoded = Encoded::parse(HASH).unwrap();
+
+        assert!(!default_params().outdated(&encoded, None));
+    }
+
+    #[test]
+    fn weaker_hash_is_outdated() {
+        for hash in [
+            "$argon2i$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+            "$argon2id$v=16$m=19456,t=2,p=1$c2FsdA$aGFzaA",
+            "$argon2id$v=19$m=4096,t=2,p=1$c2FsdA$aGFzaA",
+            "$argon2id$v=19$m=19456,t=1,p=1$c2FsdA$aGFzaA",
+        ] {
+            let encoded = Encoded::parse(hash).unwrap();
+            assert!(default_params().outdated(&encoded, None), "{hash}");
+        }
+
+        let params = Params {
+            parallelism: 2,
+            ..default_params()
+        };
+        assert!(params.outdated(&Encoded::parse(HASH).unwrap(), None));
+    }
+
+    #[test]
+    fn stronger_hash_is_not_outdated() {
+        let encoded = Encoded::parse("$argon2id$v=19$m=65536,t=3,p=4$c2FsdA$aGFzaA").unwrap();
+
+        assert!(!default_params().outdated(&encoded, None));
+    }
+
+    #[test]
+    fn hash_with_another_pepper_is_outdated() {
+        let pepper = Pepper::new(b"a pepper of sixteen bytes".to_vec());
+        let other = Pepper::new(b"another pepper of sixteen bytes".to_vec());
+        let peppered = with_key_id(HASH, &pepper.key_id);
+        let params = default_params();
+
+        assert!(params.outdated(&Encoded::parse(HASH).unwrap(), Some(&pepper)));
+        assert!(params.outdated(&Encoded::parse(&peppered).unwrap(), None));
+        assert!(params.outdated(&Encoded::parse(&peppered).unwrap(), Some(&other)));
+        assert!(!params.outdated(&Encoded::parse(&peppered).unwrap(), Some(&pepper)));
+    }
+
+    #[test]
+    fn unparsable_hash_needs_rehash() {
+        assert!(Argon::needs_rehash("plaintext"));
     }
 }
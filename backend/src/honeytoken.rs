@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sqlx::PgConnection;
+use tokio::spawn;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    audit::Origin,
+    error::CpassError,
+    jwt::{models::Principal, session},
+    webhook::{self, Notification, WebhookEvent},
+};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    /// Endpoint receiving a JSON [`Alert`] for every access, read from `HONEYTOKEN_WEBHOOK_URL`.
+    static ref WEBHOOK_URL: Option<String> = dotenvy::var("HONEYTOKEN_WEBHOOK_URL").ok();
+    static ref REVOKE_SESSIONS: bool =
+        revoke_sessions().expect("HONEYTOKEN_REVOKE_SESSIONS is checked at startup");
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Whether every session and access token of the accessor is revoked, read from
+/// `HONEYTOKEN_REVOKE_SESSIONS`.
+pub fn revoke_sessions() -> anyhow::Result<bool> {
+    match dotenvy::var("HONEYTOKEN_REVOKE_SESSIONS") {
+        Ok(revoke) => revoke
+            .parse()
+            .context("HONEYTOKEN_REVOKE_SESSIONS must be true or false"),
+        Err(_) => Ok(false),
+    }
+}
+
+/// High-severity event raised when a decoy item is retrieved.
+#[derive(Serialize, Debug)]
+pub struct Alert {
+    pub id: Uuid,
+    pub severity: &'static str,
+    pub item_id: Uuid,
+    pub owner_id: Uuid,
+    pub accessor_id: Uuid,
+    /// Session or access token the item was retrieved with.
+    pub session_id: Uuid,
+    /// `grpc` or `http`.
+    pub transport: &'static str,
+    pub sessions_revoked: bool,
+    pub triggered_at: DateTime<Utc>,
+}
+
+async fn record(
+    conn: &mut PgConnection,
+    item_id: Uuid,
+    accessor: &Principal,
+    transport: &'static str,
+) -> Result<Alert, CpassError> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO honeytoken_alerts(item_id, owner_id, accessor_id, session_id, transport)
+        SELECT id, owner_id, $2, $3, $4 FROM passwords
+        WHERE id = $1
+        RETURNING id, owner_id, triggered_at
+        "#,
+        item_id,
+        accessor.sub,
+        accessor.sid,
+        transport
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let sessions_revoked = *REVOKE_SESSIONS;
+    if sessions_revoked {
+        session::revoke_all(conn, accessor.sub).await?;
+    }
+
+    Ok(Alert {
+        id: row.id,
+        severity: "high",
+        item_id,
+        owner_id: row.owner_id,
+        accessor_id: accessor.sub,
+        session_id: accessor.sid,
+        transport,
+        sessions_revoked,
+        triggered_at: row.triggered_at,
+    })
+}
+
+async fn notify(url: &str, alert: &Alert) -> Result<(), reqwest::Error> {
+    CLIENT
+        .post(url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .json(alert)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+/// Raise an alert for the retrieval of the decoy item `item_id` by `accessor`.
+///
+/// The alert is recorded, logged, queued for the owner's webhooks and posted to the
+/// configured webhook in the background.
+/// Failures are only logged, the caller must not be able to tell a decoy from a real item.
+pub async fn trip(
+    conn: &mut PgConnection,
+    item_id: Uuid,
+    accessor: &Principal,
+    transport: &'static str,
+) {
+    let alert = match record(conn, item_id, accessor, transport).await {
+        Ok(alert) => alert,
+        Err(err) => {
+            error!(
+                target: "honeytoken",
+                %item_id,
+                accessor_id = %accessor.sub,
+                "Honeytoken accessed, failed to record the alert: {:?}",
+                err
+            );
+            return;
+        }
+    };
+
+    error!(
+        target: "honeytoken",
+        severity = alert.severity,
+        alert_id = %alert.id,
+        %item_id,
+        owner_id = %alert.owner_id,
+        accessor_id = %alert.accessor_id,
+        session_id = %alert.session_id,
+        transport = alert.transport,
+        sessions_revoked = alert.sessions_revoked,
+        "Honeytoken accessed"
+    );
+
+    let notification = Notification::new(WebhookEvent::HoneytokenTriggered, alert.owner_id)
+        .item(item_id)
+        .detail(format!(
+            "retrieved by {} over {}",
+            alert.accessor_id, alert.transport
+        ));
+    webhook::notify(conn, &Origin::default(), notification).await;
+
+    if let Some(url) = WEBHOOK_URL.as_deref() {
+        spawn(async move {
+            if let Err(err) = notify(url, &alert).await {
+                error!(
+                    target: "honeytoken",
+                    alert_id = %alert.id,
+                    "Failed to deliver the alert: {:?}",
+                    err
+                );
+            }
+        });
+    }
+}
@@ -1,15 +1,38 @@
+mod access_token;
+mod account;
+mod audit;
+mod batch;
+mod concurrency;
 mod db;
+mod device;
+mod emergency;
 mod error;
 mod hashing;
+mod honeytoken;
 mod jwt;
+mod machine_account;
+mod mail;
+mod organizations;
+mod pagination;
+mod password_policy;
+mod password_rules;
+mod policy;
+mod realtime;
+mod recovery;
+mod rotation;
+mod sharing;
+mod trash;
+mod validation;
+mod verification;
+mod webhook;
 
-use std::fs::read_to_string;
+use std::{env, fs::read_to_string, net::SocketAddr};
 
-use axum::{http::StatusCode, routing::get, Router};
+use axum::{extract::DefaultBodyLimit, http::StatusCode, middleware, routing::get, Router};
 #[cfg(feature = "swagger")]
 use routers::openapi::ApiDoc;
 use sqlx::PgPool;
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, spawn};
 use tower_http::trace::{self, TraceLayer};
 use tracing::{info, Level};
 #[cfg(feature = "swagger")]
@@ -43,20 +66,61 @@ async fn main() -> anyhow::Result<()> {
         ),
         Err(_) => dotenvy::var("DATABASE_URL")?,
     };
-    let addr = dotenvy::var("ADDR")?;
     let pool = PgPool::connect(&db_url).await?;
 
     sqlx::migrate!("./migrations").run(&pool).await?;
 
+    if env::args().nth(1).as_deref() == Some("verify-audit") {
+        return audit::verify_command(&pool).await;
+    }
+
+    let addr = dotenvy::var("ADDR")?;
+
+    // Settings read on demand are checked now, not by the first request using them.
+    account::grace_days()?;
+    honeytoken::revoke_sessions()?;
+    webhook::allow_private_urls()?;
+    password_policy::min_length()?;
+    password_policy::min_score()?;
+    hashing::params()?;
+    hashing::pepper()?;
+
+    spawn(trash::purge_task(pool.clone(), trash::retention_days()?));
+    spawn(emergency::approve_task(pool.clone()));
+    spawn(account::deletion_task(pool.clone()));
+    spawn(webhook::delivery_task(
+        pool.clone(),
+        webhook::retry_base_seconds()?,
+    ));
+    spawn(realtime::listen_task(pool.clone()));
+    spawn(mail::delivery_task(pool.clone(), mail::mailer()?));
+
+    let max_request_bytes = validation::max_request_bytes()?;
+
     let app_state = AppState { pool };
 
     let auth_app = routers::get_auth_service(app_state.clone());
     let pass_app = routers::get_pass_service(app_state.clone());
+    let org_app = routers::get_org_service(app_state.clone());
+    let emergency_app = routers::get_emergency_service(app_state.clone());
+    let token_app = routers::get_token_service(app_state.clone());
+    let notify_app = routers::get_notify_service(app_state.clone());
+    let notifications_app = routers::get_notifications_service(app_state.clone());
 
     let app = Router::new()
         .route("/api/healthcheck", get(StatusCode::CREATED))
         .nest("/api/v1/pass", pass_app)
         .nest("/api/v1/auth", auth_app)
+        .nest("/api/v1/org", org_app)
+        .nest("/api/v1/emergency", emergency_app)
+        .nest("/api/v1/tokens", token_app)
+        .nest("/api/v1/notify", notify_app)
+        .nest("/api/v1/notifications", notifications_app)
+        .route_layer(middleware::from_fn_with_state(
+            app_state.pool.clone(),
+            routers::enforce_policy,
+        ))
+        .layer(DefaultBodyLimit::max(max_request_bytes))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
@@ -70,7 +134,11 @@ async fn main() -> anyhow::Result<()> {
     info!("HTTP server listening on {}", addr);
 
     let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -1,13 +1,11 @@
 use std::env;
 
-use axum::http::HeaderMap;
+use axum::http::{header::AUTHORIZATION, HeaderMap};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use lazy_static::lazy_static;
 use rand::RngCore;
-use tonic::metadata::MetadataMap;
-use uuid::Uuid;
 
-use crate::error::CpassError;
+use crate::{access_token::TOKEN_PREFIX, error::CpassError};
 
 use super::models::Claims;
 
@@ -18,12 +16,10 @@ lazy_static! {
     };
 }
 
-pub fn create_token(user_id: &Uuid) -> Result<String, CpassError> {
-    let claims = Claims::new(user_id);
-
+pub fn create_token(claims: &Claims) -> Result<String, CpassError> {
     encode(
         &Header::new(Algorithm::HS256),
-        &claims,
+        claims,
         &EncodingKey::from_secret(&SECRET),
     )
     .map_err(CpassError::InvalidToken)
@@ -39,18 +35,35 @@ pub fn validate_token(token: &str) -> Result<Claims, CpassError> {
     .map_err(CpassError::InvalidToken)
 }
 
-pub fn claims_from_headers(headers: &impl Map) -> Result<Claims, CpassError> {
-    if !headers.contains_key("authorization") {
-        return Err(CpassError::InvalidRequest(
-            "No authorization token was found".to_string(),
-        ));
+/// Credential presented in the `authorization` header of a request.
+pub enum Credential {
+    Jwt(Claims),
+    AccessToken(String),
+}
+
+/// Token of an `authorization` header value, which must use the `Bearer` scheme.
+pub fn bearer_token(value: &str) -> Result<&str, CpassError> {
+    match value.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") && !token.is_empty() => {
+            Ok(token)
+        }
+        _ => Err(CpassError::Unauthorized(
+            "Wrong authorization Bearer format".to_string(),
+        )),
     }
+}
+
+pub fn credential_from_headers(headers: &HeaderMap) -> Result<Credential, CpassError> {
+    let value = headers
+        .get(AUTHORIZATION)
+        .ok_or_else(|| CpassError::Unauthorized("No authorization token was found".to_string()))?
+        .to_str()
+        .map_err(|_| CpassError::Unauthorized("Wrong authorization Bearer format".to_string()))?;
 
-    headers
-        .get("authorization")
-        .map_err(|_| CpassError::InvalidRequest("Wrong authorization Bearer format".to_string()))?
-        .unwrap()
-        .parse()
+    match bearer_token(value)? {
+        token if token.starts_with(TOKEN_PREFIX) => Ok(Credential::AccessToken(token.to_string())),
+        _ => value.parse().map(Credential::Jwt),
+    }
 }
 
 pub fn generate_bytes(number: usize) -> Vec<u8> {
@@ -60,27 +73,48 @@ pub fn generate_bytes(number: usize) -> Vec<u8> {
     buf
 }
 
-pub trait Map {
-    fn get(&self, key: &str) -> anyhow::Result<Option<&str>>;
-    fn contains_key(&self, key: &str) -> bool;
-}
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
 
-impl Map for HeaderMap {
-    fn get(&self, key: &str) -> anyhow::Result<Option<&str>> {
-        Ok(self.get(key).map(|x| x.to_str()).transpose()?)
+    fn jwt() -> String {
+        create_token(&Claims::new(&Uuid::new_v4(), &Uuid::new_v4(), &[])).unwrap()
     }
 
-    fn contains_key(&self, key: &str) -> bool {
-        self.contains_key(key)
+    #[test]
+    fn bearer_token_rejects_other_formats() {
+        for value in ["Basic x", "x", "Bearer ", "tokenonly", ""] {
+            assert!(
+                matches!(bearer_token(value), Err(CpassError::Unauthorized(_))),
+                "{value:?}"
+            );
+        }
     }
-}
 
-impl Map for MetadataMap {
-    fn get(&self, key: &str) -> anyhow::Result<Option<&str>> {
-        Ok(self.get(key).map(|x| x.to_str()).transpose()?)
+    #[test]
+    fn bearer_token_ignores_scheme_case() {
+        assert_eq!(bearer_token("Bearer abc").unwrap(), "abc");
+        assert_eq!(bearer_token("bearer abc").unwrap(), "abc");
+        assert_eq!(bearer_token("BEARER abc").unwrap(), "abc");
     }
 
-    fn contains_key(&self, key: &str) -> bool {
-        self.contains_key(key)
+    #[test]
+    fn claims_parse_from_bearer_header() {
+        let token = jwt();
+
+        assert!(format!("bearer {token}").parse::<Claims>().is_ok());
+        assert!(format!("Bearer {token}").parse::<Claims>().is_ok());
+        assert!(token.parse::<Claims>().is_err());
+        assert!(format!("Basic {token}").parse::<Claims>().is_err());
+    }
+
+    #[test]
+    fn missing_header_is_unauthorized() {
+        assert!(matches!(
+            credential_from_headers(&HeaderMap::new()),
+            Err(CpassError::Unauthorized(_))
+        ));
     }
 }
@@ -1,12 +1,12 @@
 use std::str::FromStr;
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::error::CpassError;
+use crate::{error::CpassError, policy::Scope};
 
-use super::generate::validate_token;
+use super::generate::{bearer_token, validate_token};
 
 const JWT_ISSUER: &str = "authentication";
 const JWT_EXPIRY_HOURS: i64 = 1;
@@ -15,32 +15,79 @@ const JWT_EXPIRY_HOURS: i64 = 1;
 pub struct Claims {
     pub iss: String,
     pub sub: Uuid,
+    /// Session the token belongs to.
+    pub sid: Uuid,
+    /// Space separated scopes of the session.
+    pub scope: String,
     pub iat: i64,
     pub exp: i64,
 }
 
 impl Claims {
-    pub fn new(user_id: &Uuid) -> Self {
+    pub fn new(user_id: &Uuid, session_id: &Uuid, scopes: &[Scope]) -> Self {
         let iat = Utc::now();
         let exp = iat + Duration::hours(JWT_EXPIRY_HOURS);
 
         Claims {
             iss: JWT_ISSUER.to_string(),
             sub: *user_id,
+            sid: *session_id,
+            scope: scopes
+                .iter()
+                .map(|scope| scope.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
             iat: iat.timestamp(),
             exp: exp.timestamp(),
         }
     }
 }
 
+/// Caller of a request, signed in with a session or an access token.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub sub: Uuid,
+    /// Session or access token the request was made with.
+    pub sid: Uuid,
+    pub scopes: Vec<Scope>,
+    /// Collections an access token is restricted to, `None` when unrestricted.
+    pub collections: Option<Vec<Uuid>>,
+    /// When the token expires, `None` for access tokens that never do.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Principal {
+    /// Whether the caller may reach items of `collection_id`, or of the personal vault
+    /// for `None`. Membership of the collection is checked separately.
+    pub fn reaches(&self, collection_id: Option<Uuid>) -> bool {
+        match (&self.collections, collection_id) {
+            (None, _) => true,
+            (Some(allowed), Some(id)) => allowed.contains(&id),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl From<Claims> for Principal {
+    fn from(claims: Claims) -> Self {
+        Principal {
+            sub: claims.sub,
+            sid: claims.sid,
+            scopes: claims
+                .scope
+                .split_whitespace()
+                .filter_map(|scope| Scope::parse(scope).ok())
+                .collect(),
+            collections: None,
+            expires_at: DateTime::from_timestamp(claims.exp, 0),
+        }
+    }
+}
+
 impl FromStr for Claims {
     type Err = CpassError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let claims: Vec<_> = s.split(' ').collect();
-        let token = claims.get(1).ok_or(CpassError::InvalidRequest(
-            "Wrong authorization Bearer format".to_string(),
-        ))?;
-        validate_token(token)
+        validate_token(bearer_token(s)?)
     }
 }
@@ -0,0 +1,152 @@
+use axum::http::HeaderMap;
+use chrono::DateTime;
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{access_token, error::CpassError, policy::Scope};
+
+use super::{
+    generate::{create_token, credential_from_headers, Credential},
+    models::{Claims, Principal},
+};
+
+/// Open a new session for the user granted `scopes` and issue its token. A session of a
+/// device ends when the device is removed.
+pub async fn start(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    device_id: Option<Uuid>,
+    scopes: &[Scope],
+) -> Result<String, CpassError> {
+    let claims = Claims::new(&user_id, &Uuid::new_v4(), scopes);
+    let expires_at = DateTime::from_timestamp(claims.exp, 0)
+        .ok_or_else(|| CpassError::Unknown("session expiry out of range".into()))?;
+
+    // Expired sessions of the user are cleaned up whenever a new one starts.
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE user_id = $1 AND expires_at < now()
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO sessions(id, user_id, device_id, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        claims.sid,
+        user_id,
+        device_id,
+        expires_at
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    create_token(&claims)
+}
+
+async fn check_session(pool: &PgPool, claims: &Claims) -> Result<(), CpassError> {
+    let active = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM sessions WHERE id = $1 AND user_id = $2) AS "active!"
+        "#,
+        claims.sid,
+        claims.sub
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if !active {
+        return Err(CpassError::Unauthorized(
+            "Session has been revoked".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Authenticate a request made with a session that was not revoked, or with a valid
+/// access token. What the caller may do is up to the [`crate::policy`].
+pub async fn authenticate(pool: &PgPool, headers: &HeaderMap) -> Result<Principal, CpassError> {
+    match credential_from_headers(headers)? {
+        Credential::Jwt(claims) => {
+            check_session(pool, &claims).await?;
+            Ok(claims.into())
+        }
+        Credential::AccessToken(token) => access_token::verify(pool, &token).await,
+    }
+}
+
+/// Whether the session or access token `principal` signed in with was neither revoked nor
+/// expired since.
+pub async fn is_active(pool: &PgPool, principal: &Principal) -> Result<bool, CpassError> {
+    let active = sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS(
+                SELECT 1 FROM sessions
+                WHERE id = $1 AND user_id = $2 AND expires_at > now()
+            )
+            OR EXISTS(
+                SELECT 1 FROM access_tokens
+                WHERE id = $1 AND user_id = $2 AND (expires_at IS NULL OR expires_at > now())
+            ) AS "active!"
+        "#,
+        principal.sid,
+        principal.sub
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(active)
+}
+
+/// End every session of the user except `keep`.
+pub async fn revoke_others(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    keep: Uuid,
+) -> Result<u64, CpassError> {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE user_id = $1 AND id <> $2
+        "#,
+        user_id,
+        keep
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// End every session of the user and revoke their access tokens. Tokens of their machine
+/// accounts are left alone.
+pub async fn revoke_all(conn: &mut PgConnection, user_id: Uuid) -> Result<u64, CpassError> {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM access_tokens
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,147 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{account, db::Db, error::CpassError};
+
+pub struct MachineAccountRow {
+    pub id: Uuid,
+    /// Address the machine account is invited to organizations and shared items with.
+    pub email: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Key material of a new machine account, generated by its owner's client.
+pub struct MachineKeys {
+    /// Vault key wrapped by a secret handed to the machine next to its access token.
+    pub vault_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    /// Private key wrapped by the vault key.
+    pub wrapped_private_key: Vec<u8>,
+}
+
+/// Create a machine account owned by `owner_id`. It has no password and only signs in
+/// with the access tokens its owner creates for it.
+pub async fn create(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    name: &str,
+    keys: MachineKeys,
+) -> Result<MachineAccountRow, CpassError> {
+    if name.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "machine account name can not be empty".to_string(),
+        ));
+    }
+    if keys.vault_key.is_empty()
+        || keys.public_key.is_empty()
+        || keys.wrapped_private_key.is_empty()
+    {
+        return Err(CpassError::InvalidRequest(
+            "vault_key, public_key and wrapped_private_key can not be empty".to_string(),
+        ));
+    }
+
+    let id = Uuid::new_v4();
+    let email = format!("machine-{}@cpass.invalid", id);
+
+    let created_at = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users(
+            id, email, username, vault_key, public_key, wrapped_private_key, machine_owner_id,
+            email_verified_at
+        )
+        SELECT $1, $2, $3, $4, $5, $6, id, now() FROM users
+        WHERE id = $7 AND machine_owner_id IS NULL
+        RETURNING created_at
+        "#,
+        id,
+        email,
+        name,
+        keys.vault_key,
+        keys.public_key,
+        keys.wrapped_private_key,
+        owner_id
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or_else(|| {
+        CpassError::Forbidden("machine accounts can not own machine accounts".to_string())
+    })?;
+
+    info!(%owner_id, machine_id = %id, "Machine account created");
+
+    Ok(MachineAccountRow {
+        id,
+        email,
+        name: name.to_string(),
+        created_at,
+    })
+}
+
+/// Every machine account of `owner_id`.
+pub async fn list(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+) -> Result<Vec<MachineAccountRow>, CpassError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, email, username, created_at FROM users
+        WHERE machine_owner_id = $1
+        ORDER BY created_at, id
+        "#,
+        owner_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| MachineAccountRow {
+            id: row.id,
+            email: row.email,
+            name: row.username,
+            created_at: row.created_at,
+        })
+        .collect())
+}
+
+/// Fail unless `machine_id` is a machine account of `owner_id`.
+pub async fn owned(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    machine_id: Uuid,
+) -> Result<(), CpassError> {
+    let owned = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND machine_owner_id = $2) AS "owned!"
+        "#,
+        machine_id,
+        owner_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    match owned {
+        true => Ok(()),
+        false => Err(CpassError::NotFound(
+            "Machine account with that id not found".to_string(),
+        )),
+    }
+}
+
+/// Delete a machine account of `owner_id` with its tokens, like any other account.
+pub async fn delete(pool: &PgPool, owner_id: Uuid, machine_id: Uuid) -> Result<(), CpassError> {
+    let mut tx = pool.tx().await?;
+
+    owned(&mut tx, owner_id, machine_id).await?;
+    account::delete_account(&mut tx, machine_id).await?;
+
+    tx.commit().await?;
+
+    info!(%owner_id, %machine_id, "Machine account deleted");
+
+    Ok(())
+}
@@ -1,14 +1,42 @@
+mod access_token;
+mod account;
+mod audit;
+mod batch;
+mod concurrency;
 mod db;
+mod device;
+mod emergency;
 mod error;
 mod hashing;
+mod honeytoken;
 mod jwt;
+mod machine_account;
+mod mail;
+mod organizations;
+mod pagination;
+mod password_policy;
+mod password_rules;
+mod policy;
 mod proto;
+mod realtime;
+mod recovery;
+mod rotation;
+mod sharing;
+mod trash;
+mod validation;
+mod verification;
+mod webhook;
+
+use std::{env, net::SocketAddr};
 
 use crate::proto::{
-    auth::AuthService, auth_proto::auth_server::AuthServer, pass::PassService,
-    pass_proto::pass_server::PassServer,
+    auth::AuthService, auth_proto::auth_server::AuthServer, emergency::EmergencyService,
+    emergency_proto::emergency_server::EmergencyServer, notify::NotifyService,
+    notify_proto::notify_server::NotifyServer, org::OrgService, org_proto::org_server::OrgServer,
+    pass::PassService, pass_proto::pass_server::PassServer, token::TokenService,
+    token_proto::token_server::TokenServer,
 };
-use axum::{http::StatusCode, routing::get, Router};
+use axum::{extract::DefaultBodyLimit, http::StatusCode, middleware, routing::get, Router};
 #[cfg(feature = "swagger")]
 use routers::openapi::ApiDoc;
 use sqlx::PgPool;
@@ -41,12 +69,38 @@ async fn main() -> anyhow::Result<()> {
     }
 
     let db_url = dotenvy::var("DATABASE_URL")?;
-    let http_addr = dotenvy::var("HTTP_ADDR")?;
-    let grpc_addr = dotenvy::var("GRPC_ADDR")?.parse()?;
     let pool = PgPool::connect(&db_url).await?;
 
     sqlx::migrate!("./migrations").run(&pool).await?;
 
+    if env::args().nth(1).as_deref() == Some("verify-audit") {
+        return audit::verify_command(&pool).await;
+    }
+
+    let http_addr = dotenvy::var("HTTP_ADDR")?;
+    let grpc_addr = dotenvy::var("GRPC_ADDR")?.parse()?;
+
+    // Settings read on demand are checked now, not by the first request using them.
+    account::grace_days()?;
+    honeytoken::revoke_sessions()?;
+    webhook::allow_private_urls()?;
+    password_policy::min_length()?;
+    password_policy::min_score()?;
+    hashing::params()?;
+    hashing::pepper()?;
+
+    spawn(trash::purge_task(pool.clone(), trash::retention_days()?));
+    spawn(emergency::approve_task(pool.clone()));
+    spawn(account::deletion_task(pool.clone()));
+    spawn(webhook::delivery_task(
+        pool.clone(),
+        webhook::retry_base_seconds()?,
+    ));
+    spawn(realtime::listen_task(pool.clone()));
+    spawn(mail::delivery_task(pool.clone(), mail::mailer()?));
+
+    let max_request_bytes = validation::max_request_bytes()?;
+
     let (_, health_service) = tonic_health::server::health_reporter();
 
     let reflection = tonic_reflection::server::Builder::configure()
@@ -54,21 +108,59 @@ async fn main() -> anyhow::Result<()> {
         .build()?;
 
     let grpc = Server::builder()
+        .layer(proto::PolicyLayer::new(pool.clone()))
         .add_service(health_service)
         .add_service(reflection)
-        .add_service(AuthServer::new(AuthService::new(pool.clone())))
-        .add_service(PassServer::new(PassService::new(pool.clone())))
+        .add_service(
+            AuthServer::new(AuthService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            PassServer::new(PassService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            OrgServer::new(OrgService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            EmergencyServer::new(EmergencyService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            TokenServer::new(TokenService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
+        .add_service(
+            NotifyServer::new(NotifyService::new(pool.clone()))
+                .max_decoding_message_size(max_request_bytes),
+        )
         .serve(grpc_addr);
 
     let app_state = AppState { pool };
 
     let auth_app = routers::get_auth_service(app_state.clone());
     let pass_app = routers::get_pass_service(app_state.clone());
+    let org_app = routers::get_org_service(app_state.clone());
+    let emergency_app = routers::get_emergency_service(app_state.clone());
+    let token_app = routers::get_token_service(app_state.clone());
+    let notify_app = routers::get_notify_service(app_state.clone());
+    let notifications_app = routers::get_notifications_service(app_state.clone());
 
     let app = Router::new()
         .route("/api/healthcheck", get(StatusCode::OK))
         .nest("/api/v1/pass", pass_app)
         .nest("/api/v1/auth", auth_app)
+        .nest("/api/v1/org", org_app)
+        .nest("/api/v1/emergency", emergency_app)
+        .nest("/api/v1/tokens", token_app)
+        .nest("/api/v1/notify", notify_app)
+        .nest("/api/v1/notifications", notifications_app)
+        .route_layer(middleware::from_fn_with_state(
+            app_state.pool.clone(),
+            routers::enforce_policy,
+        ))
+        .layer(DefaultBodyLimit::max(max_request_bytes))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
@@ -83,7 +175,10 @@ async fn main() -> anyhow::Result<()> {
     info!("gRPC server listening on {}", grpc_addr);
 
     let listener = TcpListener::bind(http_addr).await?;
-    let http = axum::serve(listener, app);
+    let http = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    );
 
     let _ = try_join!(spawn(async { http.await }), spawn(grpc));
 
@@ -0,0 +1,533 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{db::Db, error::CpassError, pagination::PasswordRow};
+
+/// Role of a member, ordered from the least to the most privileged.
+#[derive(
+    Deserialize, Serialize, ToSchema, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum OrgRole {
+    /// Can read the items of every collection.
+    ReadOnly,
+    /// Can add, change and trash items as well.
+    #[default]
+    Member,
+    /// Can manage collections, members and purge items.
+    Admin,
+    /// Can manage admins and other owners.
+    Owner,
+}
+
+impl OrgRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrgRole::ReadOnly => "read_only",
+            OrgRole::Member => "member",
+            OrgRole::Admin => "admin",
+            OrgRole::Owner => "owner",
+        }
+    }
+
+    fn from_db(role: &str) -> Self {
+        match role {
+            "owner" => OrgRole::Owner,
+            "admin" => OrgRole::Admin,
+            "member" => OrgRole::Member,
+            _ => OrgRole::ReadOnly,
+        }
+    }
+}
+
+/// An organization as seen by one of its members.
+pub struct OrganizationRow {
+    pub id: Uuid,
+    pub name: String,
+    pub role: OrgRole,
+    /// Organization key wrapped to the public key of the member.
+    pub wrapped_org_key: Vec<u8>,
+    /// `false` for invites that were not accepted yet.
+    pub accepted: bool,
+}
+
+pub struct MemberRow {
+    pub email: String,
+    pub role: OrgRole,
+    pub accepted: bool,
+}
+
+pub struct CollectionRow {
+    pub id: Uuid,
+    pub org_id: Uuid,
+    /// Name encrypted under the organization key.
+    pub name: Vec<u8>,
+}
+
+fn require_key(wrapped_org_key: &[u8]) -> Result<(), CpassError> {
+    match wrapped_org_key.is_empty() {
+        true => Err(CpassError::InvalidRequest(
+            "wrapped organization key can not be empty".to_string(),
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Role of `user_id` in the organization, `None` unless they accepted an invite.
+async fn member_role(
+    conn: &mut PgConnection,
+    org_id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<OrgRole>, CpassError> {
+    let role = sqlx::query_scalar!(
+        r#"
+        SELECT role FROM org_members
+        WHERE org_id = $1 AND user_id = $2 AND accepted_at IS NOT NULL
+        "#,
+        org_id,
+        user_id
+    )
+    .fetch_optional(conn)
+    .await?;
+
+    Ok(role.as_deref().map(OrgRole::from_db))
+}
+
+/// Check that `user_id` has at least `role` in the organization and return their role.
+pub async fn require_role(
+    conn: &mut PgConnection,
+    org_id: Uuid,
+    user_id: Uuid,
+    role: OrgRole,
+) -> Result<OrgRole, CpassError> {
+    match member_role(conn, org_id, user_id).await? {
+        Some(actual) if actual >= role => Ok(actual),
+        Some(_) => Err(CpassError::Forbidden(format!(
+            "The {} role is required",
+            role.as_str()
+        ))),
+        None => Err(CpassError::NotFound(
+            "Organization with that id not found".to_string(),
+        )),
+    }
+}
+
+/// Create an organization with `user_id` as its only owner.
+pub async fn create_organization(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    name: String,
+    wrapped_org_key: Vec<u8>,
+) -> Result<OrganizationRow, CpassError> {
+    if name.trim().is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "organization name can not be empty".to_string(),
+        ));
+    }
+    require_key(&wrapped_org_key)?;
+
+    let id = sqlx::query_scalar!(
+        r#"
+        WITH org AS (
+            INSERT INTO organizations(name) VALUES ($1)
+            RETURNING id
+        )
+        INSERT INTO org_members(org_id, user_id, role, wrapped_org_key, accepted_at)
+        SELECT id, $2, $3, $4, now() FROM org
+        RETURNING org_id
+        "#,
+        name,
+        user_id,
+        OrgRole::Owner.as_str(),
+        wrapped_org_key
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(OrganizationRow {
+        id,
+        name,
+        role: OrgRole::Owner,
+        wrapped_org_key,
+        accepted: true,
+    })
+}
+
+/// Every organization `user_id` belongs to or is invited to.
+pub async fn list_organizations(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Vec<OrganizationRow>, CpassError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT o.id, o.name, m.role, m.wrapped_org_key, m.accepted_at IS NOT NULL AS "accepted!"
+        FROM org_members m
+        JOIN organizations o ON o.id = m.org_id
+        WHERE m.user_id = $1
+        ORDER BY o.created_at, o.id
+        "#,
+        user_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| OrganizationRow {
+            id: row.id,
+            name: row.name,
+            role: OrgRole::from_db(&row.role),
+            wrapped_org_key: row.wrapped_org_key,
+            accepted: row.accepted,
+        })
+        .collect())
+}
+
+/// Every member and pending invite of an organization, visible to all of its members.
+pub async fn list_members(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    org_id: Uuid,
+) -> Result<Vec<MemberRow>, CpassError> {
+    require_role(conn, org_id, user_id, OrgRole::ReadOnly).await?;
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT u.email, m.role, m.accepted_at IS NOT NULL AS "accepted!"
+        FROM org_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.org_id = $1
+        ORDER BY m.invited_at, u.email
+        "#,
+        org_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| MemberRow {
+            email: row.email,
+            role: OrgRole::from_db(&row.role),
+            accepted: row.accepted,
+        })
+        .collect())
+}
+
+/// Invite `email` with `role`, handing them the organization key wrapped to their public key.
+///
+/// Admins can invite anyone but owners, only owners can invite other owners. Machine
+/// accounts of the caller join right away.
+pub async fn invite_member(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    org_id: Uuid,
+    email: &str,
+    role: OrgRole,
+    wrapped_org_key: Vec<u8>,
+) -> Result<(), CpassError> {
+    require_key(&wrapped_org_key)?;
+
+    let actor = require_role(conn, org_id, user_id, OrgRole::Admin).await?;
+    if role > actor {
+        return Err(CpassError::Forbidden(
+            "Only owners can invite other owners".to_string(),
+        ));
+    }
+
+    let invitee = sqlx::query!(
+        r#"
+        SELECT id, machine_owner_id FROM users
+        WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("User with that email not found".to_string()))?;
+
+    // Machine accounts can not accept invites, their owner adds them directly.
+    let accepted = match invitee.machine_owner_id {
+        Some(owner_id) if owner_id == user_id => true,
+        Some(_) => {
+            return Err(CpassError::Forbidden(
+                "Machine accounts can only be added by their owner".to_string(),
+            ))
+        }
+        None => false,
+    };
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO org_members(org_id, user_id, role, wrapped_org_key, accepted_at)
+        VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN now() END)
+        ON CONFLICT (org_id, user_id) DO NOTHING
+        "#,
+        org_id,
+        invitee.id,
+        role.as_str(),
+        wrapped_org_key,
+        accepted
+    )
+    .execute(conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(CpassError::InvalidRequest(
+            "the user is already a member or invited".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Accept a pending invite of `user_id` to the organization.
+pub async fn accept_invite(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    org_id: Uuid,
+) -> Result<(), CpassError> {
+    let res = sqlx::query!(
+        r#"
+        UPDATE org_members
+        SET accepted_at = now()
+        WHERE org_id = $1 AND user_id = $2 AND accepted_at IS NULL
+        "#,
+        org_id,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(CpassError::NotFound(
+            "Invite to that organization not found".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Remove `email` from the organization, or withdraw their invite.
+///
+/// Everyone can leave or decline an invite, admins can remove members and read-only
+/// members, owners can remove anyone. The last owner can not be removed. The removed
+/// member may still know the organization key, items they could read should be rotated.
+pub async fn remove_member(
+    pool: &PgPool,
+    user_id: Uuid,
+    org_id: Uuid,
+    email: &str,
+) -> Result<(), CpassError> {
+    let mut tx = pool.tx().await?;
+
+    // Serializes membership changes so that two owners can not remove each other.
+    sqlx::query!(
+        r#"
+        SELECT id FROM organizations
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        org_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("Organization with that id not found".to_string()))?;
+
+    let target = sqlx::query!(
+        r#"
+        SELECT m.user_id, m.role, m.accepted_at IS NOT NULL AS "accepted!"
+        FROM org_members m
+        JOIN users u ON u.id = m.user_id
+        WHERE m.org_id = $1 AND u.email = $2
+        "#,
+        org_id,
+        email
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let actor = match &target {
+        Some(target) if target.user_id == user_id => None,
+        _ => Some(require_role(&mut tx, org_id, user_id, OrgRole::Admin).await?),
+    };
+
+    let target = target
+        .ok_or_else(|| CpassError::NotFound("Member with that email not found".to_string()))?;
+    let role = OrgRole::from_db(&target.role);
+
+    if actor.is_some_and(|actor| actor < OrgRole::Owner && role >= OrgRole::Admin) {
+        return Err(CpassError::Forbidden(
+            "Only owners can remove admins and owners".to_string(),
+        ));
+    }
+
+    if target.accepted && role == OrgRole::Owner {
+        let owners = sqlx::query_scalar!(
+            r#"
+            SELECT count(*) AS "count!" FROM org_members
+            WHERE org_id = $1 AND role = $2 AND accepted_at IS NOT NULL
+            "#,
+            org_id,
+            OrgRole::Owner.as_str()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if owners <= 1 {
+            return Err(CpassError::InvalidRequest(
+                "the last owner of an organization can not be removed".to_string(),
+            ));
+        }
+    }
+
+    sqlx::query!(
+        r#"
+        DELETE FROM org_members
+        WHERE org_id = $1 AND user_id = $2
+        "#,
+        org_id,
+        target.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Create a collection, reserved to admins and owners.
+pub async fn create_collection(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    org_id: Uuid,
+    name: Vec<u8>,
+) -> Result<CollectionRow, CpassError> {
+    if name.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "collection name can not be empty".to_string(),
+        ));
+    }
+
+    require_role(conn, org_id, user_id, OrgRole::Admin).await?;
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO collections(org_id, name)
+        VALUES ($1, $2)
+        RETURNING id
+        "#,
+        org_id,
+        name
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(CollectionRow { id, org_id, name })
+}
+
+/// Every collection of an organization, visible to all of its members.
+pub async fn list_collections(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    org_id: Uuid,
+) -> Result<Vec<CollectionRow>, CpassError> {
+    require_role(conn, org_id, user_id, OrgRole::ReadOnly).await?;
+
+    let collections = sqlx::query_as!(
+        CollectionRow,
+        r#"
+        SELECT id, org_id, name FROM collections
+        WHERE org_id = $1
+        ORDER BY created_at, id
+        "#,
+        org_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(collections)
+}
+
+/// Delete a collection together with its items, reserved to admins and owners.
+pub async fn delete_collection(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    collection_id: Uuid,
+) -> Result<(), CpassError> {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM collections
+        WHERE id = $1 AND collection_can(id, $2, 'manage')
+        "#,
+        collection_id,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    if res.rows_affected() > 0 {
+        return Ok(());
+    }
+
+    let visible = sqlx::query_scalar!(
+        r#"
+        SELECT collection_can($1, $2, 'read') AS "visible!"
+        "#,
+        collection_id,
+        user_id
+    )
+    .fetch_one(conn)
+    .await?;
+
+    match visible {
+        true => Err(CpassError::Forbidden(
+            "Only admins can delete collections".to_string(),
+        )),
+        false => Err(CpassError::NotFound(
+            "Collection with that id not found".to_string(),
+        )),
+    }
+}
+
+/// Every live item of a collection, oldest first.
+pub async fn collection_items(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    collection_id: Uuid,
+) -> Result<Vec<PasswordRow>, CpassError> {
+    let visible = sqlx::query_scalar!(
+        r#"
+        SELECT collection_can($1, $2, 'read') AS "visible!"
+        "#,
+        collection_id,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !visible {
+        return Err(CpassError::NotFound(
+            "Collection with that id not found".to_string(),
+        ));
+    }
+
+    let items = sqlx::query_as!(
+        PasswordRow,
+        r#"
+        SELECT id, name, password, website, username, description, revision, item_key,
+            collection_id, created_at, updated_at
+        FROM passwords
+        WHERE collection_id = $1 AND deleted_at IS NULL
+        ORDER BY created_at, id
+        "#,
+        collection_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(items)
+}
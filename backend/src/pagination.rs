@@ -0,0 +1,206 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::CpassError;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Deserialize, ToSchema, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(Deserialize, ToSchema, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    /// Factor applied to the sort key so that descending order becomes ascending.
+    pub fn direction(self) -> i64 {
+        match self {
+            SortOrder::Asc => 1,
+            SortOrder::Desc => -1,
+        }
+    }
+}
+
+/// A live vault item as stored in the database.
+pub struct PasswordRow {
+    pub id: Uuid,
+    pub name: Vec<u8>,
+    pub password: Vec<u8>,
+    pub website: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub description: Option<Vec<u8>>,
+    pub revision: i64,
+    /// Item key wrapped for the caller, see [`crate::sharing`].
+    pub item_key: Option<Vec<u8>>,
+    /// Collection the item belongs to, `None` for items of a personal vault.
+    pub collection_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Opaque cursor pointing right after the last item of a page.
+pub struct PageToken {
+    sort: SortField,
+    order: SortOrder,
+    key: i64,
+    id: Uuid,
+}
+
+impl PageToken {
+    fn after(row: &PasswordRow, sort: SortField, order: SortOrder) -> Self {
+        let at = match sort {
+            SortField::CreatedAt => row.created_at,
+            SortField::UpdatedAt => row.updated_at,
+        };
+
+        Self {
+            sort,
+            order,
+            key: at.timestamp_micros() * order.direction(),
+            id: row.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let sort = match self.sort {
+            SortField::CreatedAt => "c",
+            SortField::UpdatedAt => "u",
+        };
+        let order = match self.order {
+            SortOrder::Asc => "a",
+            SortOrder::Desc => "d",
+        };
+
+        base64::prelude::BASE64_URL_SAFE_NO_PAD
+            .encode(format!("{}:{}:{}:{}", sort, order, self.key, self.id))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, CpassError> {
+        let invalid = || CpassError::InvalidRequest("Invalid page token".to_string());
+
+        let raw = base64::prelude::BASE64_URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+
+        let mut parts = raw.splitn(4, ':');
+        let sort = match parts.next() {
+            Some("c") => SortField::CreatedAt,
+            Some("u") => SortField::UpdatedAt,
+            _ => return Err(invalid()),
+        };
+        let order = match parts.next() {
+            Some("a") => SortOrder::Asc,
+            Some("d") => SortOrder::Desc,
+            _ => return Err(invalid()),
+        };
+        let key = parts
+            .next()
+            .and_then(|key| key.parse().ok())
+            .ok_or_else(invalid)?;
+        let id = parts
+            .next()
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            sort,
+            order,
+            key,
+            id,
+        })
+    }
+}
+
+pub struct Page {
+    pub items: Vec<PasswordRow>,
+    pub next_page_token: Option<String>,
+}
+
+/// Fetch one page of live items of a personal vault using keyset pagination on
+/// `(sort key, id)`.
+pub async fn fetch_page(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    sort: SortField,
+    order: SortOrder,
+    page_size: Option<i64>,
+    page_token: Option<&str>,
+) -> Result<Page, CpassError> {
+    let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(CpassError::InvalidRequest(format!(
+            "page_size must be between 1 and {}",
+            MAX_PAGE_SIZE
+        )));
+    }
+
+    let after = page_token.map(PageToken::decode).transpose()?;
+    if let Some(token) = &after {
+        if token.sort != sort || token.order != order {
+            return Err(CpassError::InvalidRequest(
+                "Page token was issued for a different sort order".to_string(),
+            ));
+        }
+    }
+
+    // Fetch one extra row to know whether another page follows.
+    let mut items = sqlx::query_as!(
+        PasswordRow,
+        r#"
+        SELECT
+            id AS "id!", name AS "name!", password AS "password!", website, username,
+            description, revision AS "revision!", item_key, collection_id,
+            created_at AS "created_at!", updated_at AS "updated_at!"
+        FROM (
+            SELECT *,
+                $3::BIGINT * (EXTRACT(EPOCH FROM
+                    CASE WHEN $2 THEN updated_at ELSE created_at END
+                ) * 1000000)::BIGINT AS sort_key
+            FROM passwords
+            WHERE owner_id = $1 AND collection_id IS NULL AND deleted_at IS NULL
+        ) p
+        WHERE $4::BIGINT IS NULL OR (sort_key, id) > ($4, $5::UUID)
+        ORDER BY sort_key, id
+        LIMIT $6
+        "#,
+        owner_id,
+        sort == SortField::UpdatedAt,
+        order.direction(),
+        after.as_ref().map(|token| token.key),
+        after.as_ref().map(|token| token.id),
+        page_size + 1
+    )
+    .fetch_all(conn)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    let next_page_token = if items.len() as i64 > page_size {
+        items.truncate(page_size as usize);
+        items
+            .last()
+            .map(|row| PageToken::after(row, sort, order).encode())
+    } else {
+        None
+    };
+
+    Ok(Page {
+        items,
+        next_page_token,
+    })
+}
@@ -0,0 +1,65 @@
+use anyhow::Context;
+use lazy_static::lazy_static;
+
+use crate::{
+    error::CpassError,
+    password_rules::{self, DEFAULT_MIN_LENGTH, DEFAULT_MIN_SCORE},
+};
+
+lazy_static! {
+    static ref MIN_LENGTH: usize =
+        min_length().expect("PASSWORD_MIN_LENGTH is checked at startup");
+    static ref MIN_SCORE: u8 = min_score().expect("PASSWORD_MIN_SCORE is checked at startup");
+    /// Reject the passwords of `data/common_passwords.txt.gz`, disabled by
+    /// `PASSWORD_DENYLIST=false`.
+    static ref USE_DENYLIST: bool = dotenvy::var("PASSWORD_DENYLIST")
+        .map(|value| value != "false")
+        .unwrap_or(true);
+}
+
+/// Shortest master password accepted, read from `PASSWORD_MIN_LENGTH`.
+pub fn min_length() -> anyhow::Result<usize> {
+    match dotenvy::var("PASSWORD_MIN_LENGTH") {
+        Ok(length) => length
+            .parse()
+            .context("PASSWORD_MIN_LENGTH must be a number of characters"),
+        Err(_) => Ok(DEFAULT_MIN_LENGTH),
+    }
+}
+
+/// Lowest strength score accepted, from 0 to 4, read from `PASSWORD_MIN_SCORE`.
+pub fn min_score() -> anyhow::Result<u8> {
+    match dotenvy::var("PASSWORD_MIN_SCORE") {
+        Ok(score) => {
+            let score: u8 = score
+                .parse()
+                .context("PASSWORD_MIN_SCORE must be a score from 0 to 4")?;
+            anyhow::ensure!(score <= 4, "PASSWORD_MIN_SCORE must be a score from 0 to 4");
+            Ok(score)
+        }
+        Err(_) => Ok(DEFAULT_MIN_SCORE),
+    }
+}
+
+/// Fail with every rule of the master password policy `password` breaks. `user_inputs`,
+/// like the email address and username, count as words an attacker knows.
+///
+/// Clients deriving the login password from the master password, like the CLI, always
+/// pass, they check the master password with [`password_rules`] before deriving keys.
+pub fn check(password: &str, user_inputs: &[impl AsRef<str>]) -> Result<(), CpassError> {
+    let failed = password_rules::broken_rules(
+        password,
+        user_inputs,
+        *MIN_LENGTH,
+        *MIN_SCORE,
+        *USE_DENYLIST,
+    );
+
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(CpassError::InvalidRequest(format!(
+            "the password does not meet the policy, it needs {}",
+            failed.join("; ")
+        ))),
+    }
+}
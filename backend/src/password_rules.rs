@@ -0,0 +1,248 @@
+use std::{collections::HashMap, io::Read};
+
+use flate2::read::GzDecoder;
+use lazy_static::lazy_static;
+
+pub const DEFAULT_MIN_LENGTH: usize = 12;
+pub const DEFAULT_MIN_SCORE: u8 = 3;
+/// Guesses per character no pattern explains, like zxcvbn.
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+/// Shortest run counted as a dictionary word, sequence or repeat.
+const MIN_PATTERN_LENGTH: usize = 3;
+const MAX_WORD_LENGTH: usize = 32;
+/// Rows of a QWERTY keyboard, walking along one is barely better than a sequence.
+const KEYBOARD_ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+lazy_static! {
+    /// Rank of every common password, the most common first.
+    static ref COMMON_PASSWORDS: HashMap<String, usize> = load_common_passwords();
+}
+
+fn load_common_passwords() -> HashMap<String, usize> {
+    let mut list = String::new();
+    GzDecoder::new(&include_bytes!("../data/common_passwords.txt.gz")[..])
+        .read_to_string(&mut list)
+        .expect("the bundled password list is valid gzip");
+
+    list.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .enumerate()
+        .map(|(rank, password)| (password.to_string(), rank + 1))
+        .collect()
+}
+
+/// Undo the usual letter substitutions, so `p@ssw0rd` is found as `password`.
+fn unleet(password: &str) -> String {
+    password
+        .chars()
+        .map(|c| match c {
+            '4' | '@' => 'a',
+            '8' => 'b',
+            '3' => 'e',
+            '6' | '9' => 'g',
+            '1' | '!' | '|' => 'i',
+            '0' => 'o',
+            '5' | '$' => 's',
+            '7' | '+' => 't',
+            '2' => 'z',
+            c => c,
+        })
+        .collect()
+}
+
+/// Guesses an attacker needs for `run` when it is a pattern, `None` otherwise.
+fn pattern_guesses(run: &[char], lower: &str, unleeted: &str, inputs: &[String]) -> Option<f64> {
+    let length = run.len() as f64;
+    let mut guesses: Option<f64> = None;
+    let mut consider = |candidate: f64| {
+        guesses = Some(guesses.map_or(candidate, |guesses| guesses.min(candidate)));
+    };
+
+    if let Some(rank) = COMMON_PASSWORDS.get(lower) {
+        consider(*rank as f64);
+    }
+    if let Some(rank) = COMMON_PASSWORDS.get(unleeted) {
+        consider(*rank as f64 * 2.0);
+    }
+    if inputs
+        .iter()
+        .any(|input| input == lower || input == unleeted)
+    {
+        consider(1.0);
+    }
+
+    if run.iter().all(|c| *c == run[0]) {
+        consider(BRUTEFORCE_CARDINALITY * length);
+    }
+
+    let deltas: Vec<i64> = run
+        .windows(2)
+        .map(|pair| pair[1] as i64 - pair[0] as i64)
+        .collect();
+    if deltas
+        .iter()
+        .all(|delta| *delta == deltas[0] && delta.abs() == 1)
+    {
+        consider(4.0 * length);
+    }
+
+    let reversed: String = lower.chars().rev().collect();
+    if KEYBOARD_ROWS
+        .iter()
+        .any(|row| row.contains(lower) || row.contains(&reversed))
+    {
+        consider(6.0 * length);
+    }
+
+    guesses
+}
+
+/// Strength of `password` from 0, guessed at once, to 4, out of reach, estimated like
+/// zxcvbn does: the password is split into the dictionary words, `user_inputs`, keyboard
+/// walks, sequences and repeats that make it cheapest to guess, anything else being
+/// guessed character by character.
+fn score(password: &str, user_inputs: &[impl AsRef<str>]) -> u8 {
+    let chars: Vec<char> = password.chars().collect();
+    let inputs: Vec<String> = user_inputs
+        .iter()
+        .flat_map(|input| input.as_ref().split(|c: char| !c.is_alphanumeric()))
+        .filter(|part| part.chars().count() >= MIN_PATTERN_LENGTH)
+        .map(str::to_lowercase)
+        .collect();
+
+    // Fewest guesses, as a power of ten, for the password up to each position.
+    let mut best = vec![f64::INFINITY; chars.len() + 1];
+    best[0] = 0.0;
+
+    for end in 1..=chars.len() {
+        best[end] = best[end - 1] + BRUTEFORCE_CARDINALITY.log10();
+
+        let shortest = end.saturating_sub(MAX_WORD_LENGTH);
+        for start in shortest..=end.saturating_sub(MIN_PATTERN_LENGTH) {
+            let run = &chars[start..end];
+            let lower: String = run.iter().collect::<String>().to_lowercase();
+            let unleeted = unleet(&lower);
+
+            if let Some(guesses) = pattern_guesses(run, &lower, &unleeted, &inputs) {
+                // Every extra pattern makes the attacker try one more way to combine them.
+                let guesses = guesses.max(1.0).log10() + 1.0;
+                best[end] = best[end].min(best[start] + guesses);
+            }
+        }
+    }
+
+    match best[chars.len()] {
+        guesses if guesses < 3.0 => 0,
+        guesses if guesses < 6.0 => 1,
+        guesses if guesses < 8.0 => 2,
+        guesses if guesses < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Every rule of a master password policy `password` breaks, described as what it needs.
+/// `user_inputs`, like the email address and username, count as words an attacker knows.
+///
+/// Only depends on outside crates, the CLI checks a master password with it before deriving
+/// keys from it.
+pub fn broken_rules(
+    password: &str,
+    user_inputs: &[impl AsRef<str>],
+    min_length: usize,
+    min_score: u8,
+    use_denylist: bool,
+) -> Vec<String> {
+    let mut failed = vec![];
+
+    if password.chars().count() < min_length {
+        failed.push(format!("at least {} characters", min_length));
+    }
+    if use_denylist {
+        let lower = password.to_lowercase();
+        if COMMON_PASSWORDS.contains_key(&lower) || COMMON_PASSWORDS.contains_key(&unleet(&lower)) {
+            failed.push("not a commonly used password".to_string());
+        }
+    }
+    let score = score(password, user_inputs);
+    if score < min_score {
+        failed.push(format!(
+            "a strength score of at least {} out of 4, got {}",
+            min_score, score
+        ));
+    }
+
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_INPUTS: [&str; 0] = [];
+
+    #[test]
+    fn common_passwords_score_low() {
+        for password in ["password", "123456", "iloveyou", "Password1", "qwerty123"] {
+            assert!(score(password, &NO_INPUTS) <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn leet_passwords_score_low() {
+        for password in ["p@ssw0rd", "P4$$w0rd", "dr4g0n", "m0nk3y"] {
+            assert!(score(password, &NO_INPUTS) <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn keyboard_walks_score_low() {
+        for password in [
+            "qwertyuiop",
+            "asdfghjkl",
+            "poiuytrewq",
+            "1234567890",
+            "zxcvbnm",
+        ] {
+            assert!(score(password, &NO_INPUTS) <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn sequences_and_repeats_score_low() {
+        for password in ["abcdefghij", "aaaaaaaaaaaa", "9876543210"] {
+            assert!(score(password, &NO_INPUTS) <= 1, "{}", password);
+        }
+    }
+
+    #[test]
+    fn user_inputs_score_low() {
+        let inputs = ["alice.wonder@example.com", "wonderland"];
+
+        assert!(score("wonderland", &inputs) < score("wonderland", &NO_INPUTS));
+        assert!(score("alicewonder", &inputs) <= 1);
+    }
+
+    #[test]
+    fn random_passphrase_scores_high() {
+        for password in [
+            "correct-horse-battery-staple-9",
+            "Wobbly-Kettle-Horizon-42",
+            "tX7#qLm9$vR2pW",
+        ] {
+            assert!(score(password, &NO_INPUTS) >= 3, "{}", password);
+        }
+    }
+
+    #[test]
+    fn broken_rules_are_all_listed() {
+        assert_eq!(
+            broken_rules("password", &NO_INPUTS, 12, 3, true),
+            [
+                "at least 12 characters",
+                "not a commonly used password",
+                "a strength score of at least 3 out of 4, got 0",
+            ]
+        );
+        assert!(broken_rules("Wobbly-Kettle-Horizon-42", &NO_INPUTS, 12, 3, true).is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use axum::http::HeaderMap;
+use sqlx::PgPool;
+
+use crate::{
+    error::CpassError,
+    jwt::{models::Principal, session::authenticate},
+    verification,
+};
+
+/// What a session or access token may be used for. Sessions get every scope unless the
+/// login asked for fewer, access tokens only what they were created with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Manage the account itself: profile, keys, recovery, machine accounts and access
+    /// tokens. Never granted to access tokens.
+    Account,
+    /// Read items and the key material unwrapping them.
+    VaultRead,
+    /// Add, update, trash and restore items.
+    VaultWrite,
+    /// Purge and share items and rotate the vault key.
+    VaultManage,
+    /// See organizations, their members and collections.
+    OrgRead,
+    /// Create organizations and collections and manage their members.
+    OrgManage,
+    /// Act as or appoint an emergency contact.
+    Emergency,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 7] = [
+        Scope::Account,
+        Scope::VaultRead,
+        Scope::VaultWrite,
+        Scope::VaultManage,
+        Scope::OrgRead,
+        Scope::OrgManage,
+        Scope::Emergency,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Account => "account",
+            Scope::VaultRead => "vault:read",
+            Scope::VaultWrite => "vault:write",
+            Scope::VaultManage => "vault:manage",
+            Scope::OrgRead => "org:read",
+            Scope::OrgManage => "org:manage",
+            Scope::Emergency => "emergency",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Result<Self, CpassError> {
+        Scope::ALL
+            .into_iter()
+            .find(|known| known.as_str() == scope)
+            .ok_or_else(|| CpassError::InvalidRequest(format!("unknown scope {}", scope)))
+    }
+
+    pub fn parse_all(scopes: &[String]) -> Result<Vec<Self>, CpassError> {
+        scopes.iter().map(|scope| Scope::parse(scope)).collect()
+    }
+
+    /// Scopes a login asks its session to be narrowed to, every scope when none.
+    pub fn requested(scopes: &[String]) -> Result<Vec<Self>, CpassError> {
+        match scopes.is_empty() {
+            true => Ok(Scope::ALL.to_vec()),
+            false => Scope::parse_all(scopes),
+        }
+    }
+}
+
+/// Requirement a route places on its caller.
+#[derive(Clone, Copy, Debug)]
+pub enum Permission {
+    /// Anyone, signed in or not.
+    Public,
+    /// Any session or access token, whatever its scopes.
+    Authenticated,
+    /// A session or an unrestricted access token granted the scope.
+    Scope(Scope),
+    /// Like `Scope`, but also open to access tokens restricted to collections. The handler
+    /// keeps those to their collections.
+    Restricted(Scope),
+    /// Like `Scope`, once the email address of the caller is verified.
+    Verified(Scope),
+}
+
+/// Permission of `route` in a policy table. Anything not listed is denied.
+pub fn permission_of(table: &[(&str, Permission)], route: &str) -> Result<Permission, CpassError> {
+    table
+        .iter()
+        .find(|(listed, _)| *listed == route)
+        .map(|(_, permission)| *permission)
+        .ok_or_else(|| {
+            CpassError::Forbidden("no authorization policy for this operation".to_string())
+        })
+}
+
+/// Check the caller of a request against `permission`, returning who it is unless the
+/// route is public.
+pub async fn check(
+    pool: &PgPool,
+    permission: Permission,
+    headers: &HeaderMap,
+) -> Result<Option<Principal>, CpassError> {
+    let (scope, restricted, verified) = match permission {
+        Permission::Public => return Ok(None),
+        Permission::Authenticated => (None, true, false),
+        Permission::Scope(scope) => (Some(scope), false, false),
+        Permission::Restricted(scope) => (Some(scope), true, false),
+        Permission::Verified(scope) => (Some(scope), false, true),
+    };
+
+    let principal = authenticate(pool, headers).await?;
+
+    if let Some(scope) = scope {
+        if !principal.scopes.contains(&scope) {
+            return Err(CpassError::Forbidden(format!(
+                "missing the {} scope",
+                scope.as_str()
+            )));
+        }
+    }
+    if principal.collections.is_some() && !restricted {
+        return Err(CpassError::Forbidden(
+            "access token is restricted to collections".to_string(),
+        ));
+    }
+    if verified {
+        verification::require_verified(pool, principal.sub).await?;
+    }
+
+    Ok(Some(principal))
+}
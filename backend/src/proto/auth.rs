@@ -1,12 +1,29 @@
+use chrono::{DateTime, Utc};
+
 use crate::{
+    account,
+    audit::{self, AuditEventRow, AuditQuery, Event, EventType},
     db::Db,
+    device::{self, DeviceLogin, DeviceRow},
     error::CpassError,
     hashing::Argon,
-    jwt::generate::{claims_from_headers, create_token},
+    jwt::session,
+    password_policy,
+    policy::Scope,
     proto::{
-        auth_proto::{auth_server::Auth, CreateUserRequest, LoginRequest, UpdateUserRequest, User},
-        types::Empty,
+        auth_proto::{
+            auth_server::Auth, AuditEvent, AuditEvents, CancelDeletionRequest,
+            ChangeMasterPasswordRequest, CreateUserRequest, DeleteUserRequest, DeleteUserResponse,
+            Device, DeviceApproval, Devices, KeyPair, Keys, ListAuditEventsRequest, LoginRequest,
+            PublicKey, PublicKeyRequest, RecoverAccountRequest, RecoveryCredentials, RecoveryKey,
+            RecoveryVaultKey, UpdateUserRequest, User, VerifyEmailRequest,
+        },
+        auth_user, origin,
+        types::{Empty, Uuid},
     },
+    recovery,
+    validation::Validate,
+    verification,
 };
 use sqlx::PgPool;
 use tonic::{Request, Response, Status};
@@ -21,11 +38,67 @@ impl AuthService {
     }
 }
 
+impl From<DeviceRow> for Device {
+    fn from(row: DeviceRow) -> Self {
+        Device {
+            uuid: row.id.into(),
+            name: row.name,
+            device_type: row.device_type,
+            approved_at: row.approved_at.map(|at| at.timestamp()),
+            first_seen_at: row.first_seen_at.timestamp(),
+            last_seen_at: row.last_seen_at.timestamp(),
+            last_ip: row.last_ip,
+            user_agent: row.user_agent,
+            current: row.current,
+        }
+    }
+}
+
+impl From<AuditEventRow> for AuditEvent {
+    fn from(row: AuditEventRow) -> Self {
+        AuditEvent {
+            seq: row.seq,
+            occurred_at: row.occurred_at.timestamp(),
+            event_type: row.event_type,
+            actor_id: row.actor_id.map(|id| id.into()),
+            session_id: row.session_id.map(|id| id.into()),
+            item_id: row.item_id.map(|id| id.into()),
+            ip: row.ip,
+            user_agent: row.user_agent,
+            detail: row.detail,
+            hash: row.hash,
+        }
+    }
+}
+
+fn parse_uuid(uuid: &[u8]) -> Result<uuid::Uuid, CpassError> {
+    uuid::Uuid::from_slice(uuid)
+        .map_err(|_| CpassError::InvalidRequest("Can not parse string as uuid.".to_string()))
+}
+
+fn timestamp(at: Option<i64>, field: &str) -> Result<Option<DateTime<Utc>>, CpassError> {
+    at.map(|at| {
+        DateTime::from_timestamp(at, 0)
+            .ok_or_else(|| CpassError::InvalidRequest(format!("{} is out of range", field)))
+    })
+    .transpose()
+}
+
 #[tonic::async_trait]
 impl Auth for AuthService {
     async fn login(&self, request: Request<LoginRequest>) -> Result<Response<User>, Status> {
-        let mut conn = self.pool.conn().await?;
-        let LoginRequest { email, password } = request.get_ref();
+        request.get_ref().validate()?;
+        let origin = origin(&request);
+        let mut tx = self.pool.tx().await?;
+        let LoginRequest {
+            email,
+            password,
+            scopes,
+            device_token,
+            device_name,
+            device_type,
+        } = request.into_inner();
+        let scopes = Scope::requested(&scopes)?;
 
         let user = sqlx::query!(
             r#"
@@ -33,25 +106,66 @@ impl Auth for AuthService {
             "#,
             email
         )
-        .fetch_one(&mut *conn)
+        .fetch_optional(&mut *tx)
         .await
-        .map_err(|err| match err {
-            sqlx::Error::RowNotFound => CpassError::InvalidUsernameOrPassword,
-            err => CpassError::DatabaseError(err),
-        })?;
+        .map_err(CpassError::DatabaseError)?;
+
+        // Machine accounts have no password and never log in.
+        let verified = match user.as_ref().and_then(|user| user.password.as_ref()) {
+            Some(hash) => Argon::verify(password.as_bytes(), hash)?,
+            None => false,
+        };
+        let user = match (user, verified) {
+            (Some(user), true) => user,
+            (user, _) => {
+                let event = Event::of(EventType::LoginFailed, user.map(|user| user.id));
+                audit::record(&mut tx, &origin, event.detail(email)).await?;
+                tx.commit().await.map_err(CpassError::DatabaseError)?;
+                return Err(CpassError::InvalidUsernameOrPassword.into());
+            }
+        };
+
+        account::check_not_scheduled(user.deletion_scheduled_at)?;
 
-        match Argon::verify(password.as_bytes(), &user.password) {
-            Ok(false) => return Err(CpassError::InvalidUsernameOrPassword.into()),
-            Err(e) => return Err(e.into()),
-            _ => {}
+        if let Some(hash) = &user.password {
+            account::upgrade_password_hash(&mut tx, user.id, hash, &password).await?;
         }
 
-        let token = create_token(&user.id)?;
+        let login = DeviceLogin {
+            token: device_token,
+            name: device_name,
+            device_type,
+        };
+        let device = device::record_login(&mut tx, user.id, login, &origin).await?;
+        let token = match device.approved {
+            true => Some(session::start(&mut tx, user.id, Some(device.id), &scopes).await?),
+            false => None,
+        };
+
+        if device.new {
+            let event = Event::of(EventType::NewDeviceLogin, Some(user.id)).detail(device.name);
+            audit::record(&mut tx, &origin, event).await?;
+        }
+        if token.is_some() {
+            let event = Event::of(EventType::Login, Some(user.id));
+            audit::record(&mut tx, &origin, event).await?;
+        }
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        let Some(token) = token else {
+            return Err(CpassError::Forbidden(
+                "this device waits for approval from another device of the account".to_string(),
+            )
+            .into());
+        };
 
         let user = User {
             token,
             email: user.email,
             username: user.username,
+            vault_key: user.vault_key,
+            public_key: user.public_key,
+            wrapped_private_key: user.wrapped_private_key,
         };
 
         let response = Response::new(user);
@@ -63,26 +177,40 @@ impl Auth for AuthService {
         &self,
         request: Request<CreateUserRequest>,
     ) -> Result<Response<User>, Status> {
-        let mut conn = self.pool.conn().await?;
+        request.get_ref().validate()?;
+        let origin = origin(&request);
+        let mut tx = self.pool.tx().await?;
         let CreateUserRequest {
             email,
             username,
             password,
+            vault_key,
+            recovery,
         } = request.get_ref().to_owned();
 
+        verification::validate_email(&email)?;
+        password_policy::check(&password, &[&email, &username])?;
         let hash = Argon::hash_password(password.as_bytes())?;
+        let recovery = recovery
+            .map(|key| recovery::RecoveryKey::new(&key.secret, key.vault_key))
+            .transpose()?;
+        let (recovery_secret, recovery_vault_key) =
+            recovery.map(|key| (key.secret_hash, key.vault_key)).unzip();
 
         let res = sqlx::query!(
             r#"
-            INSERT INTO users(email, username, password)
-            VALUES ($1, $2, $3)
+            INSERT INTO users(email, username, password, vault_key, recovery_secret, recovery_vault_key)
+            VALUES ($1, $2, $3, $4, $5, $6)
             RETURNING id
             "#,
             email,
             username,
-            hash
+            hash,
+            vault_key,
+            recovery_secret,
+            recovery_vault_key
         )
-        .fetch_one(&mut *conn)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|err| match err {
             sqlx::Error::Database(err) if err.is_unique_violation() => {
@@ -91,12 +219,24 @@ impl Auth for AuthService {
             err => CpassError::DatabaseError(err),
         })?;
 
-        let token = create_token(&res.id)?;
+        verification::start(&mut tx, res.id, &email).await?;
+        let token = session::start(&mut tx, res.id, None, &Scope::ALL).await?;
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::of(EventType::UserCreated, Some(res.id)),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
 
         Ok(Response::new(User {
             token,
             email,
             username,
+            vault_key,
+            public_key: None,
+            wrapped_private_key: None,
         }))
     }
 
@@ -104,60 +244,464 @@ impl Auth for AuthService {
         &self,
         request: Request<UpdateUserRequest>,
     ) -> Result<Response<Empty>, Status> {
-        let mut conn = self.pool.conn().await?;
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
         let UpdateUserRequest {
             email,
             username,
+            current_password,
             password,
+            vault_key,
         } = request.get_ref().to_owned();
 
-        let user_id = claims_from_headers(request.metadata())?.sub;
+        if let Some(email) = &email {
+            // Validation requires the keys with an email address.
+            let (Some(current_password), Some(password), Some(vault_key)) =
+                (current_password, password, vault_key)
+            else {
+                return Err(CpassError::InvalidRequest(
+                    "an email change needs the keys derived with the new address".to_string(),
+                )
+                .into());
+            };
+            let [_, current_username] = account::user_inputs(&mut tx, user_id).await?;
+            password_policy::check(&password, &[email, &current_username])?;
+            let hash = Argon::hash_password(password.as_bytes())?;
 
-        let password = password
-            .map(|pass| Argon::hash_password(pass.as_bytes()))
-            .transpose()?;
+            account::change_email(
+                &mut tx,
+                user_id,
+                email,
+                &current_password,
+                &hash,
+                &vault_key,
+            )
+            .await?;
+        }
 
         let _ = sqlx::query!(
             r#"
             UPDATE users
-            SET
-                email = COALESCE($1, email),
-                username = COALESCE($2, username),
-                password = COALESCE($3, password)
-            WHERE id = $4
+            SET username = COALESCE($1, username)
+            WHERE id = $2
             "#,
-            email,
             username,
-            password,
             user_id
         )
-        .execute(&mut *conn)
+        .execute(&mut *tx)
         .await
-        .map_err(|err| match err {
-            sqlx::Error::Database(err) if err.is_unique_violation() => {
-                CpassError::UserAlreadyExists(email.unwrap())
-            }
-            err => CpassError::DatabaseError(err),
-        })?;
+        .map_err(CpassError::DatabaseError)?;
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::UserUpdated, &principal),
+        )
+        .await?;
+        if let Some(email) = email {
+            let event = Event::by(EventType::EmailChangeRequested, &principal).detail(email);
+            audit::record(&mut tx, &origin, event).await?;
+        }
+
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn delete_user(
+        &self,
+        request: Request<DeleteUserRequest>,
+    ) -> Result<Response<DeleteUserResponse>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let DeleteUserRequest { password } = request.get_ref();
+
+        let scheduled = account::request_deletion(&mut tx, user_id, password).await?;
+
+        let event_type = match scheduled {
+            Some(_) => EventType::DeletionScheduled,
+            None => EventType::AccountDeleted,
+        };
+        audit::record(&mut tx, &origin, Event::by(event_type, &principal)).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(match scheduled {
+            Some(scheduled) => DeleteUserResponse {
+                deletes_at: Some(scheduled.deletes_at.timestamp()),
+                cancel_token: Some(scheduled.cancel_token),
+            },
+            None => DeleteUserResponse {
+                deletes_at: None,
+                cancel_token: None,
+            },
+        }))
+    }
+
+    async fn cancel_deletion(
+        &self,
+        request: Request<CancelDeletionRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let origin = origin(&request);
+        let mut tx = self.pool.tx().await?;
+        let CancelDeletionRequest { cancel_token } = request.get_ref();
+
+        let user_id = account::cancel_deletion(&mut tx, cancel_token).await?;
+
+        let event = Event::of(EventType::DeletionCancelled, Some(user_id));
+        audit::record(&mut tx, &origin, event).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn verify_email(
+        &self,
+        request: Request<VerifyEmailRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let origin = origin(&request);
+        let mut tx = self.pool.tx().await?;
+        let VerifyEmailRequest { token } = request.get_ref();
+
+        let user_id = verification::verify(&mut tx, token).await?;
+
+        let event = Event::of(EventType::EmailVerified, Some(user_id));
+        audit::record(&mut tx, &origin, event).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
 
         Ok(Response::new(Empty {}))
     }
 
-    async fn delete_user(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+    async fn resend_verification(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
         let mut conn = self.pool.conn().await?;
 
-        let user_id = claims_from_headers(request.metadata())?.sub;
+        verification::resend(&mut conn, user_id).await?;
 
-        let _ = sqlx::query!(
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn set_key_pair(&self, request: Request<KeyPair>) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let KeyPair {
+            public_key,
+            wrapped_private_key,
+        } = request.get_ref();
+
+        if public_key.is_empty() || wrapped_private_key.is_empty() {
+            return Err(Status::invalid_argument(
+                "public_key and wrapped_private_key can not be empty",
+            ));
+        }
+
+        sqlx::query!(
             r#"
-            DELETE FROM users
+            UPDATE users
+            SET public_key = $1, wrapped_private_key = $2
+            WHERE id = $3
+            "#,
+            public_key,
+            wrapped_private_key,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(CpassError::DatabaseError)?;
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::KeyPairSet, &principal),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn get_keys(&self, request: Request<Empty>) -> Result<Response<Keys>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let keys = sqlx::query_as!(
+            Keys,
+            r#"
+            SELECT vault_key, public_key, wrapped_private_key FROM users
             WHERE id = $1
             "#,
             user_id
         )
-        .execute(&mut *conn)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(CpassError::DatabaseError)?
+        .ok_or_else(|| Status::not_found("User not found"))?;
+
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::KeysRead, &principal),
+        )
+        .await?;
+
+        Ok(Response::new(keys))
+    }
+
+    async fn get_public_key(
+        &self,
+        request: Request<PublicKeyRequest>,
+    ) -> Result<Response<PublicKey>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let mut conn = self.pool.conn().await?;
+        let PublicKeyRequest { email } = request.get_ref();
+
+        let public_key = sqlx::query_scalar!(
+            r#"
+            SELECT public_key FROM users
+            WHERE email = $1
+            "#,
+            email
+        )
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(CpassError::DatabaseError)?
+        .flatten()
+        .ok_or_else(|| Status::not_found("User with that email has no public key"))?;
+
+        let event = Event::by(EventType::PublicKeyRead, &principal).detail(email);
+        audit::record(&mut conn, &origin, event).await?;
+
+        Ok(Response::new(PublicKey { public_key }))
+    }
+
+    async fn set_recovery_key(
+        &self,
+        request: Request<RecoveryKey>,
+    ) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let RecoveryKey { secret, vault_key } = request.into_inner();
+
+        let recovery = recovery::RecoveryKey::new(&secret, vault_key)?;
+        recovery::set_recovery_key(&mut tx, user_id, recovery).await?;
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::RecoveryKeySet, &principal),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn get_recovery_vault_key(
+        &self,
+        request: Request<RecoveryCredentials>,
+    ) -> Result<Response<RecoveryVaultKey>, Status> {
+        request.get_ref().validate()?;
+        let origin = origin(&request);
+        let mut conn = self.pool.conn().await?;
+        let RecoveryCredentials { email, secret } = request.get_ref();
+
+        let (user_id, vault_key) =
+            match recovery::recovery_vault_key(&mut conn, email, secret).await {
+                Ok(recovered) => recovered,
+                Err(err) => {
+                    let event = Event::of(EventType::RecoveryFailed, None).detail(email);
+                    audit::record(&mut conn, &origin, event).await?;
+                    return Err(err.into());
+                }
+            };
+
+        let event = Event::of(EventType::RecoveryVaultKeyRead, Some(user_id));
+        audit::record(&mut conn, &origin, event).await?;
+
+        Ok(Response::new(RecoveryVaultKey { vault_key }))
+    }
+
+    async fn recover_account(
+        &self,
+        request: Request<RecoverAccountRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let origin = origin(&request);
+        let RecoverAccountRequest {
+            email,
+            secret,
+            password,
+            vault_key,
+            recovery,
+        } = request.into_inner();
+
+        let recovery =
+            recovery.ok_or_else(|| Status::invalid_argument("a new recovery key is required"))?;
+        let recovery = recovery::RecoveryKey::new(&recovery.secret, recovery.vault_key)?;
+
+        let mut tx = self.pool.tx().await?;
+
+        let user_id = match recovery::recover_account(
+            &mut tx, &email, &secret, &password, vault_key, recovery,
+        )
         .await
-        .map_err(CpassError::DatabaseError);
+        {
+            Ok(user_id) => user_id,
+            Err(err @ CpassError::InvalidUsernameOrPassword) => {
+                drop(tx);
+                let mut conn = self.pool.conn().await?;
+                let event = Event::of(EventType::RecoveryFailed, None).detail(email);
+                audit::record(&mut conn, &origin, event).await?;
+                return Err(err.into());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let event = Event::of(EventType::AccountRecovered, Some(user_id));
+        audit::record(&mut tx, &origin, event).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn change_master_password(
+        &self,
+        request: Request<ChangeMasterPasswordRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let ChangeMasterPasswordRequest {
+            current_password,
+            password,
+            vault_key,
+        } = request.into_inner();
+
+        let mut tx = self.pool.tx().await?;
+
+        let user_inputs = account::user_inputs(&mut tx, user_id).await?;
+        password_policy::check(&password, &user_inputs)?;
+        let hash = Argon::hash_password(password.as_bytes())?;
+
+        account::change_master_password(&mut tx, user_id, &current_password, hash, vault_key)
+            .await?;
+
+        let event = Event::by(EventType::MasterPasswordChanged, &principal);
+        audit::record(&mut tx, &origin, event).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn list_audit_events(
+        &self,
+        request: Request<ListAuditEventsRequest>,
+    ) -> Result<Response<AuditEvents>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let ListAuditEventsRequest {
+            since,
+            until,
+            event_types,
+            before,
+            limit,
+        } = request.into_inner();
+
+        let query = AuditQuery {
+            since: timestamp(since, "since")?,
+            until: timestamp(until, "until")?,
+            event_types: EventType::parse_all(&event_types)?,
+            before,
+            limit,
+        };
+
+        let events = audit::list_events(&self.pool, user_id, query)
+            .await?
+            .into_iter()
+            .map(AuditEvent::from)
+            .collect();
+
+        Ok(Response::new(AuditEvents { events }))
+    }
+
+    async fn list_devices(&self, request: Request<Empty>) -> Result<Response<Devices>, Status> {
+        let principal = auth_user(&request)?;
+        let mut conn = self.pool.conn().await?;
+
+        let devices = device::list(&mut conn, &principal)
+            .await?
+            .into_iter()
+            .map(Device::from)
+            .collect();
+
+        Ok(Response::new(Devices { devices }))
+    }
+
+    async fn remove_device(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let mut tx = self.pool.tx().await?;
+        let device_id = parse_uuid(&request.get_ref().uuid)?;
+
+        let name = device::remove(&mut tx, principal.sub, device_id).await?;
+
+        let event = Event::by(EventType::DeviceRemoved, &principal).detail(name);
+        audit::record(&mut tx, &origin, event).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn approve_device(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let mut tx = self.pool.tx().await?;
+        let device_id = parse_uuid(&request.get_ref().uuid)?;
+
+        let name = device::approve(&mut tx, &principal, device_id).await?;
+
+        let event = Event::by(EventType::DeviceApproved, &principal).detail(name);
+        audit::record(&mut tx, &origin, event).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn set_device_approval(
+        &self,
+        request: Request<DeviceApproval>,
+    ) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let mut tx = self.pool.tx().await?;
+        let DeviceApproval { required } = request.into_inner();
+
+        device::set_approval(&mut tx, principal.sub, required).await?;
+
+        let detail = match required {
+            true => "required",
+            false => "not required",
+        };
+        let event = Event::by(EventType::DeviceApprovalChanged, &principal).detail(detail);
+        audit::record(&mut tx, &origin, event).await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
 
         Ok(Response::new(Empty {}))
     }
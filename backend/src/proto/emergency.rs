@@ -0,0 +1,235 @@
+use crate::{
+    account,
+    db::Db,
+    emergency::{self, ContactRow},
+    error::CpassError,
+    hashing::Argon,
+    password_policy,
+    proto::{
+        auth_user,
+        emergency_proto::{
+            emergency_server::Emergency, AddEmergencyContactRequest, EmergencyAccess,
+            EmergencyContact, EmergencyContacts, EmergencyStatus, TakeoverAccountRequest,
+        },
+        types::{Empty, Uuid},
+    },
+    validation::Validate,
+};
+use sqlx::PgPool;
+use tonic::{Request, Response, Status};
+
+pub struct EmergencyService {
+    pool: PgPool,
+}
+
+impl EmergencyService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl From<EmergencyAccess> for emergency::EmergencyAccess {
+    fn from(access: EmergencyAccess) -> Self {
+        match access {
+            EmergencyAccess::Read => emergency::EmergencyAccess::Read,
+            EmergencyAccess::Takeover => emergency::EmergencyAccess::Takeover,
+        }
+    }
+}
+
+impl From<emergency::EmergencyAccess> for EmergencyAccess {
+    fn from(access: emergency::EmergencyAccess) -> Self {
+        match access {
+            emergency::EmergencyAccess::Read => EmergencyAccess::Read,
+            emergency::EmergencyAccess::Takeover => EmergencyAccess::Takeover,
+        }
+    }
+}
+
+impl From<emergency::EmergencyStatus> for EmergencyStatus {
+    fn from(status: emergency::EmergencyStatus) -> Self {
+        match status {
+            emergency::EmergencyStatus::Invited => EmergencyStatus::Invited,
+            emergency::EmergencyStatus::Confirmed => EmergencyStatus::Confirmed,
+            emergency::EmergencyStatus::Requested => EmergencyStatus::Requested,
+            emergency::EmergencyStatus::Approved => EmergencyStatus::Approved,
+        }
+    }
+}
+
+impl From<ContactRow> for EmergencyContact {
+    fn from(row: ContactRow) -> Self {
+        EmergencyContact {
+            uuid: row.id.into(),
+            grantor_email: row.grantor_email,
+            grantee_email: row.grantee_email,
+            access: EmergencyAccess::from(row.access).into(),
+            wait_days: row.wait_days,
+            status: EmergencyStatus::from(row.status).into(),
+            has_vault_key: row.has_vault_key,
+            requested_at: row.requested_at.map(|at| at.timestamp()),
+            approves_at: row.approves_at.map(|at| at.timestamp()),
+            approved_at: row.approved_at.map(|at| at.timestamp()),
+        }
+    }
+}
+
+fn parse_uuid(uuid: &[u8]) -> Result<uuid::Uuid, CpassError> {
+    uuid::Uuid::from_slice(uuid)
+        .map_err(|_| CpassError::InvalidRequest("Can not parse string as uuid.".to_string()))
+}
+
+#[tonic::async_trait]
+impl Emergency for EmergencyService {
+    async fn add_emergency_contact(
+        &self,
+        request: Request<AddEmergencyContactRequest>,
+    ) -> Result<Response<EmergencyContact>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let access = request.get_ref().access().into();
+        let AddEmergencyContactRequest {
+            email,
+            wait_days,
+            wrapped_vault_key,
+            ..
+        } = request.into_inner();
+
+        let contact = emergency::add_contact(
+            &mut conn,
+            user_id,
+            &email,
+            access,
+            wait_days,
+            wrapped_vault_key,
+        )
+        .await?;
+
+        Ok(Response::new(contact.into()))
+    }
+
+    async fn list_emergency_contacts(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<EmergencyContacts>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let contacts = emergency::list_contacts(&mut conn, user_id)
+            .await?
+            .into_iter()
+            .map(EmergencyContact::from)
+            .collect();
+
+        Ok(Response::new(EmergencyContacts { contacts }))
+    }
+
+    async fn list_emergency_grants(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<EmergencyContacts>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let contacts = emergency::list_grants(&mut conn, user_id)
+            .await?
+            .into_iter()
+            .map(EmergencyContact::from)
+            .collect();
+
+        Ok(Response::new(EmergencyContacts { contacts }))
+    }
+
+    async fn accept_emergency_invite(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let id = parse_uuid(&request.get_ref().uuid)?;
+
+        emergency::accept_invite(&mut conn, user_id, id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn remove_emergency_contact(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let id = parse_uuid(&request.get_ref().uuid)?;
+
+        emergency::remove_contact(&mut conn, user_id, id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn request_emergency_access(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<EmergencyContact>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let id = parse_uuid(&request.get_ref().uuid)?;
+
+        let contact = emergency::request_access(&mut conn, user_id, id).await?;
+
+        Ok(Response::new(contact.into()))
+    }
+
+    async fn approve_emergency_access(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let id = parse_uuid(&request.get_ref().uuid)?;
+
+        emergency::approve_access(&mut conn, user_id, id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn reject_emergency_access(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let id = parse_uuid(&request.get_ref().uuid)?;
+
+        emergency::reject_access(&mut conn, user_id, id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn takeover_account(
+        &self,
+        request: Request<TakeoverAccountRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let TakeoverAccountRequest {
+            uuid,
+            password,
+            vault_key,
+        } = request.into_inner();
+
+        let id = parse_uuid(&uuid)?;
+
+        let mut conn = self.pool.conn().await?;
+        let grantor_id = emergency::takeover_grantor(&mut conn, user_id, id).await?;
+        let user_inputs = account::user_inputs(&mut conn, grantor_id).await?;
+        drop(conn);
+
+        password_policy::check(&password, &user_inputs)?;
+        let hash = Argon::hash_password(password.as_bytes())?;
+
+        emergency::takeover(&self.pool, user_id, id, hash, vault_key).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+}
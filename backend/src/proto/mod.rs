@@ -1,18 +1,270 @@
-use tonic::include_file_descriptor_set;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use sqlx::PgPool;
+use tonic::{
+    body::BoxBody,
+    codegen::http::{header::USER_AGENT, Request, Response},
+    include_file_descriptor_set,
+    transport::server::TcpConnectInfo,
+    Status,
+};
+use tower::{Layer, Service};
+
+use crate::{
+    audit::Origin,
+    error::CpassError,
+    jwt::models::Principal,
+    policy::{
+        self,
+        Permission::{self, Authenticated, Public, Restricted, Scope, Verified},
+        Scope::*,
+    },
+};
 
 pub mod auth;
+pub mod emergency;
+pub mod notify;
+pub mod org;
 pub mod pass;
+pub mod token;
+mod validation;
 
 pub mod auth_proto {
     tonic::include_proto!("auth");
 }
 
+pub mod emergency_proto {
+    tonic::include_proto!("emergency");
+}
+
+pub mod notify_proto {
+    tonic::include_proto!("notify");
+}
+
+pub mod org_proto {
+    tonic::include_proto!("org");
+}
+
 pub mod pass_proto {
     tonic::include_proto!("pass");
 }
 
+pub mod token_proto {
+    tonic::include_proto!("token");
+}
+
 pub mod types {
     tonic::include_proto!("types");
 }
 
 pub(crate) const FILE_DESCRIPTOR_SET: &[u8] = include_file_descriptor_set!("cpass_descriptor");
+
+/// Permission of every gRPC method, keyed by its path.
+const POLICY: &[(&str, Permission)] = &[
+    // Auth
+    ("/auth.Auth/Login", Public),
+    ("/auth.Auth/CreateUser", Public),
+    ("/auth.Auth/UpdateUser", Scope(Account)),
+    ("/auth.Auth/DeleteUser", Scope(Account)),
+    ("/auth.Auth/CancelDeletion", Public),
+    ("/auth.Auth/VerifyEmail", Public),
+    ("/auth.Auth/ResendVerification", Scope(Account)),
+    ("/auth.Auth/SetKeyPair", Scope(Account)),
+    ("/auth.Auth/GetKeys", Restricted(VaultRead)),
+    ("/auth.Auth/GetPublicKey", Authenticated),
+    ("/auth.Auth/SetRecoveryKey", Scope(Account)),
+    ("/auth.Auth/GetRecoveryVaultKey", Public),
+    ("/auth.Auth/RecoverAccount", Public),
+    ("/auth.Auth/ChangeMasterPassword", Scope(Account)),
+    ("/auth.Auth/ListAuditEvents", Scope(Account)),
+    ("/auth.Auth/ListDevices", Scope(Account)),
+    ("/auth.Auth/RemoveDevice", Scope(Account)),
+    ("/auth.Auth/ApproveDevice", Scope(Account)),
+    ("/auth.Auth/SetDeviceApproval", Scope(Account)),
+    // Pass
+    ("/pass.Pass/GetPassword", Restricted(VaultRead)),
+    ("/pass.Pass/GetPasswords", Scope(VaultRead)),
+    ("/pass.Pass/ListPasswords", Scope(VaultRead)),
+    ("/pass.Pass/AddPassword", Restricted(VaultWrite)),
+    ("/pass.Pass/UpdatePassword", Restricted(VaultWrite)),
+    ("/pass.Pass/DeletePassword", Restricted(VaultWrite)),
+    ("/pass.Pass/ListTrash", Scope(VaultRead)),
+    ("/pass.Pass/RestoreItem", Scope(VaultWrite)),
+    ("/pass.Pass/PurgeItem", Scope(VaultManage)),
+    ("/pass.Pass/Sync", Scope(VaultRead)),
+    ("/pass.Pass/StreamPasswords", Scope(VaultRead)),
+    ("/pass.Pass/BatchAddPasswords", Scope(VaultWrite)),
+    ("/pass.Pass/BatchUpdatePasswords", Scope(VaultWrite)),
+    ("/pass.Pass/BatchDeletePasswords", Scope(VaultWrite)),
+    ("/pass.Pass/RotateVaultKey", Scope(VaultManage)),
+    ("/pass.Pass/ShareItem", Verified(VaultManage)),
+    ("/pass.Pass/ListSharedWithMe", Scope(VaultRead)),
+    ("/pass.Pass/RevokeShare", Scope(VaultManage)),
+    ("/pass.Pass/ListCollectionItems", Restricted(VaultRead)),
+    ("/pass.Pass/GetEmergencyVault", Scope(Emergency)),
+    // Org
+    ("/org.Org/CreateOrganization", Verified(OrgManage)),
+    ("/org.Org/ListOrganizations", Scope(OrgRead)),
+    ("/org.Org/ListMembers", Scope(OrgRead)),
+    ("/org.Org/InviteMember", Verified(OrgManage)),
+    ("/org.Org/AcceptInvite", Scope(OrgManage)),
+    ("/org.Org/RemoveMember", Scope(OrgManage)),
+    ("/org.Org/CreateCollection", Scope(OrgManage)),
+    ("/org.Org/ListCollections", Scope(OrgRead)),
+    ("/org.Org/DeleteCollection", Scope(OrgManage)),
+    // Emergency
+    (
+        "/emergency.Emergency/AddEmergencyContact",
+        Verified(Emergency),
+    ),
+    (
+        "/emergency.Emergency/ListEmergencyContacts",
+        Scope(Emergency),
+    ),
+    ("/emergency.Emergency/ListEmergencyGrants", Scope(Emergency)),
+    (
+        "/emergency.Emergency/AcceptEmergencyInvite",
+        Scope(Emergency),
+    ),
+    (
+        "/emergency.Emergency/RemoveEmergencyContact",
+        Scope(Emergency),
+    ),
+    (
+        "/emergency.Emergency/RequestEmergencyAccess",
+        Scope(Emergency),
+    ),
+    (
+        "/emergency.Emergency/ApproveEmergencyAccess",
+        Scope(Emergency),
+    ),
+    (
+        "/emergency.Emergency/RejectEmergencyAccess",
+        Scope(Emergency),
+    ),
+    ("/emergency.Emergency/TakeoverAccount", Scope(Emergency)),
+    // Token
+    ("/token.Token/CreateMachineAccount", Verified(Account)),
+    ("/token.Token/ListMachineAccounts", Scope(Account)),
+    ("/token.Token/DeleteMachineAccount", Scope(Account)),
+    ("/token.Token/CreateAccessToken", Verified(Account)),
+    ("/token.Token/ListAccessTokens", Scope(Account)),
+    ("/token.Token/RevokeAccessToken", Scope(Account)),
+    // Notify
+    ("/notify.Notify/CreateWebhook", Verified(Account)),
+    ("/notify.Notify/ListWebhooks", Scope(Account)),
+    ("/notify.Notify/DeleteWebhook", Scope(Account)),
+    ("/notify.Notify/ListWebhookDeliveries", Scope(Account)),
+    ("/notify.Notify/PingWebhook", Scope(Account)),
+    ("/notify.Notify/Subscribe", Restricted(VaultRead)),
+];
+
+/// Check every call against [`POLICY`] before it reaches its service.
+#[derive(Clone)]
+pub struct PolicyLayer {
+    pool: PgPool,
+}
+
+impl PolicyLayer {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl<S> Layer<S> for PolicyLayer {
+    type Service = PolicyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PolicyService {
+            inner,
+            pool: self.pool.clone(),
+        }
+    }
+}
+
+/// Where a call came from, captured by [`PolicyLayer`].
+pub fn origin<T>(request: &tonic::Request<T>) -> Origin {
+    request
+        .extensions()
+        .get::<Origin>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Caller of a call, authenticated once by [`PolicyLayer`].
+pub fn auth_user<T>(request: &tonic::Request<T>) -> Result<Principal, CpassError> {
+    // Only missing when the method is public and should not ask for a caller.
+    request
+        .extensions()
+        .get::<Principal>()
+        .cloned()
+        .ok_or_else(|| CpassError::Unauthorized("No authorization token was found".to_string()))
+}
+
+#[derive(Clone)]
+pub struct PolicyService<S> {
+    inner: S,
+    pool: PgPool,
+}
+
+async fn enforce_policy<B>(pool: &PgPool, request: &mut Request<B>) -> Result<(), CpassError> {
+    let origin = Origin {
+        ip: request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(TcpConnectInfo::remote_addr)
+            .map(|addr| addr.ip().to_string()),
+        user_agent: request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    };
+    request.extensions_mut().insert(origin);
+
+    let path = request.uri().path();
+    // Health checks and reflection are served to anyone.
+    let permission = match path.starts_with("/grpc.") {
+        true => Public,
+        false => policy::permission_of(POLICY, path)?,
+    };
+
+    if let Some(principal) = policy::check(pool, permission, request.headers()).await? {
+        request.extensions_mut().insert(principal);
+    }
+
+    Ok(())
+}
+
+impl<S, B> Service<Request<B>> for PolicyService<S>
+where
+    S: Service<Request<B>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        // The clone has not been polled ready, keep the service that was for this call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let pool = self.pool.clone();
+
+        Box::pin(async move {
+            match enforce_policy(&pool, &mut request).await {
+                Ok(()) => inner.call(request).await,
+                Err(err) => Ok(Status::from(err).into_http()),
+            }
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{
+    db::Db,
+    error::CpassError,
+    proto::{
+        auth_user,
+        notify_proto::{
+            notify_server::Notify, ChangeNotification, CreateWebhookRequest, CreatedWebhook,
+            ListWebhookDeliveriesRequest, ListWebhooksRequest, Webhook, WebhookDeliveries,
+            WebhookDelivery, Webhooks,
+        },
+        types::{Empty, Uuid},
+    },
+    realtime::{self, Change},
+    validation::Validate,
+    webhook::{self, DeliveryRow, WebhookEvent, WebhookRow},
+};
+use sqlx::PgPool;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+pub struct NotifyService {
+    pool: PgPool,
+}
+
+impl NotifyService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            uuid: row.id.into(),
+            org_id: row.org_id.map(|id| id.into()),
+            url: row.url,
+            events: row.events,
+            created_at: row.created_at.timestamp(),
+        }
+    }
+}
+
+impl From<DeliveryRow> for WebhookDelivery {
+    fn from(row: DeliveryRow) -> Self {
+        WebhookDelivery {
+            uuid: row.id.into(),
+            event_type: row.event_type,
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at.timestamp(),
+            last_status: row.last_status,
+            last_error: row.last_error,
+            created_at: row.created_at.timestamp(),
+            delivered_at: row.delivered_at.map(|at| at.timestamp()),
+        }
+    }
+}
+
+impl From<Change> for ChangeNotification {
+    fn from(change: Change) -> Self {
+        ChangeNotification {
+            event: change.as_str().to_string(),
+            item_id: change.item_id().map(|id| id.into()),
+            revision: change.revision(),
+            session_id: change.session_id().map(|id| id.into()),
+        }
+    }
+}
+
+fn parse_uuid(uuid: &[u8]) -> Result<uuid::Uuid, CpassError> {
+    uuid::Uuid::from_slice(uuid)
+        .map_err(|_| CpassError::InvalidRequest("Can not parse string as uuid.".to_string()))
+}
+
+#[tonic::async_trait]
+impl Notify for NotifyService {
+    type SubscribeStream = ReceiverStream<Result<ChangeNotification, Status>>;
+
+    async fn create_webhook(
+        &self,
+        request: Request<CreateWebhookRequest>,
+    ) -> Result<Response<CreatedWebhook>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let CreateWebhookRequest {
+            url,
+            events,
+            org_id,
+        } = request.into_inner();
+
+        let events = WebhookEvent::parse_all(&events)?;
+        let org_id = org_id.as_deref().map(parse_uuid).transpose()?;
+
+        let created = webhook::create(&mut conn, user_id, org_id, &url, &events).await?;
+
+        Ok(Response::new(CreatedWebhook {
+            webhook: Some(created.row.into()),
+            secret: created.secret,
+        }))
+    }
+
+    async fn list_webhooks(
+        &self,
+        request: Request<ListWebhooksRequest>,
+    ) -> Result<Response<Webhooks>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let org_id = request
+            .get_ref()
+            .org_id
+            .as_deref()
+            .map(parse_uuid)
+            .transpose()?;
+
+        let webhooks = webhook::list(&mut conn, user_id, org_id)
+            .await?
+            .into_iter()
+            .map(Webhook::from)
+            .collect();
+
+        Ok(Response::new(Webhooks { webhooks }))
+    }
+
+    async fn delete_webhook(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let webhook_id = parse_uuid(&request.get_ref().uuid)?;
+
+        webhook::delete(&mut conn, user_id, webhook_id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn list_webhook_deliveries(
+        &self,
+        request: Request<ListWebhookDeliveriesRequest>,
+    ) -> Result<Response<WebhookDeliveries>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let ListWebhookDeliveriesRequest { uuid, limit } = request.get_ref();
+        let webhook_id = parse_uuid(uuid)?;
+
+        let deliveries = webhook::deliveries(&mut conn, user_id, webhook_id, *limit)
+            .await?
+            .into_iter()
+            .map(WebhookDelivery::from)
+            .collect();
+
+        Ok(Response::new(WebhookDeliveries { deliveries }))
+    }
+
+    async fn ping_webhook(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<WebhookDelivery>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let webhook_id = parse_uuid(&request.get_ref().uuid)?;
+
+        let delivery = webhook::ping(&mut conn, user_id, webhook_id).await?;
+
+        Ok(Response::new(delivery.into()))
+    }
+
+    // Streams of tonic carry a `Status` in their items.
+    #[allow(clippy::result_large_err)]
+    async fn subscribe(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let principal = auth_user(&request)?;
+
+        let subscription = realtime::subscribe(&self.pool, principal).await?;
+        let rx = subscription.into_receiver(|change| Ok(change.into()));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
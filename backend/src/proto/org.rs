@@ -0,0 +1,224 @@
+use crate::{
+    db::Db,
+    error::CpassError,
+    organizations::{self, CollectionRow, OrganizationRow},
+    proto::{
+        auth_user,
+        org_proto::{
+            org_server::Org, Collection, Collections, CreateCollectionRequest,
+            CreateOrganizationRequest, InviteMemberRequest, Member, Members, OrgRole, Organization,
+            Organizations, RemoveMemberRequest,
+        },
+        types::{Empty, Uuid},
+    },
+    validation::Validate,
+};
+use sqlx::PgPool;
+use tonic::{Request, Response, Status};
+
+pub struct OrgService {
+    pool: PgPool,
+}
+
+impl OrgService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl From<OrgRole> for organizations::OrgRole {
+    fn from(role: OrgRole) -> Self {
+        match role {
+            OrgRole::ReadOnly => organizations::OrgRole::ReadOnly,
+            OrgRole::Member => organizations::OrgRole::Member,
+            OrgRole::Admin => organizations::OrgRole::Admin,
+            OrgRole::Owner => organizations::OrgRole::Owner,
+        }
+    }
+}
+
+impl From<organizations::OrgRole> for OrgRole {
+    fn from(role: organizations::OrgRole) -> Self {
+        match role {
+            organizations::OrgRole::ReadOnly => OrgRole::ReadOnly,
+            organizations::OrgRole::Member => OrgRole::Member,
+            organizations::OrgRole::Admin => OrgRole::Admin,
+            organizations::OrgRole::Owner => OrgRole::Owner,
+        }
+    }
+}
+
+impl From<OrganizationRow> for Organization {
+    fn from(row: OrganizationRow) -> Self {
+        Organization {
+            uuid: row.id.into(),
+            name: row.name,
+            role: OrgRole::from(row.role).into(),
+            wrapped_org_key: row.wrapped_org_key,
+            accepted: row.accepted,
+        }
+    }
+}
+
+impl From<CollectionRow> for Collection {
+    fn from(row: CollectionRow) -> Self {
+        Collection {
+            uuid: row.id.into(),
+            org_uuid: row.org_id.into(),
+            name: row.name,
+        }
+    }
+}
+
+fn parse_uuid(uuid: &[u8]) -> Result<uuid::Uuid, CpassError> {
+    uuid::Uuid::from_slice(uuid)
+        .map_err(|_| CpassError::InvalidRequest("Can not parse string as uuid.".to_string()))
+}
+
+#[tonic::async_trait]
+impl Org for OrgService {
+    async fn create_organization(
+        &self,
+        request: Request<CreateOrganizationRequest>,
+    ) -> Result<Response<Organization>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let CreateOrganizationRequest {
+            name,
+            wrapped_org_key,
+        } = request.into_inner();
+
+        let org =
+            organizations::create_organization(&mut conn, user_id, name, wrapped_org_key).await?;
+
+        Ok(Response::new(org.into()))
+    }
+
+    async fn list_organizations(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<Organizations>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let organizations = organizations::list_organizations(&mut conn, user_id)
+            .await?
+            .into_iter()
+            .map(Organization::from)
+            .collect();
+
+        Ok(Response::new(Organizations { organizations }))
+    }
+
+    async fn list_members(&self, request: Request<Uuid>) -> Result<Response<Members>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let org_id = parse_uuid(&request.get_ref().uuid)?;
+
+        let members = organizations::list_members(&mut conn, user_id, org_id)
+            .await?
+            .into_iter()
+            .map(|row| Member {
+                email: row.email,
+                role: OrgRole::from(row.role).into(),
+                accepted: row.accepted,
+            })
+            .collect();
+
+        Ok(Response::new(Members { members }))
+    }
+
+    async fn invite_member(
+        &self,
+        request: Request<InviteMemberRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let role = request.get_ref().role().into();
+        let InviteMemberRequest {
+            org_uuid,
+            email,
+            wrapped_org_key,
+            ..
+        } = request.into_inner();
+
+        organizations::invite_member(
+            &mut conn,
+            user_id,
+            parse_uuid(&org_uuid)?,
+            &email,
+            role,
+            wrapped_org_key,
+        )
+        .await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn accept_invite(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let org_id = parse_uuid(&request.get_ref().uuid)?;
+
+        organizations::accept_invite(&mut conn, user_id, org_id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn remove_member(
+        &self,
+        request: Request<RemoveMemberRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let RemoveMemberRequest { org_uuid, email } = request.get_ref();
+
+        organizations::remove_member(&self.pool, user_id, parse_uuid(org_uuid)?, email).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn create_collection(
+        &self,
+        request: Request<CreateCollectionRequest>,
+    ) -> Result<Response<Collection>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let CreateCollectionRequest { org_uuid, name } = request.into_inner();
+
+        let collection =
+            organizations::create_collection(&mut conn, user_id, parse_uuid(&org_uuid)?, name)
+                .await?;
+
+        Ok(Response::new(collection.into()))
+    }
+
+    async fn list_collections(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<Collections>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let org_id = parse_uuid(&request.get_ref().uuid)?;
+
+        let collections = organizations::list_collections(&mut conn, user_id, org_id)
+            .await?
+            .into_iter()
+            .map(Collection::from)
+            .collect();
+
+        Ok(Response::new(Collections { collections }))
+    }
+
+    async fn delete_collection(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let collection_id = parse_uuid(&request.get_ref().uuid)?;
+
+        organizations::delete_collection(&mut conn, user_id, collection_id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+}
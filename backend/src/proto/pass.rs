@@ -1,18 +1,38 @@
 use crate::{
+    access_token,
+    audit::{self, Event, EventType},
+    batch::{self, BatchOutcome, ItemChange, ItemRemoval, NewItem},
+    concurrency::{conditional_write_error, ItemAction},
     db::Db,
+    emergency,
     error::CpassError,
-    jwt::generate::claims_from_headers,
+    honeytoken, organizations,
+    pagination::{self, PasswordRow},
     proto::{
+        auth_user, origin,
         pass_proto::{
-            pass_server::Pass, AddPasswordRequest, DeletePasswordRequest, Password, Passwords,
-            UpdatePasswordRequest,
+            pass_server::Pass, AddPasswordRequest, BatchAddPasswordsRequest,
+            BatchDeletePasswordsRequest, BatchItemResult, BatchResponse,
+            BatchUpdatePasswordsRequest, DeletePasswordRequest, EmergencyVault, ItemVersion,
+            ListPasswordsRequest, Password, Passwords, PasswordsPage, RevokeShareRequest,
+            RotateVaultKeyRequest, RotateVaultKeyResponse, ShareItemRequest, SharePermission,
+            SharedItem, SharedItems, SortField, SortOrder, StreamPasswordsRequest, SyncRequest,
+            SyncResponse, TrashItem, TrashItems, UpdatePasswordRequest,
         },
         types::{Empty, Uuid},
     },
+    rotation::{self, RotatedItem},
+    sharing,
+    validation::Validate,
 };
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
+use tokio::{spawn, sync::mpsc};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 use tonic::{Request, Response, Status};
 
+/// Number of rows buffered ahead of a slow `StreamPasswords` consumer.
+const STREAM_BUFFER: usize = 64;
+
 pub struct PassService {
     pool: PgPool,
 }
@@ -23,24 +43,136 @@ impl PassService {
     }
 }
 
+impl From<PasswordRow> for Password {
+    fn from(row: PasswordRow) -> Self {
+        Password {
+            uuid: row.id.into(),
+            name: row.name,
+            password: row.password,
+            website: row.website,
+            username: row.username,
+            description: row.description,
+            version: row.revision,
+            item_key: row.item_key,
+            collection_id: row.collection_id.map(|id| id.into()),
+            created_at: row.created_at.timestamp_micros(),
+            updated_at: row.updated_at.timestamp_micros(),
+        }
+    }
+}
+
+impl From<SortField> for pagination::SortField {
+    fn from(sort: SortField) -> Self {
+        match sort {
+            SortField::CreatedAt => pagination::SortField::CreatedAt,
+            SortField::UpdatedAt => pagination::SortField::UpdatedAt,
+        }
+    }
+}
+
+impl From<SortOrder> for pagination::SortOrder {
+    fn from(order: SortOrder) -> Self {
+        match order {
+            SortOrder::Asc => pagination::SortOrder::Asc,
+            SortOrder::Desc => pagination::SortOrder::Desc,
+        }
+    }
+}
+
+impl From<SharePermission> for sharing::SharePermission {
+    fn from(permission: SharePermission) -> Self {
+        match permission {
+            SharePermission::Read => sharing::SharePermission::Read,
+            SharePermission::Write => sharing::SharePermission::Write,
+        }
+    }
+}
+
+impl From<sharing::SharePermission> for SharePermission {
+    fn from(permission: sharing::SharePermission) -> Self {
+        match permission {
+            sharing::SharePermission::Read => SharePermission::Read,
+            sharing::SharePermission::Write => SharePermission::Write,
+        }
+    }
+}
+
+impl From<BatchOutcome> for BatchResponse {
+    fn from(outcome: BatchOutcome) -> Self {
+        BatchResponse {
+            committed: outcome.committed,
+            results: outcome
+                .results
+                .into_iter()
+                .map(|result| BatchItemResult {
+                    uuid: result.id.map(|id| id.into()),
+                    version: result.version,
+                    error: result.error.map(|err| format!("{:?}", err)),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_uuid(uuid: &[u8]) -> Result<uuid::Uuid, CpassError> {
+    uuid::Uuid::from_slice(uuid)
+        .map_err(|_| CpassError::InvalidRequest("Can not parse string as uuid.".to_string()))
+}
+
+/// Stream every live item of the personal vault straight from the database cursor.
+fn stream_all<'a>(
+    conn: &'a mut PgConnection,
+    owner_id: uuid::Uuid,
+    sort: pagination::SortField,
+    order: pagination::SortOrder,
+) -> impl Stream<Item = Result<PasswordRow, sqlx::Error>> + Send + 'a {
+    sqlx::query_as!(
+        PasswordRow,
+        r#"
+        SELECT id, name, password, website, username, description, revision, item_key,
+            collection_id, created_at, updated_at
+        FROM passwords
+        WHERE owner_id = $1 AND collection_id IS NULL AND deleted_at IS NULL
+        ORDER BY
+            $3::BIGINT * (EXTRACT(EPOCH FROM
+                CASE WHEN $2 THEN updated_at ELSE created_at END
+            ) * 1000000)::BIGINT,
+            id
+        "#,
+        owner_id,
+        sort == pagination::SortField::UpdatedAt,
+        order.direction()
+    )
+    .fetch(conn)
+}
+
 #[tonic::async_trait]
 impl Pass for PassService {
+    type StreamPasswordsStream = ReceiverStream<Result<Password, Status>>;
+
     async fn get_password(&self, request: Request<Uuid>) -> Result<Response<Password>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
         let mut conn = self.pool.conn().await?;
         let Uuid { uuid } = request.get_ref();
         let pass_id = uuid::Uuid::from_slice(uuid)
             .map_err(|_| Status::invalid_argument("Can not parse string as uuid."))?;
 
-        let owner_id = claims_from_headers(request.metadata())?.sub;
-
         let row = sqlx::query!(
             r#"
-            SELECT id, password, name, website, username, description
-            FROM passwords
-            WHERE id = $1 AND owner_id = $2
+            SELECT
+                p.id, p.password, p.name, p.website, p.username, p.description, p.revision,
+                COALESCE(s.wrapped_item_key, p.item_key) AS item_key, p.collection_id,
+                p.created_at, p.updated_at, p.honeytoken
+            FROM passwords p
+            LEFT JOIN shares s ON s.item_id = p.id AND s.recipient_id = $2
+            WHERE p.id = $1 AND p.deleted_at IS NULL AND item_can(p.id, $2, $3)
+                AND ($4::UUID[] IS NULL OR p.collection_id = ANY($4))
             "#,
             pass_id,
-            owner_id
+            principal.sub,
+            ItemAction::Read.as_str(),
+            principal.collections.as_deref()
         )
         .fetch_one(&mut *conn)
         .await
@@ -49,6 +181,17 @@ impl Pass for PassService {
             _ => CpassError::DatabaseError(err).into(),
         })?;
 
+        if row.honeytoken {
+            honeytoken::trip(&mut conn, pass_id, &principal, "grpc").await;
+        }
+
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::ItemRead, &principal).item(pass_id),
+        )
+        .await?;
+
         Ok(Response::new(Password {
             uuid: row.id.into(),
             name: row.name,
@@ -56,63 +199,125 @@ impl Pass for PassService {
             website: row.website,
             username: row.username,
             description: row.description,
+            version: row.revision,
+            item_key: row.item_key,
+            collection_id: row.collection_id.map(|id| id.into()),
+            created_at: row.created_at.timestamp_micros(),
+            updated_at: row.updated_at.timestamp_micros(),
         }))
     }
 
     async fn get_passwords(&self, request: Request<Empty>) -> Result<Response<Passwords>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
         let mut conn = self.pool.conn().await?;
 
-        let owner_id = claims_from_headers(request.metadata())?.sub;
-
-        let passwords = sqlx::query!(
+        let passwords = sqlx::query_as!(
+            PasswordRow,
             r#"
-            SELECT id, password, name, website, username, description
+            SELECT id, name, password, website, username, description, revision, item_key,
+                collection_id, created_at, updated_at
             FROM passwords
-            WHERE owner_id = $1
+            WHERE owner_id = $1 AND collection_id IS NULL AND deleted_at IS NULL
             "#,
             owner_id
         )
         .fetch_all(&mut *conn)
         .await
-        .map_err(CpassError::DatabaseError)?;
+        .map_err(CpassError::DatabaseError)?
+        .into_iter()
+        .map(Password::from)
+        .collect::<Vec<Password>>();
 
-        let passwords = passwords
-            .into_iter()
-            .map(|x| Password {
-                uuid: x.id.into(),
-                name: x.name,
-                password: x.password,
-                website: x.website,
-                username: x.username,
-                description: x.description,
-            })
-            .collect::<Vec<Password>>();
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::ItemsListed, &principal).whole_vault(),
+        )
+        .await?;
 
         Ok(Response::new(Passwords { passwords }))
     }
+
+    async fn list_passwords(
+        &self,
+        request: Request<ListPasswordsRequest>,
+    ) -> Result<Response<PasswordsPage>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let ListPasswordsRequest {
+            page_size,
+            page_token,
+            ..
+        } = request.get_ref();
+
+        let page = pagination::fetch_page(
+            &mut conn,
+            owner_id,
+            request.get_ref().sort().into(),
+            request.get_ref().order().into(),
+            page_size.map(i64::from),
+            page_token.as_deref(),
+        )
+        .await?;
+
+        let mut event = Event::by(EventType::ItemsListed, &principal);
+        if page_token.is_none() {
+            event = event.whole_vault();
+        }
+        audit::record(&mut conn, &origin, event).await?;
+
+        Ok(Response::new(PasswordsPage {
+            passwords: page.items.into_iter().map(Password::from).collect(),
+            next_page_token: page.next_page_token,
+        }))
+    }
+
     async fn add_password(
         &self,
         request: Request<AddPasswordRequest>,
-    ) -> Result<Response<Uuid>, Status> {
-        let mut conn = self.pool.conn().await?;
+    ) -> Result<Response<Password>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
         let AddPasswordRequest {
             name,
             password,
             website,
             username,
             description,
+            item_key,
+            collection_id,
+            honeytoken,
         } = request.get_ref();
 
-        let owner_id = claims_from_headers(request.metadata())?.sub;
+        let collection_id = collection_id.as_deref().map(parse_uuid).transpose()?;
 
-        let password = hex::decode(password)
-            .map_err(|_| Status::invalid_argument("Can not decode password from hex"))?;
+        if !principal.reaches(collection_id) {
+            return Err(CpassError::Forbidden(
+                "Not allowed to add items to that collection".to_string(),
+            )
+            .into());
+        }
 
-        let row = sqlx::query!(
+        let row = sqlx::query_as!(
+            PasswordRow,
             r#"
-            INSERT INTO passwords(owner_id, name, password, website, username, description)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING id
+            INSERT INTO passwords(
+                owner_id, name, password, website, username, description, item_key, collection_id,
+                honeytoken
+            )
+            SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9
+            WHERE $8::UUID IS NULL OR collection_can($8, $1, 'write')
+            RETURNING
+                id, name, password, website, username, description, revision, item_key,
+                collection_id, created_at, updated_at
             "#,
             owner_id,
             name,
@@ -120,21 +325,37 @@ impl Pass for PassService {
             website.as_ref(),
             username.as_ref(),
             description.as_ref(),
+            item_key.as_ref(),
+            collection_id,
+            honeytoken
         )
-        .fetch_one(&mut *conn)
+        .fetch_optional(&mut *tx)
         .await
-        .map_err(CpassError::DatabaseError)?;
+        .map_err(CpassError::DatabaseError)?
+        .ok_or_else(|| {
+            CpassError::Forbidden("Not allowed to add items to that collection".to_string())
+        })?;
 
-        Ok(Response::new(Uuid {
-            uuid: row.id.into(),
-        }))
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::ItemCreated, &principal).item(row.id),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(row.into()))
     }
 
     async fn update_password(
         &self,
         request: Request<UpdatePasswordRequest>,
-    ) -> Result<Response<Empty>, Status> {
-        let mut conn = self.pool.conn().await?;
+    ) -> Result<Response<ItemVersion>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
         let UpdatePasswordRequest {
             uuid,
             name,
@@ -142,21 +363,24 @@ impl Pass for PassService {
             website,
             username,
             description,
+            expected_version,
+            item_key,
         } = request.get_ref().to_owned();
 
-        let owner_id = claims_from_headers(request.metadata())?.sub;
-
-        let password = password
-            .map(|data| {
-                hex::decode(data)
-                    .map_err(|_| Status::invalid_argument("Can not decode password from hex"))
-            })
-            .transpose()?;
+        if expected_version == 0 {
+            return Err(CpassError::PreconditionRequired(
+                "expected_version is required".to_string(),
+            )
+            .into());
+        }
 
         let pass_id = uuid::Uuid::from_slice(&uuid)
             .map_err(|_| Status::invalid_argument("Can not parse string as uuid."))?;
+        let action = ItemAction::update(item_key.as_ref());
 
-        let _ = sqlx::query!(
+        access_token::check_item(&mut tx, &principal, pass_id).await?;
+
+        let version = sqlx::query_scalar!(
             r#"
             UPDATE passwords
             SET
@@ -164,8 +388,10 @@ impl Pass for PassService {
                 password = COALESCE($2, password),
                 website = COALESCE($3, website),
                 username = COALESCE($4, username),
-                description = COALESCE($5, description)
-            WHERE id = $6 AND owner_id = $7
+                description = COALESCE($5, description),
+                item_key = COALESCE($9, item_key)
+            WHERE id = $6 AND deleted_at IS NULL AND revision = $8 AND item_can(id, $7, $10)
+            RETURNING revision
             "#,
             name,
             password,
@@ -173,38 +399,687 @@ impl Pass for PassService {
             username,
             description,
             pass_id,
-            owner_id
+            user_id,
+            expected_version,
+            item_key.as_ref(),
+            action.as_str()
         )
-        .execute(&mut *conn)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(CpassError::DatabaseError)?;
 
-        Ok(Response::new(Empty {}))
+        let Some(version) = version else {
+            return Err(conditional_write_error(&mut tx, pass_id, user_id, action)
+                .await
+                .into());
+        };
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::ItemUpdated, &principal).item(pass_id),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(ItemVersion { version }))
     }
 
     async fn delete_password(
         &self,
         request: Request<DeletePasswordRequest>,
     ) -> Result<Response<Empty>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let DeletePasswordRequest {
+            uuid,
+            expected_version,
+        } = request.get_ref();
+
+        if *expected_version == 0 {
+            return Err(CpassError::PreconditionRequired(
+                "expected_version is required".to_string(),
+            )
+            .into());
+        }
+
+        let pass_id = uuid::Uuid::from_slice(uuid)
+            .map_err(|_| Status::invalid_argument("Can not parse string as uuid."))?;
+
+        access_token::check_item(&mut tx, &principal, pass_id).await?;
+
+        let res = sqlx::query!(
+            r#"
+            UPDATE passwords
+            SET deleted_at = now()
+            WHERE id = $1 AND deleted_at IS NULL AND revision = $3 AND item_can(id, $2, $4)
+            "#,
+            pass_id,
+            user_id,
+            expected_version,
+            ItemAction::Trash.as_str()
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(CpassError::DatabaseError)?;
+
+        if res.rows_affected() == 0 {
+            return Err(
+                conditional_write_error(&mut tx, pass_id, user_id, ItemAction::Trash)
+                    .await
+                    .into(),
+            );
+        }
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::ItemTrashed, &principal).item(pass_id),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn list_trash(&self, request: Request<Empty>) -> Result<Response<TrashItems>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
         let mut conn = self.pool.conn().await?;
-        let DeletePasswordRequest { uuid } = request.get_ref();
 
-        let owner_id = claims_from_headers(request.metadata())?.sub;
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, password, name, website, username, description, revision, item_key,
+                collection_id, created_at, updated_at, deleted_at AS "deleted_at!"
+            FROM passwords
+            WHERE deleted_at IS NOT NULL AND (
+                (owner_id = $1 AND collection_id IS NULL)
+                OR (collection_id IS NOT NULL AND item_can(id, $1, $2))
+            )
+            ORDER BY deleted_at DESC
+            "#,
+            user_id,
+            ItemAction::Restore.as_str()
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(CpassError::DatabaseError)?;
+
+        let items = rows
+            .into_iter()
+            .map(|x| TrashItem {
+                password: Some(Password {
+                    uuid: x.id.into(),
+                    name: x.name,
+                    password: x.password,
+                    website: x.website,
+                    username: x.username,
+                    description: x.description,
+                    version: x.revision,
+                    item_key: x.item_key,
+                    collection_id: x.collection_id.map(|id| id.into()),
+                    created_at: x.created_at.timestamp_micros(),
+                    updated_at: x.updated_at.timestamp_micros(),
+                }),
+                deleted_at: x.deleted_at.timestamp_micros(),
+            })
+            .collect::<Vec<TrashItem>>();
+
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::TrashListed, &principal),
+        )
+        .await?;
+
+        Ok(Response::new(TrashItems { items }))
+    }
+
+    async fn restore_item(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let Uuid { uuid } = request.get_ref();
+
+        let pass_id = uuid::Uuid::from_slice(uuid)
+            .map_err(|_| Status::invalid_argument("Can not parse string as uuid."))?;
+
+        let res = sqlx::query!(
+            r#"
+            UPDATE passwords
+            SET deleted_at = NULL
+            WHERE id = $1 AND deleted_at IS NOT NULL AND item_can(id, $2, $3)
+            "#,
+            pass_id,
+            user_id,
+            ItemAction::Restore.as_str()
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(CpassError::DatabaseError)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Status::not_found(
+                "Password with that id not found in trash",
+            ));
+        }
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::ItemRestored, &principal).item(pass_id),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn purge_item(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let Uuid { uuid } = request.get_ref();
+
         let pass_id = uuid::Uuid::from_slice(uuid)
             .map_err(|_| Status::invalid_argument("Can not parse string as uuid."))?;
 
-        let _ = sqlx::query!(
+        let res = sqlx::query!(
             r#"
             DELETE FROM passwords
-            WHERE id = $1 AND owner_id = $2
+            WHERE id = $1 AND deleted_at IS NOT NULL AND item_can(id, $2, $3)
             "#,
             pass_id,
+            user_id,
+            ItemAction::Purge.as_str()
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(CpassError::DatabaseError)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Status::not_found(
+                "Password with that id not found in trash",
+            ));
+        }
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::ItemPurged, &principal).item(pass_id),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn sync(&self, request: Request<SyncRequest>) -> Result<Response<SyncResponse>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let SyncRequest { since_revision } = request.get_ref().to_owned();
+
+        if since_revision < 0 {
+            return Err(Status::invalid_argument(
+                "since_revision can not be negative",
+            ));
+        }
+
+        sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+            .execute(&mut *tx)
+            .await
+            .map_err(CpassError::DatabaseError)?;
+
+        let revision = sqlx::query_scalar!(
+            r#"
+            SELECT revision FROM users WHERE id = $1
+            "#,
             owner_id
         )
-        .execute(&mut *conn)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(CpassError::DatabaseError)?;
+
+        let changed = sqlx::query_as!(
+            PasswordRow,
+            r#"
+            SELECT id, name, password, website, username, description, revision, item_key,
+                collection_id, created_at, updated_at
+            FROM passwords
+            WHERE owner_id = $1 AND collection_id IS NULL AND revision > $2 AND deleted_at IS NULL
+            ORDER BY revision
+            "#,
+            owner_id,
+            since_revision
+        )
+        .fetch_all(&mut *tx)
         .await
-        .map_err(CpassError::DatabaseError);
+        .map_err(CpassError::DatabaseError)?
+        .into_iter()
+        .map(Password::from)
+        .collect::<Vec<Password>>();
+
+        let deleted = sqlx::query_scalar!(
+            r#"
+            SELECT id AS "id!" FROM passwords
+            WHERE owner_id = $1 AND collection_id IS NULL AND revision > $2
+                AND deleted_at IS NOT NULL
+            UNION ALL
+            SELECT id FROM password_tombstones
+            WHERE owner_id = $1 AND revision > $2
+            "#,
+            owner_id,
+            since_revision
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(CpassError::DatabaseError)?
+        .into_iter()
+        .map(|id| Uuid { uuid: id.into() })
+        .collect::<Vec<Uuid>>();
+
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        // Appending to the audit chain could fail to serialize in a repeatable read
+        // transaction.
+        let mut conn = self.pool.conn().await?;
+        let mut event = Event::by(EventType::VaultSynced, &principal)
+            .detail(format!("since revision {}", since_revision));
+        if since_revision == 0 {
+            event = event.whole_vault();
+        }
+        audit::record(&mut conn, &origin, event).await?;
+
+        Ok(Response::new(SyncResponse {
+            revision,
+            changed,
+            deleted,
+        }))
+    }
+
+    async fn stream_passwords(
+        &self,
+        request: Request<StreamPasswordsRequest>,
+    ) -> Result<Response<Self::StreamPasswordsStream>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let sort = request.get_ref().sort().into();
+        let order = request.get_ref().order().into();
+
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::ItemsStreamed, &principal).whole_vault(),
+        )
+        .await?;
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+
+        spawn(async move {
+            let mut rows = stream_all(&mut conn, owner_id, sort, order);
+
+            while let Some(row) = rows.next().await {
+                let item = row
+                    .map(Password::from)
+                    .map_err(|err| CpassError::DatabaseError(err).into());
+
+                // The receiver is gone once the client cancels the call.
+                if tx.send(item).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn batch_add_passwords(
+        &self,
+        request: Request<BatchAddPasswordsRequest>,
+    ) -> Result<Response<BatchResponse>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
+        let BatchAddPasswordsRequest {
+            items,
+            all_or_nothing,
+        } = request.into_inner();
+
+        let items = items
+            .into_iter()
+            .map(|item| {
+                Ok(NewItem {
+                    name: item.name,
+                    password: item.password,
+                    website: item.website,
+                    username: item.username,
+                    description: item.description,
+                    item_key: item.item_key,
+                    collection_id: item.collection_id.as_deref().map(parse_uuid).transpose()?,
+                    honeytoken: item.honeytoken,
+                })
+            })
+            .collect();
+
+        let mut tx = self.pool.tx().await?;
+        let outcome = batch::add_items(&mut tx, owner_id, items, all_or_nothing).await?;
+
+        audit::record_batch(
+            &mut tx,
+            &origin,
+            Event::by(EventType::BatchAdded, &principal),
+            &outcome,
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(outcome.into()))
+    }
+
+    async fn batch_update_passwords(
+        &self,
+        request: Request<BatchUpdatePasswordsRequest>,
+    ) -> Result<Response<BatchResponse>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let BatchUpdatePasswordsRequest {
+            items,
+            all_or_nothing,
+        } = request.into_inner();
+
+        let items = items
+            .into_iter()
+            .map(|item| {
+                Ok(ItemChange {
+                    id: parse_uuid(&item.uuid)?,
+                    name: item.name,
+                    password: item.password,
+                    website: item.website,
+                    username: item.username,
+                    description: item.description,
+                    item_key: item.item_key,
+                    expected_version: item.expected_version,
+                })
+            })
+            .collect();
+
+        let mut tx = self.pool.tx().await?;
+        let outcome = batch::update_items(&mut tx, user_id, items, all_or_nothing).await?;
+
+        audit::record_batch(
+            &mut tx,
+            &origin,
+            Event::by(EventType::BatchUpdated, &principal),
+            &outcome,
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(outcome.into()))
+    }
+
+    async fn batch_delete_passwords(
+        &self,
+        request: Request<BatchDeletePasswordsRequest>,
+    ) -> Result<Response<BatchResponse>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let BatchDeletePasswordsRequest {
+            items,
+            all_or_nothing,
+        } = request.into_inner();
+
+        let items = items
+            .into_iter()
+            .map(|item| {
+                Ok(ItemRemoval {
+                    id: parse_uuid(&item.uuid)?,
+                    expected_version: item.expected_version,
+                })
+            })
+            .collect();
+
+        let mut tx = self.pool.tx().await?;
+        let outcome = batch::delete_items(&mut tx, user_id, items, all_or_nothing).await?;
+
+        audit::record_batch(
+            &mut tx,
+            &origin,
+            Event::by(EventType::BatchDeleted, &principal),
+            &outcome,
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(outcome.into()))
+    }
+
+    async fn rotate_vault_key(
+        &self,
+        request: Request<RotateVaultKeyRequest>,
+    ) -> Result<Response<RotateVaultKeyResponse>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let RotateVaultKeyRequest {
+            expected_revision,
+            wrapped_vault_key,
+            items,
+            wrapped_private_key,
+        } = request.into_inner();
+
+        let items = items
+            .into_iter()
+            .map(|item| {
+                Ok(RotatedItem {
+                    id: parse_uuid(&item.uuid)?,
+                    name: item.name,
+                    password: item.password,
+                    website: item.website,
+                    username: item.username,
+                    description: item.description,
+                    item_key: item.item_key,
+                })
+            })
+            .collect::<Result<Vec<_>, CpassError>>()?;
+
+        let mut tx = self.pool.tx().await?;
+        let rotation = rotation::rotate_vault_key(
+            &mut tx,
+            principal.sub,
+            principal.sid,
+            expected_revision,
+            wrapped_vault_key,
+            wrapped_private_key,
+            items,
+        )
+        .await?;
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::VaultKeyRotated, &principal)
+                .detail(format!("revision {}", rotation.revision)),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(RotateVaultKeyResponse {
+            revision: rotation.revision,
+            recovery_key_cleared: rotation.recovery_key_cleared,
+            emergency_keys_cleared: rotation.emergency_keys_cleared,
+        }))
+    }
+
+    async fn share_item(
+        &self,
+        request: Request<ShareItemRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        request.get_ref().validate()?;
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let permission = request.get_ref().permission().into();
+        let ShareItemRequest {
+            uuid,
+            recipient_email,
+            wrapped_item_key,
+            ..
+        } = request.into_inner();
+
+        let pass_id = parse_uuid(&uuid)?;
+
+        sharing::share_item(
+            &mut tx,
+            owner_id,
+            pass_id,
+            &recipient_email,
+            wrapped_item_key,
+            permission,
+        )
+        .await?;
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::ItemShared, &principal)
+                .item(pass_id)
+                .detail(recipient_email),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn list_shared_with_me(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<SharedItems>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let items = sharing::shared_with(&mut conn, user_id)
+            .await?
+            .into_iter()
+            .map(|row| SharedItem {
+                password: Some(row.item.into()),
+                permission: SharePermission::from(row.permission).into(),
+                owner_email: row.owner_email,
+            })
+            .collect();
+
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::SharedListed, &principal),
+        )
+        .await?;
+
+        Ok(Response::new(SharedItems { items }))
+    }
+
+    async fn revoke_share(
+        &self,
+        request: Request<RevokeShareRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let owner_id = principal.sub;
+        let mut tx = self.pool.tx().await?;
+        let RevokeShareRequest {
+            uuid,
+            recipient_email,
+        } = request.get_ref();
+
+        let pass_id = parse_uuid(uuid)?;
+
+        sharing::revoke_share(&mut tx, owner_id, pass_id, recipient_email).await?;
+
+        audit::record(
+            &mut tx,
+            &origin,
+            Event::by(EventType::ShareRevoked, &principal)
+                .item(pass_id)
+                .detail(recipient_email.as_str()),
+        )
+        .await?;
+        tx.commit().await.map_err(CpassError::DatabaseError)?;
 
         Ok(Response::new(Empty {}))
     }
+
+    async fn list_collection_items(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<Passwords>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut conn = self.pool.conn().await?;
+        let collection_id = parse_uuid(&request.get_ref().uuid)?;
+
+        if !principal.reaches(Some(collection_id)) {
+            return Err(Status::not_found("Collection with that id not found"));
+        }
+
+        let passwords = organizations::collection_items(&mut conn, user_id, collection_id)
+            .await?
+            .into_iter()
+            .map(Password::from)
+            .collect();
+
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::CollectionItemsListed, &principal)
+                .detail(collection_id.to_string()),
+        )
+        .await?;
+
+        Ok(Response::new(Passwords { passwords }))
+    }
+
+    async fn get_emergency_vault(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<EmergencyVault>, Status> {
+        let principal = auth_user(&request)?;
+        let origin = origin(&request);
+        let user_id = principal.sub;
+        let mut conn = self.pool.conn().await?;
+        let id = parse_uuid(&request.get_ref().uuid)?;
+
+        let (wrapped_vault_key, items) = emergency::vault(&mut conn, user_id, id).await?;
+
+        audit::record(
+            &mut conn,
+            &origin,
+            Event::by(EventType::EmergencyVaultRead, &principal).detail(id.to_string()),
+        )
+        .await?;
+
+        Ok(Response::new(EmergencyVault {
+            wrapped_vault_key,
+            passwords: items.into_iter().map(Password::from).collect(),
+        }))
+    }
 }
@@ -0,0 +1,199 @@
+use chrono::DateTime;
+
+use crate::{
+    access_token::{self, AccessTokenRow},
+    db::Db,
+    error::CpassError,
+    machine_account::{self, MachineAccountRow, MachineKeys},
+    policy::Scope,
+    proto::{
+        auth_user,
+        token_proto::{
+            token_server::Token, AccessToken, AccessTokens, CreateAccessTokenRequest,
+            CreateMachineAccountRequest, CreatedAccessToken, MachineAccount, MachineAccounts,
+        },
+        types::{Empty, Uuid},
+    },
+    validation::Validate,
+};
+use sqlx::PgPool;
+use tonic::{Request, Response, Status};
+
+pub struct TokenService {
+    pool: PgPool,
+}
+
+impl TokenService {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+impl From<MachineAccountRow> for MachineAccount {
+    fn from(row: MachineAccountRow) -> Self {
+        MachineAccount {
+            uuid: row.id.into(),
+            email: row.email,
+            name: row.name,
+            created_at: row.created_at.timestamp(),
+        }
+    }
+}
+
+impl From<AccessTokenRow> for AccessToken {
+    fn from(row: AccessTokenRow) -> Self {
+        AccessToken {
+            uuid: row.id.into(),
+            holder: row.holder,
+            name: row.name,
+            prefix: row.prefix,
+            scopes: row.scopes,
+            collection_ids: row
+                .collection_ids
+                .unwrap_or_default()
+                .into_iter()
+                .map(|id| id.into())
+                .collect(),
+            created_at: row.created_at.timestamp(),
+            expires_at: row.expires_at.map(|at| at.timestamp()),
+            last_used_at: row.last_used_at.map(|at| at.timestamp()),
+        }
+    }
+}
+
+fn parse_uuid(uuid: &[u8]) -> Result<uuid::Uuid, CpassError> {
+    uuid::Uuid::from_slice(uuid)
+        .map_err(|_| CpassError::InvalidRequest("Can not parse string as uuid.".to_string()))
+}
+
+#[tonic::async_trait]
+impl Token for TokenService {
+    async fn create_machine_account(
+        &self,
+        request: Request<CreateMachineAccountRequest>,
+    ) -> Result<Response<MachineAccount>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let CreateMachineAccountRequest {
+            name,
+            vault_key,
+            public_key,
+            wrapped_private_key,
+        } = request.into_inner();
+
+        let machine = machine_account::create(
+            &mut conn,
+            user_id,
+            &name,
+            MachineKeys {
+                vault_key,
+                public_key,
+                wrapped_private_key,
+            },
+        )
+        .await?;
+
+        Ok(Response::new(machine.into()))
+    }
+
+    async fn list_machine_accounts(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<MachineAccounts>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let machine_accounts = machine_account::list(&mut conn, user_id)
+            .await?
+            .into_iter()
+            .map(MachineAccount::from)
+            .collect();
+
+        Ok(Response::new(MachineAccounts { machine_accounts }))
+    }
+
+    async fn delete_machine_account(
+        &self,
+        request: Request<Uuid>,
+    ) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let machine_id = parse_uuid(&request.get_ref().uuid)?;
+
+        machine_account::delete(&self.pool, user_id, machine_id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn create_access_token(
+        &self,
+        request: Request<CreateAccessTokenRequest>,
+    ) -> Result<Response<CreatedAccessToken>, Status> {
+        request.get_ref().validate()?;
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let CreateAccessTokenRequest {
+            name,
+            scopes,
+            collection_ids,
+            machine_account_id,
+            expires_at,
+        } = request.into_inner();
+
+        let scopes = Scope::parse_all(&scopes)?;
+        let collection_ids = collection_ids
+            .iter()
+            .map(|id| parse_uuid(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let machine_id = machine_account_id.as_deref().map(parse_uuid).transpose()?;
+        let expires_at = expires_at
+            .map(|at| {
+                DateTime::from_timestamp(at, 0).ok_or_else(|| {
+                    CpassError::InvalidRequest("expires_at is out of range".to_string())
+                })
+            })
+            .transpose()?;
+
+        let created = access_token::create_token(
+            &mut conn,
+            user_id,
+            machine_id,
+            &name,
+            &scopes,
+            (!collection_ids.is_empty()).then_some(collection_ids),
+            expires_at,
+        )
+        .await?;
+
+        Ok(Response::new(CreatedAccessToken {
+            access_token: Some(created.row.into()),
+            token: created.token,
+        }))
+    }
+
+    async fn list_access_tokens(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<AccessTokens>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+
+        let access_tokens = access_token::list_tokens(&mut conn, user_id)
+            .await?
+            .into_iter()
+            .map(AccessToken::from)
+            .collect();
+
+        Ok(Response::new(AccessTokens { access_tokens }))
+    }
+
+    async fn revoke_access_token(&self, request: Request<Uuid>) -> Result<Response<Empty>, Status> {
+        let user_id = auth_user(&request)?.sub;
+        let mut conn = self.pool.conn().await?;
+        let token_id = parse_uuid(&request.get_ref().uuid)?;
+
+        access_token::revoke_token(&mut conn, user_id, token_id).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+}
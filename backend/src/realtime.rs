@@ -0,0 +1,368 @@
+use std::{future::pending, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::{
+    select, spawn,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+    time::{interval_at, sleep_until, Instant, Interval, MissedTickBehavior},
+};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    error::CpassError,
+    jwt::{models::Principal, session},
+};
+
+/// Channel the triggers of the database publish changes on, shared by every instance.
+const CHANNEL: &str = "cpass_changes";
+/// Changes held for a slow subscriber before it misses some and is told to resync.
+const BROADCAST_BUFFER: usize = 1024;
+/// Changes buffered ahead of a slow connection.
+const SUBSCRIBER_BUFFER: usize = 64;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How often a subscription checks its session still exists, in case the change revoking
+/// it was missed.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref CHANGES: broadcast::Sender<Arc<Published>> = broadcast::channel(BROADCAST_BUFFER).0;
+}
+
+/// Change pushed to a subscriber. Only ids travel, clients fetch or sync what changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// An item was created, updated, restored, or shared with the subscriber.
+    ItemChanged {
+        item_id: Uuid,
+        revision: Option<i64>,
+    },
+    /// An item was moved to the trash, purged, or is no longer shared with the subscriber.
+    ItemDeleted {
+        item_id: Uuid,
+        revision: Option<i64>,
+    },
+    /// A session or access token of the subscriber was revoked. The subscription of that
+    /// session ends with it.
+    SessionRevoked { session_id: Uuid },
+    /// Changes may have been missed, the subscriber should sync.
+    Resync,
+}
+
+impl Change {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Change::ItemChanged { .. } => "item.changed",
+            Change::ItemDeleted { .. } => "item.deleted",
+            Change::SessionRevoked { .. } => "session.revoked",
+            Change::Resync => "resync",
+        }
+    }
+
+    pub fn item_id(&self) -> Option<Uuid> {
+        match self {
+            Change::ItemChanged { item_id, .. } | Change::ItemDeleted { item_id, .. } => {
+                Some(*item_id)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn revision(&self) -> Option<i64> {
+        match self {
+            Change::ItemChanged { revision, .. } | Change::ItemDeleted { revision, .. } => {
+                *revision
+            }
+            _ => None,
+        }
+    }
+
+    pub fn session_id(&self) -> Option<Uuid> {
+        match self {
+            Change::SessionRevoked { session_id } => Some(*session_id),
+            _ => None,
+        }
+    }
+}
+
+/// Who a published change is for.
+#[derive(Debug)]
+enum Audience {
+    User(Uuid),
+    /// Members of the organization who can read the collection.
+    Collection {
+        org_id: Uuid,
+        collection_id: Uuid,
+    },
+    Everyone,
+}
+
+#[derive(Debug)]
+struct Published {
+    audience: Audience,
+    change: Change,
+}
+
+/// Payload of a notification, see `publish_change` in the migrations.
+#[derive(Deserialize)]
+struct Payload {
+    event: String,
+    user_id: Option<Uuid>,
+    org_id: Option<Uuid>,
+    collection_id: Option<Uuid>,
+    item_id: Option<Uuid>,
+    revision: Option<i64>,
+    session_id: Option<Uuid>,
+}
+
+impl Published {
+    fn resync() -> Self {
+        Published {
+            audience: Audience::Everyone,
+            change: Change::Resync,
+        }
+    }
+
+    fn parse(payload: &str) -> Option<Self> {
+        let payload: Payload = serde_json::from_str(payload).ok()?;
+
+        let audience = match (payload.user_id, payload.org_id, payload.collection_id) {
+            (Some(user_id), _, _) => Audience::User(user_id),
+            (None, Some(org_id), Some(collection_id)) => Audience::Collection {
+                org_id,
+                collection_id,
+            },
+            _ => return None,
+        };
+
+        let change = match (payload.event.as_str(), payload.item_id, payload.session_id) {
+            ("item.changed", Some(item_id), _) => Change::ItemChanged {
+                item_id,
+                revision: payload.revision,
+            },
+            ("item.deleted", Some(item_id), _) => Change::ItemDeleted {
+                item_id,
+                revision: payload.revision,
+            },
+            ("session.revoked", _, Some(session_id)) => Change::SessionRevoked { session_id },
+            _ => return None,
+        };
+
+        Some(Published { audience, change })
+    }
+}
+
+fn broadcast(published: Published) {
+    // Fails only when nobody is subscribed on this instance.
+    let _ = CHANGES.send(Arc::new(published));
+}
+
+/// Forward the changes published by every instance to the subscribers of this one.
+pub async fn listen_task(pool: PgPool) {
+    loop {
+        if let Err(err) = listen(&pool).await {
+            error!("Failed to listen for changes: {:?}", err);
+        }
+
+        // Whatever was published while not listening is lost.
+        broadcast(Published::resync());
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(CHANNEL).await?;
+
+    loop {
+        match listener.try_recv().await? {
+            Some(notification) => match Published::parse(notification.payload()) {
+                Some(published) => broadcast(published),
+                None => warn!("Ignoring malformed change {}", notification.payload()),
+            },
+            // The connection was lost, the next call reconnects and listens again.
+            None => broadcast(Published::resync()),
+        }
+    }
+}
+
+/// Changes one caller may see, for as long as its session lasts.
+pub struct Subscription {
+    pool: PgPool,
+    principal: Principal,
+    /// Organizations the caller was a member of when subscribing.
+    orgs: Vec<Uuid>,
+    receiver: broadcast::Receiver<Arc<Published>>,
+    /// When the token subscribed with expires.
+    expires_at: Option<Instant>,
+    session_check: Interval,
+    ended: bool,
+}
+
+/// Subscribe the caller to changes of its items, those shared with it and those of its
+/// collections, until its token expires or its session is revoked. Access tokens
+/// restricted to collections only get their collections. Organizations joined later take
+/// a new subscription.
+pub async fn subscribe(pool: &PgPool, principal: Principal) -> Result<Subscription, CpassError> {
+    // Subscribing first, so nothing published while the memberships load is missed.
+    let receiver = CHANGES.subscribe();
+
+    let orgs = sqlx::query_scalar!(
+        r#"
+        SELECT org_id
+        FROM org_members
+        WHERE user_id = $1 AND accepted_at IS NOT NULL
+        "#,
+        principal.sub
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let expires_at = principal.expires_at.map(|expires_at| {
+        let left = (expires_at - Utc::now()).to_std().unwrap_or_default();
+        Instant::now() + left
+    });
+    let mut session_check = interval_at(
+        Instant::now() + SESSION_CHECK_INTERVAL,
+        SESSION_CHECK_INTERVAL,
+    );
+    session_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    Ok(Subscription {
+        pool: pool.clone(),
+        principal,
+        orgs,
+        receiver,
+        expires_at,
+        session_check,
+        ended: false,
+    })
+}
+
+impl Subscription {
+    /// Wait for the next change of the subscriber, `None` once its session is revoked or
+    /// its token expired.
+    pub async fn next(&mut self) -> Option<Change> {
+        if self.ended {
+            return None;
+        }
+
+        loop {
+            let expires_at = self.expires_at;
+            let expired = async move {
+                match expires_at {
+                    Some(expires_at) => sleep_until(expires_at).await,
+                    None => pending().await,
+                }
+            };
+
+            let published = select! {
+                received = self.receiver.recv() => match received {
+                    Ok(published) => published,
+                    Err(RecvError::Lagged(_)) => return Some(Change::Resync),
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = expired => {
+                    self.ended = true;
+                    return None;
+                }
+                _ = self.session_check.tick() => {
+                    if !self.session_active().await {
+                        self.ended = true;
+                        return None;
+                    }
+                    continue;
+                }
+            };
+
+            if !self.sees(&published).await {
+                continue;
+            }
+
+            if published.change.session_id() == Some(self.principal.sid) {
+                self.ended = true;
+            }
+            return Some(published.change);
+        }
+    }
+
+    async fn session_active(&self) -> bool {
+        session::is_active(&self.pool, &self.principal)
+            .await
+            .unwrap_or_else(|err| {
+                error!("Failed to check the session of a subscription: {:?}", err);
+                false
+            })
+    }
+
+    async fn sees(&self, published: &Published) -> bool {
+        match published.audience {
+            Audience::Everyone => true,
+            Audience::User(user_id) => {
+                user_id == self.principal.sub
+                    && (self.principal.reaches(None)
+                        || matches!(published.change, Change::SessionRevoked { .. }))
+            }
+            Audience::Collection {
+                org_id,
+                collection_id,
+            } => {
+                self.orgs.contains(&org_id)
+                    && self.principal.reaches(Some(collection_id))
+                    && self.can_read(collection_id).await
+            }
+        }
+    }
+
+    /// Members may have left or been removed since subscribing.
+    async fn can_read(&self, collection_id: Uuid) -> bool {
+        let allowed = sqlx::query_scalar!(
+            r#"
+            SELECT collection_can($1, $2, 'read') AS "allowed!"
+            "#,
+            collection_id,
+            self.principal.sub
+        )
+        .fetch_one(&self.pool)
+        .await;
+
+        allowed.unwrap_or_else(|err| {
+            error!("Failed to check access to a collection: {:?}", err);
+            false
+        })
+    }
+
+    /// Push the changes, mapped by `map`, to a channel read by the connection of the
+    /// subscriber. Stops when the session is revoked, the token expires or the connection
+    /// goes away.
+    pub fn into_receiver<T, F>(mut self, map: F) -> mpsc::Receiver<T>
+    where
+        T: Send + 'static,
+        F: Fn(Change) -> T + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BUFFER);
+
+        spawn(async move {
+            loop {
+                let change = select! {
+                    change = self.next() => change,
+                    _ = tx.closed() => break,
+                };
+                let Some(change) = change else {
+                    break;
+                };
+                if tx.send(map(change)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        rx
+    }
+}
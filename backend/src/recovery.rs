@@ -0,0 +1,135 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{account, error::CpassError, hashing::Argon, jwt::session, password_policy};
+
+/// Recovery key of a user as stored by the server.
+pub struct RecoveryKey {
+    /// Hash of the secret the client derives from the recovery key.
+    pub secret_hash: String,
+    /// Vault key wrapped by the recovery key.
+    pub vault_key: Vec<u8>,
+}
+
+impl RecoveryKey {
+    pub fn new(secret: &str, vault_key: Vec<u8>) -> Result<Self, CpassError> {
+        if secret.is_empty() || vault_key.is_empty() {
+            return Err(CpassError::InvalidRequest(
+                "recovery secret and vault key can not be empty".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            secret_hash: Argon::hash_password(secret.as_bytes())?,
+            vault_key,
+        })
+    }
+}
+
+/// Replace the recovery key of `user_id`, the previous one stops working.
+pub async fn set_recovery_key(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    recovery: RecoveryKey,
+) -> Result<(), CpassError> {
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET recovery_secret = $1, recovery_vault_key = $2
+        WHERE id = $3
+        "#,
+        recovery.secret_hash,
+        recovery.vault_key,
+        user_id
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Id and wrapped recovery vault key of the user `email` if `secret` matches their
+/// recovery key. The row stays locked until the end of the transaction.
+async fn verify(
+    conn: &mut PgConnection,
+    email: &str,
+    secret: &str,
+) -> Result<(Uuid, Vec<u8>), CpassError> {
+    let user = sqlx::query!(
+        r#"
+        SELECT id, recovery_secret, recovery_vault_key FROM users
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        email
+    )
+    .fetch_optional(conn)
+    .await?
+    .ok_or(CpassError::InvalidUsernameOrPassword)?;
+
+    let (Some(hash), Some(vault_key)) = (user.recovery_secret, user.recovery_vault_key) else {
+        return Err(CpassError::InvalidUsernameOrPassword);
+    };
+
+    match Argon::verify(secret.as_bytes(), &hash)? {
+        true => Ok((user.id, vault_key)),
+        false => Err(CpassError::InvalidUsernameOrPassword),
+    }
+}
+
+/// The id of `email` and their vault key wrapped by their recovery key, the first step of
+/// a recovery.
+pub async fn recovery_vault_key(
+    conn: &mut PgConnection,
+    email: &str,
+    secret: &str,
+) -> Result<(Uuid, Vec<u8>), CpassError> {
+    verify(conn, email, secret).await
+}
+
+/// Set a new master password for `email` with their recovery key.
+///
+/// `password` is the new login password, checked against the password policy once the
+/// recovery key matched, and `vault_key` the unchanged vault key wrapped by the new master
+/// password, so items need no re-encryption. The used recovery key is replaced by
+/// `recovery` and every session is revoked. Returns the id of the recovered account.
+pub async fn recover_account(
+    conn: &mut PgConnection,
+    email: &str,
+    secret: &str,
+    password: &str,
+    vault_key: Vec<u8>,
+    recovery: RecoveryKey,
+) -> Result<Uuid, CpassError> {
+    if vault_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "vault key can not be empty".to_string(),
+        ));
+    }
+
+    let (user_id, _) = verify(&mut *conn, email, secret).await?;
+
+    let user_inputs = account::user_inputs(&mut *conn, user_id).await?;
+    password_policy::check(password, &user_inputs)?;
+    let password_hash = Argon::hash_password(password.as_bytes())?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET password = $1, vault_key = $2, recovery_secret = $3, recovery_vault_key = $4,
+            pending_email = NULL, pending_password = NULL, pending_vault_key = NULL
+        WHERE id = $5
+        "#,
+        password_hash,
+        vault_key,
+        recovery.secret_hash,
+        recovery.vault_key,
+        user_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    session::revoke_all(conn, user_id).await?;
+
+    Ok(user_id)
+}
@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{error::CpassError, jwt::session};
+
+/// An item re-encrypted by the client under the new vault key.
+pub struct RotatedItem {
+    pub id: Uuid,
+    pub name: Vec<u8>,
+    pub password: Vec<u8>,
+    pub website: Option<Vec<u8>>,
+    pub username: Option<Vec<u8>>,
+    pub description: Option<Vec<u8>>,
+    /// Item key re-wrapped by the new vault key, required for items that have one.
+    pub item_key: Option<Vec<u8>>,
+}
+
+/// Outcome of a rotation, telling the client which keys it has to wrap again.
+pub struct Rotation {
+    /// New revision of the vault.
+    pub revision: i64,
+    /// The recovery key wrapped the previous vault key and was cleared.
+    pub recovery_key_cleared: bool,
+    /// Emergency contacts whose wrapped vault key was cleared.
+    pub emergency_keys_cleared: i64,
+}
+
+/// Replace the wrapped vault key and the ciphertext of every item, in the transaction of
+/// the caller.
+///
+/// `items` must cover every personal item of the user, trashed ones included, items of
+/// collections are encrypted under the organization key and left alone. The vault must
+/// still be at `expected_revision`, otherwise nothing is written. Users with a key pair
+/// must upload their private key wrapped by the new vault key as well. Vault keys wrapped
+/// for emergency contacts and by the recovery key are cleared until the user wraps the new
+/// one, the returned [`Rotation`] tells which were. Every session of the user except
+/// `session_id` is revoked.
+pub async fn rotate_vault_key(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    session_id: Uuid,
+    expected_revision: i64,
+    wrapped_vault_key: Vec<u8>,
+    wrapped_private_key: Option<Vec<u8>>,
+    items: Vec<RotatedItem>,
+) -> Result<Rotation, CpassError> {
+    if wrapped_vault_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "wrapped vault key can not be empty".to_string(),
+        ));
+    }
+
+    // Locking the user row blocks every other write to the vault until we are done.
+    let user = sqlx::query!(
+        r#"
+        SELECT
+            revision,
+            wrapped_private_key IS NOT NULL AS "has_key_pair!",
+            recovery_vault_key IS NOT NULL AS "has_recovery_key!"
+        FROM users
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        owner_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if user.revision != expected_revision {
+        return Err(CpassError::VersionMismatch(user.revision));
+    }
+
+    if user.has_key_pair != wrapped_private_key.is_some() {
+        return Err(CpassError::InvalidRequest(
+            "the private key must be re-wrapped if and only if the user has a key pair".to_string(),
+        ));
+    }
+
+    let stored = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM passwords
+        WHERE owner_id = $1 AND collection_id IS NULL
+        "#,
+        owner_id
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .collect::<HashSet<Uuid>>();
+
+    let mut uploaded = HashSet::with_capacity(items.len());
+    for item in &items {
+        if !uploaded.insert(item.id) {
+            return Err(CpassError::InvalidRequest(format!(
+                "item {} was uploaded more than once",
+                item.id
+            )));
+        }
+    }
+
+    let missing = stored.difference(&uploaded).count();
+    let unknown = uploaded.difference(&stored).count();
+    if missing > 0 || unknown > 0 {
+        return Err(CpassError::InvalidRequest(format!(
+            "every item must be re-encrypted, {} missing and {} unknown",
+            missing, unknown
+        )));
+    }
+
+    for item in items {
+        // An item key can be re-wrapped but neither dropped nor introduced by a rotation.
+        let res = sqlx::query!(
+            r#"
+            UPDATE passwords
+            SET
+                name = $1,
+                password = $2,
+                website = $3,
+                username = $4,
+                description = $5,
+                item_key = $8
+            WHERE id = $6 AND owner_id = $7 AND collection_id IS NULL
+                AND (item_key IS NULL) = ($8::BYTEA IS NULL)
+            "#,
+            item.name,
+            item.password,
+            item.website,
+            item.username,
+            item.description,
+            item.id,
+            owner_id,
+            item.item_key
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(CpassError::InvalidRequest(format!(
+                "the item key of item {} must be re-wrapped",
+                item.id
+            )));
+        }
+    }
+
+    let revision = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET
+            vault_key = $1,
+            wrapped_private_key = COALESCE($3, wrapped_private_key),
+            recovery_secret = NULL,
+            recovery_vault_key = NULL,
+            pending_email = NULL,
+            pending_password = NULL,
+            pending_vault_key = NULL
+        WHERE id = $2
+        RETURNING revision
+        "#,
+        wrapped_vault_key,
+        owner_id,
+        wrapped_private_key
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let emergency_keys_cleared = sqlx::query!(
+        r#"
+        UPDATE emergency_contacts
+        SET wrapped_vault_key = NULL
+        WHERE grantor_id = $1 AND wrapped_vault_key IS NOT NULL
+        "#,
+        owner_id
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    session::revoke_others(conn, owner_id, session_id).await?;
+
+    Ok(Rotation {
+        revision,
+        recovery_key_cleared: user.has_recovery_key,
+        emergency_keys_cleared: emergency_keys_cleared as i64,
+    })
+}
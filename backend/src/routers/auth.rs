@@ -1,20 +1,33 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Json, State},
-    http::{HeaderMap, StatusCode},
-    response::Response,
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 
 use crate::{
+    account,
+    audit::{self, AuditQuery, Event, EventType, Origin},
     db::Db,
+    device::{self, DeviceLogin},
     error::CpassError,
     hashing::Argon,
-    jwt::generate::{claims_from_headers, create_token},
-    AppState,
+    jwt::session,
+    password_policy,
+    policy::Scope,
+    recovery, verification, AppState,
 };
 
-use super::models::{CreateUserRequest, LoginRequest, UpdateUserRequest, User};
+use super::{
+    models::{
+        AuditEvent, ChangeMasterPasswordRequest, CreateUserRequest, DeleteUserRequest, Device,
+        DeviceApproval, KeyPair, Keys, ListAuditEventsQuery, LoginRequest, PublicKey,
+        RecoverAccountRequest, RecoveryCredentials, RecoveryKey, RecoveryVaultKey,
+        ScheduledDeletion, UpdateUserRequest, User,
+    },
+    AuthUser, ValidJson,
+};
 
 /// Login a user
 #[utoipa::path(
@@ -24,15 +37,26 @@ use super::models::{CreateUserRequest, LoginRequest, UpdateUserRequest, User};
     request_body = LoginRequest,
     responses(
         (status = 200, description = "User is logged in", body = User),
+        (status = 400, description = "Unknown scope or invalid device"),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Device waits for approval, sent no device token when approval is required, or the account is scheduled for deletion"),
     )
 )]
 pub async fn login(
+    origin: Origin,
     State(state): State<Arc<AppState>>,
-    Json(request): Json<LoginRequest>,
+    ValidJson(request): ValidJson<LoginRequest>,
 ) -> Result<(StatusCode, Json<User>), Response<String>> {
-    let mut conn = state.pool.conn().await?;
-    let LoginRequest { email, password } = request;
+    let mut tx = state.pool.tx().await?;
+    let LoginRequest {
+        email,
+        password,
+        scopes,
+        device_token,
+        device_name,
+        device_type,
+    } = request;
+    let scopes = Scope::requested(&scopes)?;
 
     let user = sqlx::query!(
         r#"
@@ -41,32 +65,73 @@ pub async fn login(
         "#,
         email
     )
-    .fetch_one(&mut *conn)
+    .fetch_optional(&mut *tx)
     .await
-    .map_err(|err| match err {
-        sqlx::Error::RowNotFound => CpassError::InvalidUsernameOrPassword,
-        err => CpassError::DatabaseError(err),
-    })?;
+    .map_err(CpassError::DatabaseError)?;
 
-    match Argon::verify(password.as_bytes(), &user.password) {
-        Ok(false) => return Err(CpassError::InvalidUsernameOrPassword.into()),
-        Err(e) => return Err(e.into()),
-        _ => {}
+    // Machine accounts have no password and never log in.
+    let verified = match user.as_ref().and_then(|user| user.password.as_ref()) {
+        Some(hash) => Argon::verify(password.as_bytes(), hash)?,
+        None => false,
+    };
+    let user = match (user, verified) {
+        (Some(user), true) => user,
+        (user, _) => {
+            let event = Event::of(EventType::LoginFailed, user.map(|user| user.id));
+            audit::record(&mut tx, &origin, event.detail(email)).await?;
+            tx.commit().await.map_err(CpassError::DatabaseError)?;
+            return Err(CpassError::InvalidUsernameOrPassword.into());
+        }
+    };
+
+    account::check_not_scheduled(user.deletion_scheduled_at)?;
+
+    if let Some(hash) = &user.password {
+        account::upgrade_password_hash(&mut tx, user.id, hash, &password).await?;
+    }
+
+    let login = DeviceLogin {
+        token: device_token,
+        name: device_name,
+        device_type,
+    };
+    let device = device::record_login(&mut tx, user.id, login, &origin).await?;
+    let token = match device.approved {
+        true => Some(session::start(&mut tx, user.id, Some(device.id), &scopes).await?),
+        false => None,
+    };
+
+    if device.new {
+        let event = Event::of(EventType::NewDeviceLogin, Some(user.id)).detail(device.name);
+        audit::record(&mut tx, &origin, event).await?;
+    }
+    if token.is_some() {
+        let event = Event::of(EventType::Login, Some(user.id));
+        audit::record(&mut tx, &origin, event).await?;
     }
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
 
-    let token = create_token(&user.id)?;
+    let Some(token) = token else {
+        return Err(CpassError::Forbidden(
+            "this device waits for approval from another device of the account".to_string(),
+        )
+        .into());
+    };
 
     let response: Json<User> = User {
         email,
         token,
         username: user.username,
+        vault_key: user.vault_key,
+        public_key: user.public_key,
+        wrapped_private_key: user.wrapped_private_key,
     }
     .into();
 
     Ok((StatusCode::OK, response))
 }
 
-/// Create a new user
+/// Create a new user and mail a verification token to its email address
 #[utoipa::path(
     post,
     path = "/api/v1/auth/user",
@@ -74,33 +139,47 @@ pub async fn login(
     request_body = CreateUserRequest,
     responses(
         (status = 201, description = "User is created", body = User),
+        (status = 400, description = "Invalid email address"),
         (status = 409, description = "User already exists"),
     )
 )]
 pub async fn create_user(
+    origin: Origin,
     State(state): State<Arc<AppState>>,
-    Json(request): Json<CreateUserRequest>,
+    ValidJson(request): ValidJson<CreateUserRequest>,
 ) -> Result<(StatusCode, Json<User>), Response<String>> {
-    let mut conn = state.pool.conn().await?;
+    let mut tx = state.pool.tx().await?;
     let CreateUserRequest {
         email,
         username,
         password,
+        vault_key,
+        recovery,
     } = request;
 
+    verification::validate_email(&email)?;
+    password_policy::check(&password, &[&email, &username])?;
     let hash = Argon::hash_password(password.as_bytes())?;
+    let recovery = recovery
+        .map(|key| recovery::RecoveryKey::new(&key.secret, key.vault_key))
+        .transpose()?;
+    let (recovery_secret, recovery_vault_key) =
+        recovery.map(|key| (key.secret_hash, key.vault_key)).unzip();
 
     let res = sqlx::query!(
         r#"
-        INSERT INTO users(email, username, password)
-        VALUES ($1, $2, $3)
+        INSERT INTO users(email, username, password, vault_key, recovery_secret, recovery_vault_key)
+        VALUES ($1, $2, $3, $4, $5, $6)
         RETURNING id
         "#,
         email,
         username,
-        hash
+        hash,
+        vault_key,
+        recovery_secret,
+        recovery_vault_key
     )
-    .fetch_one(&mut *conn)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|err| match err {
         sqlx::Error::Database(err) if err.is_unique_violation() => {
@@ -109,19 +188,32 @@ pub async fn create_user(
         err => CpassError::DatabaseError(err),
     })?;
 
-    let token = create_token(&res.id)?;
+    verification::start(&mut tx, res.id, &email).await?;
+    let token = session::start(&mut tx, res.id, None, &Scope::ALL).await?;
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::of(EventType::UserCreated, Some(res.id)),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
 
     let response: Json<User> = User {
         email,
         token,
         username,
+        vault_key,
+        public_key: None,
+        wrapped_private_key: None,
     }
     .into();
 
     Ok((StatusCode::CREATED, response))
 }
 
-/// Update a user information
+/// Update a user information. A new email address takes effect once the token mailed to
+/// it is verified, along with the login password and vault key wrapping derived with it
 #[utoipa::path(
     put,
     path = "/api/v1/auth/user",
@@ -129,80 +221,664 @@ pub async fn create_user(
     request_body = UpdateUserRequest,
     responses(
         (status = 204, description = "User is updated"),
-        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Invalid email address or missing keys for it"),
+        (status = 401, description = "Unauthorized or wrong current password"),
+        (status = 409, description = "Email address is taken"),
     )
 )]
 pub async fn update_user(
-    headers: HeaderMap,
+    AuthUser(principal): AuthUser,
+    origin: Origin,
     State(state): State<Arc<AppState>>,
-    Json(request): Json<UpdateUserRequest>,
+    ValidJson(request): ValidJson<UpdateUserRequest>,
 ) -> Result<StatusCode, Response<String>> {
-    let mut conn = state.pool.conn().await?;
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
     let UpdateUserRequest {
         email,
         username,
+        current_password,
         password,
+        vault_key,
     } = request;
 
-    let user_id = claims_from_headers(&headers)?.sub;
+    if let Some(email) = &email {
+        // Validation requires the keys with an email address.
+        let (Some(current_password), Some(password), Some(vault_key)) =
+            (current_password, password, vault_key)
+        else {
+            return Err(CpassError::InvalidRequest(
+                "an email change needs the keys derived with the new address".to_string(),
+            )
+            .into());
+        };
+        let [_, current_username] = account::user_inputs(&mut tx, user_id).await?;
+        password_policy::check(&password, &[email, &current_username])?;
+        let hash = Argon::hash_password(password.as_bytes())?;
 
-    let password = password
-        .map(|pass| Argon::hash_password(pass.as_bytes()))
-        .transpose()?;
+        account::change_email(
+            &mut tx,
+            user_id,
+            email,
+            &current_password,
+            &hash,
+            &vault_key,
+        )
+        .await?;
+    }
 
     let _ = sqlx::query!(
         r#"
         UPDATE users
-        SET
-            email = COALESCE($1, email),
-            username = COALESCE($2, username),
-            password = COALESCE($3, password)
-        WHERE id = $4
+        SET username = COALESCE($1, username)
+        WHERE id = $2
         "#,
-        email,
         username,
-        password,
         user_id
     )
-    .execute(&mut *conn)
+    .execute(&mut *tx)
     .await
-    .map_err(|err| match err {
-        sqlx::Error::Database(err) if err.is_unique_violation() => {
-            CpassError::UserAlreadyExists(email.unwrap())
-        }
-        err => CpassError::DatabaseError(err),
-    })?;
+    .map_err(CpassError::DatabaseError)?;
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::UserUpdated, &principal),
+    )
+    .await?;
+    if let Some(email) = email {
+        let event = Event::by(EventType::EmailChangeRequested, &principal).detail(email);
+        audit::record(&mut tx, &origin, event).await?;
+    }
+
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
 
     Ok(StatusCode::NO_CONTENT)
 }
 
-/// Delete a user
+/// Delete a user, after a grace period when one is configured
 #[utoipa::path(
     delete,
     path = "/api/v1/auth/user",
     tag = "Auth",
+    request_body = DeleteUserRequest,
     responses(
+        (status = 202, description = "Deletion is scheduled", body = ScheduledDeletion),
         (status = 204, description = "User is deleted"),
-        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "An organization would be left without an owner"),
+        (status = 401, description = "Unauthorized or wrong password"),
     )
 )]
 pub async fn delete_user(
-    headers: HeaderMap,
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<DeleteUserRequest>,
+) -> Result<axum::response::Response, Response<String>> {
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+
+    let scheduled = account::request_deletion(&mut tx, user_id, &request.password).await?;
+
+    let event_type = match scheduled {
+        Some(_) => EventType::DeletionScheduled,
+        None => EventType::AccountDeleted,
+    };
+    audit::record(&mut tx, &origin, Event::by(event_type, &principal)).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(match scheduled {
+        Some(scheduled) => (
+            StatusCode::ACCEPTED,
+            Json(ScheduledDeletion {
+                deletes_at: scheduled.deletes_at,
+                cancel_token: scheduled.cancel_token,
+            }),
+        )
+            .into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    })
+}
+
+/// Cancel a scheduled deletion
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/deletion/{token}/cancel",
+    tag = "Auth",
+    params(
+        ("token" = String, Path, description = "Token returned when the deletion was requested"),
+    ),
+    responses(
+        (status = 204, description = "Deletion is cancelled"),
+        (status = 404, description = "No deletion with that token"),
+    )
+)]
+pub async fn cancel_deletion(
+    origin: Origin,
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let mut tx = state.pool.tx().await?;
+
+    let user_id = account::cancel_deletion(&mut tx, &token).await?;
+
+    let event = Event::of(EventType::DeletionCancelled, Some(user_id));
+    audit::record(&mut tx, &origin, event).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Verify the email address a token was mailed to
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/email/{token}/verify",
+    tag = "Auth",
+    params(
+        ("token" = String, Path, description = "Token mailed to the address"),
+    ),
+    responses(
+        (status = 204, description = "Email address is verified"),
+        (status = 404, description = "No verification with that token, or it expired"),
+        (status = 409, description = "Email address was taken meanwhile"),
+    )
+)]
+pub async fn verify_email(
+    origin: Origin,
+    Path(token): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let mut tx = state.pool.tx().await?;
+
+    let user_id = verification::verify(&mut tx, &token).await?;
+
+    let event = Event::of(EventType::EmailVerified, Some(user_id));
+    audit::record(&mut tx, &origin, event).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Mail a new verification token to the address waiting to be verified
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/email/verification",
+    tag = "Auth",
+    responses(
+        (status = 202, description = "Verification mail is queued"),
+        (status = 400, description = "Email address is already verified"),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn resend_verification(
+    AuthUser(principal): AuthUser,
     State(state): State<Arc<AppState>>,
 ) -> Result<StatusCode, Response<String>> {
     let mut conn = state.pool.conn().await?;
-    let user_id = claims_from_headers(&headers)?.sub;
 
-    let _ = sqlx::query!(
+    verification::resend(&mut conn, principal.sub).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set the key pair used to receive shared items
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/keys",
+    tag = "Auth",
+    request_body = KeyPair,
+    responses(
+        (status = 204, description = "Key pair is set"),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn set_key_pair(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<KeyPair>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+    let KeyPair {
+        public_key,
+        wrapped_private_key,
+    } = request;
+
+    if public_key.is_empty() || wrapped_private_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "public_key and wrapped_private_key can not be empty".to_string(),
+        )
+        .into());
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET public_key = $1, wrapped_private_key = $2
+        WHERE id = $3
+        "#,
+        public_key,
+        wrapped_private_key,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::KeyPairSet, &principal),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get the wrapped keys of the user
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/keys",
+    tag = "Auth",
+    responses(
+        (status = 200, description = "Returns the keys of the user", body = Keys),
+        (status = 403, description = "Missing the vault:read scope"),
+    )
+)]
+pub async fn get_keys(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Keys>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let keys = sqlx::query_as!(
+        Keys,
         r#"
-        DELETE FROM users
+        SELECT vault_key, public_key, wrapped_private_key FROM users
         WHERE id = $1
         "#,
         user_id
     )
-    .execute(&mut *conn)
+    .fetch_optional(&mut *conn)
     .await
-    .map_err(CpassError::DatabaseError);
+    .map_err(CpassError::DatabaseError)?
+    .ok_or_else(|| CpassError::NotFound("User not found".to_string()))?;
+
+    audit::record(
+        &mut conn,
+        &origin,
+        Event::by(EventType::KeysRead, &principal),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(keys)))
+}
+
+/// Get the public key of a user to share items with them
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/public_key/{email}",
+    tag = "Auth",
+    params(
+        ("email" = String, Path, description = "Email of the user"),
+    ),
+    responses(
+        (status = 200, description = "Returns the public key", body = PublicKey),
+        (status = 404, description = "User not found or has no key pair"),
+    )
+)]
+pub async fn get_public_key(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(email): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<PublicKey>), Response<String>> {
+    let mut conn = state.pool.conn().await?;
+
+    let public_key = sqlx::query_scalar!(
+        r#"
+        SELECT public_key FROM users
+        WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(CpassError::DatabaseError)?
+    .flatten()
+    .ok_or_else(|| CpassError::NotFound("User with that email has no public key".to_string()))?;
+
+    let event = Event::by(EventType::PublicKeyRead, &principal).detail(email);
+    audit::record(&mut conn, &origin, event).await?;
+
+    Ok((StatusCode::OK, Json(PublicKey { public_key })))
+}
+
+/// Replace the recovery key of the user
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/recovery_key",
+    tag = "Auth",
+    request_body = RecoveryKey,
+    responses(
+        (status = 204, description = "Recovery key is set"),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn set_recovery_key(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<RecoveryKey>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+    let RecoveryKey { secret, vault_key } = request;
+
+    let recovery = recovery::RecoveryKey::new(&secret, vault_key)?;
+    recovery::set_recovery_key(&mut tx, user_id, recovery).await?;
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::RecoveryKeySet, &principal),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get the vault key wrapped by the recovery key of a user
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/recovery/vault_key",
+    tag = "Auth",
+    request_body = RecoveryCredentials,
+    responses(
+        (status = 200, description = "Returns the wrapped vault key", body = RecoveryVaultKey),
+        (status = 401, description = "Wrong email or recovery key"),
+    )
+)]
+pub async fn get_recovery_vault_key(
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<RecoveryCredentials>,
+) -> Result<(StatusCode, Json<RecoveryVaultKey>), Response<String>> {
+    let mut conn = state.pool.conn().await?;
+    let RecoveryCredentials { email, secret } = request;
+
+    let (user_id, vault_key) = match recovery::recovery_vault_key(&mut conn, &email, &secret).await
+    {
+        Ok(recovered) => recovered,
+        Err(err) => {
+            let event = Event::of(EventType::RecoveryFailed, None).detail(email);
+            audit::record(&mut conn, &origin, event).await?;
+            return Err(err.into());
+        }
+    };
+
+    let event = Event::of(EventType::RecoveryVaultKeyRead, Some(user_id));
+    audit::record(&mut conn, &origin, event).await?;
+
+    Ok((StatusCode::OK, Json(RecoveryVaultKey { vault_key })))
+}
+
+/// Set a new master password with the recovery key
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/recovery",
+    tag = "Auth",
+    request_body = RecoverAccountRequest,
+    responses(
+        (status = 204, description = "Master password is replaced"),
+        (status = 401, description = "Wrong email or recovery key"),
+    )
+)]
+pub async fn recover_account(
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<RecoverAccountRequest>,
+) -> Result<StatusCode, Response<String>> {
+    let RecoverAccountRequest {
+        email,
+        secret,
+        password,
+        vault_key,
+        recovery,
+    } = request;
+
+    let recovery = recovery::RecoveryKey::new(&recovery.secret, recovery.vault_key)?;
+
+    let mut tx = state.pool.tx().await?;
+
+    let user_id =
+        match recovery::recover_account(&mut tx, &email, &secret, &password, vault_key, recovery)
+            .await
+        {
+            Ok(user_id) => user_id,
+            Err(err @ CpassError::InvalidUsernameOrPassword) => {
+                drop(tx);
+                let mut conn = state.pool.conn().await?;
+                let event = Event::of(EventType::RecoveryFailed, None).detail(email);
+                audit::record(&mut conn, &origin, event).await?;
+                return Err(err.into());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+    let event = Event::of(EventType::AccountRecovered, Some(user_id));
+    audit::record(&mut tx, &origin, event).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the master password, re-wrapping the vault key
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/master_password",
+    tag = "Auth",
+    request_body = ChangeMasterPasswordRequest,
+    responses(
+        (status = 204, description = "Master password is changed, every session is revoked"),
+        (status = 401, description = "Unauthorized or wrong current password"),
+    )
+)]
+pub async fn change_master_password(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<ChangeMasterPasswordRequest>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let ChangeMasterPasswordRequest {
+        current_password,
+        password,
+        vault_key,
+    } = request;
+
+    let mut tx = state.pool.tx().await?;
+
+    let user_inputs = account::user_inputs(&mut tx, user_id).await?;
+    password_policy::check(&password, &user_inputs)?;
+    let hash = Argon::hash_password(password.as_bytes())?;
+
+    account::change_master_password(&mut tx, user_id, &current_password, hash, vault_key).await?;
+
+    let event = Event::by(EventType::MasterPasswordChanged, &principal);
+    audit::record(&mut tx, &origin, event).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get the audit events of the user
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/audit",
+    tag = "Auth",
+    params(ListAuditEventsQuery),
+    responses(
+        (status = 200, description = "Returns the audit events, newest first", body = Vec<AuditEvent>),
+        (status = 400, description = "Unknown event type or limit out of range"),
+    )
+)]
+pub async fn list_audit_events(
+    AuthUser(principal): AuthUser,
+    Query(query): Query<ListAuditEventsQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<AuditEvent>>), Response<String>> {
+    let ListAuditEventsQuery {
+        since,
+        until,
+        types,
+        before,
+        limit,
+    } = query;
+
+    let event_types = types
+        .iter()
+        .flat_map(|types| types.split(','))
+        .map(|event_type| event_type.trim().to_string())
+        .filter(|event_type| !event_type.is_empty())
+        .collect::<Vec<String>>();
+
+    let query = AuditQuery {
+        since,
+        until,
+        event_types: EventType::parse_all(&event_types)?,
+        before,
+        limit,
+    };
+
+    let events = audit::list_events(&state.pool, principal.sub, query)
+        .await?
+        .into_iter()
+        .map(AuditEvent::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(events)))
+}
+
+/// Get the devices the user logged in from
+#[utoipa::path(
+    get,
+    path = "/api/v1/auth/devices",
+    tag = "Auth",
+    responses(
+        (status = 200, description = "Returns the devices, oldest first", body = Vec<Device>),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn list_devices(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Device>>), Response<String>> {
+    let mut conn = state.pool.conn().await?;
+
+    let devices = device::list(&mut conn, &principal)
+        .await?
+        .into_iter()
+        .map(Device::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(devices)))
+}
+
+/// Remove a device, ending its sessions
+#[utoipa::path(
+    delete,
+    path = "/api/v1/auth/devices/{id}",
+    tag = "Auth",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Device id"),
+    ),
+    responses(
+        (status = 204, description = "Device is removed"),
+        (status = 404, description = "Device not found"),
+    )
+)]
+pub async fn remove_device(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let mut tx = state.pool.tx().await?;
+
+    let name = device::remove(&mut tx, principal.sub, id).await?;
+
+    let event = Event::by(EventType::DeviceRemoved, &principal).detail(name);
+    audit::record(&mut tx, &origin, event).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Approve a device waiting for approval, from an approved device
+#[utoipa::path(
+    post,
+    path = "/api/v1/auth/devices/{id}/approve",
+    tag = "Auth",
+    params(
+        ("id" = uuid::Uuid, Path, description = "Device id"),
+    ),
+    responses(
+        (status = 204, description = "Device is approved, its next login gets a session"),
+        (status = 403, description = "Caller is not on an approved device"),
+        (status = 404, description = "No device waiting for approval with that id"),
+    )
+)]
+pub async fn approve_device(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let mut tx = state.pool.tx().await?;
+
+    let name = device::approve(&mut tx, &principal, id).await?;
+
+    let event = Event::by(EventType::DeviceApproved, &principal).detail(name);
+    audit::record(&mut tx, &origin, event).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Require, or stop requiring, new devices to be approved before they get a session
+#[utoipa::path(
+    put,
+    path = "/api/v1/auth/devices/approval",
+    tag = "Auth",
+    request_body = DeviceApproval,
+    responses(
+        (status = 204, description = "Setting is saved"),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn set_device_approval(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<DeviceApproval>,
+) -> Result<StatusCode, Response<String>> {
+    let mut tx = state.pool.tx().await?;
+    let DeviceApproval { required } = request;
+
+    device::set_approval(&mut tx, principal.sub, required).await?;
+
+    let detail = match required {
+        true => "required",
+        false => "not required",
+    };
+    let event = Event::by(EventType::DeviceApprovalChanged, &principal).detail(detail);
+    audit::record(&mut tx, &origin, event).await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
 
     Ok(StatusCode::NO_CONTENT)
 }
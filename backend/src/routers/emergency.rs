@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Response,
+};
+
+use super::{
+    models::{AddEmergencyContactRequest, EmergencyContact, TakeoverAccountRequest},
+    AuthUser, ValidJson,
+};
+use crate::{account, db::Db, emergency, hashing::Argon, password_policy, AppState};
+
+/// Designate an emergency contact, or change an existing one
+#[utoipa::path(
+    post,
+    path = "/api/v1/emergency/contacts",
+    tag = "Emergency",
+    request_body = AddEmergencyContactRequest,
+    responses(
+        (status = 201, description = "Emergency contact saved", body = EmergencyContact),
+        (status = 400, description = "Key is empty, waiting period is out of range or the contact has no key pair"),
+        (status = 404, description = "User not found"),
+    )
+)]
+pub async fn add_contact(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<AddEmergencyContactRequest>,
+) -> Result<(StatusCode, Json<EmergencyContact>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+    let AddEmergencyContactRequest {
+        email,
+        access,
+        wait_days,
+        wrapped_vault_key,
+    } = request;
+
+    let contact = emergency::add_contact(
+        &mut conn,
+        user_id,
+        &email,
+        access,
+        wait_days,
+        wrapped_vault_key,
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(contact.into())))
+}
+
+/// Get the emergency contacts of the user
+#[utoipa::path(
+    get,
+    path = "/api/v1/emergency/contacts",
+    tag = "Emergency",
+    responses(
+        (status = 200, description = "Returns the emergency contacts", body = Vec<EmergencyContact>),
+    )
+)]
+pub async fn list_contacts(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<EmergencyContact>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let contacts = emergency::list_contacts(&mut conn, user_id)
+        .await?
+        .into_iter()
+        .map(EmergencyContact::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(contacts)))
+}
+
+/// Get the users who designated the user as their emergency contact
+#[utoipa::path(
+    get,
+    path = "/api/v1/emergency/grants",
+    tag = "Emergency",
+    responses(
+        (status = 200, description = "Returns the emergency grants", body = Vec<EmergencyContact>),
+    )
+)]
+pub async fn list_grants(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<EmergencyContact>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let grants = emergency::list_grants(&mut conn, user_id)
+        .await?
+        .into_iter()
+        .map(EmergencyContact::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(grants)))
+}
+
+/// Accept to be an emergency contact
+#[utoipa::path(
+    post,
+    path = "/api/v1/emergency/grants/{id}/accept",
+    tag = "Emergency",
+    responses(
+        (status = 204, description = "Invite accepted"),
+        (status = 400, description = "Invite was already accepted"),
+        (status = 404, description = "Emergency contact not found"),
+    )
+)]
+pub async fn accept_emergency_invite(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    emergency::accept_invite(&mut conn, user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove an emergency contact, either the grantor or the grantee can
+#[utoipa::path(
+    delete,
+    path = "/api/v1/emergency/contacts/{id}",
+    tag = "Emergency",
+    responses(
+        (status = 204, description = "Emergency contact removed"),
+        (status = 404, description = "Emergency contact not found"),
+    )
+)]
+pub async fn remove_contact(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    emergency::remove_contact(&mut conn, user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Request emergency access, starting the waiting period
+#[utoipa::path(
+    post,
+    path = "/api/v1/emergency/grants/{id}/request",
+    tag = "Emergency",
+    responses(
+        (status = 200, description = "Access requested", body = EmergencyContact),
+        (status = 400, description = "Invite not accepted or access already requested"),
+        (status = 404, description = "Emergency contact not found"),
+    )
+)]
+pub async fn request_access(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<EmergencyContact>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let contact = emergency::request_access(&mut conn, user_id, id).await?;
+
+    Ok((StatusCode::OK, Json(contact.into())))
+}
+
+/// Approve a pending emergency access request
+#[utoipa::path(
+    post,
+    path = "/api/v1/emergency/contacts/{id}/approve",
+    tag = "Emergency",
+    responses(
+        (status = 204, description = "Access approved"),
+        (status = 400, description = "No pending request"),
+        (status = 404, description = "Emergency contact not found"),
+    )
+)]
+pub async fn approve_access(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    emergency::approve_access(&mut conn, user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reject a pending emergency access request or revoke an approved one
+#[utoipa::path(
+    post,
+    path = "/api/v1/emergency/contacts/{id}/reject",
+    tag = "Emergency",
+    responses(
+        (status = 204, description = "Access rejected"),
+        (status = 400, description = "No pending or approved request"),
+        (status = 404, description = "Emergency contact not found"),
+    )
+)]
+pub async fn reject_access(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    emergency::reject_access(&mut conn, user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Set a new master password for the grantor of an approved takeover access
+#[utoipa::path(
+    post,
+    path = "/api/v1/emergency/grants/{id}/takeover",
+    tag = "Emergency",
+    request_body = TakeoverAccountRequest,
+    responses(
+        (status = 204, description = "Master password of the grantor replaced"),
+        (status = 403, description = "Takeover access has not been approved"),
+        (status = 404, description = "Emergency contact not found"),
+    )
+)]
+pub async fn takeover_account(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<TakeoverAccountRequest>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let TakeoverAccountRequest {
+        password,
+        vault_key,
+    } = request;
+
+    let mut conn = state.pool.conn().await?;
+    let grantor_id = emergency::takeover_grantor(&mut conn, user_id, id).await?;
+    let user_inputs = account::user_inputs(&mut conn, grantor_id).await?;
+    drop(conn);
+
+    password_policy::check(&password, &user_inputs)?;
+    let hash = Argon::hash_password(password.as_bytes())?;
+
+    emergency::takeover(&state.pool, user_id, id, hash, vault_key).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
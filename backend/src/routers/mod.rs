@@ -1,28 +1,293 @@
 pub mod auth;
+pub mod emergency;
 mod models;
+pub mod notify;
 #[cfg(feature = "swagger")]
 pub mod openapi;
+pub mod org;
 pub mod pass;
+pub mod token;
+mod validation;
 
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
-use crate::AppState;
+use crate::{
+    audit::Origin,
+    error::CpassError,
+    jwt::models::Principal,
+    policy::{
+        self,
+        Permission::{self, Authenticated, Public, Restricted, Scope, Verified},
+        Scope::*,
+    },
+    validation::Validate,
+    AppState,
+};
 use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequest, FromRequestParts, Json, MatchedPath, Request, State},
+    http::{header::USER_AGENT, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
     routing::{delete, get, post, put},
     Router,
 };
+use serde::de::DeserializeOwned;
+use sqlx::PgPool;
 
 use self::{
-    auth::{create_user, delete_user, login, update_user},
-    pass::{add_password, delete_password, get_password, get_passwords, update_password},
+    auth::{
+        approve_device, cancel_deletion, change_master_password, create_user, delete_user,
+        get_keys, get_public_key, get_recovery_vault_key, list_audit_events, list_devices, login,
+        recover_account, remove_device, resend_verification, set_device_approval, set_key_pair,
+        set_recovery_key, update_user, verify_email,
+    },
+    emergency::{
+        accept_emergency_invite, add_contact, approve_access, list_contacts, list_grants,
+        reject_access, remove_contact, request_access, takeover_account,
+    },
+    notify::{
+        create_webhook, delete_webhook, list_webhook_deliveries, list_webhooks, ping_webhook,
+        subscribe,
+    },
+    org::{
+        accept_invite, create_collection, create_organization, delete_collection, invite_member,
+        list_collections, list_members, list_organizations, remove_member,
+    },
+    pass::{
+        add_password, batch_add_passwords, batch_delete_passwords, batch_update_passwords,
+        delete_password, get_emergency_vault, get_password, get_passwords, list_collection_items,
+        list_shared_with_me, list_trash, purge_item, restore_item, revoke_share, rotate_vault_key,
+        share_item, sync, update_password,
+    },
+    token::{
+        create_access_token, create_machine_account, delete_machine_account, list_access_tokens,
+        list_machine_accounts, revoke_access_token,
+    },
 };
 
+/// Permission of every HTTP route, keyed by method and route pattern.
+const POLICY: &[(&str, Permission)] = &[
+    ("GET /api/healthcheck", Public),
+    // Auth
+    ("POST /api/v1/auth/login", Public),
+    ("POST /api/v1/auth/user", Public),
+    ("PUT /api/v1/auth/user", Scope(Account)),
+    ("DELETE /api/v1/auth/user", Scope(Account)),
+    ("GET /api/v1/auth/deletion/:token/cancel", Public),
+    ("GET /api/v1/auth/email/:token/verify", Public),
+    ("POST /api/v1/auth/email/verification", Scope(Account)),
+    ("PUT /api/v1/auth/keys", Scope(Account)),
+    ("GET /api/v1/auth/keys", Restricted(VaultRead)),
+    ("GET /api/v1/auth/public_key/:email", Authenticated),
+    ("PUT /api/v1/auth/recovery_key", Scope(Account)),
+    ("POST /api/v1/auth/recovery/vault_key", Public),
+    ("POST /api/v1/auth/recovery", Public),
+    ("PUT /api/v1/auth/master_password", Scope(Account)),
+    ("GET /api/v1/auth/audit", Scope(Account)),
+    ("GET /api/v1/auth/devices", Scope(Account)),
+    ("DELETE /api/v1/auth/devices/:id", Scope(Account)),
+    ("POST /api/v1/auth/devices/:id/approve", Scope(Account)),
+    ("PUT /api/v1/auth/devices/approval", Scope(Account)),
+    // Pass
+    ("GET /api/v1/pass/passwords", Scope(VaultRead)),
+    ("POST /api/v1/pass/password", Restricted(VaultWrite)),
+    ("GET /api/v1/pass/password/:id", Restricted(VaultRead)),
+    ("PUT /api/v1/pass/password/:id", Restricted(VaultWrite)),
+    ("DELETE /api/v1/pass/password/:id", Restricted(VaultWrite)),
+    ("GET /api/v1/pass/trash", Scope(VaultRead)),
+    ("DELETE /api/v1/pass/trash/:id", Scope(VaultManage)),
+    ("POST /api/v1/pass/trash/:id/restore", Scope(VaultWrite)),
+    ("GET /api/v1/pass/sync", Scope(VaultRead)),
+    ("POST /api/v1/pass/batch/add", Scope(VaultWrite)),
+    ("POST /api/v1/pass/batch/update", Scope(VaultWrite)),
+    ("POST /api/v1/pass/batch/delete", Scope(VaultWrite)),
+    ("POST /api/v1/pass/vault/rotate", Scope(VaultManage)),
+    (
+        "POST /api/v1/pass/password/:id/shares",
+        Verified(VaultManage),
+    ),
+    (
+        "DELETE /api/v1/pass/password/:id/shares/:email",
+        Scope(VaultManage),
+    ),
+    ("GET /api/v1/pass/shared", Scope(VaultRead)),
+    (
+        "GET /api/v1/pass/collections/:id/passwords",
+        Restricted(VaultRead),
+    ),
+    ("GET /api/v1/pass/emergency/:id/vault", Scope(Emergency)),
+    // Org
+    ("POST /api/v1/org/organizations", Verified(OrgManage)),
+    ("GET /api/v1/org/organizations", Scope(OrgRead)),
+    ("GET /api/v1/org/organizations/:id/members", Scope(OrgRead)),
+    (
+        "POST /api/v1/org/organizations/:id/members",
+        Verified(OrgManage),
+    ),
+    (
+        "DELETE /api/v1/org/organizations/:id/members/:email",
+        Scope(OrgManage),
+    ),
+    (
+        "POST /api/v1/org/organizations/:id/accept",
+        Scope(OrgManage),
+    ),
+    (
+        "POST /api/v1/org/organizations/:id/collections",
+        Scope(OrgManage),
+    ),
+    (
+        "GET /api/v1/org/organizations/:id/collections",
+        Scope(OrgRead),
+    ),
+    ("DELETE /api/v1/org/collections/:id", Scope(OrgManage)),
+    // Emergency
+    ("POST /api/v1/emergency/contacts", Verified(Emergency)),
+    ("GET /api/v1/emergency/contacts", Scope(Emergency)),
+    ("DELETE /api/v1/emergency/contacts/:id", Scope(Emergency)),
+    (
+        "POST /api/v1/emergency/contacts/:id/approve",
+        Scope(Emergency),
+    ),
+    (
+        "POST /api/v1/emergency/contacts/:id/reject",
+        Scope(Emergency),
+    ),
+    ("GET /api/v1/emergency/grants", Scope(Emergency)),
+    ("POST /api/v1/emergency/grants/:id/accept", Scope(Emergency)),
+    (
+        "POST /api/v1/emergency/grants/:id/request",
+        Scope(Emergency),
+    ),
+    (
+        "POST /api/v1/emergency/grants/:id/takeover",
+        Scope(Emergency),
+    ),
+    // Token
+    ("POST /api/v1/tokens/machine_accounts", Verified(Account)),
+    ("GET /api/v1/tokens/machine_accounts", Scope(Account)),
+    ("DELETE /api/v1/tokens/machine_accounts/:id", Scope(Account)),
+    ("POST /api/v1/tokens/access_tokens", Verified(Account)),
+    ("GET /api/v1/tokens/access_tokens", Scope(Account)),
+    ("DELETE /api/v1/tokens/access_tokens/:id", Scope(Account)),
+    // Notify
+    ("POST /api/v1/notify/webhooks", Verified(Account)),
+    ("GET /api/v1/notify/webhooks", Scope(Account)),
+    ("DELETE /api/v1/notify/webhooks/:id", Scope(Account)),
+    ("GET /api/v1/notify/webhooks/:id/deliveries", Scope(Account)),
+    ("POST /api/v1/notify/webhooks/:id/ping", Scope(Account)),
+    ("GET /api/v1/notifications", Restricted(VaultRead)),
+];
+
+/// Check every request against [`POLICY`] before it reaches its handler. Applied as a
+/// route layer, so it only sees requests matching a route.
+pub async fn enforce_policy(
+    State(pool): State<PgPool>,
+    matched: MatchedPath,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, Response<String>> {
+    let route = format!("{} {}", request.method(), matched.as_str());
+    let permission = policy::permission_of(POLICY, &route)?;
+
+    let origin = Origin {
+        ip: request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string()),
+        user_agent: request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    };
+    request.extensions_mut().insert(origin);
+
+    if let Some(principal) = policy::check(&pool, permission, request.headers()).await? {
+        request.extensions_mut().insert(principal);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Caller of a request, authenticated once by [`enforce_policy`].
+pub struct AuthUser(pub Principal);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = Response<String>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Only missing when the route is public and should not ask for a caller.
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .map(AuthUser)
+            .ok_or_else(|| {
+                CpassError::Unauthorized("No authorization token was found".to_string()).into()
+            })
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Origin {
+    type Rejection = Response<String>;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Origin>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// JSON body checked against the [`Validate`] rules of its model, failing with every
+/// invalid field before the handler runs.
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(body) = Json::<T>::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        body.validate()
+            .map_err(|err| Response::<String>::from(err).into_response())?;
+
+        Ok(ValidJson(body))
+    }
+}
+
 pub fn get_auth_service(app_state: AppState) -> Router {
     Router::new()
         .route("/login", post(login))
         .route("/user", post(create_user))
         .route("/user", put(update_user))
         .route("/user", delete(delete_user))
+        .route("/deletion/:token/cancel", get(cancel_deletion))
+        .route("/email/:token/verify", get(verify_email))
+        .route("/email/verification", post(resend_verification))
+        .route("/keys", put(set_key_pair))
+        .route("/keys", get(get_keys))
+        .route("/public_key/:email", get(get_public_key))
+        .route("/recovery_key", put(set_recovery_key))
+        .route("/recovery/vault_key", post(get_recovery_vault_key))
+        .route("/recovery", post(recover_account))
+        .route("/master_password", put(change_master_password))
+        .route("/audit", get(list_audit_events))
+        .route("/devices", get(list_devices))
+        .route("/devices/:id", delete(remove_device))
+        .route("/devices/:id/approve", post(approve_device))
+        .route("/devices/approval", put(set_device_approval))
         .with_state(Arc::new(app_state))
 }
 
@@ -33,5 +298,73 @@ pub fn get_pass_service(app_state: AppState) -> Router {
         .route("/password/:id", get(get_password))
         .route("/password/:id", put(update_password))
         .route("/password/:id", delete(delete_password))
+        .route("/trash", get(list_trash))
+        .route("/trash/:id", delete(purge_item))
+        .route("/trash/:id/restore", post(restore_item))
+        .route("/sync", get(sync))
+        .route("/batch/add", post(batch_add_passwords))
+        .route("/batch/update", post(batch_update_passwords))
+        .route("/batch/delete", post(batch_delete_passwords))
+        .route("/vault/rotate", post(rotate_vault_key))
+        .route("/password/:id/shares", post(share_item))
+        .route("/password/:id/shares/:email", delete(revoke_share))
+        .route("/shared", get(list_shared_with_me))
+        .route("/collections/:id/passwords", get(list_collection_items))
+        .route("/emergency/:id/vault", get(get_emergency_vault))
+        .with_state(Arc::new(app_state))
+}
+
+pub fn get_org_service(app_state: AppState) -> Router {
+    Router::new()
+        .route("/organizations", post(create_organization))
+        .route("/organizations", get(list_organizations))
+        .route("/organizations/:id/members", get(list_members))
+        .route("/organizations/:id/members", post(invite_member))
+        .route("/organizations/:id/members/:email", delete(remove_member))
+        .route("/organizations/:id/accept", post(accept_invite))
+        .route("/organizations/:id/collections", post(create_collection))
+        .route("/organizations/:id/collections", get(list_collections))
+        .route("/collections/:id", delete(delete_collection))
+        .with_state(Arc::new(app_state))
+}
+
+pub fn get_emergency_service(app_state: AppState) -> Router {
+    Router::new()
+        .route("/contacts", post(add_contact))
+        .route("/contacts", get(list_contacts))
+        .route("/contacts/:id", delete(remove_contact))
+        .route("/contacts/:id/approve", post(approve_access))
+        .route("/contacts/:id/reject", post(reject_access))
+        .route("/grants", get(list_grants))
+        .route("/grants/:id/accept", post(accept_emergency_invite))
+        .route("/grants/:id/request", post(request_access))
+        .route("/grants/:id/takeover", post(takeover_account))
+        .with_state(Arc::new(app_state))
+}
+
+pub fn get_token_service(app_state: AppState) -> Router {
+    Router::new()
+        .route("/machine_accounts", post(create_machine_account))
+        .route("/machine_accounts", get(list_machine_accounts))
+        .route("/machine_accounts/:id", delete(delete_machine_account))
+        .route("/access_tokens", post(create_access_token))
+        .route("/access_tokens", get(list_access_tokens))
+        .route("/access_tokens/:id", delete(revoke_access_token))
+        .with_state(Arc::new(app_state))
+}
+
+pub fn get_notify_service(app_state: AppState) -> Router {
+    Router::new()
+        .route("/webhooks", post(create_webhook))
+        .route("/webhooks", get(list_webhooks))
+        .route("/webhooks/:id", delete(delete_webhook))
+        .route("/webhooks/:id/deliveries", get(list_webhook_deliveries))
+        .route("/webhooks/:id/ping", post(ping_webhook))
+        .with_state(Arc::new(app_state))
+}
+
+pub fn get_notifications_service(app_state: AppState) -> Router {
+    Router::new()
+        .route("/", get(subscribe))
         .with_state(Arc::new(app_state))
 }
@@ -1,15 +1,37 @@
 use base64::Engine;
-use serde::{
-    de::{self, Visitor},
-    ser::SerializeStruct,
-    Deserialize, Serialize,
+use chrono::{DateTime, Utc};
+use serde::{de, Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    access_token::AccessTokenRow,
+    audit::AuditEventRow,
+    batch::BatchOutcome,
+    device::DeviceRow,
+    emergency::{ContactRow, EmergencyAccess, EmergencyStatus},
+    machine_account::MachineAccountRow,
+    organizations::{CollectionRow, MemberRow, OrgRole, OrganizationRow},
+    pagination::{PasswordRow, SortField, SortOrder},
+    realtime::Change,
+    rotation::Rotation,
+    sharing::SharePermission,
+    webhook::{DeliveryRow, WebhookRow},
 };
-use utoipa::ToSchema;
 
 #[derive(Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
+    /// Scopes to narrow the session to, every scope when missing or empty.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Secret of 16 to 256 characters the client generated once and sends with every
+    /// login, recognizing the device. Required when the account requires device approval.
+    pub device_token: Option<String>,
+    /// Name shown in the device list, the user agent when missing.
+    pub device_name: Option<String>,
+    /// `browser`, `desktop`, `mobile`, `cli` or `other`.
+    pub device_type: Option<String>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -17,6 +39,51 @@ pub struct User {
     pub email: String,
     pub token: String,
     pub username: String,
+    /// Vault key wrapped by the client, base64 encoded.
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub vault_key: Option<Vec<u8>>,
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub public_key: Option<Vec<u8>>,
+    /// Private key wrapped by the vault key, base64 encoded.
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub wrapped_private_key: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct KeyPair {
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub public_key: Vec<u8>,
+    /// Private key wrapped by the vault key, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_private_key: Vec<u8>,
+}
+
+/// Key material of the caller, for clients signed in with an access token.
+#[derive(Serialize, ToSchema)]
+pub struct Keys {
+    /// Vault key wrapped by the client, base64 encoded.
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub vault_key: Option<Vec<u8>>,
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub public_key: Option<Vec<u8>>,
+    /// Private key wrapped by the vault key, base64 encoded.
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub wrapped_private_key: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PublicKey {
+    #[serde(serialize_with = "serialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub public_key: Vec<u8>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -24,244 +91,858 @@ pub struct CreateUserRequest {
     pub email: String,
     pub username: String,
     pub password: String,
+    /// Vault key wrapped by the client, base64 encoded.
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub vault_key: Option<Vec<u8>>,
+    #[serde(default)]
+    pub recovery: Option<RecoveryKey>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RecoveryKey {
+    /// Secret derived from the recovery key, checked like a login password.
+    pub secret: String,
+    /// Vault key wrapped by the recovery key, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub vault_key: Vec<u8>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RecoveryCredentials {
+    pub email: String,
+    pub secret: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RecoveryVaultKey {
+    /// Vault key wrapped by the recovery key, base64 encoded.
+    #[serde(serialize_with = "serialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub vault_key: Vec<u8>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RecoverAccountRequest {
+    pub email: String,
+    /// Secret derived from the recovery key being used.
+    pub secret: String,
+    /// Login password derived from the new master password.
+    pub password: String,
+    /// Unchanged vault key wrapped by the new master password, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub vault_key: Vec<u8>,
+    /// Recovery key replacing the one being used.
+    pub recovery: RecoveryKey,
 }
 
 #[derive(Deserialize, ToSchema)]
 pub struct UpdateUserRequest {
+    /// New address, taking effect once verified. The login password and the vault key
+    /// wrapping are derived with it, so it comes with `current_password`, `password` and
+    /// `vault_key`.
     pub email: Option<String>,
     pub username: Option<String>,
+    /// Current login password, confirming an email change.
+    pub current_password: Option<String>,
+    /// Login password derived from the master password with the new address. Only set with
+    /// `email`, the master password is changed with `/api/v1/auth/master_password`.
     pub password: Option<String>,
+    /// Vault key wrapped by the key derived from the master password with the new address,
+    /// base64 encoded.
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub vault_key: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeleteUserRequest {
+    /// Login password derived from the master password.
+    pub password: String,
 }
 
-#[derive(ToSchema)]
+#[derive(Serialize, ToSchema)]
+pub struct ScheduledDeletion {
+    pub deletes_at: DateTime<Utc>,
+    /// Token for `/api/v1/auth/deletion/{token}/cancel`.
+    pub cancel_token: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ListAuditEventsQuery {
+    /// Only events at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only events before this time.
+    pub until: Option<DateTime<Utc>>,
+    /// Comma separated event types like `auth.login,pass.item_read`, every type when missing.
+    pub types: Option<String>,
+    /// Only events with a smaller `seq`, to page through older events.
+    pub before: Option<i64>,
+    /// Maximum number of events returned, between 1 and 1000.
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditEvent {
+    pub seq: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub event_type: String,
+    pub actor_id: Option<uuid::Uuid>,
+    pub session_id: Option<uuid::Uuid>,
+    pub item_id: Option<uuid::Uuid>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: Option<String>,
+    /// Hex encoded hash chaining the event to the one before.
+    pub hash: String,
+}
+
+impl From<AuditEventRow> for AuditEvent {
+    fn from(row: AuditEventRow) -> Self {
+        AuditEvent {
+            seq: row.seq,
+            occurred_at: row.occurred_at,
+            event_type: row.event_type,
+            actor_id: row.actor_id,
+            session_id: row.session_id,
+            item_id: row.item_id,
+            ip: row.ip,
+            user_agent: row.user_agent,
+            detail: row.detail,
+            hash: hex::encode(row.hash),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Device {
+    pub id: uuid::Uuid,
+    pub name: String,
+    /// `browser`, `desktop`, `mobile`, `cli` or `other`.
+    pub device_type: String,
+    /// Unset while the device waits for approval.
+    pub approved_at: Option<DateTime<Utc>>,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub last_ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Whether the caller uses this device.
+    pub current: bool,
+}
+
+impl From<DeviceRow> for Device {
+    fn from(row: DeviceRow) -> Self {
+        Device {
+            id: row.id,
+            name: row.name,
+            device_type: row.device_type,
+            approved_at: row.approved_at,
+            first_seen_at: row.first_seen_at,
+            last_seen_at: row.last_seen_at,
+            last_ip: row.last_ip,
+            user_agent: row.user_agent,
+            current: row.current,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceApproval {
+    /// Whether new devices wait for approval from an approved device.
+    pub required: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangeMasterPasswordRequest {
+    /// Login password derived from the current master password.
+    pub current_password: String,
+    /// Login password derived from the new master password.
+    pub password: String,
+    /// Unchanged vault key wrapped by the new master password, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub vault_key: Vec<u8>,
+}
+
+#[derive(Deserialize, ToSchema)]
 pub struct AddPasswordRequest {
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
     pub name: Vec<u8>,
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
     pub password: Vec<u8>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub website: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub username: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub description: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub item_key: Option<Vec<u8>>,
+    /// Collection of an organization the password is added to, the personal vault otherwise.
+    #[serde(default)]
+    pub collection_id: Option<uuid::Uuid>,
+    /// Plant a decoy, retrieving it raises a security alert. Never returned by the server.
+    #[serde(default)]
+    pub honeytoken: bool,
 }
 
-#[derive(ToSchema)]
+/// Fields left out are kept, at least one must be set.
+#[derive(Deserialize, ToSchema)]
 pub struct UpdatePasswordRequest {
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub name: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub password: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub website: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub username: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub description: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub item_key: Option<Vec<u8>>,
 }
 
-#[derive(ToSchema)]
+/// A vault item, as every endpoint returns it. The gRPC `pass.Password` carries the same
+/// fields.
+#[derive(Serialize, ToSchema)]
 pub struct Password {
+    #[serde(rename = "id")]
     pub uuid: uuid::Uuid,
+    #[serde(serialize_with = "serialize_base64")]
+    #[schema(value_type = String, format = Byte)]
     pub name: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64")]
+    #[schema(value_type = String, format = Byte)]
     pub password: Vec<u8>,
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub website: Option<Vec<u8>>,
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub username: Option<Vec<u8>>,
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
     pub description: Option<Vec<u8>>,
+    pub version: i64,
+    /// Item key wrapped for the caller, by the vault key of the owner, to the public key
+    /// of a share recipient or by the organization key for passwords of a collection.
+    #[serde(serialize_with = "serialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub item_key: Option<Vec<u8>>,
+    pub collection_id: Option<uuid::Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
-fn to_base64(data: &[u8]) -> String {
-    base64::prelude::BASE64_STANDARD.encode(data)
+impl From<PasswordRow> for Password {
+    fn from(row: PasswordRow) -> Self {
+        Password {
+            uuid: row.id,
+            name: row.name,
+            password: row.password,
+            website: row.website,
+            username: row.username,
+            description: row.description,
+            version: row.revision,
+            item_key: row.item_key,
+            collection_id: row.collection_id,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        }
+    }
 }
 
-fn from_base64(data: &str) -> Option<Vec<u8>> {
-    base64::prelude::BASE64_STANDARD
-        .decode(data.as_bytes())
-        .ok()
+#[derive(Deserialize, IntoParams)]
+pub struct ListPasswordsQuery {
+    /// Maximum number of passwords returned, between 1 and 1000.
+    pub page_size: Option<i64>,
+    /// Token returned as `next_page_token` by the previous page.
+    pub page_token: Option<String>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PasswordsPage {
+    pub passwords: Vec<Password>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchAddRequest {
+    pub items: Vec<AddPasswordRequest>,
+    /// Roll back every item if any of them fails.
+    #[serde(default)]
+    pub all_or_nothing: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchUpdateItem {
+    pub id: uuid::Uuid,
+    /// Version of the password being updated.
+    pub version: i64,
+    pub changes: UpdatePasswordRequest,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchUpdateRequest {
+    pub items: Vec<BatchUpdateItem>,
+    /// Roll back every item if any of them fails.
+    #[serde(default)]
+    pub all_or_nothing: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchDeleteItem {
+    pub id: uuid::Uuid,
+    /// Version of the password being deleted.
+    pub version: i64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchDeleteRequest {
+    pub items: Vec<BatchDeleteItem>,
+    /// Roll back every item if any of them fails.
+    #[serde(default)]
+    pub all_or_nothing: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BatchItemResult {
+    pub id: Option<uuid::Uuid>,
+    pub version: Option<i64>,
+    pub error: Option<String>,
 }
 
-impl Serialize for Password {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut state = serializer.serialize_struct("Password", 6)?;
-        state.serialize_field("id", &self.uuid)?;
-        state.serialize_field("name", &to_base64(&self.name))?;
-        state.serialize_field("password", &to_base64(&self.password))?;
-        state.serialize_field("website", &self.website.as_ref().map(|x| to_base64(x)))?;
-        state.serialize_field("username", &self.username.as_ref().map(|x| to_base64(x)))?;
-        state.serialize_field(
-            "description",
-            &self.description.as_ref().map(|x| to_base64(x)),
-        )?;
-        state.end()
+#[derive(Serialize, ToSchema)]
+pub struct BatchResponse {
+    pub committed: bool,
+    pub results: Vec<BatchItemResult>,
+}
+
+impl From<BatchOutcome> for BatchResponse {
+    fn from(outcome: BatchOutcome) -> Self {
+        BatchResponse {
+            committed: outcome.committed,
+            results: outcome
+                .results
+                .into_iter()
+                .map(|result| BatchItemResult {
+                    id: result.id,
+                    version: result.version,
+                    error: result.error.map(|err| format!("{:?}", err)),
+                })
+                .collect(),
+        }
     }
 }
 
-struct UpdatePasswordRequestVisitor;
+#[derive(Deserialize, ToSchema)]
+pub struct RotatedItem {
+    pub id: uuid::Uuid,
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub name: Vec<u8>,
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub password: Vec<u8>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub website: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub username: Option<Vec<u8>>,
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub description: Option<Vec<u8>>,
+    /// Item key re-wrapped by the new vault key, required for items that have one.
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub item_key: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RotateVaultKeyRequest {
+    /// Vault revision the items were downloaded at.
+    pub expected_revision: i64,
+    /// New vault key wrapped by the client, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_vault_key: Vec<u8>,
+    /// Every item of the vault, trashed ones included, encrypted under the new key.
+    pub items: Vec<RotatedItem>,
+    /// Private key re-wrapped by the new vault key, required for users with a key pair.
+    #[serde(default, deserialize_with = "deserialize_base64_opt")]
+    #[schema(value_type = Option<String>, format = Byte)]
+    pub wrapped_private_key: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ShareItemRequest {
+    pub recipient_email: String,
+    /// Item key wrapped to the public key of the recipient, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_item_key: Vec<u8>,
+    #[serde(default)]
+    pub permission: SharePermission,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SharedItem {
+    #[serde(flatten)]
+    pub password: Password,
+    pub permission: SharePermission,
+    pub owner_email: String,
+}
 
-impl<'de> Visitor<'de> for UpdatePasswordRequestVisitor {
-    type Value = UpdatePasswordRequest;
+#[derive(Serialize, ToSchema)]
+pub struct RotateVaultKeyResponse {
+    pub revision: i64,
+    /// The recovery key wrapped the previous vault key and was cleared, a new one has to
+    /// be set.
+    pub recovery_key_cleared: bool,
+    /// Emergency contacts whose wrapped vault key was cleared, the new vault key has to be
+    /// wrapped for them again.
+    pub emergency_keys_cleared: i64,
+}
 
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("a struct representing UpdatePasswordRequest")
+impl From<Rotation> for RotateVaultKeyResponse {
+    fn from(rotation: Rotation) -> Self {
+        RotateVaultKeyResponse {
+            revision: rotation.revision,
+            recovery_key_cleared: rotation.recovery_key_cleared,
+            emergency_keys_cleared: rotation.emergency_keys_cleared,
+        }
     }
+}
 
-    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
-    where
-        A: serde::de::MapAccess<'de>,
-    {
-        let mut name = None;
-        let mut password = None;
-        let mut website = None;
-        let mut username = None;
-        let mut description = None;
-
-        while let Some(key) = map.next_key()? {
-            match key {
-                "name" => {
-                    if name.is_some() {
-                        return Err(de::Error::duplicate_field("name"));
-                    }
-                    name = Some(map.next_value()?)
-                }
-                "password" => {
-                    if password.is_some() {
-                        return Err(de::Error::duplicate_field("password"));
-                    }
-
-                    password = Some(map.next_value()?)
-                }
-                "website" => {
-                    if website.is_some() {
-                        return Err(de::Error::duplicate_field("website"));
-                    }
-                    website = Some(map.next_value()?)
-                }
-                "username" => {
-                    if username.is_some() {
-                        return Err(de::Error::duplicate_field("username"));
-                    }
-                    username = Some(map.next_value()?)
-                }
-                "description" => {
-                    if description.is_some() {
-                        return Err(de::Error::duplicate_field("description"));
-                    }
-                    description = Some(map.next_value()?)
-                }
-                _ => {
-                    let _: de::IgnoredAny = map.next_value()?;
-                }
-            }
+#[derive(Serialize, ToSchema)]
+pub struct TrashItem {
+    #[serde(flatten)]
+    pub password: Password,
+    pub deleted_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct SyncQuery {
+    /// Last revision the client has seen, `0` for a full sync.
+    #[serde(default)]
+    pub since_revision: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncResponse {
+    pub revision: i64,
+    pub changed: Vec<Password>,
+    pub deleted: Vec<uuid::Uuid>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+    /// Organization key wrapped to the public key of the creator, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_org_key: Vec<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Organization {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub role: OrgRole,
+    /// Organization key wrapped to the public key of the user, base64 encoded.
+    #[serde(serialize_with = "serialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_org_key: Vec<u8>,
+    /// `false` for invites that were not accepted yet.
+    pub accepted: bool,
+}
+
+impl From<OrganizationRow> for Organization {
+    fn from(row: OrganizationRow) -> Self {
+        Organization {
+            id: row.id,
+            name: row.name,
+            role: row.role,
+            wrapped_org_key: row.wrapped_org_key,
+            accepted: row.accepted,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Member {
+    pub email: String,
+    pub role: OrgRole,
+    pub accepted: bool,
+}
+
+impl From<MemberRow> for Member {
+    fn from(row: MemberRow) -> Self {
+        Member {
+            email: row.email,
+            role: row.role,
+            accepted: row.accepted,
         }
+    }
+}
 
-        let to_result = || de::Error::custom("Can not decode `password` from base64");
+#[derive(Deserialize, ToSchema)]
+pub struct InviteMemberRequest {
+    pub email: String,
+    #[serde(default)]
+    pub role: OrgRole,
+    /// Organization key wrapped to the public key of the invitee, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_org_key: Vec<u8>,
+}
 
-        Ok(UpdatePasswordRequest {
-            name: name.map(from_base64).ok_or_else(to_result)?,
-            password: password.map(from_base64).ok_or_else(to_result)?,
-            website: website.map(from_base64).ok_or_else(to_result)?,
-            username: username.map(from_base64).ok_or_else(to_result)?,
-            description: description.map(from_base64).ok_or_else(to_result)?,
-        })
+#[derive(Deserialize, ToSchema)]
+pub struct CreateCollectionRequest {
+    /// Name encrypted under the organization key, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub name: Vec<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Collection {
+    pub id: uuid::Uuid,
+    pub org_id: uuid::Uuid,
+    #[serde(serialize_with = "serialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub name: Vec<u8>,
+}
+
+impl From<CollectionRow> for Collection {
+    fn from(row: CollectionRow) -> Self {
+        Collection {
+            id: row.id,
+            org_id: row.org_id,
+            name: row.name,
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddEmergencyContactRequest {
+    pub email: String,
+    #[serde(default)]
+    pub access: EmergencyAccess,
+    /// Days a request waits for the grantor before it is approved automatically, 1 to 90.
+    pub wait_days: i32,
+    /// Vault key of the grantor wrapped to the public key of the contact, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_vault_key: Vec<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EmergencyContact {
+    pub id: uuid::Uuid,
+    pub grantor_email: String,
+    pub grantee_email: String,
+    pub access: EmergencyAccess,
+    pub wait_days: i32,
+    pub status: EmergencyStatus,
+    /// `false` after a key rotation of the grantor until they wrap the new vault key.
+    pub has_vault_key: bool,
+    pub requested_at: Option<DateTime<Utc>>,
+    /// When the pending request gets approved unless the grantor rejects it first.
+    pub approves_at: Option<DateTime<Utc>>,
+    pub approved_at: Option<DateTime<Utc>>,
+}
+
+impl From<ContactRow> for EmergencyContact {
+    fn from(row: ContactRow) -> Self {
+        EmergencyContact {
+            id: row.id,
+            grantor_email: row.grantor_email,
+            grantee_email: row.grantee_email,
+            access: row.access,
+            wait_days: row.wait_days,
+            status: row.status,
+            has_vault_key: row.has_vault_key,
+            requested_at: row.requested_at,
+            approves_at: row.approves_at,
+            approved_at: row.approved_at,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EmergencyVault {
+    /// Vault key of the grantor wrapped to the public key of the caller, base64 encoded.
+    #[serde(serialize_with = "serialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_vault_key: Vec<u8>,
+    pub passwords: Vec<Password>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TakeoverAccountRequest {
+    /// Login password derived from the new master password of the grantor.
+    pub password: String,
+    /// Unchanged vault key of the grantor wrapped by the new master password, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub vault_key: Vec<u8>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateMachineAccountRequest {
+    pub name: String,
+    /// Vault key of the machine account wrapped by a secret handed to the machine, base64
+    /// encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub vault_key: Vec<u8>,
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub public_key: Vec<u8>,
+    /// Private key wrapped by the vault key of the machine account, base64 encoded.
+    #[serde(deserialize_with = "deserialize_base64")]
+    #[schema(value_type = String, format = Byte)]
+    pub wrapped_private_key: Vec<u8>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MachineAccount {
+    pub id: uuid::Uuid,
+    /// Address the machine account is invited to organizations with.
+    pub email: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<MachineAccountRow> for MachineAccount {
+    fn from(row: MachineAccountRow) -> Self {
+        MachineAccount {
+            id: row.id,
+            email: row.email,
+            name: row.name,
+            created_at: row.created_at,
+        }
     }
 }
 
-impl<'de> Deserialize<'de> for UpdatePasswordRequest {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: de::Deserializer<'de>,
-    {
-        deserializer.deserialize_struct(
-            "UpdatePasswordRequest",
-            &[
-                "uuid",
-                "name",
-                "password",
-                "website",
-                "username",
-                "description",
-            ],
-            UpdatePasswordRequestVisitor,
-        )
+#[derive(Deserialize, ToSchema)]
+pub struct CreateAccessTokenRequest {
+    pub name: String,
+    /// Any scope but `account`, only `vault:read` and `vault:write` when restricted.
+    pub scopes: Vec<String>,
+    /// Restricts the token to these collections, unrestricted when missing.
+    pub collection_ids: Option<Vec<uuid::Uuid>>,
+    /// Machine account the token acts as, the caller when missing.
+    pub machine_account_id: Option<uuid::Uuid>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AccessToken {
+    pub id: uuid::Uuid,
+    /// Email of the user or machine account the token acts as.
+    pub holder: String,
+    pub name: String,
+    /// Public start of the token.
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub collection_ids: Option<Vec<uuid::Uuid>>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<AccessTokenRow> for AccessToken {
+    fn from(row: AccessTokenRow) -> Self {
+        AccessToken {
+            id: row.id,
+            holder: row.holder,
+            name: row.name,
+            prefix: row.prefix,
+            scopes: row.scopes,
+            collection_ids: row.collection_ids,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+        }
     }
 }
 
-struct AddPasswordRequestVisitor;
+#[derive(Serialize, ToSchema)]
+pub struct CreatedAccessToken {
+    pub access_token: AccessToken,
+    /// Shown once, only its hash is stored.
+    pub token: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateWebhookRequest {
+    /// Endpoint the signed JSON payloads are posted to. Its host must only resolve to public
+    /// addresses, redirects are not followed.
+    pub url: String,
+    /// Events like `login` or `vault.exported` the webhook is notified of.
+    pub events: Vec<String>,
+    /// Organization whose members' events the webhook gets, the caller when missing.
+    pub org_id: Option<uuid::Uuid>,
+}
 
-impl<'de> Visitor<'de> for AddPasswordRequestVisitor {
-    type Value = AddPasswordRequest;
+#[derive(Serialize, ToSchema)]
+pub struct Webhook {
+    pub id: uuid::Uuid,
+    pub org_id: Option<uuid::Uuid>,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
 
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        formatter.write_str("a struct representing AddPasswordRequest")
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            org_id: row.org_id,
+            url: row.url,
+            events: row.events,
+            created_at: row.created_at,
+        }
     }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    pub webhook: Webhook,
+    /// Key of the HMAC-SHA256 signature of every payload, shown once.
+    pub secret: String,
+}
 
-    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
-    where
-        A: serde::de::MapAccess<'de>,
-    {
-        let mut name = None;
-        let mut password = None;
-        let mut website = None;
-        let mut username = None;
-        let mut description = None;
-
-        while let Some(key) = map.next_key()? {
-            match key {
-                "name" => {
-                    if name.is_some() {
-                        return Err(de::Error::duplicate_field("name"));
-                    }
-                    name = Some(map.next_value()?)
-                }
-                "password" => {
-                    if password.is_some() {
-                        return Err(de::Error::duplicate_field("password"));
-                    }
-
-                    password = Some(map.next_value::<String>()?)
-                }
-                "website" => {
-                    if website.is_some() {
-                        return Err(de::Error::duplicate_field("website"));
-                    }
-                    website = Some(map.next_value()?)
-                }
-                "username" => {
-                    if username.is_some() {
-                        return Err(de::Error::duplicate_field("username"));
-                    }
-                    username = Some(map.next_value()?)
-                }
-                "description" => {
-                    if description.is_some() {
-                        return Err(de::Error::duplicate_field("description"));
-                    }
-                    description = Some(map.next_value()?)
-                }
-                _ => {
-                    let _: de::IgnoredAny = map.next_value()?;
-                }
-            }
+#[derive(Deserialize, IntoParams)]
+pub struct ListWebhooksQuery {
+    /// Organization to list the webhooks of, the caller's own when missing.
+    pub org_id: Option<uuid::Uuid>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ListWebhookDeliveriesQuery {
+    /// Maximum number of deliveries returned, between 1 and 100.
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: uuid::Uuid,
+    pub event_type: String,
+    /// `pending`, `delivered` or `failed` once out of attempts.
+    pub status: String,
+    pub attempts: i32,
+    /// When a pending delivery is attempted next.
+    pub next_attempt_at: DateTime<Utc>,
+    /// HTTP status of the last attempt.
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+impl From<DeliveryRow> for WebhookDelivery {
+    fn from(row: DeliveryRow) -> Self {
+        WebhookDelivery {
+            id: row.id,
+            event_type: row.event_type,
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at,
+            last_status: row.last_status,
+            last_error: row.last_error,
+            created_at: row.created_at,
+            delivered_at: row.delivered_at,
         }
+    }
+}
+
+fn to_base64(data: &[u8]) -> String {
+    base64::prelude::BASE64_STANDARD.encode(data)
+}
+
+fn from_base64(data: &str) -> Option<Vec<u8>> {
+    base64::prelude::BASE64_STANDARD
+        .decode(data.as_bytes())
+        .ok()
+}
+
+fn serialize_base64<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    to_base64(data).serialize(serializer)
+}
 
-        let to_result = || de::Error::custom("Can not decode `password` from base64");
+fn serialize_base64_opt<S>(data: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    data.as_deref().map(to_base64).serialize(serializer)
+}
 
-        let name = name.ok_or_else(|| de::Error::missing_field("name"))?;
-        let password = password.ok_or_else(|| de::Error::missing_field("password"))?;
+fn deserialize_base64<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let data = String::deserialize(deserializer)?;
+    from_base64(&data).ok_or_else(|| de::Error::custom("Can not decode value from base64"))
+}
 
-        Ok(AddPasswordRequest {
-            name,
-            password: from_base64(&password).ok_or_else(to_result)?,
-            website: website.map(from_base64).ok_or_else(to_result)?,
-            username: username.map(from_base64).ok_or_else(to_result)?,
-            description: description.map(from_base64).ok_or_else(to_result)?,
+fn deserialize_base64_opt<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|data| {
+            from_base64(&data).ok_or_else(|| de::Error::custom("Can not decode value from base64"))
         })
-    }
+        .transpose()
 }
 
-impl<'de> Deserialize<'de> for AddPasswordRequest {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: de::Deserializer<'de>,
-    {
-        deserializer.deserialize_struct(
-            "AddPasswordRequest",
-            &["name", "password", "website", "username", "description"],
-            AddPasswordRequestVisitor,
-        )
+/// Data of the events on `/api/v1/notifications`. Only ids are sent, what changed is
+/// fetched or synced.
+#[derive(Serialize, ToSchema)]
+pub struct ChangeNotification {
+    /// `item.changed`, `item.deleted`, `session.revoked`, or `resync` when changes may have
+    /// been missed.
+    pub event: String,
+    pub item_id: Option<uuid::Uuid>,
+    /// Revision of the item for a sync, missing when it was purged.
+    pub revision: Option<i64>,
+    /// Session or access token revoked. The stream of that session ends after it.
+    pub session_id: Option<uuid::Uuid>,
+}
+
+impl From<Change> for ChangeNotification {
+    fn from(change: Change) -> Self {
+        ChangeNotification {
+            event: change.as_str().to_string(),
+            item_id: change.item_id(),
+            revision: change.revision(),
+            session_id: change.session_id(),
+        }
     }
 }
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{
+    models::{
+        ChangeNotification, CreateWebhookRequest, CreatedWebhook, ListWebhookDeliveriesQuery,
+        ListWebhooksQuery, Webhook, WebhookDelivery,
+    },
+    AuthUser, ValidJson,
+};
+use crate::{
+    db::Db,
+    realtime::{self, Change},
+    webhook::{self, WebhookEvent},
+    AppState,
+};
+
+/// Create a webhook notified of security events of the user or of an organization
+#[utoipa::path(
+    post,
+    path = "/api/v1/notify/webhooks",
+    tag = "Notify",
+    request_body = CreateWebhookRequest,
+    responses(
+        (status = 201, description = "Webhook created, the secret is shown once", body = CreatedWebhook),
+        (status = 400, description = "Invalid url, url not resolving to public addresses only, or unknown event"),
+        (status = 403, description = "Not an admin of the organization"),
+        (status = 404, description = "Organization not found"),
+    )
+)]
+pub async fn create_webhook(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<CreateWebhookRequest>,
+) -> Result<(StatusCode, Json<CreatedWebhook>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+    let CreateWebhookRequest {
+        url,
+        events,
+        org_id,
+    } = request;
+
+    let events = WebhookEvent::parse_all(&events)?;
+
+    let created = webhook::create(&mut conn, user_id, org_id, &url, &events).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedWebhook {
+            webhook: created.row.into(),
+            secret: created.secret,
+        }),
+    ))
+}
+
+/// Get the webhooks of the user or of an organization
+#[utoipa::path(
+    get,
+    path = "/api/v1/notify/webhooks",
+    tag = "Notify",
+    params(ListWebhooksQuery),
+    responses(
+        (status = 200, description = "Returns the webhooks", body = Vec<Webhook>),
+        (status = 403, description = "Not an admin of the organization"),
+        (status = 404, description = "Organization not found"),
+    )
+)]
+pub async fn list_webhooks(
+    AuthUser(principal): AuthUser,
+    Query(ListWebhooksQuery { org_id }): Query<ListWebhooksQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Webhook>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let webhooks = webhook::list(&mut conn, user_id, org_id)
+        .await?
+        .into_iter()
+        .map(Webhook::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(webhooks)))
+}
+
+/// Delete a webhook with its delivery log
+#[utoipa::path(
+    delete,
+    path = "/api/v1/notify/webhooks/{id}",
+    tag = "Notify",
+    responses(
+        (status = 204, description = "Webhook deleted"),
+        (status = 404, description = "Webhook not found"),
+    )
+)]
+pub async fn delete_webhook(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    webhook::delete(&mut conn, user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get the delivery log of a webhook
+#[utoipa::path(
+    get,
+    path = "/api/v1/notify/webhooks/{id}/deliveries",
+    tag = "Notify",
+    params(ListWebhookDeliveriesQuery),
+    responses(
+        (status = 200, description = "Returns the deliveries, newest first", body = Vec<WebhookDelivery>),
+        (status = 404, description = "Webhook not found"),
+    )
+)]
+pub async fn list_webhook_deliveries(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    Query(ListWebhookDeliveriesQuery { limit }): Query<ListWebhookDeliveriesQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<WebhookDelivery>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let deliveries = webhook::deliveries(&mut conn, user_id, id, limit)
+        .await?
+        .into_iter()
+        .map(WebhookDelivery::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(deliveries)))
+}
+
+/// Queue a ping to a webhook to test its endpoint
+#[utoipa::path(
+    post,
+    path = "/api/v1/notify/webhooks/{id}/ping",
+    tag = "Notify",
+    responses(
+        (status = 202, description = "Ping queued", body = WebhookDelivery),
+        (status = 404, description = "Webhook not found"),
+    )
+)]
+pub async fn ping_webhook(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<WebhookDelivery>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let delivery = webhook::ping(&mut conn, user_id, id).await?;
+
+    Ok((StatusCode::ACCEPTED, Json(delivery.into())))
+}
+
+fn change_event(change: Change) -> Result<Event, axum::Error> {
+    Event::default()
+        .event(change.as_str())
+        .json_data(ChangeNotification::from(change))
+}
+
+/// Stream changes of the vault as server-sent events named after the change, until the
+/// session is revoked or the token expires
+#[utoipa::path(
+    get,
+    path = "/api/v1/notifications",
+    tag = "Notify",
+    responses(
+        (status = 200, description = "Stream of changes", body = ChangeNotification, content_type = "text/event-stream"),
+    )
+)]
+pub async fn subscribe(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<Sse<ReceiverStream<Result<Event, axum::Error>>>, Response<String>> {
+    let subscription = realtime::subscribe(&state.pool, principal).await?;
+    let rx = subscription.into_receiver(change_event);
+
+    Ok(Sse::new(ReceiverStream::new(rx)).keep_alive(KeepAlive::default()))
+}
@@ -1,11 +1,32 @@
-use super::{auth::*, models::*, pass::*};
+use super::{auth::*, emergency::*, models::*, notify::*, org::*, pass::*, token::*};
+use crate::{
+    emergency::{EmergencyAccess, EmergencyStatus},
+    organizations::OrgRole,
+    pagination::{SortField, SortOrder},
+    sharing::SharePermission,
+};
 use utoipa::OpenApi;
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
-        login, create_user, update_user, delete_user,
-        get_password, add_password, get_passwords, update_password, delete_password
+        login, create_user, update_user, delete_user, cancel_deletion, verify_email,
+        resend_verification, set_key_pair, get_keys,
+        get_public_key, set_recovery_key, get_recovery_vault_key, recover_account, change_master_password,
+        list_audit_events, list_devices, remove_device, approve_device, set_device_approval,
+        get_password, add_password, get_passwords, update_password, delete_password,
+        list_trash, restore_item, purge_item, sync,
+        batch_add_passwords, batch_update_passwords, batch_delete_passwords,
+        rotate_vault_key, share_item, list_shared_with_me, revoke_share, list_collection_items,
+        create_organization, list_organizations, list_members, invite_member, accept_invite,
+        remove_member, create_collection, list_collections, delete_collection,
+        get_emergency_vault, add_contact, list_contacts, list_grants,
+        accept_emergency_invite, remove_contact, request_access, approve_access,
+        reject_access, takeover_account,
+        create_machine_account, list_machine_accounts, delete_machine_account,
+        create_access_token, list_access_tokens, revoke_access_token,
+        create_webhook, list_webhooks, delete_webhook, list_webhook_deliveries, ping_webhook,
+        subscribe
     ),
     components(
         schemas(
@@ -13,13 +34,72 @@ use utoipa::OpenApi;
             CreateUserRequest,
             UpdateUserRequest,
             User,
+            KeyPair,
+            Keys,
+            PublicKey,
+            RecoveryKey,
+            RecoveryCredentials,
+            RecoveryVaultKey,
+            RecoverAccountRequest,
+            ChangeMasterPasswordRequest,
+            DeleteUserRequest,
+            ScheduledDeletion,
+            AuditEvent,
+            Device,
+            DeviceApproval,
             Password,
             AddPasswordRequest,
             UpdatePasswordRequest,
+            TrashItem,
+            SyncResponse,
+            PasswordsPage,
+            SortField,
+            SortOrder,
+            BatchAddRequest,
+            BatchUpdateItem,
+            BatchUpdateRequest,
+            BatchDeleteItem,
+            BatchDeleteRequest,
+            BatchItemResult,
+            BatchResponse,
+            RotatedItem,
+            RotateVaultKeyRequest,
+            RotateVaultKeyResponse,
+            SharePermission,
+            ShareItemRequest,
+            SharedItem,
+            OrgRole,
+            CreateOrganizationRequest,
+            Organization,
+            Member,
+            InviteMemberRequest,
+            CreateCollectionRequest,
+            Collection,
+            EmergencyAccess,
+            EmergencyStatus,
+            AddEmergencyContactRequest,
+            EmergencyContact,
+            EmergencyVault,
+            TakeoverAccountRequest,
+            CreateMachineAccountRequest,
+            MachineAccount,
+            CreateAccessTokenRequest,
+            AccessToken,
+            CreatedAccessToken,
+            CreateWebhookRequest,
+            Webhook,
+            CreatedWebhook,
+            WebhookDelivery,
+            ChangeNotification,
         ),
     ),
     tags(
         (name = "Auth", description = "Authentication and user management"),
+        (name = "Password", description = "Vault items, trash, sync, batches, sharing and key rotation"),
+        (name = "Organization", description = "Organizations, members and collections"),
+        (name = "Emergency", description = "Emergency contacts and access"),
+        (name = "Token", description = "Access tokens and machine accounts"),
+        (name = "Notify", description = "Webhooks notified of security events and changes pushed to clients"),
     ),
 )]
 pub struct ApiDoc;
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Response,
+};
+
+use super::{
+    models::{
+        Collection, CreateCollectionRequest, CreateOrganizationRequest, InviteMemberRequest,
+        Member, Organization,
+    },
+    AuthUser, ValidJson,
+};
+use crate::{db::Db, organizations, AppState};
+
+/// Create an organization
+#[utoipa::path(
+    post,
+    path = "/api/v1/org/organizations",
+    tag = "Organization",
+    request_body = CreateOrganizationRequest,
+    responses(
+        (status = 201, description = "Organization created", body = Organization),
+        (status = 400, description = "Name or key is empty"),
+    )
+)]
+pub async fn create_organization(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<CreateOrganizationRequest>,
+) -> Result<(StatusCode, Json<Organization>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+    let CreateOrganizationRequest {
+        name,
+        wrapped_org_key,
+    } = request;
+
+    let org = organizations::create_organization(&mut conn, user_id, name, wrapped_org_key).await?;
+
+    Ok((StatusCode::CREATED, Json(org.into())))
+}
+
+/// Get the organizations of the user, pending invites included
+#[utoipa::path(
+    get,
+    path = "/api/v1/org/organizations",
+    tag = "Organization",
+    responses(
+        (status = 200, description = "Returns the organizations", body = Vec<Organization>),
+    )
+)]
+pub async fn list_organizations(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Organization>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let organizations = organizations::list_organizations(&mut conn, user_id)
+        .await?
+        .into_iter()
+        .map(Organization::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(organizations)))
+}
+
+/// Get the members of an organization
+#[utoipa::path(
+    get,
+    path = "/api/v1/org/organizations/{id}/members",
+    tag = "Organization",
+    responses(
+        (status = 200, description = "Returns the members and pending invites", body = Vec<Member>),
+        (status = 404, description = "Organization not found"),
+    )
+)]
+pub async fn list_members(
+    AuthUser(principal): AuthUser,
+    Path(org_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Member>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let members = organizations::list_members(&mut conn, user_id, org_id)
+        .await?
+        .into_iter()
+        .map(Member::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(members)))
+}
+
+/// Invite a user to an organization
+#[utoipa::path(
+    post,
+    path = "/api/v1/org/organizations/{id}/members",
+    tag = "Organization",
+    request_body = InviteMemberRequest,
+    responses(
+        (status = 204, description = "User is invited"),
+        (status = 403, description = "The role of the user is too low"),
+        (status = 404, description = "Organization or user not found"),
+    )
+)]
+pub async fn invite_member(
+    AuthUser(principal): AuthUser,
+    Path(org_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<InviteMemberRequest>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+    let InviteMemberRequest {
+        email,
+        role,
+        wrapped_org_key,
+    } = request;
+
+    organizations::invite_member(&mut conn, user_id, org_id, &email, role, wrapped_org_key).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Accept an invite to an organization
+#[utoipa::path(
+    post,
+    path = "/api/v1/org/organizations/{id}/accept",
+    tag = "Organization",
+    responses(
+        (status = 204, description = "Invite accepted"),
+        (status = 404, description = "Invite not found"),
+    )
+)]
+pub async fn accept_invite(
+    AuthUser(principal): AuthUser,
+    Path(org_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    organizations::accept_invite(&mut conn, user_id, org_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove a member from an organization, or leave it
+#[utoipa::path(
+    delete,
+    path = "/api/v1/org/organizations/{id}/members/{email}",
+    tag = "Organization",
+    params(
+        ("email" = String, Path, description = "Email of the member"),
+    ),
+    responses(
+        (status = 204, description = "Member removed"),
+        (status = 400, description = "Member is the last owner"),
+        (status = 403, description = "The role of the user is too low"),
+        (status = 404, description = "Organization or member not found"),
+    )
+)]
+pub async fn remove_member(
+    AuthUser(principal): AuthUser,
+    Path((org_id, email)): Path<(uuid::Uuid, String)>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+
+    organizations::remove_member(&state.pool, user_id, org_id, &email).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Create a collection in an organization
+#[utoipa::path(
+    post,
+    path = "/api/v1/org/organizations/{id}/collections",
+    tag = "Organization",
+    request_body = CreateCollectionRequest,
+    responses(
+        (status = 201, description = "Collection created", body = Collection),
+        (status = 403, description = "The role of the user is too low"),
+        (status = 404, description = "Organization not found"),
+    )
+)]
+pub async fn create_collection(
+    AuthUser(principal): AuthUser,
+    Path(org_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<CreateCollectionRequest>,
+) -> Result<(StatusCode, Json<Collection>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let collection =
+        organizations::create_collection(&mut conn, user_id, org_id, request.name).await?;
+
+    Ok((StatusCode::CREATED, Json(collection.into())))
+}
+
+/// Get the collections of an organization
+#[utoipa::path(
+    get,
+    path = "/api/v1/org/organizations/{id}/collections",
+    tag = "Organization",
+    responses(
+        (status = 200, description = "Returns the collections", body = Vec<Collection>),
+        (status = 404, description = "Organization not found"),
+    )
+)]
+pub async fn list_collections(
+    AuthUser(principal): AuthUser,
+    Path(org_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Collection>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let collections = organizations::list_collections(&mut conn, user_id, org_id)
+        .await?
+        .into_iter()
+        .map(Collection::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(collections)))
+}
+
+/// Delete a collection together with its passwords
+#[utoipa::path(
+    delete,
+    path = "/api/v1/org/collections/{id}",
+    tag = "Organization",
+    responses(
+        (status = 204, description = "Collection deleted"),
+        (status = 403, description = "The role of the user is too low"),
+        (status = 404, description = "Collection not found"),
+    )
+)]
+pub async fn delete_collection(
+    AuthUser(principal): AuthUser,
+    Path(collection_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    organizations::delete_collection(&mut conn, user_id, collection_id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
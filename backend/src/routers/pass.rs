@@ -1,13 +1,53 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::{Json, Path, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Json, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::Response,
 };
 
-use super::models::{AddPasswordRequest, Password, UpdatePasswordRequest};
-use crate::{db::Db, error::CpassError, jwt::generate::claims_from_headers, AppState};
+use super::{
+    models::{
+        AddPasswordRequest, BatchAddRequest, BatchDeleteRequest, BatchResponse, BatchUpdateRequest,
+        EmergencyVault, ListPasswordsQuery, Password, PasswordsPage, RotateVaultKeyRequest,
+        RotateVaultKeyResponse, ShareItemRequest, SharedItem, SyncQuery, SyncResponse, TrashItem,
+        UpdatePasswordRequest,
+    },
+    AuthUser, ValidJson,
+};
+use crate::{
+    access_token,
+    audit::{self, Event, EventType, Origin},
+    batch::{self, ItemChange, ItemRemoval, NewItem},
+    concurrency::{conditional_write_error, ItemAction},
+    db::Db,
+    emergency,
+    error::CpassError,
+    honeytoken, organizations,
+    pagination::{fetch_page, PasswordRow},
+    rotation::{self, RotatedItem},
+    sharing, AppState,
+};
+
+fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Parse the item version from the `If-Match` header, required for every write.
+fn if_match(headers: &HeaderMap) -> Result<i64, CpassError> {
+    let value = headers
+        .get(header::IF_MATCH)
+        .ok_or_else(|| CpassError::PreconditionRequired("If-Match header is required".to_string()))?
+        .to_str()
+        .map_err(|_| CpassError::InvalidRequest("Wrong If-Match format".to_string()))?;
+
+    value
+        .trim()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map_err(|_| CpassError::InvalidRequest("Wrong If-Match format".to_string()))
+}
 
 /// Get a password by id
 #[utoipa::path(
@@ -15,26 +55,41 @@ use crate::{db::Db, error::CpassError, jwt::generate::claims_from_headers, AppSt
     path = "/api/v1/pass/password/{id}",
     tag = "Password",
     responses(
-        (status = 200, description = "Returns the password", body = Password),
+        (status = 200, description = "Returns the password", body = Password,
+            headers(("ETag" = String, description = "Current version of the password"))),
         (status = 404, description = "Password not found"),
     )
 )]
 pub async fn get_password(
-    headers: HeaderMap,
+    AuthUser(principal): AuthUser,
+    origin: Origin,
     Path(pass_id): Path<uuid::Uuid>,
     State(state): State<Arc<AppState>>,
-) -> Result<(StatusCode, Json<Password>), Response<String>> {
+) -> Result<
+    (
+        StatusCode,
+        [(header::HeaderName, String); 1],
+        Json<Password>,
+    ),
+    Response<String>,
+> {
     let mut conn = state.pool.conn().await?;
-    let owner_id = claims_from_headers(&headers)?.sub;
 
     let row = sqlx::query!(
         r#"
-        SELECT id, password, name, website, username, description
-        FROM passwords
-        WHERE id = $1 AND owner_id = $2
+        SELECT
+            p.id, p.password, p.name, p.website, p.username, p.description, p.revision,
+            COALESCE(s.wrapped_item_key, p.item_key) AS item_key, p.collection_id,
+            p.created_at, p.updated_at, p.honeytoken
+        FROM passwords p
+        LEFT JOIN shares s ON s.item_id = p.id AND s.recipient_id = $2
+        WHERE p.id = $1 AND p.deleted_at IS NULL AND item_can(p.id, $2, $3)
+            AND ($4::UUID[] IS NULL OR p.collection_id = ANY($4))
         "#,
         pass_id,
-        owner_id
+        principal.sub,
+        ItemAction::Read.as_str(),
+        principal.collections.as_deref()
     )
     .fetch_one(&mut *conn)
     .await
@@ -45,6 +100,11 @@ pub async fn get_password(
         _ => CpassError::DatabaseError(err),
     })?;
 
+    if row.honeytoken {
+        honeytoken::trip(&mut conn, pass_id, &principal, "http").await;
+    }
+
+    let version = etag(row.revision);
     let response: Json<Password> = Password {
         uuid: row.id,
         name: row.name,
@@ -52,52 +112,73 @@ pub async fn get_password(
         website: row.website,
         username: row.username,
         description: row.description,
+        version: row.revision,
+        item_key: row.item_key,
+        collection_id: row.collection_id,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
     }
     .into();
 
-    Ok((StatusCode::OK, response))
+    audit::record(
+        &mut conn,
+        &origin,
+        Event::by(EventType::ItemRead, &principal).item(pass_id),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, [(header::ETAG, version)], response))
 }
 
-/// Get all passwords
+/// Get a page of passwords
 #[utoipa::path(
     get,
     path = "/api/v1/pass/passwords",
     tag = "Password",
+    params(ListPasswordsQuery),
     responses(
-        (status = 200, description = "Returns all passwords", body = Vec<Password>),
+        (status = 200, description = "Returns a page of passwords", body = PasswordsPage),
+        (status = 400, description = "Invalid page size or page token"),
     )
 )]
 pub async fn get_passwords(
-    headers: HeaderMap,
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Query(query): Query<ListPasswordsQuery>,
     State(state): State<Arc<AppState>>,
-) -> Result<(StatusCode, Json<Vec<Password>>), Response<String>> {
+) -> Result<(StatusCode, Json<PasswordsPage>), Response<String>> {
+    let owner_id = principal.sub;
     let mut conn = state.pool.conn().await?;
-    let owner_id = claims_from_headers(&headers)?.sub;
 
-    let passwords = sqlx::query!(
-        r#"
-        SELECT id, password, name, website, username, description
-        FROM passwords
-        WHERE owner_id = $1
-        "#,
-        owner_id
+    let ListPasswordsQuery {
+        page_size,
+        page_token,
+        sort,
+        order,
+    } = query;
+
+    let first_page = page_token.is_none();
+    let page = fetch_page(
+        &mut conn,
+        owner_id,
+        sort,
+        order,
+        page_size,
+        page_token.as_deref(),
     )
-    .fetch_all(&mut *conn)
-    .await
-    .map_err(CpassError::DatabaseError)?;
+    .await?;
 
-    let response: Json<Vec<Password>> = passwords
-        .into_iter()
-        .map(|x| Password {
-            uuid: x.id,
-            name: x.name,
-            password: x.password,
-            website: x.website,
-            username: x.username,
-            description: x.description,
-        })
-        .collect::<Vec<Password>>()
-        .into();
+    let response: Json<PasswordsPage> = PasswordsPage {
+        passwords: page.items.into_iter().map(Password::from).collect(),
+        next_page_token: page.next_page_token,
+    }
+    .into();
+
+    let mut event = Event::by(EventType::ItemsListed, &principal);
+    if first_page {
+        event = event.whole_vault();
+    }
+    audit::record(&mut conn, &origin, event).await?;
 
     Ok((StatusCode::OK, response))
 }
@@ -107,31 +188,58 @@ pub async fn get_passwords(
     post,
     path = "/api/v1/pass/password",
     tag = "Password",
-    request_body = AddPasswordResponse,
+    request_body = AddPasswordRequest,
     responses(
-        (status = 201, description = "Password created"),
+        (status = 201, description = "Returns the created password", body = Password,
+            headers(("ETag" = String, description = "Version of the password"))),
+        (status = 400, description = "Invalid fields"),
+        (status = 403, description = "No write access to the collection"),
     )
 )]
 pub async fn add_password(
-    headers: HeaderMap,
+    AuthUser(principal): AuthUser,
+    origin: Origin,
     State(state): State<Arc<AppState>>,
-    Json(request): Json<AddPasswordRequest>,
-) -> Result<StatusCode, Response<String>> {
-    let mut conn = state.pool.conn().await?;
+    ValidJson(request): ValidJson<AddPasswordRequest>,
+) -> Result<
+    (
+        StatusCode,
+        [(header::HeaderName, String); 1],
+        Json<Password>,
+    ),
+    Response<String>,
+> {
+    let owner_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
     let AddPasswordRequest {
         name,
         password,
         website,
         username,
         description,
+        item_key,
+        collection_id,
+        honeytoken,
     } = request;
 
-    let owner_id = claims_from_headers(&headers)?.sub;
+    if !principal.reaches(collection_id) {
+        return Err(CpassError::Forbidden(
+            "Not allowed to add items to that collection".to_string(),
+        )
+        .into());
+    }
 
-    let _ = sqlx::query!(
+    let row = sqlx::query_as!(
+        PasswordRow,
         r#"
-        INSERT INTO passwords(owner_id, name, password, website, username, description)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        INSERT INTO passwords(
+            owner_id, name, password, website, username, description, item_key, collection_id,
+            honeytoken
+        )
+        SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9
+        WHERE $8::UUID IS NULL OR collection_can($8, $1, 'write')
+        RETURNING id, name, password, website, username, description, revision, item_key,
+            collection_id, created_at, updated_at
         "#,
         owner_id,
         name,
@@ -139,12 +247,35 @@ pub async fn add_password(
         website,
         username,
         description,
+        item_key,
+        collection_id,
+        honeytoken
     )
-    .execute(&mut *conn)
+    .fetch_optional(&mut *tx)
     .await
     .map_err(CpassError::DatabaseError)?;
 
-    Ok(StatusCode::CREATED)
+    let Some(row) = row else {
+        return Err(CpassError::Forbidden(
+            "Not allowed to add items to that collection".to_string(),
+        )
+        .into());
+    };
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::ItemCreated, &principal).item(row.id),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    let version = etag(row.revision);
+    Ok((
+        StatusCode::CREATED,
+        [(header::ETAG, version)],
+        Password::from(row).into(),
+    ))
 }
 
 /// Update a password by id
@@ -152,19 +283,31 @@ pub async fn add_password(
     put,
     path = "/api/v1/pass/password/{id}",
     tag = "Password",
-    request_body = AddPassword,
+    request_body = UpdatePasswordRequest,
+    params(
+        ("If-Match" = String, Header, description = "Version of the password being updated"),
+    ),
     responses(
-        (status = 204, description = "Password updated"),
+        (status = 204, description = "Password updated",
+            headers(("ETag" = String, description = "New version of the password"))),
+        (status = 400, description = "Invalid fields"),
+        (status = 403, description = "Password is read-only for the user"),
+        (status = 404, description = "Password not found"),
+        (status = 409, description = "Password was modified concurrently"),
+        (status = 428, description = "If-Match header is missing"),
     )
 )]
 pub async fn update_password(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
     headers: HeaderMap,
     State(state): State<Arc<AppState>>,
     Path(pass_id): Path<uuid::Uuid>,
-    Json(request): Json<UpdatePasswordRequest>,
-) -> Result<StatusCode, Response<String>> {
-    let mut conn = state.pool.conn().await?;
-    let owner_id = claims_from_headers(&headers)?.sub;
+    ValidJson(request): ValidJson<UpdatePasswordRequest>,
+) -> Result<(StatusCode, [(header::HeaderName, String); 1]), Response<String>> {
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+    let expected_version = if_match(&headers)?;
 
     let UpdatePasswordRequest {
         name,
@@ -172,9 +315,13 @@ pub async fn update_password(
         website,
         username,
         description,
+        item_key,
     } = request;
+    let action = ItemAction::update(item_key.as_ref());
+
+    access_token::check_item(&mut tx, &principal, pass_id).await?;
 
-    let _ = sqlx::query!(
+    let version = sqlx::query_scalar!(
         r#"
         UPDATE passwords
         SET
@@ -182,8 +329,10 @@ pub async fn update_password(
             password = COALESCE($2, password),
             website = COALESCE($3, website),
             username = COALESCE($4, username),
-            description = COALESCE($5, description)
-        WHERE id = $6 AND owner_id = $7
+            description = COALESCE($5, description),
+            item_key = COALESCE($9, item_key)
+        WHERE id = $6 AND deleted_at IS NULL AND revision = $8 AND item_can(id, $7, $10)
+        RETURNING revision
         "#,
         name,
         password,
@@ -191,43 +340,773 @@ pub async fn update_password(
         username,
         description,
         pass_id,
-        owner_id
+        user_id,
+        expected_version,
+        item_key.as_ref(),
+        action.as_str()
     )
-    .execute(&mut *conn)
+    .fetch_optional(&mut *tx)
     .await
-    .map_err(CpassError::DatabaseError);
+    .map_err(CpassError::DatabaseError)?;
 
-    Ok(StatusCode::NO_CONTENT)
+    let Some(version) = version else {
+        return Err(conditional_write_error(&mut tx, pass_id, user_id, action)
+            .await
+            .into());
+    };
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::ItemUpdated, &principal).item(pass_id),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag(version))]))
 }
 
-/// Delete a password by id
+/// Move a password to the trash by id
 #[utoipa::path(
     delete,
     path = "/api/v1/pass/password/{id}",
     tag = "Password",
+    params(
+        ("If-Match" = String, Header, description = "Version of the password being deleted"),
+    ),
     responses(
-        (status = 204, description = "Password deleted"),
+        (status = 204, description = "Password moved to trash"),
+        (status = 403, description = "Password is read-only for the user"),
+        (status = 404, description = "Password not found"),
+        (status = 409, description = "Password was modified concurrently"),
+        (status = 428, description = "If-Match header is missing"),
     )
 )]
 pub async fn delete_password(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
     headers: HeaderMap,
     Path(pass_id): Path<uuid::Uuid>,
     State(state): State<Arc<AppState>>,
 ) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+    let expected_version = if_match(&headers)?;
+
+    access_token::check_item(&mut tx, &principal, pass_id).await?;
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE passwords
+        SET deleted_at = now()
+        WHERE id = $1 AND deleted_at IS NULL AND revision = $3 AND item_can(id, $2, $4)
+        "#,
+        pass_id,
+        user_id,
+        expected_version,
+        ItemAction::Trash.as_str()
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    if res.rows_affected() == 0 {
+        return Err(
+            conditional_write_error(&mut tx, pass_id, user_id, ItemAction::Trash)
+                .await
+                .into(),
+        );
+    }
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::ItemTrashed, &principal).item(pass_id),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get all passwords in the trash
+#[utoipa::path(
+    get,
+    path = "/api/v1/pass/trash",
+    tag = "Password",
+    responses(
+        (status = 200, description = "Returns all trashed passwords the user can restore",
+            body = Vec<TrashItem>),
+    )
+)]
+pub async fn list_trash(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<TrashItem>>), Response<String>> {
+    let user_id = principal.sub;
     let mut conn = state.pool.conn().await?;
-    let owner_id = claims_from_headers(&headers)?.sub;
 
-    let _ = sqlx::query!(
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, password, name, website, username, description, revision, item_key,
+            collection_id, created_at, updated_at, deleted_at AS "deleted_at!"
+        FROM passwords
+        WHERE deleted_at IS NOT NULL AND (
+            (owner_id = $1 AND collection_id IS NULL)
+            OR (collection_id IS NOT NULL AND item_can(id, $1, $2))
+        )
+        ORDER BY deleted_at DESC
+        "#,
+        user_id,
+        ItemAction::Restore.as_str()
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    let response: Json<Vec<TrashItem>> = rows
+        .into_iter()
+        .map(|x| TrashItem {
+            password: Password {
+                uuid: x.id,
+                name: x.name,
+                password: x.password,
+                website: x.website,
+                username: x.username,
+                description: x.description,
+                version: x.revision,
+                item_key: x.item_key,
+                collection_id: x.collection_id,
+                created_at: x.created_at,
+                updated_at: x.updated_at,
+            },
+            deleted_at: x.deleted_at,
+        })
+        .collect::<Vec<TrashItem>>()
+        .into();
+
+    audit::record(
+        &mut conn,
+        &origin,
+        Event::by(EventType::TrashListed, &principal),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, response))
+}
+
+/// Restore a password from the trash by id
+#[utoipa::path(
+    post,
+    path = "/api/v1/pass/trash/{id}/restore",
+    tag = "Password",
+    responses(
+        (status = 204, description = "Password restored"),
+        (status = 404, description = "Password not found in trash"),
+    )
+)]
+pub async fn restore_item(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(pass_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE passwords
+        SET deleted_at = NULL
+        WHERE id = $1 AND deleted_at IS NOT NULL AND item_can(id, $2, $3)
+        "#,
+        pass_id,
+        user_id,
+        ItemAction::Restore.as_str()
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    if res.rows_affected() == 0 {
+        return Err(
+            CpassError::NotFound("Password with that id not found in trash".to_string()).into(),
+        );
+    }
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::ItemRestored, &principal).item(pass_id),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Permanently delete a password from the trash by id
+#[utoipa::path(
+    delete,
+    path = "/api/v1/pass/trash/{id}",
+    tag = "Password",
+    responses(
+        (status = 204, description = "Password purged"),
+        (status = 404, description = "Password not found in trash"),
+    )
+)]
+pub async fn purge_item(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(pass_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+
+    let res = sqlx::query!(
         r#"
         DELETE FROM passwords
-        WHERE id = $1 AND owner_id = $2
+        WHERE id = $1 AND deleted_at IS NOT NULL AND item_can(id, $2, $3)
         "#,
         pass_id,
+        user_id,
+        ItemAction::Purge.as_str()
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    if res.rows_affected() == 0 {
+        return Err(
+            CpassError::NotFound("Password with that id not found in trash".to_string()).into(),
+        );
+    }
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::ItemPurged, &principal).item(pass_id),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get changes since the given revision
+#[utoipa::path(
+    get,
+    path = "/api/v1/pass/sync",
+    tag = "Password",
+    params(SyncQuery),
+    responses(
+        (status = 200, description = "Returns changed and deleted passwords", body = SyncResponse),
+    )
+)]
+pub async fn sync(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Query(SyncQuery { since_revision }): Query<SyncQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<SyncResponse>), Response<String>> {
+    let owner_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+
+    if since_revision < 0 {
+        return Err(
+            CpassError::InvalidRequest("since_revision can not be negative".to_string()).into(),
+        );
+    }
+
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+        .execute(&mut *tx)
+        .await
+        .map_err(CpassError::DatabaseError)?;
+
+    let revision = sqlx::query_scalar!(
+        r#"
+        SELECT revision FROM users WHERE id = $1
+        "#,
         owner_id
     )
-    .execute(&mut *conn)
+    .fetch_one(&mut *tx)
     .await
     .map_err(CpassError::DatabaseError)?;
 
+    let changed = sqlx::query_as!(
+        PasswordRow,
+        r#"
+        SELECT id, name, password, website, username, description, revision, item_key,
+            collection_id, created_at, updated_at
+        FROM passwords
+        WHERE owner_id = $1 AND collection_id IS NULL AND revision > $2 AND deleted_at IS NULL
+        ORDER BY revision
+        "#,
+        owner_id,
+        since_revision
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(CpassError::DatabaseError)?
+    .into_iter()
+    .map(Password::from)
+    .collect::<Vec<Password>>();
+
+    let deleted = sqlx::query_scalar!(
+        r#"
+        SELECT id AS "id!" FROM passwords
+        WHERE owner_id = $1 AND collection_id IS NULL AND revision > $2
+            AND deleted_at IS NOT NULL
+        UNION ALL
+        SELECT id FROM password_tombstones
+        WHERE owner_id = $1 AND revision > $2
+        "#,
+        owner_id,
+        since_revision
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    // Appending to the audit chain could fail to serialize in a repeatable read transaction.
+    let mut conn = state.pool.conn().await?;
+
+    let response: Json<SyncResponse> = SyncResponse {
+        revision,
+        changed,
+        deleted,
+    }
+    .into();
+
+    let mut event = Event::by(EventType::VaultSynced, &principal)
+        .detail(format!("since revision {}", since_revision));
+    if since_revision == 0 {
+        event = event.whole_vault();
+    }
+    audit::record(&mut conn, &origin, event).await?;
+
+    Ok((StatusCode::OK, response))
+}
+
+/// Add passwords in a single transaction
+#[utoipa::path(
+    post,
+    path = "/api/v1/pass/batch/add",
+    tag = "Password",
+    request_body = BatchAddRequest,
+    responses(
+        (status = 200, description = "Per-item results of the batch", body = BatchResponse),
+    )
+)]
+pub async fn batch_add_passwords(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<BatchAddRequest>,
+) -> Result<(StatusCode, Json<BatchResponse>), Response<String>> {
+    let owner_id = principal.sub;
+    let BatchAddRequest {
+        items,
+        all_or_nothing,
+    } = request;
+
+    let items = items
+        .into_iter()
+        .map(|item| {
+            Ok(NewItem {
+                name: item.name,
+                password: item.password,
+                website: item.website,
+                username: item.username,
+                description: item.description,
+                item_key: item.item_key,
+                collection_id: item.collection_id,
+                honeytoken: item.honeytoken,
+            })
+        })
+        .collect();
+
+    let mut tx = state.pool.tx().await?;
+    let outcome = batch::add_items(&mut tx, owner_id, items, all_or_nothing).await?;
+
+    audit::record_batch(
+        &mut tx,
+        &origin,
+        Event::by(EventType::BatchAdded, &principal),
+        &outcome,
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok((StatusCode::OK, Json(outcome.into())))
+}
+
+/// Update passwords in a single transaction
+#[utoipa::path(
+    post,
+    path = "/api/v1/pass/batch/update",
+    tag = "Password",
+    request_body = BatchUpdateRequest,
+    responses(
+        (status = 200, description = "Per-item results of the batch", body = BatchResponse),
+    )
+)]
+pub async fn batch_update_passwords(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<BatchUpdateRequest>,
+) -> Result<(StatusCode, Json<BatchResponse>), Response<String>> {
+    let user_id = principal.sub;
+    let BatchUpdateRequest {
+        items,
+        all_or_nothing,
+    } = request;
+
+    let items = items
+        .into_iter()
+        .map(|item| {
+            Ok(ItemChange {
+                id: item.id,
+                name: item.changes.name,
+                password: item.changes.password,
+                website: item.changes.website,
+                username: item.changes.username,
+                description: item.changes.description,
+                item_key: item.changes.item_key,
+                expected_version: item.version,
+            })
+        })
+        .collect();
+
+    let mut tx = state.pool.tx().await?;
+    let outcome = batch::update_items(&mut tx, user_id, items, all_or_nothing).await?;
+
+    audit::record_batch(
+        &mut tx,
+        &origin,
+        Event::by(EventType::BatchUpdated, &principal),
+        &outcome,
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok((StatusCode::OK, Json(outcome.into())))
+}
+
+/// Move passwords to the trash in a single transaction
+#[utoipa::path(
+    post,
+    path = "/api/v1/pass/batch/delete",
+    tag = "Password",
+    request_body = BatchDeleteRequest,
+    responses(
+        (status = 200, description = "Per-item results of the batch", body = BatchResponse),
+        (status = 400, description = "Empty batch or too many items"),
+    )
+)]
+pub async fn batch_delete_passwords(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<BatchDeleteRequest>,
+) -> Result<(StatusCode, Json<BatchResponse>), Response<String>> {
+    let user_id = principal.sub;
+    let BatchDeleteRequest {
+        items,
+        all_or_nothing,
+    } = request;
+
+    let items = items
+        .into_iter()
+        .map(|item| {
+            Ok(ItemRemoval {
+                id: item.id,
+                expected_version: item.version,
+            })
+        })
+        .collect();
+
+    let mut tx = state.pool.tx().await?;
+    let outcome = batch::delete_items(&mut tx, user_id, items, all_or_nothing).await?;
+
+    audit::record_batch(
+        &mut tx,
+        &origin,
+        Event::by(EventType::BatchDeleted, &principal),
+        &outcome,
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok((StatusCode::OK, Json(outcome.into())))
+}
+
+/// Re-encrypt the whole vault under a new key
+#[utoipa::path(
+    post,
+    path = "/api/v1/pass/vault/rotate",
+    tag = "Password",
+    request_body = RotateVaultKeyRequest,
+    responses(
+        (status = 200, description = "Vault key is rotated. The recovery key and the vault \
+            keys wrapped for emergency contacts are cleared, the response tells which need \
+            to be wrapped again", body = RotateVaultKeyResponse),
+        (status = 400, description = "Not every item was re-encrypted"),
+        (status = 409, description = "Vault was modified since it was downloaded"),
+    )
+)]
+pub async fn rotate_vault_key(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<RotateVaultKeyRequest>,
+) -> Result<(StatusCode, Json<RotateVaultKeyResponse>), Response<String>> {
+    let RotateVaultKeyRequest {
+        expected_revision,
+        wrapped_vault_key,
+        items,
+        wrapped_private_key,
+    } = request;
+
+    let items = items
+        .into_iter()
+        .map(|item| RotatedItem {
+            id: item.id,
+            name: item.name,
+            password: item.password,
+            website: item.website,
+            username: item.username,
+            description: item.description,
+            item_key: item.item_key,
+        })
+        .collect();
+
+    let mut tx = state.pool.tx().await?;
+    let rotation = rotation::rotate_vault_key(
+        &mut tx,
+        principal.sub,
+        principal.sid,
+        expected_revision,
+        wrapped_vault_key,
+        wrapped_private_key,
+        items,
+    )
+    .await?;
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::VaultKeyRotated, &principal)
+            .detail(format!("revision {}", rotation.revision)),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok((StatusCode::OK, Json(rotation.into())))
+}
+
+/// Share a password with another user
+#[utoipa::path(
+    post,
+    path = "/api/v1/pass/password/{id}/shares",
+    tag = "Password",
+    request_body = ShareItemRequest,
+    responses(
+        (status = 204, description = "Password is shared"),
+        (status = 400, description = "Password has no item key"),
+        (status = 404, description = "Password or recipient not found"),
+    )
+)]
+pub async fn share_item(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(pass_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<ShareItemRequest>,
+) -> Result<StatusCode, Response<String>> {
+    let owner_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+    let ShareItemRequest {
+        recipient_email,
+        wrapped_item_key,
+        permission,
+    } = request;
+
+    sharing::share_item(
+        &mut tx,
+        owner_id,
+        pass_id,
+        &recipient_email,
+        wrapped_item_key,
+        permission,
+    )
+    .await?;
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::ItemShared, &principal)
+            .item(pass_id)
+            .detail(recipient_email),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Get all passwords shared with the user
+#[utoipa::path(
+    get,
+    path = "/api/v1/pass/shared",
+    tag = "Password",
+    responses(
+        (status = 200, description = "Returns the shared passwords", body = Vec<SharedItem>),
+    )
+)]
+pub async fn list_shared_with_me(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<SharedItem>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let items = sharing::shared_with(&mut conn, user_id)
+        .await?
+        .into_iter()
+        .map(|row| SharedItem {
+            password: row.item.into(),
+            permission: row.permission,
+            owner_email: row.owner_email,
+        })
+        .collect();
+
+    audit::record(
+        &mut conn,
+        &origin,
+        Event::by(EventType::SharedListed, &principal),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(items)))
+}
+
+/// Stop sharing a password with a user
+#[utoipa::path(
+    delete,
+    path = "/api/v1/pass/password/{id}/shares/{email}",
+    tag = "Password",
+    params(
+        ("email" = String, Path, description = "Email of the recipient"),
+    ),
+    responses(
+        (status = 204, description = "Share is revoked"),
+        (status = 404, description = "Share not found"),
+    )
+)]
+pub async fn revoke_share(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path((pass_id, email)): Path<(uuid::Uuid, String)>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let owner_id = principal.sub;
+    let mut tx = state.pool.tx().await?;
+
+    sharing::revoke_share(&mut tx, owner_id, pass_id, &email).await?;
+
+    audit::record(
+        &mut tx,
+        &origin,
+        Event::by(EventType::ShareRevoked, &principal)
+            .item(pass_id)
+            .detail(email),
+    )
+    .await?;
+    tx.commit().await.map_err(CpassError::DatabaseError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get all passwords of a collection
+#[utoipa::path(
+    get,
+    path = "/api/v1/pass/collections/{id}/passwords",
+    tag = "Password",
+    responses(
+        (status = 200, description = "Returns the passwords of the collection", body = Vec<Password>),
+        (status = 404, description = "Collection not found"),
+    )
+)]
+pub async fn list_collection_items(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(collection_id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<Password>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    if !principal.reaches(Some(collection_id)) {
+        return Err(CpassError::NotFound("Collection with that id not found".to_string()).into());
+    }
+
+    let passwords = organizations::collection_items(&mut conn, user_id, collection_id)
+        .await?
+        .into_iter()
+        .map(Password::from)
+        .collect();
+
+    audit::record(
+        &mut conn,
+        &origin,
+        Event::by(EventType::CollectionItemsListed, &principal).detail(collection_id.to_string()),
+    )
+    .await?;
+
+    Ok((StatusCode::OK, Json(passwords)))
+}
+
+/// Get the vault of a user who granted the caller emergency access
+#[utoipa::path(
+    get,
+    path = "/api/v1/pass/emergency/{id}/vault",
+    tag = "Password",
+    responses(
+        (status = 200, description = "Returns the wrapped vault key and the passwords", body = EmergencyVault),
+        (status = 403, description = "Emergency access has not been approved"),
+        (status = 404, description = "Emergency contact not found"),
+    )
+)]
+pub async fn get_emergency_vault(
+    AuthUser(principal): AuthUser,
+    origin: Origin,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<EmergencyVault>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let (wrapped_vault_key, items) = emergency::vault(&mut conn, user_id, id).await?;
+
+    audit::record(
+        &mut conn,
+        &origin,
+        Event::by(EventType::EmergencyVaultRead, &principal).detail(id.to_string()),
+    )
+    .await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(EmergencyVault {
+            wrapped_vault_key,
+            passwords: items.into_iter().map(Password::from).collect(),
+        }),
+    ))
+}
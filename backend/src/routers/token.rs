@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Response,
+};
+
+use super::{
+    models::{
+        AccessToken, CreateAccessTokenRequest, CreateMachineAccountRequest, CreatedAccessToken,
+        MachineAccount,
+    },
+    AuthUser, ValidJson,
+};
+use crate::{
+    access_token,
+    db::Db,
+    machine_account::{self, MachineKeys},
+    policy::Scope,
+    AppState,
+};
+
+/// Create a machine account, a principal without a password using access tokens only
+#[utoipa::path(
+    post,
+    path = "/api/v1/tokens/machine_accounts",
+    tag = "Token",
+    request_body = CreateMachineAccountRequest,
+    responses(
+        (status = 201, description = "Machine account created", body = MachineAccount),
+        (status = 400, description = "Name or keys are empty"),
+        (status = 403, description = "Machine accounts can not own machine accounts"),
+    )
+)]
+pub async fn create_machine_account(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<CreateMachineAccountRequest>,
+) -> Result<(StatusCode, Json<MachineAccount>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+    let CreateMachineAccountRequest {
+        name,
+        vault_key,
+        public_key,
+        wrapped_private_key,
+    } = request;
+
+    let machine = machine_account::create(
+        &mut conn,
+        user_id,
+        &name,
+        MachineKeys {
+            vault_key,
+            public_key,
+            wrapped_private_key,
+        },
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(machine.into())))
+}
+
+/// Get the machine accounts of the user
+#[utoipa::path(
+    get,
+    path = "/api/v1/tokens/machine_accounts",
+    tag = "Token",
+    responses(
+        (status = 200, description = "Returns the machine accounts", body = Vec<MachineAccount>),
+    )
+)]
+pub async fn list_machine_accounts(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<MachineAccount>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let machines = machine_account::list(&mut conn, user_id)
+        .await?
+        .into_iter()
+        .map(MachineAccount::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(machines)))
+}
+
+/// Delete a machine account with its access tokens
+#[utoipa::path(
+    delete,
+    path = "/api/v1/tokens/machine_accounts/{id}",
+    tag = "Token",
+    responses(
+        (status = 204, description = "Machine account deleted"),
+        (status = 400, description = "The machine account is the last owner of an organization"),
+        (status = 404, description = "Machine account not found"),
+    )
+)]
+pub async fn delete_machine_account(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+
+    machine_account::delete(&state.pool, user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Create an access token for the user or one of their machine accounts
+#[utoipa::path(
+    post,
+    path = "/api/v1/tokens/access_tokens",
+    tag = "Token",
+    request_body = CreateAccessTokenRequest,
+    responses(
+        (status = 201, description = "Access token created, the token is shown once",
+            body = CreatedAccessToken),
+        (status = 400, description = "Unknown scope, no scope or expiry in the past"),
+        (status = 404, description = "Machine account or collection not found"),
+    )
+)]
+pub async fn create_access_token(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+    ValidJson(request): ValidJson<CreateAccessTokenRequest>,
+) -> Result<(StatusCode, Json<CreatedAccessToken>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+    let CreateAccessTokenRequest {
+        name,
+        scopes,
+        collection_ids,
+        machine_account_id,
+        expires_at,
+    } = request;
+
+    let scopes = Scope::parse_all(&scopes)?;
+
+    let created = access_token::create_token(
+        &mut conn,
+        user_id,
+        machine_account_id,
+        &name,
+        &scopes,
+        collection_ids,
+        expires_at,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedAccessToken {
+            access_token: created.row.into(),
+            token: created.token,
+        }),
+    ))
+}
+
+/// Get the access tokens of the user and of their machine accounts
+#[utoipa::path(
+    get,
+    path = "/api/v1/tokens/access_tokens",
+    tag = "Token",
+    responses(
+        (status = 200, description = "Returns the access tokens", body = Vec<AccessToken>),
+    )
+)]
+pub async fn list_access_tokens(
+    AuthUser(principal): AuthUser,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<Vec<AccessToken>>), Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    let tokens = access_token::list_tokens(&mut conn, user_id)
+        .await?
+        .into_iter()
+        .map(AccessToken::from)
+        .collect();
+
+    Ok((StatusCode::OK, Json(tokens)))
+}
+
+/// Revoke an access token
+#[utoipa::path(
+    delete,
+    path = "/api/v1/tokens/access_tokens/{id}",
+    tag = "Token",
+    responses(
+        (status = 204, description = "Access token revoked"),
+        (status = 404, description = "Access token not found"),
+    )
+)]
+pub async fn revoke_access_token(
+    AuthUser(principal): AuthUser,
+    Path(id): Path<uuid::Uuid>,
+    State(state): State<Arc<AppState>>,
+) -> Result<StatusCode, Response<String>> {
+    let user_id = principal.sub;
+    let mut conn = state.pool.conn().await?;
+
+    access_token::revoke_token(&mut conn, user_id, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
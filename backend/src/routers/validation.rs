@@ -0,0 +1,245 @@
+use super::models::{
+    AddEmergencyContactRequest, AddPasswordRequest, BatchAddRequest, BatchDeleteRequest,
+    BatchUpdateItem, BatchUpdateRequest, ChangeMasterPasswordRequest, CreateAccessTokenRequest,
+    CreateCollectionRequest, CreateMachineAccountRequest, CreateOrganizationRequest,
+    CreateUserRequest, CreateWebhookRequest, DeleteUserRequest, DeviceApproval,
+    InviteMemberRequest, KeyPair, LoginRequest, RecoverAccountRequest, RecoveryCredentials,
+    RecoveryKey, RotateVaultKeyRequest, RotatedItem, ShareItemRequest, TakeoverAccountRequest,
+    UpdatePasswordRequest, UpdateUserRequest,
+};
+use crate::validation::{self, Validate, Validator};
+
+impl Validate for LoginRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::login_fields(
+            v,
+            &self.email,
+            &self.password,
+            &self.scopes,
+            self.device_token.as_deref(),
+            self.device_name.as_deref(),
+            self.device_type.as_deref(),
+        );
+    }
+}
+
+impl Validate for CreateUserRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::create_user_fields(
+            v,
+            &self.email,
+            &self.username,
+            &self.password,
+            self.vault_key.as_deref(),
+            self.recovery.as_ref(),
+        );
+    }
+}
+
+impl Validate for UpdateUserRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::update_user_fields(
+            v,
+            self.email.as_deref(),
+            self.username.as_deref(),
+            self.current_password.as_deref(),
+            self.password.as_deref(),
+            self.vault_key.as_deref(),
+        );
+    }
+}
+
+impl Validate for DeleteUserRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::delete_user_fields(v, &self.password);
+    }
+}
+
+impl Validate for DeviceApproval {
+    /// A single flag, any value is valid.
+    fn check(&self, _: &mut Validator) {}
+}
+
+impl Validate for KeyPair {
+    fn check(&self, v: &mut Validator) {
+        validation::key_pair_fields(v, &self.public_key, &self.wrapped_private_key);
+    }
+}
+
+impl Validate for RecoveryKey {
+    fn check(&self, v: &mut Validator) {
+        validation::recovery_key_fields(v, &self.secret, &self.vault_key);
+    }
+}
+
+impl Validate for RecoveryCredentials {
+    fn check(&self, v: &mut Validator) {
+        validation::recovery_credentials_fields(v, &self.email, &self.secret);
+    }
+}
+
+impl Validate for RecoverAccountRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::recover_account_fields(
+            v,
+            &self.email,
+            &self.secret,
+            &self.password,
+            &self.vault_key,
+            Some(&self.recovery),
+        );
+    }
+}
+
+impl Validate for ChangeMasterPasswordRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::change_master_password_fields(
+            v,
+            &self.current_password,
+            &self.password,
+            &self.vault_key,
+        );
+    }
+}
+
+impl Validate for AddPasswordRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::item_fields(
+            v,
+            &self.name,
+            &self.password,
+            self.website.as_deref(),
+            self.username.as_deref(),
+            self.description.as_deref(),
+            self.item_key.as_deref(),
+        );
+    }
+}
+
+impl Validate for UpdatePasswordRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::item_change_fields(
+            v,
+            self.name.as_deref(),
+            self.password.as_deref(),
+            self.website.as_deref(),
+            self.username.as_deref(),
+            self.description.as_deref(),
+            self.item_key.as_deref(),
+        );
+    }
+}
+
+impl Validate for BatchAddRequest {
+    fn check(&self, v: &mut Validator) {
+        v.items("items", &self.items);
+    }
+}
+
+impl Validate for BatchUpdateItem {
+    fn check(&self, v: &mut Validator) {
+        v.nested("changes", &self.changes);
+    }
+}
+
+impl Validate for BatchUpdateRequest {
+    fn check(&self, v: &mut Validator) {
+        v.items("items", &self.items);
+    }
+}
+
+impl Validate for BatchDeleteRequest {
+    fn check(&self, v: &mut Validator) {
+        v.batch_size("items", self.items.len());
+    }
+}
+
+impl Validate for RotatedItem {
+    fn check(&self, v: &mut Validator) {
+        validation::item_fields(
+            v,
+            &self.name,
+            &self.password,
+            self.website.as_deref(),
+            self.username.as_deref(),
+            self.description.as_deref(),
+            self.item_key.as_deref(),
+        );
+    }
+}
+
+impl Validate for RotateVaultKeyRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::rotate_vault_key_fields(
+            v,
+            &self.wrapped_vault_key,
+            &self.items,
+            self.wrapped_private_key.as_deref(),
+        );
+    }
+}
+
+impl Validate for ShareItemRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::share_item_fields(v, &self.recipient_email, &self.wrapped_item_key);
+    }
+}
+
+impl Validate for CreateOrganizationRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::create_organization_fields(v, &self.name, &self.wrapped_org_key);
+    }
+}
+
+impl Validate for InviteMemberRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::invite_member_fields(v, &self.email, &self.wrapped_org_key);
+    }
+}
+
+impl Validate for CreateCollectionRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::create_collection_fields(v, &self.name);
+    }
+}
+
+impl Validate for AddEmergencyContactRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::emergency_contact_fields(v, &self.email, &self.wrapped_vault_key);
+    }
+}
+
+impl Validate for TakeoverAccountRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::takeover_fields(v, &self.password, &self.vault_key);
+    }
+}
+
+impl Validate for CreateMachineAccountRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::machine_account_fields(
+            v,
+            &self.name,
+            &self.vault_key,
+            &self.public_key,
+            &self.wrapped_private_key,
+        );
+    }
+}
+
+impl Validate for CreateAccessTokenRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::access_token_fields(
+            v,
+            &self.name,
+            &self.scopes,
+            self.collection_ids.as_deref().unwrap_or_default(),
+        );
+    }
+}
+
+impl Validate for CreateWebhookRequest {
+    fn check(&self, v: &mut Validator) {
+        validation::webhook_fields(v, &self.url, &self.events);
+    }
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{error::CpassError, pagination::PasswordRow};
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SharePermission {
+    #[default]
+    Read,
+    Write,
+}
+
+impl SharePermission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SharePermission::Read => "read",
+            SharePermission::Write => "write",
+        }
+    }
+
+    fn from_db(permission: &str) -> Self {
+        match permission {
+            "write" => SharePermission::Write,
+            _ => SharePermission::Read,
+        }
+    }
+}
+
+/// An item someone else shared with the caller.
+pub struct SharedItemRow {
+    /// The item, its `item_key` is wrapped to the public key of the recipient.
+    pub item: PasswordRow,
+    pub permission: SharePermission,
+    pub owner_email: String,
+}
+
+/// Give `recipient_email` access to an item of `owner_id`, or change an existing share.
+pub async fn share_item(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    item_id: Uuid,
+    recipient_email: &str,
+    wrapped_item_key: Vec<u8>,
+    permission: SharePermission,
+) -> Result<(), CpassError> {
+    if wrapped_item_key.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "wrapped item key can not be empty".to_string(),
+        ));
+    }
+
+    let recipient_id = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM users
+        WHERE email = $1
+        "#,
+        recipient_email
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("User with that email not found".to_string()))?;
+
+    if recipient_id == owner_id {
+        return Err(CpassError::InvalidRequest(
+            "an item can not be shared with its owner".to_string(),
+        ));
+    }
+
+    let has_item_key = sqlx::query_scalar!(
+        r#"
+        SELECT item_key IS NOT NULL AS "has_item_key!" FROM passwords
+        WHERE id = $1 AND owner_id = $2 AND collection_id IS NULL AND deleted_at IS NULL
+        "#,
+        item_id,
+        owner_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("Password with that id not found".to_string()))?;
+
+    if !has_item_key {
+        return Err(CpassError::InvalidRequest(
+            "the item has no item key, re-encrypt it under its own key before sharing".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO shares(item_id, recipient_id, wrapped_item_key, permission)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (item_id, recipient_id)
+        DO UPDATE SET wrapped_item_key = EXCLUDED.wrapped_item_key, permission = EXCLUDED.permission
+        "#,
+        item_id,
+        recipient_id,
+        wrapped_item_key,
+        permission.as_str()
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove the access of `recipient_email` to an item of `owner_id`.
+pub async fn revoke_share(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    item_id: Uuid,
+    recipient_email: &str,
+) -> Result<(), CpassError> {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM shares s
+        USING passwords p, users u
+        WHERE s.item_id = p.id AND s.recipient_id = u.id
+            AND p.id = $1 AND p.owner_id = $2 AND p.collection_id IS NULL AND u.email = $3
+        "#,
+        item_id,
+        owner_id,
+        recipient_email
+    )
+    .execute(conn)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(CpassError::NotFound(
+            "Share for that item and user not found".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Every live item shared with `recipient_id`, oldest share first.
+pub async fn shared_with(
+    conn: &mut PgConnection,
+    recipient_id: Uuid,
+) -> Result<Vec<SharedItemRow>, CpassError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            p.id, p.name, p.password, p.website, p.username, p.description, p.revision,
+            p.created_at, p.updated_at, s.wrapped_item_key, s.permission, u.email AS owner_email
+        FROM shares s
+        JOIN passwords p ON p.id = s.item_id
+        JOIN users u ON u.id = p.owner_id
+        WHERE s.recipient_id = $1 AND p.deleted_at IS NULL
+        ORDER BY s.created_at, p.id
+        "#,
+        recipient_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| SharedItemRow {
+            item: PasswordRow {
+                id: row.id,
+                name: row.name,
+                password: row.password,
+                website: row.website,
+                username: row.username,
+                description: row.description,
+                revision: row.revision,
+                item_key: Some(row.wrapped_item_key),
+                collection_id: None,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            },
+            permission: SharePermission::from_db(&row.permission),
+            owner_email: row.owner_email,
+        })
+        .collect())
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::error::CpassError;
+
+const DEFAULT_RETENTION_DAYS: i32 = 30;
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Number of days a deleted item stays in the trash before it is purged.
+///
+/// Read from `TRASH_RETENTION_DAYS`, defaults to 30 days.
+pub fn retention_days() -> anyhow::Result<i32> {
+    match dotenvy::var("TRASH_RETENTION_DAYS") {
+        Ok(days) => Ok(days.parse()?),
+        Err(_) => Ok(DEFAULT_RETENTION_DAYS),
+    }
+}
+
+/// Permanently delete every trashed item older than `retention_days`.
+pub async fn purge_expired(pool: &PgPool, retention_days: i32) -> Result<u64, CpassError> {
+    let res = sqlx::query!(
+        r#"
+        DELETE FROM passwords
+        WHERE deleted_at < now() - make_interval(days => $1)
+        "#,
+        retention_days
+    )
+    .execute(pool)
+    .await
+    .map_err(CpassError::DatabaseError)?;
+
+    Ok(res.rows_affected())
+}
+
+/// Background task purging expired trash items once an hour.
+pub async fn purge_task(pool: PgPool, retention_days: i32) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match purge_expired(&pool, retention_days).await {
+            Ok(0) => {}
+            Ok(count) => info!("Purged {} expired items from trash", count),
+            Err(err) => error!("Failed to purge trash: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,729 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::{batch::MAX_BATCH_SIZE, device::DeviceType, error::CpassError};
+
+/// Longest address accepted, as SMTP allows.
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_USERNAME_LENGTH: usize = 64;
+/// Names shown in lists, like those of organizations, machine accounts and tokens.
+const MAX_NAME_LENGTH: usize = 100;
+/// Login passwords and the secrets derived from recovery keys, hashed on every request.
+const MAX_SECRET_LENGTH: usize = 1024;
+/// Device tokens are generated by clients, short ones could be guessed.
+const MIN_DEVICE_TOKEN_LENGTH: usize = 16;
+const MAX_DEVICE_TOKEN_LENGTH: usize = 256;
+const MAX_URL_LENGTH: usize = 2048;
+/// Entries of lists like scopes, events or collection ids.
+const MAX_LIST_LENGTH: usize = 100;
+/// Encrypted fields of an item, notes included.
+const MAX_BLOB_BYTES: usize = 64 * 1024;
+/// Wrapped keys and public keys.
+const MAX_KEY_BYTES: usize = 16 * 1024;
+const DEFAULT_MAX_REQUEST_BYTES: usize = 8 * 1024 * 1024;
+
+/// Largest request body, or gRPC message, accepted, read from `MAX_REQUEST_BYTES`. A
+/// vault key rotation sends the whole vault at once and needs the most.
+pub fn max_request_bytes() -> anyhow::Result<usize> {
+    match dotenvy::var("MAX_REQUEST_BYTES") {
+        Ok(bytes) => Ok(bytes.parse()?),
+        Err(_) => Ok(DEFAULT_MAX_REQUEST_BYTES),
+    }
+}
+
+/// Field of a request breaking a constraint. Encoded as `types.FieldError` in the details
+/// of gRPC statuses, defined here as the HTTP server builds without the protos.
+#[derive(Clone, PartialEq, prost::Message, Serialize)]
+pub struct FieldError {
+    /// Path of the field, like `email` or `items[2].name`.
+    #[prost(string, tag = "1")]
+    pub field: String,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+/// Every field error of a request, `types.FieldErrors`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FieldErrors {
+    #[prost(message, repeated, tag = "1")]
+    pub fields: Vec<FieldError>,
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.field.is_empty() {
+            true => write!(f, "{}", self.message),
+            false => write!(f, "{}: {}", self.field, self.message),
+        }
+    }
+}
+
+/// Request checked before its handler runs, on either transport.
+pub trait Validate {
+    fn check(&self, v: &mut Validator);
+
+    /// Fail with every field error of the request at once.
+    fn validate(&self) -> Result<(), CpassError> {
+        let mut v = Validator::default();
+        self.check(&mut v);
+        v.finish()
+    }
+}
+
+/// Collects the field errors of a request. Every rule names the field it checks, nested
+/// requests prefix their fields with the path to them.
+#[derive(Default)]
+pub struct Validator {
+    prefix: String,
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    /// Record that `field` breaks a constraint unless `ok`. An empty `field` stands for the
+    /// request itself.
+    pub fn require(&mut self, field: &str, ok: bool, message: impl Into<String>) -> &mut Self {
+        if !ok {
+            let path = format!("{}{}", self.prefix, field);
+            self.errors.push(FieldError {
+                field: path.trim_end_matches('.').to_string(),
+                message: message.into(),
+            });
+        }
+        self
+    }
+
+    fn text(&mut self, field: &str, value: &str, max: usize) -> &mut Self {
+        let length = value.chars().count();
+        if value.trim().is_empty() {
+            self.require(field, false, "can not be empty")
+        } else {
+            self.require(
+                field,
+                length <= max,
+                format!("can not be longer than {} characters", max),
+            )
+        }
+    }
+
+    fn bytes(&mut self, field: &str, value: &[u8], max: usize) -> &mut Self {
+        if value.is_empty() {
+            self.require(field, false, "can not be empty")
+        } else {
+            self.require(
+                field,
+                value.len() <= max,
+                format!("can not be larger than {} bytes", max),
+            )
+        }
+    }
+
+    pub fn email(&mut self, field: &str, email: &str) -> &mut Self {
+        if email.len() > MAX_EMAIL_LENGTH {
+            return self.require(
+                field,
+                false,
+                format!("can not be longer than {} characters", MAX_EMAIL_LENGTH),
+            );
+        }
+        self.require(
+            field,
+            email.parse::<lettre::Address>().is_ok(),
+            "is not a valid email address",
+        )
+    }
+
+    pub fn username(&mut self, field: &str, username: &str) -> &mut Self {
+        self.text(field, username, MAX_USERNAME_LENGTH)
+    }
+
+    pub fn name(&mut self, field: &str, name: &str) -> &mut Self {
+        self.text(field, name, MAX_NAME_LENGTH)
+    }
+
+    /// A login password, or a secret checked like one. Not trimmed, whitespace counts.
+    pub fn secret(&mut self, field: &str, secret: &str) -> &mut Self {
+        if secret.is_empty() {
+            self.require(field, false, "can not be empty")
+        } else {
+            self.require(
+                field,
+                secret.len() <= MAX_SECRET_LENGTH,
+                format!("can not be longer than {} bytes", MAX_SECRET_LENGTH),
+            )
+        }
+    }
+
+    /// The secret a client generated to tell its device apart.
+    pub fn device_token(&mut self, field: &str, token: &str) -> &mut Self {
+        self.require(
+            field,
+            (MIN_DEVICE_TOKEN_LENGTH..=MAX_DEVICE_TOKEN_LENGTH).contains(&token.chars().count()),
+            format!(
+                "must be between {} and {} characters",
+                MIN_DEVICE_TOKEN_LENGTH, MAX_DEVICE_TOKEN_LENGTH
+            ),
+        )
+    }
+
+    pub fn device_type(&mut self, field: &str, device_type: &str) -> &mut Self {
+        let known: Vec<&str> = DeviceType::ALL.iter().map(|known| known.as_str()).collect();
+        self.require(
+            field,
+            DeviceType::parse(device_type).is_ok(),
+            format!("must be one of {}", known.join(", ")),
+        )
+    }
+
+    pub fn url(&mut self, field: &str, url: &str) -> &mut Self {
+        self.text(field, url, MAX_URL_LENGTH)
+    }
+
+    /// An encrypted field of an item.
+    pub fn blob(&mut self, field: &str, blob: &[u8]) -> &mut Self {
+        self.bytes(field, blob, MAX_BLOB_BYTES)
+    }
+
+    /// A wrapped key or a public key.
+    pub fn key(&mut self, field: &str, key: &[u8]) -> &mut Self {
+        self.bytes(field, key, MAX_KEY_BYTES)
+    }
+
+    /// A list of at most [`MAX_LIST_LENGTH`] entries, each checked by `rule`.
+    pub fn list<T>(
+        &mut self,
+        field: &str,
+        list: &[T],
+        rule: impl for<'a> Fn(&'a mut Self, &str, &T) -> &'a mut Self,
+    ) -> &mut Self {
+        self.require(
+            field,
+            list.len() <= MAX_LIST_LENGTH,
+            format!("can not have more than {} entries", MAX_LIST_LENGTH),
+        );
+        for (index, entry) in list.iter().enumerate() {
+            rule(self, &format!("{}[{}]", field, index), entry);
+        }
+        self
+    }
+
+    /// Check a request nested in another one.
+    pub fn nested(&mut self, field: &str, request: &impl Validate) -> &mut Self {
+        let prefix = std::mem::take(&mut self.prefix);
+        self.prefix = format!("{}{}.", prefix, field);
+        request.check(self);
+        self.prefix = prefix;
+        self
+    }
+
+    /// The number of items of a batch, from 1 to [`MAX_BATCH_SIZE`].
+    pub fn batch_size(&mut self, field: &str, size: usize) -> &mut Self {
+        self.require(
+            field,
+            (1..=MAX_BATCH_SIZE).contains(&size),
+            format!("must have from 1 to {} entries", MAX_BATCH_SIZE),
+        )
+    }
+
+    /// Check every request of a batch, the number of items is left to the batch.
+    pub fn items(&mut self, field: &str, items: &[impl Validate]) -> &mut Self {
+        for (index, item) in items.iter().enumerate() {
+            self.nested(&format!("{}[{}]", field, index), item);
+        }
+        self
+    }
+
+    pub fn finish(self) -> Result<(), CpassError> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(CpassError::Validation(self.errors)),
+        }
+    }
+}
+
+// Rules of the requests both transports accept, each transport passes the fields of its own
+// request type so the two can not check them differently.
+
+pub fn login_fields(
+    v: &mut Validator,
+    email: &str,
+    password: &str,
+    scopes: &[String],
+    device_token: Option<&str>,
+    device_name: Option<&str>,
+    device_type: Option<&str>,
+) {
+    v.email("email", email).secret("password", password).list(
+        "scopes",
+        scopes,
+        |v, field, scope| v.name(field, scope),
+    );
+    if let Some(device_token) = device_token {
+        v.device_token("device_token", device_token);
+    }
+    if let Some(device_name) = device_name {
+        v.name("device_name", device_name);
+    }
+    if let Some(device_type) = device_type {
+        v.device_type("device_type", device_type);
+    }
+}
+
+pub fn create_user_fields(
+    v: &mut Validator,
+    email: &str,
+    username: &str,
+    password: &str,
+    vault_key: Option<&[u8]>,
+    recovery: Option<&impl Validate>,
+) {
+    v.email("email", email)
+        .username("username", username)
+        .secret("password", password);
+    if let Some(vault_key) = vault_key {
+        v.key("vault_key", vault_key);
+    }
+    if let Some(recovery) = recovery {
+        v.nested("recovery", recovery);
+    }
+}
+
+/// A new email address comes with the current login password, and with the login password
+/// and wrapped vault key derived with the new address. They can not be set without one.
+pub fn update_user_fields(
+    v: &mut Validator,
+    email: Option<&str>,
+    username: Option<&str>,
+    current_password: Option<&str>,
+    password: Option<&str>,
+    vault_key: Option<&[u8]>,
+) {
+    v.require(
+        "",
+        email.is_some() || username.is_some() || password.is_some(),
+        "at least one field must be set",
+    );
+    if let Some(username) = username {
+        v.username("username", username);
+    }
+
+    let keys = [
+        ("current_password", current_password.is_some()),
+        ("password", password.is_some()),
+        ("vault_key", vault_key.is_some()),
+    ];
+    match email {
+        Some(email) => {
+            v.email("email", email);
+            for (field, set) in keys {
+                v.require(field, set, "is required to change the email address");
+            }
+        }
+        None => {
+            for (field, set) in keys {
+                v.require(
+                    field,
+                    !set,
+                    "can only be set with email, the master password is changed on its own",
+                );
+            }
+        }
+    }
+    if let Some(current_password) = current_password {
+        v.secret("current_password", current_password);
+    }
+    if let Some(password) = password {
+        v.secret("password", password);
+    }
+    if let Some(vault_key) = vault_key {
+        v.key("vault_key", vault_key);
+    }
+}
+
+pub fn delete_user_fields(v: &mut Validator, password: &str) {
+    v.secret("password", password);
+}
+
+pub fn key_pair_fields(v: &mut Validator, public_key: &[u8], wrapped_private_key: &[u8]) {
+    v.key("public_key", public_key)
+        .key("wrapped_private_key", wrapped_private_key);
+}
+
+pub fn recovery_key_fields(v: &mut Validator, secret: &str, vault_key: &[u8]) {
+    v.secret("secret", secret).key("vault_key", vault_key);
+}
+
+pub fn recovery_credentials_fields(v: &mut Validator, email: &str, secret: &str) {
+    v.email("email", email).secret("secret", secret);
+}
+
+pub fn recover_account_fields(
+    v: &mut Validator,
+    email: &str,
+    secret: &str,
+    password: &str,
+    vault_key: &[u8],
+    recovery: Option<&impl Validate>,
+) {
+    v.email("email", email)
+        .secret("secret", secret)
+        .secret("password", password)
+        .key("vault_key", vault_key);
+    match recovery {
+        Some(recovery) => v.nested("recovery", recovery),
+        None => v.require("recovery", false, "is required"),
+    };
+}
+
+pub fn change_master_password_fields(
+    v: &mut Validator,
+    current_password: &str,
+    password: &str,
+    vault_key: &[u8],
+) {
+    v.secret("current_password", current_password)
+        .secret("password", password)
+        .key("vault_key", vault_key);
+}
+
+/// The fields of a new item, or of an item rewrapped by a vault key rotation.
+pub fn item_fields(
+    v: &mut Validator,
+    name: &[u8],
+    password: &[u8],
+    website: Option<&[u8]>,
+    username: Option<&[u8]>,
+    description: Option<&[u8]>,
+    item_key: Option<&[u8]>,
+) {
+    v.blob("name", name).blob("password", password);
+    if let Some(website) = website {
+        v.blob("website", website);
+    }
+    if let Some(username) = username {
+        v.blob("username", username);
+    }
+    if let Some(description) = description {
+        v.blob("description", description);
+    }
+    if let Some(item_key) = item_key {
+        v.key("item_key", item_key);
+    }
+}
+
+/// The fields of an item update, at least one of them is set.
+pub fn item_change_fields(
+    v: &mut Validator,
+    name: Option<&[u8]>,
+    password: Option<&[u8]>,
+    website: Option<&[u8]>,
+    username: Option<&[u8]>,
+    description: Option<&[u8]>,
+    item_key: Option<&[u8]>,
+) {
+    let fields = [
+        ("name", name),
+        ("password", password),
+        ("website", website),
+        ("username", username),
+        ("description", description),
+    ];
+
+    v.require(
+        "",
+        fields.iter().any(|(_, value)| value.is_some()) || item_key.is_some(),
+        "at least one field must be set",
+    );
+    for (field, value) in fields {
+        if let Some(value) = value {
+            v.blob(field, value);
+        }
+    }
+    if let Some(item_key) = item_key {
+        v.key("item_key", item_key);
+    }
+}
+
+pub fn rotate_vault_key_fields(
+    v: &mut Validator,
+    wrapped_vault_key: &[u8],
+    items: &[impl Validate],
+    wrapped_private_key: Option<&[u8]>,
+) {
+    v.key("wrapped_vault_key", wrapped_vault_key)
+        .items("items", items);
+    if let Some(wrapped_private_key) = wrapped_private_key {
+        v.key("wrapped_private_key", wrapped_private_key);
+    }
+}
+
+pub fn share_item_fields(v: &mut Validator, recipient_email: &str, wrapped_item_key: &[u8]) {
+    v.email("recipient_email", recipient_email)
+        .key("wrapped_item_key", wrapped_item_key);
+}
+
+pub fn create_organization_fields(v: &mut Validator, name: &str, wrapped_org_key: &[u8]) {
+    v.name("name", name).key("wrapped_org_key", wrapped_org_key);
+}
+
+pub fn invite_member_fields(v: &mut Validator, email: &str, wrapped_org_key: &[u8]) {
+    v.email("email", email)
+        .key("wrapped_org_key", wrapped_org_key);
+}
+
+pub fn create_collection_fields(v: &mut Validator, name: &[u8]) {
+    v.blob("name", name);
+}
+
+pub fn emergency_contact_fields(v: &mut Validator, email: &str, wrapped_vault_key: &[u8]) {
+    v.email("email", email)
+        .key("wrapped_vault_key", wrapped_vault_key);
+}
+
+pub fn takeover_fields(v: &mut Validator, password: &str, vault_key: &[u8]) {
+    v.secret("password", password).key("vault_key", vault_key);
+}
+
+pub fn machine_account_fields(
+    v: &mut Validator,
+    name: &str,
+    vault_key: &[u8],
+    public_key: &[u8],
+    wrapped_private_key: &[u8],
+) {
+    v.name("name", name)
+        .key("vault_key", vault_key)
+        .key("public_key", public_key)
+        .key("wrapped_private_key", wrapped_private_key);
+}
+
+pub fn access_token_fields<T>(
+    v: &mut Validator,
+    name: &str,
+    scopes: &[String],
+    collection_ids: &[T],
+) {
+    v.name("name", name)
+        .list("scopes", scopes, |v, field, scope| v.name(field, scope))
+        .list("collection_ids", collection_ids, |v, _, _| v);
+}
+
+pub fn webhook_fields(v: &mut Validator, url: &str, events: &[String]) {
+    v.url("url", url)
+        .list("events", events, |v, field, event| v.name(field, event));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Entry(&'static str);
+
+    impl Validate for Entry {
+        fn check(&self, v: &mut Validator) {
+            v.name("name", self.0);
+        }
+    }
+
+    struct Batch(Vec<Entry>);
+
+    impl Validate for Batch {
+        fn check(&self, v: &mut Validator) {
+            v.batch_size("items", self.0.len()).items("items", &self.0);
+        }
+    }
+
+    /// Field errors of the rules `check` runs, as shown to clients.
+    fn errors(check: impl FnOnce(&mut Validator)) -> Vec<String> {
+        let mut v = Validator::default();
+        check(&mut v);
+        match v.finish() {
+            Ok(()) => vec![],
+            Err(CpassError::Validation(errors)) => errors.iter().map(ToString::to_string).collect(),
+            Err(err) => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn email_syntax() {
+        assert!(errors(|v| {
+            v.email("email", "alice@example.com");
+        })
+        .is_empty());
+
+        for email in ["alice", "alice@", "@example.com", "ali ce@example.com", ""] {
+            assert_eq!(
+                errors(|v| {
+                    v.email("email", email);
+                }),
+                ["email: is not a valid email address"],
+                "{}",
+                email
+            );
+        }
+
+        let long = format!("{}@example.com", "a".repeat(MAX_EMAIL_LENGTH));
+        assert_eq!(
+            errors(|v| {
+                v.email("email", &long);
+            }),
+            ["email: can not be longer than 254 characters"]
+        );
+    }
+
+    #[test]
+    fn text_is_trimmed_but_secrets_are_not() {
+        assert_eq!(
+            errors(|v| {
+                v.name("name", " \t ").username("username", "");
+            }),
+            ["name: can not be empty", "username: can not be empty"]
+        );
+        assert!(errors(|v| {
+            v.secret("password", "   ");
+        })
+        .is_empty());
+        assert_eq!(
+            errors(|v| {
+                v.secret("password", "");
+            }),
+            ["password: can not be empty"]
+        );
+    }
+
+    #[test]
+    fn text_counts_characters_and_secrets_count_bytes() {
+        // Two bytes each, the name fits and the secret does not.
+        let accents = "é".repeat(MAX_NAME_LENGTH);
+        assert!(errors(|v| {
+            v.name("name", &accents);
+        })
+        .is_empty());
+        assert_eq!(
+            errors(|v| {
+                v.name("name", &format!("{}é", accents));
+            }),
+            ["name: can not be longer than 100 characters"]
+        );
+
+        assert!(errors(|v| {
+            v.secret("password", &"a".repeat(MAX_SECRET_LENGTH));
+        })
+        .is_empty());
+        assert_eq!(
+            errors(|v| {
+                v.secret("password", &"é".repeat(MAX_SECRET_LENGTH / 2 + 1));
+            }),
+            ["password: can not be longer than 1024 bytes"]
+        );
+    }
+
+    #[test]
+    fn blobs_and_keys_count_bytes() {
+        assert!(errors(|v| {
+            v.blob("name", &vec![0; MAX_BLOB_BYTES])
+                .key("vault_key", &vec![0; MAX_KEY_BYTES]);
+        })
+        .is_empty());
+        assert_eq!(
+            errors(|v| {
+                v.blob("name", &vec![0; MAX_BLOB_BYTES + 1])
+                    .key("vault_key", &[]);
+            }),
+            [
+                "name: can not be larger than 65536 bytes",
+                "vault_key: can not be empty"
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_fields_are_prefixed_with_their_path() {
+        let batch = Batch(vec![Entry("first"), Entry("second"), Entry(" ")]);
+        assert_eq!(
+            errors(|v| {
+                v.items("items", &batch.0);
+            }),
+            ["items[2].name: can not be empty"]
+        );
+
+        assert_eq!(
+            errors(|v| {
+                v.nested("recovery", &Entry(""))
+                    .nested("rotation", &batch)
+                    .name("name", "");
+            }),
+            [
+                "recovery.name: can not be empty",
+                "rotation.items[2].name: can not be empty",
+                "name: can not be empty",
+            ]
+        );
+
+        assert_eq!(
+            errors(|v| {
+                v.list("scopes", &["vault:read", ""], |v, field, scope| {
+                    v.name(field, scope)
+                });
+            }),
+            ["scopes[1]: can not be empty"]
+        );
+    }
+
+    #[test]
+    fn batches_have_from_one_to_max_items() {
+        for size in [1, MAX_BATCH_SIZE] {
+            assert!(errors(|v| {
+                v.batch_size("items", size);
+            })
+            .is_empty());
+        }
+        for size in [0, MAX_BATCH_SIZE + 1] {
+            assert_eq!(
+                errors(|v| {
+                    v.batch_size("items", size);
+                }),
+                ["items: must have from 1 to 1000 entries"]
+            );
+        }
+    }
+
+    #[test]
+    fn empty_update_needs_a_field() {
+        assert_eq!(
+            errors(|v| update_user_fields(v, None, None, None, None, None)),
+            ["at least one field must be set"]
+        );
+        assert!(
+            errors(|v| update_user_fields(v, None, Some("alice"), None, None, None)).is_empty()
+        );
+        assert_eq!(
+            errors(|v| item_change_fields(v, None, None, None, None, None, None)),
+            ["at least one field must be set"]
+        );
+    }
+
+    #[test]
+    fn device_fields_of_a_login() {
+        let login = |v: &mut Validator, token, device_type| {
+            login_fields(
+                v,
+                "alice@example.com",
+                "secret",
+                &[],
+                Some(token),
+                Some("laptop"),
+                Some(device_type),
+            )
+        };
+
+        assert!(errors(|v| login(v, "0123456789abcdef", "cli")).is_empty());
+        assert_eq!(
+            errors(|v| login(v, "0123456789abcde", "toaster")),
+            [
+                "device_token: must be between 16 and 256 characters",
+                "device_type: must be one of browser, desktop, mobile, cli, other",
+            ]
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use ring::digest::{digest, SHA256};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::CpassError,
+    jwt::{generate::generate_bytes, session},
+    mail::{self, Template},
+};
+
+/// How long a verification token stays valid.
+const TOKEN_TTL_HOURS: i32 = 24;
+
+/// Fail unless `email` is a syntactically valid address.
+pub fn validate_email(email: &str) -> Result<(), CpassError> {
+    email
+        .parse::<lettre::Address>()
+        .map(|_| ())
+        .map_err(|_| CpassError::InvalidRequest(format!("{} is not a valid email address", email)))
+}
+
+/// Mail a token proving ownership of `email` to it, replacing any earlier token of the
+/// user.
+async fn issue(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    email: &str,
+    template: Template,
+) -> Result<(), CpassError> {
+    let token = hex::encode(generate_bytes(32));
+    let token_hash = digest(&SHA256, token.as_bytes());
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_verifications(token_hash, user_id, email, expires_at)
+        VALUES ($1, $2, $3, now() + make_interval(hours => $4))
+        ON CONFLICT (user_id) DO UPDATE
+        SET token_hash = EXCLUDED.token_hash, email = EXCLUDED.email,
+            created_at = now(), expires_at = EXCLUDED.expires_at
+        "#,
+        token_hash.as_ref(),
+        user_id,
+        email,
+        TOKEN_TTL_HOURS
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let link = mail::link(&format!("/api/v1/auth/email/{}/verify", token));
+    mail::enqueue(
+        conn,
+        Some(user_id),
+        email,
+        template,
+        &[
+            ("email", email),
+            ("link", &link),
+            ("token", &token),
+            ("hours", &TOKEN_TTL_HOURS.to_string()),
+        ],
+    )
+    .await
+}
+
+/// Start verifying the address of an account that was just created.
+pub async fn start(conn: &mut PgConnection, user_id: Uuid, email: &str) -> Result<(), CpassError> {
+    issue(conn, user_id, email, Template::VerifyEmail).await
+}
+
+/// Mail a new token for whatever address of the user waits to be verified, a pending
+/// change first.
+pub async fn resend(conn: &mut PgConnection, user_id: Uuid) -> Result<(), CpassError> {
+    let user = sqlx::query!(
+        r#"
+        SELECT email, pending_email, email_verified_at IS NOT NULL AS "verified!"
+        FROM users
+        WHERE id = $1 AND machine_owner_id IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| CpassError::NotFound("User not found".to_string()))?;
+
+    match (user.pending_email, user.verified) {
+        (Some(pending), _) => issue(conn, user_id, &pending, Template::ConfirmEmailChange).await,
+        (None, false) => issue(conn, user_id, &user.email, Template::VerifyEmail).await,
+        (None, true) => Err(CpassError::InvalidRequest(
+            "the email address is already verified".to_string(),
+        )),
+    }
+}
+
+/// Ask for the address of the user to change to `email`, with the login password hash and
+/// the wrapped vault key derived with it. The change takes effect once the new address is
+/// verified, the current one is told about it meanwhile. Asking for the current address
+/// drops a pending change.
+pub async fn request_change(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    email: &str,
+    password_hash: &str,
+    vault_key: &[u8],
+) -> Result<(), CpassError> {
+    validate_email(email)?;
+
+    let current = sqlx::query_scalar!(
+        r#"
+        SELECT email FROM users
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if current == email {
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET pending_email = NULL, pending_password = NULL, pending_vault_key = NULL
+            WHERE id = $1
+            "#,
+            user_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM email_verifications
+            WHERE user_id = $1 AND email <> $2
+            "#,
+            user_id,
+            email
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        return Ok(());
+    }
+
+    let taken = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM users WHERE email = $1) AS "taken!"
+        "#,
+        email
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if taken {
+        return Err(CpassError::UserAlreadyExists(email.to_string()));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET pending_email = $2, pending_password = $3, pending_vault_key = $4
+        WHERE id = $1
+        "#,
+        user_id,
+        email,
+        password_hash,
+        vault_key
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    issue(conn, user_id, email, Template::ConfirmEmailChange).await?;
+    mail::enqueue(
+        conn,
+        Some(user_id),
+        &current,
+        Template::EmailChangeRequested,
+        &[("email", &current), ("new_email", email)],
+    )
+    .await
+}
+
+/// Mark the address a token was mailed to as verified, returning its user. A pending
+/// change to that address takes effect along with the login password and vault key that
+/// came with it, which signs out every session of the user.
+pub async fn verify(conn: &mut PgConnection, token: &str) -> Result<Uuid, CpassError> {
+    let token_hash = digest(&SHA256, token.as_bytes());
+    let not_found = || CpassError::NotFound("Verification not found or expired".to_string());
+
+    let verification = sqlx::query!(
+        r#"
+        DELETE FROM email_verifications
+        WHERE token_hash = $1
+        RETURNING user_id, email, expires_at > now() AS "valid!"
+        "#,
+        token_hash.as_ref()
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .filter(|verification| verification.valid)
+    .ok_or_else(not_found)?;
+
+    // Nothing matches once the user asked for another address in the meantime, or changed
+    // their master password or vault key, which drops the pending change.
+    let user = sqlx::query!(
+        r#"
+        UPDATE users u
+        SET email = $2,
+            password = CASE WHEN old.email = $2 THEN u.password
+                ELSE COALESCE(old.pending_password, u.password) END,
+            vault_key = CASE WHEN old.email = $2 THEN u.vault_key
+                ELSE COALESCE(old.pending_vault_key, u.vault_key) END,
+            pending_email = NULL,
+            pending_password = NULL,
+            pending_vault_key = NULL,
+            email_verified_at = now()
+        FROM users old
+        WHERE u.id = $1 AND old.id = u.id AND (old.email = $2 OR old.pending_email = $2)
+        RETURNING u.id, old.email <> $2 AS "changed!"
+        "#,
+        verification.user_id,
+        verification.email
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|err| match err {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            CpassError::UserAlreadyExists(verification.email.clone())
+        }
+        err => CpassError::DatabaseError(err),
+    })?
+    .ok_or_else(not_found)?;
+
+    if user.changed {
+        session::revoke_all(conn, user.id).await?;
+    }
+
+    Ok(user.id)
+}
+
+/// Fail unless the email address of `user_id` is verified, which sensitive operations
+/// require, see [`crate::policy::Permission::Verified`].
+pub async fn require_verified(pool: &PgPool, user_id: Uuid) -> Result<(), CpassError> {
+    let verified = sqlx::query_scalar!(
+        r#"
+        SELECT email_verified_at IS NOT NULL AS "verified!"
+        FROM users
+        WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(false);
+
+    match verified {
+        true => Ok(()),
+        false => Err(CpassError::Forbidden(
+            "the email address of the account must be verified first".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,1089 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::CONTENT_TYPE,
+    redirect, Url,
+};
+use ring::hmac;
+use serde_json::json;
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio::net::lookup_host;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    audit::{EventType, Origin},
+    error::CpassError,
+    jwt::generate::generate_bytes,
+    organizations::{self, OrgRole},
+};
+
+/// Start of every webhook secret.
+const SECRET_PREFIX: &str = "whsec_";
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+/// Deliveries attempted per round of the delivery task.
+const DELIVERY_BATCH: i64 = 50;
+/// How long a claimed delivery is hidden from other instances, well over `DELIVERY_TIMEOUT`.
+const CLAIM_SECONDS: f64 = 60.0;
+const MAX_ATTEMPTS: i32 = 8;
+const DEFAULT_RETRY_BASE_SECONDS: i64 = 30;
+/// Longest wait between two attempts.
+const MAX_RETRY_SECONDS: i64 = 6 * 60 * 60;
+/// Failed logins within `FAILED_LOGIN_WINDOW_MINUTES` making a burst.
+const FAILED_LOGIN_BURST: i64 = 5;
+const FAILED_LOGIN_WINDOW_MINUTES: i32 = 15;
+const MAX_DELIVERY_PAGE: i64 = 100;
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        // A redirect would lead deliveries to an address that was never checked.
+        .redirect(redirect::Policy::none())
+        .no_proxy()
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("webhook client builds");
+    static ref ALLOW_PRIVATE_URLS: bool =
+        allow_private_urls().expect("WEBHOOK_ALLOW_PRIVATE_URLS is checked at startup");
+}
+
+/// Security event a webhook can be notified of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookEvent {
+    /// Successful login, with where it came from.
+    Login,
+    /// Login from a device the user never logged in from before, see [`crate::device`].
+    NewDeviceLogin,
+    /// Every `FAILED_LOGIN_BURST` failed logins within `FAILED_LOGIN_WINDOW_MINUTES`.
+    FailedLoginBurst,
+    /// The master password, recovery key or key pair changed, or the account was recovered.
+    CredentialsChanged,
+    /// The whole vault was read, in one go or from the first of its pages.
+    VaultExported,
+    /// A decoy item was retrieved, see [`crate::honeytoken`].
+    HoneytokenTriggered,
+    /// Sent on request to test an endpoint, can not be subscribed to.
+    Ping,
+}
+
+impl WebhookEvent {
+    /// Events a webhook can subscribe to.
+    pub const SUBSCRIBABLE: [WebhookEvent; 6] = [
+        WebhookEvent::Login,
+        WebhookEvent::NewDeviceLogin,
+        WebhookEvent::FailedLoginBurst,
+        WebhookEvent::CredentialsChanged,
+        WebhookEvent::VaultExported,
+        WebhookEvent::HoneytokenTriggered,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Login => "login",
+            WebhookEvent::NewDeviceLogin => "login.new_device",
+            WebhookEvent::FailedLoginBurst => "login.failed_burst",
+            WebhookEvent::CredentialsChanged => "credentials.changed",
+            WebhookEvent::VaultExported => "vault.exported",
+            WebhookEvent::HoneytokenTriggered => "honeytoken.triggered",
+            WebhookEvent::Ping => "ping",
+        }
+    }
+
+    pub fn parse(event: &str) -> Result<Self, CpassError> {
+        WebhookEvent::SUBSCRIBABLE
+            .into_iter()
+            .find(|known| known.as_str() == event)
+            .ok_or_else(|| CpassError::InvalidRequest(format!("unknown webhook event {}", event)))
+    }
+
+    pub fn parse_all(events: &[String]) -> Result<Vec<Self>, CpassError> {
+        events
+            .iter()
+            .map(|event| WebhookEvent::parse(event))
+            .collect()
+    }
+}
+
+/// A security event about to be sent to the webhooks subscribed to it.
+pub struct Notification {
+    event: WebhookEvent,
+    user_id: Uuid,
+    item_id: Option<Uuid>,
+    detail: Option<String>,
+}
+
+impl Notification {
+    /// Event concerning `user_id`. Their own webhooks and those of the organizations they
+    /// are a member of get it.
+    pub fn new(event: WebhookEvent, user_id: Uuid) -> Self {
+        Notification {
+            event,
+            user_id,
+            item_id: None,
+            detail: None,
+        }
+    }
+
+    pub fn item(mut self, item_id: Uuid) -> Self {
+        self.item_id = Some(item_id);
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+pub struct WebhookRow {
+    pub id: Uuid,
+    /// Organization whose members' events the webhook gets, `None` for the user's own.
+    pub org_id: Option<Uuid>,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A freshly created webhook, `secret` is never shown again.
+pub struct NewWebhook {
+    pub row: WebhookRow,
+    pub secret: String,
+}
+
+pub struct DeliveryRow {
+    pub id: Uuid,
+    pub event_type: String,
+    /// `pending`, `delivered` or `failed` once out of attempts.
+    pub status: String,
+    pub attempts: i32,
+    /// When a pending delivery is attempted next.
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+struct DueDelivery {
+    id: Uuid,
+    event_type: String,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Read from `WEBHOOK_RETRY_BASE_SECONDS`, the wait before the first retry. Defaults to 30
+/// seconds and doubles with every further attempt.
+pub fn retry_base_seconds() -> anyhow::Result<i64> {
+    match dotenvy::var("WEBHOOK_RETRY_BASE_SECONDS") {
+        Ok(seconds) => Ok(seconds.parse()?),
+        Err(_) => Ok(DEFAULT_RETRY_BASE_SECONDS),
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `{timestamp}.{payload}` under the webhook secret, sent as
+/// `X-Cpass-Signature: sha256=<signature>` next to `X-Cpass-Timestamp`.
+pub fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, format!("{}.{}", timestamp, payload).as_bytes());
+
+    hex::encode(tag.as_ref())
+}
+
+/// Whether webhooks may post to loopback, private and other internal addresses, for receivers
+/// on the host or network of the server. Read from `WEBHOOK_ALLOW_PRIVATE_URLS`, off by
+/// default.
+pub fn allow_private_urls() -> anyhow::Result<bool> {
+    match dotenvy::var("WEBHOOK_ALLOW_PRIVATE_URLS") {
+        Ok(allow) => allow
+            .parse()
+            .context("WEBHOOK_ALLOW_PRIVATE_URLS must be true or false"),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Whether `ip` only reaches the host of the server or its network, or is not a unicast
+/// address of the internet at all.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // 0.0.0.0/8, this network.
+                || a == 0
+                // 100.64.0.0/10, shared by carrier-grade NATs.
+                || (a == 100 && b & 0xc0 == 64)
+                // 192.0.0.0/24, IETF protocol assignments.
+                || (a == 192 && b == 0 && c == 0)
+                // 198.18.0.0/15, benchmarking.
+                || (a == 198 && b & 0xfe == 18)
+                // 240.0.0.0/4, reserved.
+                || a >= 240
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+            }
+        },
+    }
+}
+
+/// IPv4 address `ip` reaches: IPv4-mapped `::ffff:a.b.c.d`, IPv4-compatible `::a.b.c.d`
+/// and NAT64 `64:ff9b::a.b.c.d`.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return Some(ip);
+    }
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0, _, _] | [0x64, 0xff9b, 0, 0, 0, 0, _, _] => {
+            let [.., a, b, c, d] = ip.octets();
+            Some(Ipv4Addr::new(a, b, c, d))
+        }
+        _ => None,
+    }
+}
+
+fn check_address(ip: IpAddr) -> Result<(), String> {
+    match is_internal(ip) && !*ALLOW_PRIVATE_URLS {
+        true => Err(format!("{} is not a public address", ip)),
+        false => Ok(()),
+    }
+}
+
+/// Addresses of `host`, failing when any of them is internal.
+async fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .map_err(|err| format!("{} can not be resolved: {}", host, err))?
+        .collect();
+    for addr in &addrs {
+        check_address(addr.ip())?;
+    }
+
+    Ok(addrs)
+}
+
+/// Resolver of the webhook client. Checking the addresses it connects to, rather than only
+/// those the URL resolved to earlier, keeps a DNS answer changed in between from pointing
+/// a delivery at an internal address.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Check that `url` is an http or https URL whose host only has public addresses.
+async fn check_url(url: &str) -> Result<(), String> {
+    let url = match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => return Err("url must be an absolute http or https URL".to_string()),
+    };
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return Err("url must be an absolute http or https URL".to_string()),
+    };
+
+    match host.parse() {
+        Ok(ip) => check_address(ip),
+        Err(_) => {
+            let port = url.port_or_known_default().unwrap_or(0);
+            resolve_public(host, port).await.map(|_| ())
+        }
+    }
+}
+
+/// Check that `user_id` may manage the webhook: it is theirs, or they are an admin of its
+/// organization.
+async fn require_manageable(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    webhook_id: Uuid,
+) -> Result<(), CpassError> {
+    let not_found = || CpassError::NotFound("Webhook with that id not found".to_string());
+
+    let owner = sqlx::query!(
+        r#"
+        SELECT user_id, org_id FROM webhooks
+        WHERE id = $1
+        "#,
+        webhook_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(not_found)?;
+
+    match (owner.user_id, owner.org_id) {
+        (Some(owner_id), _) if owner_id == user_id => Ok(()),
+        (_, Some(org_id)) => {
+            match organizations::require_role(conn, org_id, user_id, OrgRole::Admin).await {
+                Err(CpassError::NotFound(_)) => Err(not_found()),
+                other => other.map(|_| ()),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+/// Create a webhook of `user_id`, or of the organization `org_id` they are an admin of.
+pub async fn create(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    org_id: Option<Uuid>,
+    url: &str,
+    events: &[WebhookEvent],
+) -> Result<NewWebhook, CpassError> {
+    check_url(url).await.map_err(|reason| {
+        CpassError::InvalidRequest(format!("can not post webhooks to that url: {}", reason))
+    })?;
+    if events.is_empty() {
+        return Err(CpassError::InvalidRequest(
+            "a webhook must subscribe to at least one event".to_string(),
+        ));
+    }
+    if let Some(org_id) = org_id {
+        organizations::require_role(conn, org_id, user_id, OrgRole::Admin).await?;
+    }
+
+    let secret = format!("{}{}", SECRET_PREFIX, hex::encode(generate_bytes(32)));
+    let events: Vec<String> = events
+        .iter()
+        .map(|event| event.as_str().to_string())
+        .collect();
+
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO webhooks(user_id, org_id, url, secret, events)
+        VALUES (CASE WHEN $2::UUID IS NULL THEN $1::UUID END, $2, $3, $4, $5)
+        RETURNING id, created_at
+        "#,
+        user_id,
+        org_id,
+        url,
+        secret,
+        &events
+    )
+    .fetch_one(conn)
+    .await?;
+
+    info!(%user_id, webhook_id = %row.id, org_id = ?org_id, "Webhook created");
+
+    Ok(NewWebhook {
+        row: WebhookRow {
+            id: row.id,
+            org_id,
+            url: url.to_string(),
+            events,
+            created_at: row.created_at,
+        },
+        secret,
+    })
+}
+
+/// Webhooks of `user_id`, or of the organization `org_id` they are an admin of.
+pub async fn list(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    org_id: Option<Uuid>,
+) -> Result<Vec<WebhookRow>, CpassError> {
+    if let Some(org_id) = org_id {
+        organizations::require_role(conn, org_id, user_id, OrgRole::Admin).await?;
+    }
+
+    let rows = sqlx::query_as!(
+        WebhookRow,
+        r#"
+        SELECT id, org_id, url, events, created_at FROM webhooks
+        WHERE CASE WHEN $2::UUID IS NULL THEN user_id = $1 ELSE org_id = $2 END
+        ORDER BY created_at
+        "#,
+        user_id,
+        org_id
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Delete a webhook along with its outbox and delivery log.
+pub async fn delete(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    webhook_id: Uuid,
+) -> Result<(), CpassError> {
+    require_manageable(conn, user_id, webhook_id).await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM webhooks
+        WHERE id = $1
+        "#,
+        webhook_id
+    )
+    .execute(conn)
+    .await?;
+
+    info!(%user_id, %webhook_id, "Webhook deleted");
+
+    Ok(())
+}
+
+/// Delivery log of a webhook, newest first.
+pub async fn deliveries(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    webhook_id: Uuid,
+    limit: Option<i64>,
+) -> Result<Vec<DeliveryRow>, CpassError> {
+    let limit = limit.unwrap_or(MAX_DELIVERY_PAGE);
+    if !(1..=MAX_DELIVERY_PAGE).contains(&limit) {
+        return Err(CpassError::InvalidRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_DELIVERY_PAGE
+        )));
+    }
+
+    require_manageable(conn, user_id, webhook_id).await?;
+
+    let rows = sqlx::query_as!(
+        DeliveryRow,
+        r#"
+        SELECT
+            id, event_type, status, attempts, next_attempt_at, last_status, last_error,
+            created_at, delivered_at
+        FROM webhook_deliveries
+        WHERE webhook_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2
+        "#,
+        webhook_id,
+        limit
+    )
+    .fetch_all(conn)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Queue a `ping` to the webhook, to test its endpoint and signature checks.
+pub async fn ping(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    webhook_id: Uuid,
+) -> Result<DeliveryRow, CpassError> {
+    require_manageable(conn, user_id, webhook_id).await?;
+
+    let payload = json!({
+        "id": Uuid::new_v4(),
+        "event": WebhookEvent::Ping.as_str(),
+        "occurred_at": Utc::now(),
+        "webhook_id": webhook_id,
+    })
+    .to_string();
+
+    let row = sqlx::query_as!(
+        DeliveryRow,
+        r#"
+        INSERT INTO webhook_deliveries(webhook_id, event_type, payload)
+        VALUES ($1, $2, $3)
+        RETURNING
+            id, event_type, status, attempts, next_attempt_at, last_status, last_error,
+            created_at, delivered_at
+        "#,
+        webhook_id,
+        WebhookEvent::Ping.as_str(),
+        payload
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok(row)
+}
+
+async fn enqueue(
+    conn: &mut PgConnection,
+    origin: &Origin,
+    notification: Notification,
+) -> Result<u64, CpassError> {
+    let Notification {
+        event,
+        user_id,
+        item_id,
+        detail,
+    } = notification;
+
+    let targets = sqlx::query!(
+        r#"
+        SELECT w.id, u.email
+        FROM webhooks w
+        JOIN users u ON u.id = $1
+        WHERE $2 = ANY(w.events) AND (
+            w.user_id = $1
+            OR w.org_id IN (
+                SELECT org_id FROM org_members
+                WHERE user_id = $1 AND accepted_at IS NOT NULL
+            )
+        )
+        "#,
+        user_id,
+        event.as_str()
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let Some(email) = targets.first().map(|target| target.email.clone()) else {
+        return Ok(0);
+    };
+    let webhook_ids: Vec<Uuid> = targets.into_iter().map(|target| target.id).collect();
+
+    let payload = json!({
+        "id": Uuid::new_v4(),
+        "event": event.as_str(),
+        "occurred_at": Utc::now(),
+        "user_id": user_id,
+        "email": email,
+        "ip": origin.ip,
+        "user_agent": origin.user_agent,
+        "item_id": item_id,
+        "detail": detail,
+    })
+    .to_string();
+
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO webhook_deliveries(webhook_id, event_type, payload)
+        SELECT id, $2, $3 FROM UNNEST($1::UUID[]) AS id
+        "#,
+        &webhook_ids,
+        event.as_str(),
+        payload
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Queue `notification` for every webhook subscribed to it. Failures are only logged, the
+/// event it reports already happened, and rolled back to a savepoint so the transaction of
+/// the caller goes on.
+pub async fn notify(conn: &mut PgConnection, origin: &Origin, notification: Notification) {
+    let event = notification.event.as_str();
+    let user_id = notification.user_id;
+
+    let queued = async {
+        let mut savepoint = conn.begin().await?;
+        enqueue(&mut savepoint, origin, notification).await?;
+        savepoint.commit().await?;
+        Ok::<_, CpassError>(())
+    };
+    if let Err(err) = queued.await {
+        error!(
+            target: "webhook",
+            event,
+            %user_id,
+            "Failed to queue the webhook notification: {:?}",
+            err
+        );
+    }
+}
+
+/// Number of failed logins of `user_id` in the current window when it completes a burst.
+async fn failed_login_burst(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Option<i64>, CpassError> {
+    let failed = sqlx::query_scalar!(
+        r#"
+        SELECT count(*) AS "count!" FROM audit_events
+        WHERE actor_id = $1 AND event_type = $2
+            AND occurred_at > now() - make_interval(mins => $3)
+        "#,
+        user_id,
+        EventType::LoginFailed.as_str(),
+        FAILED_LOGIN_WINDOW_MINUTES
+    )
+    .fetch_one(conn)
+    .await?;
+
+    Ok((failed > 0 && failed % FAILED_LOGIN_BURST == 0).then_some(failed))
+}
+
+/// Notify the webhooks of `user_id` of an audit event when it is a security event.
+/// `whole_vault` tells reads of the whole vault from those of a page or of changes.
+pub async fn audit_event(
+    conn: &mut PgConnection,
+    origin: &Origin,
+    event_type: EventType,
+    whole_vault: bool,
+    user_id: Uuid,
+) {
+    let event = match event_type {
+        _ if whole_vault => WebhookEvent::VaultExported,
+        EventType::Login => WebhookEvent::Login,
+        EventType::LoginFailed => WebhookEvent::FailedLoginBurst,
+        EventType::MasterPasswordChanged
+        | EventType::RecoveryKeySet
+        | EventType::KeyPairSet
+        | EventType::AccountRecovered => WebhookEvent::CredentialsChanged,
+        _ => return,
+    };
+
+    let notification = match event {
+        WebhookEvent::FailedLoginBurst => match failed_login_burst(conn, user_id).await {
+            Ok(Some(failed)) => Notification::new(event, user_id).detail(format!(
+                "{} failed logins in the last {} minutes",
+                failed, FAILED_LOGIN_WINDOW_MINUTES
+            )),
+            Ok(None) => return,
+            Err(err) => {
+                error!(
+                    target: "webhook",
+                    %user_id,
+                    "Failed to count the failed logins: {:?}",
+                    err
+                );
+                return;
+            }
+        },
+        WebhookEvent::CredentialsChanged | WebhookEvent::VaultExported => {
+            Notification::new(event, user_id).detail(event_type.as_str())
+        }
+        _ => Notification::new(event, user_id),
+    };
+
+    notify(conn, origin, notification).await;
+}
+
+/// Post a delivery, returning the HTTP status of the response or why there was none.
+async fn send(delivery: &DueDelivery) -> (Option<u16>, Result<(), String>) {
+    // The URL was checked when the webhook was created, its host may resolve elsewhere now.
+    if let Err(reason) = check_url(&delivery.url).await {
+        return (None, Err(reason));
+    }
+
+    let timestamp = Utc::now().timestamp();
+    let signature = sign(&delivery.secret, timestamp, &delivery.payload);
+
+    let response = CLIENT
+        .post(&delivery.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(CONTENT_TYPE, "application/json")
+        .header("X-Cpass-Event", &delivery.event_type)
+        .header("X-Cpass-Delivery", delivery.id.to_string())
+        .header("X-Cpass-Timestamp", timestamp.to_string())
+        .header("X-Cpass-Signature", format!("sha256={}", signature))
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            match status.is_success() {
+                true => (Some(status.as_u16()), Ok(())),
+                false => (
+                    Some(status.as_u16()),
+                    Err(format!("endpoint responded {}", status)),
+                ),
+            }
+        }
+        Err(err) => (None, Err(err.to_string())),
+    }
+}
+
+/// Wait before the attempt following the `attempts`th one.
+pub fn backoff_seconds(retry_base_seconds: i64, attempts: i32) -> i64 {
+    let exponent = attempts.clamp(1, 32) as u32 - 1;
+
+    retry_base_seconds
+        .saturating_mul(2i64.saturating_pow(exponent))
+        .min(MAX_RETRY_SECONDS)
+}
+
+/// Attempt every delivery that is due, scheduling a retry for those that fail.
+pub async fn deliver_due(pool: &PgPool, retry_base_seconds: i64) -> Result<u64, CpassError> {
+    let due = sqlx::query_as!(
+        DueDelivery,
+        r#"
+        UPDATE webhook_deliveries d
+        SET next_attempt_at = now() + make_interval(secs => $2)
+        FROM webhooks w
+        WHERE w.id = d.webhook_id AND d.id IN (
+            SELECT id FROM webhook_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= now()
+            ORDER BY next_attempt_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING d.id, d.event_type, d.payload, d.attempts, w.url, w.secret
+        "#,
+        DELIVERY_BATCH,
+        CLAIM_SECONDS
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut delivered = 0;
+    for delivery in due {
+        let attempts = delivery.attempts + 1;
+        let (status, outcome) = send(&delivery).await;
+        let last_status = status.map(i32::from);
+
+        match outcome {
+            Ok(()) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE webhook_deliveries
+                    SET status = 'delivered', attempts = $2, last_status = $3, last_error = NULL,
+                        delivered_at = now()
+                    WHERE id = $1
+                    "#,
+                    delivery.id,
+                    attempts,
+                    last_status
+                )
+                .execute(pool)
+                .await?;
+
+                delivered += 1;
+            }
+            Err(reason) => {
+                let state = match attempts >= MAX_ATTEMPTS {
+                    true => "failed",
+                    false => "pending",
+                };
+
+                sqlx::query!(
+                    r#"
+                    UPDATE webhook_deliveries
+                    SET status = $2, attempts = $3, last_status = $4, last_error = $5,
+                        next_attempt_at = now() + make_interval(secs => $6)
+                    WHERE id = $1
+                    "#,
+                    delivery.id,
+                    state,
+                    attempts,
+                    last_status,
+                    reason,
+                    backoff_seconds(retry_base_seconds, attempts) as f64
+                )
+                .execute(pool)
+                .await?;
+
+                error!(
+                    target: "webhook",
+                    delivery_id = %delivery.id,
+                    attempts,
+                    state,
+                    "Failed to deliver the webhook: {}",
+                    reason
+                );
+            }
+        }
+    }
+
+    Ok(delivered)
+}
+
+/// Background task working through the outbox every few seconds.
+pub async fn delivery_task(pool: PgPool, retry_base_seconds: i64) {
+    let mut interval = tokio::time::interval(DELIVERY_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match deliver_due(&pool, retry_base_seconds).await {
+            Ok(0) => {}
+            Ok(count) => info!("Delivered {} webhooks", count),
+            Err(err) => error!("Failed to deliver webhooks: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, Once};
+
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+
+    use super::*;
+
+    #[test]
+    fn internal_addresses() {
+        let internal = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "0.1.2.3",
+            "100.64.0.1",
+            "100.127.255.254",
+            "192.0.0.8",
+            "198.18.0.1",
+            "198.19.255.255",
+            "240.0.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "239.255.255.250",
+            "ff02::1",
+            "ff0e::1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+            "::127.0.0.1",
+            "::10.0.0.1",
+        ];
+        for ip in internal {
+            assert!(is_internal(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in [
+            "93.184.215.14",
+            "8.8.8.8",
+            "100.63.255.255",
+            "100.128.0.1",
+            "192.0.1.1",
+            "198.17.0.1",
+            "198.20.0.1",
+            "223.255.255.254",
+            "2606:4700::1111",
+            "64:ff9b::808:808",
+            "::8.8.8.8",
+        ] {
+            assert!(!is_internal(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256_of_timestamp_and_payload() {
+        assert_eq!(
+            sign("whsec_test", 1700000000, r#"{"event":"ping"}"#),
+            "aa8efe37b751e71157c508c5ac4acb1e9fe5225db98355dfc00f4b680afbc447"
+        );
+        assert_ne!(
+            sign("whsec_test", 1700000001, r#"{"event":"ping"}"#),
+            sign("whsec_test", 1700000000, r#"{"event":"ping"}"#)
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff_seconds(30, 1), 30);
+        assert_eq!(backoff_seconds(30, 2), 60);
+        assert_eq!(backoff_seconds(30, 3), 120);
+        assert_eq!(backoff_seconds(30, 7), 1920);
+        assert_eq!(backoff_seconds(30, 12), MAX_RETRY_SECONDS);
+        assert_eq!(backoff_seconds(30, i32::MAX), MAX_RETRY_SECONDS);
+        assert_eq!(backoff_seconds(i64::MAX, 2), MAX_RETRY_SECONDS);
+    }
+
+    /// A request the receiver got.
+    struct Received {
+        headers: HeaderMap,
+        body: String,
+    }
+
+    /// Local HTTP endpoint answering deliveries with `statuses` in turn, then 200.
+    async fn receiver(statuses: Vec<StatusCode>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(vec![]));
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+
+        let app = Router::new().route(
+            "/hook",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, body: String| async move {
+                    received.lock().unwrap().push(Received { headers, body });
+                    statuses.lock().unwrap().next().unwrap_or(StatusCode::OK)
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (url, received)
+    }
+
+    /// Receivers listen on loopback, which deliveries only reach when allowed.
+    fn allow_private_urls() {
+        static ALLOW: Once = Once::new();
+        ALLOW.call_once(|| std::env::set_var("WEBHOOK_ALLOW_PRIVATE_URLS", "true"));
+    }
+
+    fn header<'a>(received: &'a Received, name: &str) -> &'a str {
+        received.headers[name].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn delivery_is_signed() {
+        allow_private_urls();
+        let (url, received) = receiver(vec![]).await;
+        let delivery = DueDelivery {
+            id: Uuid::new_v4(),
+            event_type: "login".to_string(),
+            payload: r#"{"event":"login"}"#.to_string(),
+            attempts: 0,
+            url,
+            secret: "whsec_test".to_string(),
+        };
+
+        assert_eq!(send(&delivery).await, (Some(200), Ok(())));
+
+        let received = received.lock().unwrap();
+        let [request] = &received[..] else {
+            panic!("expected one request, got {}", received.len());
+        };
+        let timestamp = header(request, "x-cpass-timestamp").parse().unwrap();
+        assert_eq!(request.body, delivery.payload);
+        assert_eq!(header(request, "x-cpass-event"), "login");
+        assert_eq!(header(request, "x-cpass-delivery"), delivery.id.to_string());
+        assert_eq!(
+            header(request, "x-cpass-signature"),
+            format!(
+                "sha256={}",
+                sign("whsec_test", timestamp, &delivery.payload)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_reports_the_status() {
+        allow_private_urls();
+        let (url, _) = receiver(vec![StatusCode::SERVICE_UNAVAILABLE]).await;
+        let delivery = DueDelivery {
+            id: Uuid::new_v4(),
+            event_type: "login".to_string(),
+            payload: "{}".to_string(),
+            attempts: 0,
+            url,
+            secret: "whsec_test".to_string(),
+        };
+
+        let (status, outcome) = send(&delivery).await;
+
+        assert_eq!(status, Some(503));
+        assert!(outcome.unwrap_err().contains("503"));
+    }
+
+    /// Attempts, status and seconds until the next attempt of a delivery.
+    async fn attempt_state(pool: &PgPool, id: Uuid) -> (i32, String, Option<i32>, f64) {
+        sqlx::query_as(
+            r#"
+            SELECT attempts, status, last_status,
+                extract(epoch FROM next_attempt_at - now())::FLOAT8
+            FROM webhook_deliveries
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in CPASS_TEST_DATABASE_URL"]
+    async fn failed_deliveries_are_retried_with_backoff() {
+        allow_private_urls();
+        let database_url = std::env::var("CPASS_TEST_DATABASE_URL")
+            .expect("CPASS_TEST_DATABASE_URL names the test database");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        let (url, received) = receiver(vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ])
+        .await;
+
+        let id: Uuid = sqlx::query_scalar(
+            r#"
+            WITH u AS (
+                INSERT INTO users(email, username, password) VALUES ($1, 'webhook', '')
+                RETURNING id
+            ), w AS (
+                INSERT INTO webhooks(user_id, url, secret, events)
+                SELECT id, $2, 'whsec_test', '{login}' FROM u
+                RETURNING id
+            )
+            INSERT INTO webhook_deliveries(webhook_id, event_type, payload)
+            SELECT id, 'login', '{}' FROM w
+            RETURNING id
+            "#,
+        )
+        .bind(format!("webhook-{}@example.com", Uuid::new_v4().simple()))
+        .bind(url)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+        deliver_due(&pool, 60).await.unwrap();
+        let (attempts, status, last_status, wait) = attempt_state(&pool, id).await;
+        assert_eq!(
+            (attempts, status.as_str(), last_status),
+            (1, "pending", Some(503))
+        );
+        assert!((55.0..=60.0).contains(&wait), "{wait}");
+
+        // Not due yet.
+        deliver_due(&pool, 60).await.unwrap();
+        assert_eq!(attempt_state(&pool, id).await.0, 1);
+
+        let make_due = "UPDATE webhook_deliveries SET next_attempt_at = now() WHERE id = $1";
+        sqlx::query(make_due).bind(id).execute(&pool).await.unwrap();
+        deliver_due(&pool, 60).await.unwrap();
+        let (attempts, status, last_status, wait) = attempt_state(&pool, id).await;
+        assert_eq!(
+            (attempts, status.as_str(), last_status),
+            (2, "pending", Some(500))
+        );
+        assert!((115.0..=120.0).contains(&wait), "{wait}");
+
+        sqlx::query(make_due).bind(id).execute(&pool).await.unwrap();
+        deliver_due(&pool, 60).await.unwrap();
+        let (attempts, status, last_status, _) = attempt_state(&pool, id).await;
+        assert_eq!(
+            (attempts, status.as_str(), last_status),
+            (3, "delivered", Some(200))
+        );
+        assert_eq!(received.lock().unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,659 @@
+//! Contract of the item API: the same scenarios run against the HTTP and gRPC transports
+//! of the `cpass` server, which must agree on every outcome.
+//!
+//! The server is started on a database named by `CPASS_TEST_DATABASE_URL`, which it
+//! migrates. The tests are ignored by default, run them with one set:
+//!
+//! ```sh
+//! CPASS_TEST_DATABASE_URL=postgres://localhost/cpass_test cargo test --test contract -- --ignored
+//! ```
+
+use std::{
+    net::TcpListener,
+    process::{Child, Command},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use prost::Message;
+use serde_json::{json, Value};
+use tonic::{metadata::MetadataValue, transport::Channel, Code, Status};
+use uuid::Uuid;
+
+use proto::{
+    auth::{auth_client::AuthClient, CreateUserRequest},
+    pass::{
+        pass_client::PassClient, AddPasswordRequest, DeletePasswordRequest, UpdatePasswordRequest,
+    },
+    types::FieldErrors,
+};
+
+mod proto {
+    pub mod auth {
+        tonic::include_proto!("auth");
+    }
+
+    pub mod pass {
+        tonic::include_proto!("pass");
+    }
+
+    pub mod types {
+        tonic::include_proto!("types");
+    }
+}
+
+const MASTER_PASSWORD: &str = "Wobbly-Kettle-Horizon-42";
+/// Not valid UTF-8, so neither transport can get away with treating fields as text.
+const BINARY_PASSWORD: &[u8] = &[0x00, 0x9f, 0xff, 0x10, 0x0a, 0xc3, 0x28, 0xfe];
+
+/// The `cpass` server, serving both transports, killed once dropped.
+struct Server {
+    child: Child,
+    http: String,
+    grpc: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Start the server and wait until both transports accept requests.
+async fn server() -> Server {
+    let database_url = std::env::var("CPASS_TEST_DATABASE_URL")
+        .expect("CPASS_TEST_DATABASE_URL names the database of the contract tests");
+    let (http_addr, grpc_addr) = (free_addr(), free_addr());
+
+    let child = Command::new(env!("CARGO_BIN_EXE_cpass"))
+        .env("DATABASE_URL", database_url)
+        .env("HTTP_ADDR", &http_addr)
+        .env("GRPC_ADDR", &grpc_addr)
+        .env("MAIL_DIR", std::env::temp_dir().join("cpass-contract-mail"))
+        .spawn()
+        .expect("the cpass server starts");
+    let server = Server {
+        child,
+        http: format!("http://{}", http_addr),
+        grpc: format!("http://{}", grpc_addr),
+    };
+
+    let client = reqwest::Client::new();
+    for _ in 0..100 {
+        let http = client
+            .get(format!("{}/api/healthcheck", server.http))
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success());
+        let grpc = Channel::from_shared(server.grpc.clone())
+            .unwrap()
+            .connect()
+            .await
+            .is_ok();
+        if http && grpc {
+            return server;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("the cpass server did not come up");
+}
+
+fn unique_email() -> String {
+    format!("contract-{}@example.com", Uuid::new_v4().simple())
+}
+
+/// An item as either transport returns it. Timestamps are in microseconds, the precision
+/// both transports carry them with.
+#[derive(Clone, Debug, PartialEq)]
+struct Item {
+    id: Uuid,
+    name: Vec<u8>,
+    password: Vec<u8>,
+    website: Option<Vec<u8>>,
+    username: Option<Vec<u8>>,
+    description: Option<Vec<u8>>,
+    version: i64,
+    item_key: Option<Vec<u8>>,
+    collection_id: Option<Uuid>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[derive(Clone, Default)]
+struct NewItem {
+    name: Vec<u8>,
+    password: Vec<u8>,
+    website: Option<Vec<u8>>,
+    username: Option<Vec<u8>>,
+    description: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Default)]
+struct Changes {
+    name: Option<Vec<u8>>,
+    password: Option<Vec<u8>>,
+}
+
+/// How a request failed, whatever the transport.
+#[derive(Debug, PartialEq)]
+enum Failure {
+    /// Paths of the invalid fields.
+    Invalid(Vec<String>),
+    NotFound,
+    Conflict,
+    Other(String),
+}
+
+trait Transport {
+    const NAME: &'static str;
+
+    /// Authenticate every later request with `token`.
+    fn authenticate(&mut self, token: String);
+    async fn create_user(&mut self, email: &str) -> Result<String, Failure>;
+    async fn add(&mut self, item: &NewItem) -> Result<Item, Failure>;
+    async fn get(&mut self, id: Uuid) -> Result<Item, Failure>;
+    /// Returns the new version.
+    async fn update(&mut self, id: Uuid, version: i64, changes: &Changes) -> Result<i64, Failure>;
+    async fn delete(&mut self, id: Uuid, version: i64) -> Result<(), Failure>;
+}
+
+struct Http {
+    client: reqwest::Client,
+    base: String,
+    token: Option<String>,
+}
+
+impl Http {
+    fn new(server: &Server) -> Self {
+        Http {
+            client: reqwest::Client::new(),
+            base: format!("{}/api/v1", server.http),
+            token: None,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.base, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, Failure> {
+        let response = request
+            .send()
+            .await
+            .map_err(|err| Failure::Other(err.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        Err(match status.as_u16() {
+            400 => match serde_json::from_str::<Value>(&body) {
+                Ok(error) => Failure::Invalid(
+                    error["fields"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .map(|field| field["field"].as_str().unwrap().to_string())
+                        .collect(),
+                ),
+                Err(_) => Failure::Other(format!("{} {}", status, body)),
+            },
+            404 => Failure::NotFound,
+            409 => Failure::Conflict,
+            _ => Failure::Other(format!("{} {}", status, body)),
+        })
+    }
+
+    fn bytes(value: &Value) -> Option<Vec<u8>> {
+        value.as_str().map(|value| STANDARD.decode(value).unwrap())
+    }
+
+    fn timestamp(value: &Value) -> i64 {
+        value
+            .as_str()
+            .unwrap()
+            .parse::<DateTime<Utc>>()
+            .unwrap()
+            .timestamp_micros()
+    }
+
+    fn item(item: &Value) -> Item {
+        Item {
+            id: item["id"].as_str().unwrap().parse().unwrap(),
+            name: Http::bytes(&item["name"]).unwrap(),
+            password: Http::bytes(&item["password"]).unwrap(),
+            website: Http::bytes(&item["website"]),
+            username: Http::bytes(&item["username"]),
+            description: Http::bytes(&item["description"]),
+            version: item["version"].as_i64().unwrap(),
+            item_key: Http::bytes(&item["item_key"]),
+            collection_id: item["collection_id"].as_str().map(|id| id.parse().unwrap()),
+            created_at: Http::timestamp(&item["created_at"]),
+            updated_at: Http::timestamp(&item["updated_at"]),
+        }
+    }
+
+    fn etag(response: &reqwest::Response) -> i64 {
+        response.headers()["etag"]
+            .to_str()
+            .unwrap()
+            .trim_matches('"')
+            .parse()
+            .unwrap()
+    }
+}
+
+fn base64_opt(value: &Option<Vec<u8>>) -> Option<String> {
+    value.as_ref().map(|value| STANDARD.encode(value))
+}
+
+impl Transport for Http {
+    const NAME: &'static str = "http";
+
+    fn authenticate(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    async fn create_user(&mut self, email: &str) -> Result<String, Failure> {
+        let request = self
+            .request(reqwest::Method::POST, "/auth/user")
+            .json(&json!({
+                "email": email,
+                "username": "contract",
+                "password": MASTER_PASSWORD,
+            }));
+        let user: Value = Http::send(request).await?.json().await.unwrap();
+        Ok(user["token"].as_str().unwrap().to_string())
+    }
+
+    async fn add(&mut self, item: &NewItem) -> Result<Item, Failure> {
+        let request = self
+            .request(reqwest::Method::POST, "/pass/password")
+            .json(&json!({
+                "name": STANDARD.encode(&item.name),
+                "password": STANDARD.encode(&item.password),
+                "website": base64_opt(&item.website),
+                "username": base64_opt(&item.username),
+                "description": base64_opt(&item.description),
+            }));
+        let response = Http::send(request).await?;
+        assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+        let version = Http::etag(&response);
+
+        let item = Http::item(&response.json().await.unwrap());
+        assert_eq!(version, item.version, "the ETag is the version of the item");
+        Ok(item)
+    }
+
+    async fn get(&mut self, id: Uuid) -> Result<Item, Failure> {
+        let request = self.request(reqwest::Method::GET, &format!("/pass/password/{}", id));
+        let response = Http::send(request).await?;
+        Ok(Http::item(&response.json().await.unwrap()))
+    }
+
+    async fn update(&mut self, id: Uuid, version: i64, changes: &Changes) -> Result<i64, Failure> {
+        let request = self
+            .request(reqwest::Method::PUT, &format!("/pass/password/{}", id))
+            .header("if-match", format!("\"{}\"", version))
+            .json(&json!({
+                "name": base64_opt(&changes.name),
+                "password": base64_opt(&changes.password),
+            }));
+        let response = Http::send(request).await?;
+        Ok(Http::etag(&response))
+    }
+
+    async fn delete(&mut self, id: Uuid, version: i64) -> Result<(), Failure> {
+        let request = self
+            .request(reqwest::Method::DELETE, &format!("/pass/password/{}", id))
+            .header("if-match", format!("\"{}\"", version));
+        Http::send(request).await.map(|_| ())
+    }
+}
+
+struct Grpc {
+    auth: AuthClient<Channel>,
+    pass: PassClient<Channel>,
+    token: Option<String>,
+}
+
+impl Grpc {
+    async fn new(server: &Server) -> Self {
+        let channel = Channel::from_shared(server.grpc.clone())
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        Grpc {
+            auth: AuthClient::new(channel.clone()),
+            pass: PassClient::new(channel),
+            token: None,
+        }
+    }
+
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(token) = &self.token {
+            let value: MetadataValue<_> = format!("Bearer {}", token).parse().unwrap();
+            request.metadata_mut().insert("authorization", value);
+        }
+        request
+    }
+
+    fn failure(status: Status) -> Failure {
+        match status.code() {
+            Code::InvalidArgument if !status.details().is_empty() => Failure::Invalid(
+                FieldErrors::decode(status.details())
+                    .unwrap()
+                    .fields
+                    .into_iter()
+                    .map(|field| field.field)
+                    .collect(),
+            ),
+            Code::NotFound => Failure::NotFound,
+            Code::Aborted => Failure::Conflict,
+            _ => Failure::Other(format!("{:?} {}", status.code(), status.message())),
+        }
+    }
+
+    fn item(item: proto::pass::Password) -> Item {
+        Item {
+            id: Uuid::from_slice(&item.uuid).unwrap(),
+            name: item.name,
+            password: item.password,
+            website: item.website,
+            username: item.username,
+            description: item.description,
+            version: item.version,
+            item_key: item.item_key,
+            collection_id: item.collection_id.map(|id| Uuid::from_slice(&id).unwrap()),
+            created_at: item.created_at,
+            updated_at: item.updated_at,
+        }
+    }
+}
+
+impl Transport for Grpc {
+    const NAME: &'static str = "grpc";
+
+    fn authenticate(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
+    async fn create_user(&mut self, email: &str) -> Result<String, Failure> {
+        let request = self.request(CreateUserRequest {
+            email: email.to_string(),
+            username: "contract".to_string(),
+            password: MASTER_PASSWORD.to_string(),
+            ..Default::default()
+        });
+        let user = self
+            .auth
+            .create_user(request)
+            .await
+            .map_err(Grpc::failure)?;
+        Ok(user.into_inner().token)
+    }
+
+    async fn add(&mut self, item: &NewItem) -> Result<Item, Failure> {
+        let request = self.request(AddPasswordRequest {
+            name: item.name.clone(),
+            password: item.password.clone(),
+            website: item.website.clone(),
+            username: item.username.clone(),
+            description: item.description.clone(),
+            ..Default::default()
+        });
+        let item = self
+            .pass
+            .add_password(request)
+            .await
+            .map_err(Grpc::failure)?;
+        Ok(Grpc::item(item.into_inner()))
+    }
+
+    async fn get(&mut self, id: Uuid) -> Result<Item, Failure> {
+        let request = self.request(proto::types::Uuid {
+            uuid: id.as_bytes().to_vec(),
+        });
+        let item = self
+            .pass
+            .get_password(request)
+            .await
+            .map_err(Grpc::failure)?;
+        Ok(Grpc::item(item.into_inner()))
+    }
+
+    async fn update(&mut self, id: Uuid, version: i64, changes: &Changes) -> Result<i64, Failure> {
+        let request = self.request(UpdatePasswordRequest {
+            uuid: id.as_bytes().to_vec(),
+            name: changes.name.clone(),
+            password: changes.password.clone(),
+            expected_version: version,
+            ..Default::default()
+        });
+        let version = self
+            .pass
+            .update_password(request)
+            .await
+            .map_err(Grpc::failure)?;
+        Ok(version.into_inner().version)
+    }
+
+    async fn delete(&mut self, id: Uuid, version: i64) -> Result<(), Failure> {
+        let request = self.request(DeletePasswordRequest {
+            uuid: id.as_bytes().to_vec(),
+            expected_version: version,
+        });
+        self.pass
+            .delete_password(request)
+            .await
+            .map(|_| ())
+            .map_err(Grpc::failure)
+    }
+}
+
+fn new_item() -> NewItem {
+    NewItem {
+        name: b"contract item".to_vec(),
+        password: BINARY_PASSWORD.to_vec(),
+        website: Some(b"https://example.com".to_vec()),
+        username: None,
+        description: Some(b"notes".to_vec()),
+    }
+}
+
+/// Every scenario of the contract, on one transport.
+async fn run_contract<T: Transport>(transport: &mut T) {
+    let name = T::NAME;
+
+    let invalid = transport.create_user("not an email").await;
+    assert_eq!(
+        invalid,
+        Err(Failure::Invalid(vec!["email".to_string()])),
+        "{}: an invalid email is a field error",
+        name
+    );
+
+    let token = transport.create_user(&unique_email()).await.unwrap();
+    transport.authenticate(token);
+
+    let started = Utc::now().timestamp_micros();
+    let new = new_item();
+    let added = transport.add(&new).await.unwrap();
+    assert_eq!(added.name, new.name, "{}: add returns the name", name);
+    assert_eq!(
+        added.password, BINARY_PASSWORD,
+        "{}: binary passwords are stored as sent",
+        name
+    );
+    assert_eq!(
+        added.website, new.website,
+        "{}: add returns the website",
+        name
+    );
+    assert_eq!(added.username, None, "{}: unset fields stay unset", name);
+    assert!(added.version > 0, "{}: add returns the version", name);
+    assert_eq!(added.collection_id, None, "{}: items are personal", name);
+    assert!(
+        (started..=Utc::now().timestamp_micros()).contains(&added.created_at),
+        "{}: add returns the creation time",
+        name
+    );
+    assert_eq!(
+        added.created_at, added.updated_at,
+        "{}: a new item was never updated",
+        name
+    );
+
+    let fetched = transport.get(added.id).await.unwrap();
+    assert_eq!(fetched, added, "{}: get returns the created item", name);
+
+    let empty = transport
+        .update(added.id, added.version, &Changes::default())
+        .await;
+    assert_eq!(
+        empty,
+        Err(Failure::Invalid(vec![String::new()])),
+        "{}: an update without changes is rejected",
+        name
+    );
+
+    let empty_name = transport.add(&NewItem::default()).await;
+    assert_eq!(
+        empty_name,
+        Err(Failure::Invalid(vec![
+            "name".to_string(),
+            "password".to_string()
+        ])),
+        "{}: items need a name and a password",
+        name
+    );
+
+    let changes = Changes {
+        name: Some(b"renamed".to_vec()),
+        ..Default::default()
+    };
+    let version = transport
+        .update(added.id, added.version, &changes)
+        .await
+        .unwrap();
+    assert!(
+        version > added.version,
+        "{}: updates bump the version",
+        name
+    );
+
+    let updated = transport.get(added.id).await.unwrap();
+    assert_eq!(
+        updated.name, b"renamed",
+        "{}: updates change the item",
+        name
+    );
+    assert_eq!(
+        updated.password, BINARY_PASSWORD,
+        "{}: updates keep unchanged fields",
+        name
+    );
+    assert_eq!(
+        updated.version, version,
+        "{}: get returns the new version",
+        name
+    );
+    assert_eq!(
+        updated.created_at, added.created_at,
+        "{}: updates keep the creation time",
+        name
+    );
+    assert!(
+        updated.updated_at >= added.updated_at,
+        "{}: updates move the update time",
+        name
+    );
+
+    let stale = transport.update(added.id, added.version, &changes).await;
+    assert_eq!(
+        stale,
+        Err(Failure::Conflict),
+        "{}: updates of a stale version conflict",
+        name
+    );
+
+    transport.delete(added.id, version).await.unwrap();
+    assert_eq!(
+        transport.get(added.id).await,
+        Err(Failure::NotFound),
+        "{}: deleted items are not found",
+        name
+    );
+    assert_eq!(
+        transport.get(Uuid::new_v4()).await,
+        Err(Failure::NotFound),
+        "{}: unknown items are not found",
+        name
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs a database in CPASS_TEST_DATABASE_URL"]
+async fn http_contract() {
+    let server = server().await;
+    run_contract(&mut Http::new(&server)).await;
+}
+
+#[tokio::test]
+#[ignore = "needs a database in CPASS_TEST_DATABASE_URL"]
+async fn grpc_contract() {
+    let server = server().await;
+    run_contract(&mut Grpc::new(&server).await).await;
+}
+
+/// Items written on one transport read the same on the other.
+#[tokio::test]
+#[ignore = "needs a database in CPASS_TEST_DATABASE_URL"]
+async fn transports_agree() {
+    let server = server().await;
+    let mut http = Http::new(&server);
+    let mut grpc = Grpc::new(&server).await;
+
+    let token = http.create_user(&unique_email()).await.unwrap();
+    http.authenticate(token.clone());
+    grpc.authenticate(token);
+
+    let from_http = http.add(&new_item()).await.unwrap();
+    assert_eq!(grpc.get(from_http.id).await.unwrap(), from_http);
+
+    let from_grpc = grpc.add(&new_item()).await.unwrap();
+    assert_eq!(http.get(from_grpc.id).await.unwrap(), from_grpc);
+
+    let version = grpc
+        .update(
+            from_http.id,
+            from_http.version,
+            &Changes {
+                password: Some(b"\xff\x00".to_vec()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        http.get(from_http.id).await.unwrap(),
+        grpc.get(from_http.id).await.unwrap()
+    );
+    assert_eq!(http.get(from_http.id).await.unwrap().version, version);
+}
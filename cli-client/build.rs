@@ -5,6 +5,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let out_dir: PathBuf = env::var("OUT_DIR")?.into();
 
     tonic_build::configure()
+        // Status details for clients to decode, nothing here reads or builds them.
+        .type_attribute("types.FieldError", "#[allow(dead_code)]")
+        .type_attribute("types.FieldErrors", "#[allow(dead_code)]")
         .build_server(false)
         .build_client(true)
         .file_descriptor_set_path(out_dir.join("cpass_descriptor.bin"))
@@ -13,6 +16,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "proto/types.proto",
                 "proto/auth_service.proto",
                 "proto/pass_service.proto",
+                "proto/notify_service.proto",
             ],
             &["proto"],
         )?;
@@ -6,6 +6,10 @@ pub struct Args {
     #[arg(short, long)]
     pub master_password: String,
 
+    /// Address of the cpass gRPC server.
+    #[arg(short, long, default_value = "http://127.0.0.1:50051")]
+    pub server: String,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -20,4 +24,48 @@ pub enum Commands {
         #[arg(short, long)]
         username: String,
     },
+
+    /// Re-encrypt every item of the vault under a freshly generated key.
+    #[command(name = "rotate_key")]
+    RotateKey {
+        #[arg(short, long)]
+        email: String,
+    },
+
+    /// Set the master password as the new one of the account using its recovery key.
+    #[command(name = "recover")]
+    Recover {
+        #[arg(short, long)]
+        email: String,
+
+        #[arg(short, long)]
+        recovery_key: String,
+    },
+
+    /// Replace the master password, the vault key is re-wrapped and items are untouched.
+    #[command(name = "change_master_password")]
+    ChangeMasterPassword {
+        #[arg(short, long)]
+        email: String,
+
+        #[arg(short, long)]
+        new_master_password: String,
+    },
+
+    /// Move the account to a new email address, the login password and vault key follow it.
+    #[command(name = "change_email")]
+    ChangeEmail {
+        #[arg(short, long)]
+        email: String,
+
+        #[arg(short, long)]
+        new_email: String,
+    },
+
+    /// Print changes of the vault as they happen, until the session is revoked.
+    #[command(name = "watch")]
+    Watch {
+        #[arg(short, long)]
+        email: String,
+    },
 }
@@ -0,0 +1,384 @@
+use std::error::Error;
+
+use tonic::{transport::Channel, Code, Request};
+
+use crate::{
+    crypto::{generate_recovery_key, CipherKey, MasterKeys},
+    password_rules::{self, DEFAULT_MIN_LENGTH, DEFAULT_MIN_SCORE},
+    proto::{
+        auth::{
+            auth_client::AuthClient, ChangeMasterPasswordRequest, CreateUserRequest, LoginRequest,
+            RecoverAccountRequest, RecoveryCredentials, RecoveryKey, UpdateUserRequest,
+        },
+        notify::notify_client::NotifyClient,
+        pass::{
+            pass_client::PassClient, Password, RotateVaultKeyRequest, RotatedItem, SyncRequest,
+        },
+        types::Empty,
+    },
+};
+
+fn authorized<T>(token: &str, message: T) -> Result<Request<T>, Box<dyn Error>> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("authorization", format!("Bearer {}", token).parse()?);
+    Ok(request)
+}
+
+/// Login of the CLI, which keeps no state and is recognized by its user agent.
+fn login_request(email: String, password: String) -> LoginRequest {
+    LoginRequest {
+        email,
+        password,
+        scopes: vec![],
+        device_token: None,
+        device_name: Some("cpass cli".to_string()),
+        device_type: Some("cli".to_string()),
+    }
+}
+
+/// A fresh recovery key for `vault_key` and the message the server stores for it.
+fn new_recovery_key(vault_key: &CipherKey) -> Result<(String, RecoveryKey), Box<dyn Error>> {
+    let recovery_key = generate_recovery_key();
+    let keys = MasterKeys::from_recovery_key(&recovery_key)?;
+
+    Ok((
+        recovery_key,
+        RecoveryKey {
+            secret: keys.login_password,
+            vault_key: vault_key.wrap(&keys.wrapping_key)?,
+        },
+    ))
+}
+
+/// Refuse a master password breaking the default policy of the server. The server only
+/// sees the login password derived from it, so it can not check the master password itself.
+fn check_master_password(
+    master_password: &str,
+    user_inputs: &[&str],
+) -> Result<(), Box<dyn Error>> {
+    let failed = password_rules::broken_rules(
+        master_password,
+        user_inputs,
+        DEFAULT_MIN_LENGTH,
+        DEFAULT_MIN_SCORE,
+        true,
+    );
+
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(format!(
+            "The master password does not meet the policy, it needs {}",
+            failed.join("; ")
+        )
+        .into()),
+    }
+}
+
+fn print_recovery_key(recovery_key: &str) {
+    println!("Recovery key: {}", recovery_key);
+    println!("Store it offline, it is the only way back in without the master password");
+}
+
+pub async fn create_user(
+    server: String,
+    master_password: &str,
+    email: String,
+    username: String,
+) -> Result<(), Box<dyn Error>> {
+    check_master_password(master_password, &[&email, &username])?;
+    let keys = MasterKeys::derive(master_password, &email)?;
+    let vault_key = CipherKey::generate();
+    let (recovery_key, recovery) = new_recovery_key(&vault_key)?;
+
+    let mut auth = AuthClient::connect(server).await?;
+    let user = auth
+        .create_user(CreateUserRequest {
+            email,
+            username,
+            password: keys.login_password,
+            vault_key: Some(vault_key.wrap(&keys.wrapping_key)?),
+            recovery: Some(recovery),
+        })
+        .await?
+        .into_inner();
+
+    println!("User {} created", user.email);
+    print_recovery_key(&recovery_key);
+
+    Ok(())
+}
+
+/// Set `master_password` as the new master password of `email` with their recovery key.
+pub async fn recover(
+    server: String,
+    master_password: &str,
+    email: String,
+    recovery_key: &str,
+) -> Result<(), Box<dyn Error>> {
+    check_master_password(master_password, &[&email])?;
+    let recovery_keys = MasterKeys::from_recovery_key(recovery_key)?;
+    let keys = MasterKeys::derive(master_password, &email)?;
+
+    let mut auth = AuthClient::connect(server).await?;
+    let wrapped = auth
+        .get_recovery_vault_key(RecoveryCredentials {
+            email: email.clone(),
+            secret: recovery_keys.login_password.clone(),
+        })
+        .await?
+        .into_inner()
+        .vault_key;
+    let vault_key = CipherKey::unwrap(&wrapped, &recovery_keys.wrapping_key)?;
+    let (recovery_key, recovery) = new_recovery_key(&vault_key)?;
+
+    auth.recover_account(RecoverAccountRequest {
+        email,
+        secret: recovery_keys.login_password,
+        password: keys.login_password,
+        vault_key: vault_key.wrap(&keys.wrapping_key)?,
+        recovery: Some(recovery),
+    })
+    .await?;
+
+    println!("Master password replaced, the previous recovery key no longer works");
+    print_recovery_key(&recovery_key);
+
+    Ok(())
+}
+
+/// Items with their own item key only need that key re-wrapped, the others are
+/// encrypted directly under the vault key and are re-encrypted field by field.
+fn rotate_item(
+    password: Password,
+    current: &CipherKey,
+    fresh: &CipherKey,
+) -> Result<RotatedItem, Box<dyn Error>> {
+    if let Some(item_key) = password.item_key {
+        return Ok(RotatedItem {
+            uuid: password.uuid,
+            name: password.name,
+            password: password.password,
+            website: password.website,
+            username: password.username,
+            description: password.description,
+            item_key: Some(current.reencrypt(fresh, &item_key)?),
+        });
+    }
+
+    let reencrypt = |data: &[u8]| current.reencrypt(fresh, data);
+    let optional = |data: Option<Vec<u8>>| data.as_deref().map(reencrypt).transpose();
+
+    Ok(RotatedItem {
+        name: reencrypt(&password.name)?,
+        password: reencrypt(&password.password)?,
+        website: optional(password.website)?,
+        username: optional(password.username)?,
+        description: optional(password.description)?,
+        uuid: password.uuid,
+        item_key: None,
+    })
+}
+
+pub async fn rotate_key(
+    server: String,
+    master_password: &str,
+    email: String,
+) -> Result<(), Box<dyn Error>> {
+    let keys = MasterKeys::derive(master_password, &email)?;
+
+    let mut auth = AuthClient::connect(server.clone()).await?;
+    let user = auth
+        .login(login_request(email, keys.login_password))
+        .await?
+        .into_inner();
+
+    let wrapped = user.vault_key.ok_or("Account has no vault key to rotate")?;
+    let current = CipherKey::unwrap(&wrapped, &keys.wrapping_key)?;
+    let fresh = CipherKey::generate();
+    let wrapped_private_key = user
+        .wrapped_private_key
+        .map(|key| current.reencrypt(&fresh, &key))
+        .transpose()?;
+
+    let mut pass: PassClient<Channel> = PassClient::connect(server).await?;
+
+    // The revision of the full sync pins the snapshot, the server refuses the rotation
+    // if anything changed after it, including the trash.
+    let snapshot = pass
+        .sync(authorized(&user.token, SyncRequest { since_revision: 0 })?)
+        .await?
+        .into_inner();
+    let trash = pass
+        .list_trash(authorized(&user.token, Empty {})?)
+        .await?
+        .into_inner();
+
+    let items = snapshot
+        .changed
+        .into_iter()
+        .chain(trash.items.into_iter().filter_map(|item| item.password))
+        .map(|password| rotate_item(password, &current, &fresh))
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = items.len();
+
+    let response = pass
+        .rotate_vault_key(authorized(
+            &user.token,
+            RotateVaultKeyRequest {
+                expected_revision: snapshot.revision,
+                wrapped_vault_key: fresh.wrap(&keys.wrapping_key)?,
+                items,
+                wrapped_private_key,
+            },
+        )?)
+        .await;
+
+    match response {
+        Ok(response) => {
+            let rotation = response.into_inner();
+            println!(
+                "Vault key rotated, {} items re-encrypted at revision {}",
+                count, rotation.revision
+            );
+
+            // The cleared recovery key wrapped the previous vault key.
+            if rotation.recovery_key_cleared {
+                let (recovery_key, recovery) = new_recovery_key(&fresh)?;
+                auth.set_recovery_key(authorized(&user.token, recovery)?)
+                    .await?;
+                print_recovery_key(&recovery_key);
+            }
+            if rotation.emergency_keys_cleared > 0 {
+                println!(
+                    "{} emergency contacts lost access until the vault key is wrapped for them again",
+                    rotation.emergency_keys_cleared
+                );
+            }
+
+            Ok(())
+        }
+        Err(status) if status.code() == Code::Aborted => {
+            Err("The vault was modified during the rotation, run the command again".into())
+        }
+        Err(status) => Err(status.into()),
+    }
+}
+
+/// Re-wrap the vault key by `new_master_password`, items are left untouched.
+pub async fn change_master_password(
+    server: String,
+    master_password: &str,
+    email: String,
+    new_master_password: &str,
+) -> Result<(), Box<dyn Error>> {
+    let keys = MasterKeys::derive(master_password, &email)?;
+
+    let mut auth = AuthClient::connect(server).await?;
+    let user = auth
+        .login(login_request(email.clone(), keys.login_password.clone()))
+        .await?
+        .into_inner();
+
+    check_master_password(new_master_password, &[&email, &user.username])?;
+    let new_keys = MasterKeys::derive(new_master_password, &email)?;
+
+    let wrapped = user
+        .vault_key
+        .ok_or("Account has no vault key to re-wrap")?;
+    let vault_key = CipherKey::unwrap(&wrapped, &keys.wrapping_key)?;
+
+    auth.change_master_password(authorized(
+        &user.token,
+        ChangeMasterPasswordRequest {
+            current_password: keys.login_password,
+            password: new_keys.login_password,
+            vault_key: vault_key.wrap(&new_keys.wrapping_key)?,
+        },
+    )?)
+    .await?;
+
+    println!("Master password changed, every session was signed out");
+
+    Ok(())
+}
+
+/// Request the move to `new_email`, keys are salted by the address so both are derived again.
+pub async fn change_email(
+    server: String,
+    master_password: &str,
+    email: String,
+    new_email: String,
+) -> Result<(), Box<dyn Error>> {
+    let keys = MasterKeys::derive(master_password, &email)?;
+    let new_keys = MasterKeys::derive(master_password, &new_email)?;
+
+    let mut auth = AuthClient::connect(server).await?;
+    let user = auth
+        .login(login_request(email, keys.login_password.clone()))
+        .await?
+        .into_inner();
+
+    let wrapped = user
+        .vault_key
+        .ok_or("Account has no vault key to re-wrap")?;
+    let vault_key = CipherKey::unwrap(&wrapped, &keys.wrapping_key)?;
+
+    auth.update_user(authorized(
+        &user.token,
+        UpdateUserRequest {
+            email: Some(new_email),
+            username: None,
+            password: Some(new_keys.login_password),
+            current_password: Some(keys.login_password),
+            vault_key: Some(vault_key.wrap(&new_keys.wrapping_key)?),
+        },
+    )?)
+    .await?;
+
+    println!(
+        "Open the link mailed to the new address to complete the change, every session is then signed out"
+    );
+
+    Ok(())
+}
+
+/// Print the changes pushed to a new session until it is revoked.
+pub async fn watch(
+    server: String,
+    master_password: &str,
+    email: String,
+) -> Result<(), Box<dyn Error>> {
+    let keys = MasterKeys::derive(master_password, &email)?;
+
+    let mut auth = AuthClient::connect(server.clone()).await?;
+    let user = auth
+        .login(login_request(email, keys.login_password))
+        .await?
+        .into_inner();
+
+    let mut notify = NotifyClient::connect(server).await?;
+    let mut changes = notify
+        .subscribe(authorized(&user.token, Empty {})?)
+        .await?
+        .into_inner();
+
+    while let Some(change) = changes.message().await? {
+        let id = change
+            .item_id
+            .or(change.session_id)
+            .map(|id| uuid::Uuid::from_slice(&id))
+            .transpose()?;
+
+        match (id, change.revision) {
+            (Some(id), Some(revision)) => println!("{} {} @{}", change.event, id, revision),
+            (Some(id), None) => println!("{} {}", change.event, id),
+            _ => println!("{}", change.event),
+        }
+    }
+
+    println!("Session revoked");
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng},
+    AeadCore, Aes256Gcm, Key, Nonce,
+};
+
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// A 256-bit AES-GCM key.
+pub struct CipherKey(Key<Aes256Gcm>);
+
+/// Keys derived from the master password, which never leaves the client.
+pub struct MasterKeys {
+    /// Secret sent to the server in place of the master password.
+    pub login_password: String,
+    /// Key wrapping the vault key stored on the server.
+    pub wrapping_key: CipherKey,
+}
+
+impl MasterKeys {
+    pub fn derive(master_password: &str, email: &str) -> Result<Self, argon2::Error> {
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            mem_cost: 65536,
+            time_cost: 3,
+            lanes: 4,
+            hash_length: 2 * KEY_LEN as u32,
+            ..argon2::Config::default()
+        };
+        let salt = format!("cpass:{}", email.to_lowercase());
+        let raw = argon2::hash_raw(master_password.as_bytes(), salt.as_bytes(), &config)?;
+
+        Ok(Self::split(&raw))
+    }
+
+    /// Keys derived from a recovery key. It is random already, a cheap derivation is enough.
+    pub fn from_recovery_key(recovery_key: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            hash_length: 2 * KEY_LEN as u32,
+            ..argon2::Config::default()
+        };
+        let recovery_key = hex::decode(recovery_key.replace('-', ""))?;
+        if recovery_key.len() != KEY_LEN {
+            return Err("Recovery key has a wrong length".into());
+        }
+        let raw = argon2::hash_raw(&recovery_key, b"cpass:recovery", &config)?;
+
+        Ok(Self::split(&raw))
+    }
+
+    fn split(raw: &[u8]) -> Self {
+        let (login, wrapping) = raw.split_at(KEY_LEN);
+
+        Self {
+            login_password: hex::encode(login),
+            wrapping_key: CipherKey(*Key::<Aes256Gcm>::from_slice(wrapping)),
+        }
+    }
+}
+
+/// A random recovery key, printed as dash separated groups of hex digits.
+pub fn generate_recovery_key() -> String {
+    hex::encode(Aes256Gcm::generate_key(OsRng))
+        .as_bytes()
+        .chunks(8)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+impl CipherKey {
+    pub fn generate() -> Self {
+        Self(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// Encrypt `plaintext`, the random nonce is prepended to the ciphertext.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = Aes256Gcm::new(&self.0)
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| "Can not encrypt data")?;
+
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        if data.len() < NONCE_LEN {
+            return Err("Ciphertext is too short");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+        Aes256Gcm::new(&self.0)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Can not decrypt data, wrong key or corrupted ciphertext")
+    }
+
+    pub fn wrap(&self, wrapping_key: &CipherKey) -> Result<Vec<u8>, &'static str> {
+        wrapping_key.encrypt(self.0.as_slice())
+    }
+
+    pub fn unwrap(wrapped: &[u8], wrapping_key: &CipherKey) -> Result<Self, &'static str> {
+        let raw = wrapping_key.decrypt(wrapped)?;
+        if raw.len() != KEY_LEN {
+            return Err("Wrapped vault key has a wrong length");
+        }
+
+        Ok(Self(*Key::<Aes256Gcm>::from_slice(&raw)))
+    }
+
+    /// Decrypt `data` with this key and encrypt it again under `to`.
+    pub fn reencrypt(&self, to: &CipherKey, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        to.encrypt(&self.decrypt(data)?)
+    }
+}
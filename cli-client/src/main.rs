@@ -1,4 +1,9 @@
 mod cli;
+mod commands;
+mod crypto;
+// The rules the server checks master passwords with, shared like the proto files.
+#[path = "../../backend/src/password_rules.rs"]
+mod password_rules;
 mod proto;
 
 use clap::Parser;
@@ -7,12 +12,36 @@ use clap::Parser;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = cli::Args::parse();
 
-    println!("master_password = {:?}", cli.master_password);
-
     match cli.command {
         cli::Commands::CreateUser { email, username } => {
-            println!("email = {:?}", email);
-            println!("username = {:?}", username);
+            commands::create_user(cli.server, &cli.master_password, email, username).await?;
+        }
+        cli::Commands::RotateKey { email } => {
+            commands::rotate_key(cli.server, &cli.master_password, email).await?;
+        }
+        cli::Commands::Recover {
+            email,
+            recovery_key,
+        } => {
+            commands::recover(cli.server, &cli.master_password, email, &recovery_key).await?;
+        }
+        cli::Commands::ChangeMasterPassword {
+            email,
+            new_master_password,
+        } => {
+            commands::change_master_password(
+                cli.server,
+                &cli.master_password,
+                email,
+                &new_master_password,
+            )
+            .await?;
+        }
+        cli::Commands::ChangeEmail { email, new_email } => {
+            commands::change_email(cli.server, &cli.master_password, email, new_email).await?;
+        }
+        cli::Commands::Watch { email } => {
+            commands::watch(cli.server, &cli.master_password, email).await?;
         }
     }
 
@@ -2,6 +2,10 @@ pub mod auth {
     tonic::include_proto!("auth");
 }
 
+pub mod notify {
+    tonic::include_proto!("notify");
+}
+
 pub mod pass {
     tonic::include_proto!("pass");
 }